@@ -0,0 +1,471 @@
+use actix_web::{
+    body::{to_bytes, BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method},
+    web, Error, HttpMessage, HttpResponse, Responder,
+};
+use chrono::NaiveDateTime;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::export::{self, Column, ExportParams};
+use crate::login_guard::client_ip;
+use crate::pagination::{Page, PageParams, SortField, Sorting};
+use crate::user::{access_policy, path_in_scope, AccessPolicy, Claims};
+
+// Entity recorded for each admin scope, most specific first.
+// The table, when set, is used to snapshot the row before and after the change.
+const AUDITED_ENTITIES: &[(&str, &str, Option<&str>)] = &[
+    ("/admin/events/rsvp/email", "event_rsvp_email", None),
+    ("/admin/events/rsvp", "event_rsvp", Some("eventrsvp")),
+    ("/admin/events", "event", Some("events")),
+    ("/admin/home_group/rsvp/email", "home_group_registration_email", None),
+    ("/admin/home_group/rsvp", "home_group_registration", Some("homegroupregistrations")),
+    ("/admin/home_group", "home_group", Some("homegroups")),
+    ("/admin/servings/rsvp/email", "serving_rsvp_email", None),
+    ("/admin/servings/rsvp", "serving_rsvp", Some("servingrsvps")),
+    ("/admin/servings", "serving", Some("serving")),
+    ("/admin/media/youtube", "youtube_sync", None),
+    ("/admin/media", "media", Some("media")),
+    ("/admin/users", "user", Some("users")),
+    ("/admin/permissions", "permission", None),
+    ("/admin/security", "security_policy", None),
+];
+
+// POST routes under /admin that do not change anything
+const READ_ONLY_ROUTES: &[&str] = &[
+    "/admin/users/verify-password",
+];
+
+// Never copied into the audit log
+const REDACTED_FIELDS: &[&str] = &["password_hash", "totp_secret"];
+
+// Largest JSON export, which is built in memory; CSV and XLSX are streamed
+const MAX_EXPORT_ROWS: i64 = 10_000;
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct AuditEntry {
+    pub id: i32,
+    pub actor_id: Option<i32>,
+    pub actor_username: Option<String>,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: Option<i32>,
+    pub method: String,
+    pub path: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub changes: Option<Value>,
+    pub ip_address: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AuditQuery {
+    pub actor_id: Option<i32>,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<i32>,
+    pub action: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportFormat {
+    pub format: Option<String>,
+}
+
+// What a request is about to change
+struct AuditTarget {
+    action: String,
+    entity_type: String,
+    entity_id: Option<i32>,
+    table: Option<&'static str>,
+}
+
+// Mutating requests on admin routes are audited; user routes such as the
+// watch history are not
+fn audit_target(method: &Method, path: &str) -> Option<AuditTarget> {
+    if !matches!(*method, Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+        return None;
+    }
+
+    if !matches!(access_policy(path), AccessPolicy::AdminOnly | AccessPolicy::Permission(_))
+        || READ_ONLY_ROUTES.contains(&path)
+    {
+        return None;
+    }
+
+    let (scope, entity_type, table) = AUDITED_ENTITIES
+        .iter()
+        .find(|(scope, _, _)| path_in_scope(path, scope))
+        .map(|(scope, entity_type, table)| (*scope, entity_type.to_string(), *table))
+        .unwrap_or_else(|| {
+            // Unlisted admin scope: name the entity after its first segment
+            let name = path.trim_start_matches("/admin/").split('/').next().unwrap_or("admin");
+            ("/admin", name.to_string(), None)
+        });
+
+    let segments: Vec<&str> = path[scope.len()..]
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    let entity_id = segments.iter().find_map(|segment| segment.parse::<i32>().ok());
+
+    let action = match *method {
+        Method::DELETE => "delete".to_string(),
+        Method::PUT | Method::PATCH => "update".to_string(),
+        _ => match segments.iter().rev().find(|segment| segment.parse::<i32>().is_err()) {
+            None | Some(&"add") => "create".to_string(),
+            Some(verb) => verb.to_string(),
+        },
+    };
+
+    Some(AuditTarget { action, entity_type, entity_id, table })
+}
+
+// Current row as JSON, without secrets
+async fn snapshot(pool: &PgPool, table: &str, id: i32) -> Option<Value> {
+    // `table` only ever comes from AUDITED_ENTITIES
+    let result = sqlx::query_scalar::<_, Value>(&format!(
+        "SELECT row_to_json(t)::jsonb FROM {} t WHERE id = $1",
+        table
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await;
+
+    match result {
+        Ok(Some(mut row)) => {
+            if let Some(fields) = row.as_object_mut() {
+                for field in REDACTED_FIELDS {
+                    fields.remove(*field);
+                }
+            }
+            Some(row)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Failed to snapshot {} {}: {:?}", table, id, e);
+            None
+        }
+    }
+}
+
+// Fields whose value differs, as {"field": {"before": .., "after": ..}}
+fn diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut changes = Map::new();
+    for key in before.keys().chain(after.keys()) {
+        if changes.contains_key(key) {
+            continue;
+        }
+        let (old, new) = (before.get(key), after.get(key));
+        if old != new {
+            changes.insert(key.clone(), json!({ "before": old, "after": new }));
+        }
+    }
+
+    Value::Object(changes)
+}
+
+// Records every successful mutating admin request.
+// Registered inside AuthMiddleware so the actor's claims are available.
+#[derive(Clone)]
+pub struct AuditMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for AuditMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = AuditMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditMiddlewareService { service: Rc::new(service) })
+    }
+}
+
+pub struct AuditMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuditMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let target = match audit_target(req.method(), req.path()) {
+                Some(target) => target,
+                None => return service.call(req).await.map(ServiceResponse::map_into_boxed_body),
+            };
+
+            let pool = match req.app_data::<web::Data<PgPool>>() {
+                Some(pool) => pool.clone(),
+                None => {
+                    eprintln!("PgPool is not registered as app data, request not audited");
+                    return service.call(req).await.map(ServiceResponse::map_into_boxed_body);
+                }
+            };
+
+            let actor = req.extensions().get::<Claims>().cloned();
+            let ip = client_ip(req.request());
+            let method = req.method().to_string();
+            let path = req.path().to_string();
+
+            let before = match (target.table, target.entity_id) {
+                (Some(table), Some(id)) => snapshot(&pool, table, id).await,
+                _ => None,
+            };
+
+            let res = service.call(req).await?;
+            if !res.status().is_success() {
+                return Ok(res.map_into_boxed_body());
+            }
+
+            // Buffer the body to learn the id of created entities
+            let (http_req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let body = match to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    let e: Box<dyn std::error::Error> = e.into();
+                    eprintln!("Failed to read response body of {}: {}", path, e);
+                    return Err(actix_web::error::ErrorInternalServerError("Failed to read response"));
+                }
+            };
+
+            let entity_id = target.entity_id.or_else(|| {
+                serde_json::from_slice::<Value>(&body)
+                    .ok()
+                    .and_then(|value| value.get("id").and_then(Value::as_i64))
+                    .map(|id| id as i32)
+            });
+
+            let after = match (target.table, entity_id) {
+                (Some(table), Some(id)) if target.action != "delete" => snapshot(&pool, table, id).await,
+                _ => None,
+            };
+
+            let changes = diff(before.as_ref(), after.as_ref());
+
+            let result = sqlx::query(
+                "INSERT INTO audit_log
+                 (actor_id, actor_username, action, entity_type, entity_id, method, path,
+                  before, after, changes, ip_address)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"
+            )
+            .bind(actor.as_ref().map(|claims| claims.sub))
+            .bind(actor.as_ref().map(|claims| claims.username.clone()))
+            .bind(&target.action)
+            .bind(&target.entity_type)
+            .bind(entity_id)
+            .bind(&method)
+            .bind(&path)
+            .bind(before)
+            .bind(after)
+            .bind(changes)
+            .bind(&ip)
+            .execute(pool.get_ref())
+            .await;
+
+            // The change already happened, so a failed write is only logged
+            if let Err(e) = result {
+                eprintln!("Failed to write audit log for {} {}: {:?}", method, path, e);
+            }
+
+            let res = res.set_body(BoxBody::new(body));
+            Ok(ServiceResponse::new(http_req, res))
+        })
+    }
+}
+
+fn push_filters(query_builder: &mut QueryBuilder<'_, Postgres>, params: &AuditQuery) {
+    if let Some(actor_id) = params.actor_id {
+        query_builder.push(" AND actor_id = ");
+        query_builder.push_bind(actor_id);
+    }
+
+    if let Some(entity_type) = &params.entity_type {
+        query_builder.push(" AND entity_type = ");
+        query_builder.push_bind(entity_type.clone());
+    }
+
+    if let Some(entity_id) = params.entity_id {
+        query_builder.push(" AND entity_id = ");
+        query_builder.push_bind(entity_id);
+    }
+
+    if let Some(action) = &params.action {
+        query_builder.push(" AND action = ");
+        query_builder.push_bind(action.clone());
+    }
+
+    if let Some(from) = params.from {
+        query_builder.push(" AND created_at >= ");
+        query_builder.push_bind(from);
+    }
+
+    if let Some(to) = params.to {
+        query_builder.push(" AND created_at <= ");
+        query_builder.push_bind(to);
+    }
+}
+
+const AUDIT_FIELDS: &str = "id, actor_id, actor_username, action, entity_type, entity_id,
+    method, path, before, after, changes, ip_address, created_at";
+
+// Sorts of the audit log, newest first by default
+static AUDIT_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("created_at", "COALESCE(created_at, TIMESTAMP 'epoch')", "TIMESTAMP"),
+        SortField::new("action", "action", "TEXT"),
+        SortField::new("entity_type", "entity_type", "TEXT"),
+    ],
+    descending: true,
+    id: "id",
+};
+
+// Browse the audit log, newest first (admin only)
+pub async fn get_audit_log(
+    pool: web::Data<PgPool>,
+    params: web::Query<AuditQuery>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &AUDIT_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM audit_log WHERE 1=1");
+    push_filters(&mut count_query, &params);
+
+    let total: i64 = match count_query.build_query_as::<(i64,)>().fetch_one(pool.get_ref()).await {
+        Ok((total,)) => total,
+        Err(e) => {
+            eprintln!("Error counting audit log entries: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch audit log"
+            }));
+        }
+    };
+
+    let mut query_builder = QueryBuilder::new(format!(
+        "SELECT {}, {} FROM audit_log WHERE 1=1",
+        AUDIT_FIELDS,
+        page.key_columns()
+    ));
+    push_filters(&mut query_builder, &params);
+    page.push_to(&mut query_builder);
+
+    let entries = match query_builder.build().fetch_all(pool.get_ref()).await {
+        Ok(rows) => page.finish(rows, total, |row| AuditEntry::from_row(row)),
+        Err(e) => Err(e),
+    };
+
+    match entries {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => {
+            eprintln!("Error fetching audit log: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch audit log"
+            }))
+        }
+    }
+}
+
+fn export_columns() -> Vec<Column> {
+    vec![
+        Column::number("id", "id"),
+        Column::text("created_at", "created_at"),
+        Column::number("actor_id", "actor_id"),
+        Column::text("actor_username", "actor_username"),
+        Column::text("action", "action"),
+        Column::text("entity_type", "entity_type"),
+        Column::number("entity_id", "entity_id"),
+        Column::text("method", "method"),
+        Column::text("path", "path"),
+        Column::text("ip_address", "ip_address"),
+        Column::text("changes", "changes"),
+    ]
+}
+
+// Download the filtered audit log as CSV (default), XLSX or JSON (admin only)
+pub async fn export_audit_log(
+    pool: web::Data<PgPool>,
+    params: web::Query<AuditQuery>,
+    export: web::Query<ExportFormat>,
+    export_params: web::Query<ExportParams>,
+) -> impl Responder {
+    if export.format.as_deref() != Some("json") {
+        let (format, columns) = match export::parse_params(&export_params, export_columns()) {
+            Ok(selection) => selection,
+            Err(response) => return response,
+        };
+
+        let mut query = export::select(&columns);
+        query.push(" FROM audit_log WHERE 1=1");
+        push_filters(&mut query, &params);
+        query.push(" ORDER BY created_at DESC, id DESC");
+        return export::stream(pool.get_ref(), query, columns, format, "audit_log");
+    }
+
+    // One more than the limit tells whether entries would be left out
+    let mut query_builder = QueryBuilder::new(format!("SELECT {} FROM audit_log WHERE 1=1", AUDIT_FIELDS));
+    push_filters(&mut query_builder, &params);
+    query_builder.push(" ORDER BY created_at DESC, id DESC LIMIT ");
+    query_builder.push_bind(MAX_EXPORT_ROWS + 1);
+
+    let entries = match query_builder
+        .build_query_as::<AuditEntry>()
+        .fetch_all(pool.get_ref())
+        .await
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error exporting audit log: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to export audit log"
+            }));
+        }
+    };
+
+    if entries.len() as i64 > MAX_EXPORT_ROWS {
+        return HttpResponse::BadRequest().json(json!({
+            "message": format!(
+                "More than {} entries match; narrow the filters or export as CSV",
+                MAX_EXPORT_ROWS
+            )
+        }));
+    }
+
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"audit_log.json\""))
+        .json(entries)
+}
@@ -0,0 +1,635 @@
+use actix_web::{http::header, web, HttpResponse, Responder};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use serde::Deserialize;
+use sqlx::{FromRow, PgPool, QueryBuilder};
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::events::{self, Event, OccurrenceOverride, EVENT_COLUMNS, MINUTES_PER_DAY};
+use crate::time_zone;
+
+// iCalendar (RFC 5545) export of events. Each event keeps the same UID in
+// the feed, the per-event download and the confirmation mail, and SEQUENCE
+// is the event's revision, so calendar apps update existing entries on edits.
+// Cancelled occurrences are EXDATEs and changed ones are separate VEVENTs
+// with a RECURRENCE-ID, as calendar apps expect. Times carry the event's
+// IANA zone as TZID, defined by a VTIMEZONE with the zone's offset changes
+// from the first year an event needs through next year; next year's changes
+// then repeat yearly.
+// Cancelled and deleted events stay in the feed with STATUS:CANCELLED, so
+// calendar apps mark or remove the entries they already have.
+
+// Past one-off events older than this, and events deleted longer ago, are
+// left out of the feed
+const FEED_HISTORY_DAYS: i64 = 90;
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarFeedParams {
+    category: Option<String>,
+    home_group_id: Option<i32>,
+}
+
+// What the feed still knows of a deleted event, see 022_event_sequence.sql
+#[derive(Debug, FromRow)]
+struct DeletedEvent {
+    id: i32,
+    event_title: String,
+    event_date: NaiveDate,
+    event_time: NaiveTime,
+    recurrence_rule: Option<String>,
+    category: Option<String>,
+    all_day: bool,
+    time_zone: Option<String>,
+    sequence: i32,
+    deleted_at: NaiveDateTime,
+}
+
+impl DeletedEvent {
+    // A published feed can't delete entries, only cancel them
+    fn into_event(self) -> Event {
+        Event {
+            id: self.id,
+            event_title: self.event_title,
+            event_date: self.event_date,
+            event_time: self.event_time,
+            address: None,
+            description: None,
+            recurrence_rule: self.recurrence_rule,
+            recurrence_exceptions: Vec::new(),
+            category: self.category,
+            home_group_id: None,
+            duration_minutes: None,
+            all_day: self.all_day,
+            time_zone: self.time_zone,
+            capacity: None,
+            tags: Vec::new(),
+            status: "cancelled".to_string(),
+            status_reason: None,
+            sequence: self.sequence,
+            created_at: None,
+            updated_at: Some(self.deleted_at),
+        }
+    }
+}
+
+fn uid_domain() -> String {
+    env::var("CALENDAR_UID_DOMAIN").unwrap_or_else(|_| "church-app".to_string())
+}
+
+/// Stable UID of an event across feeds, downloads and emails
+pub fn event_uid(event_id: i32) -> String {
+    format!("event-{}@{}", event_id, uid_domain())
+}
+
+// Escape TEXT values (RFC 5545 section 3.3.11)
+fn escape_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Fold content lines longer than 75 octets (RFC 5545 section 3.1)
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+
+    folded.push_str("\r\n");
+    folded
+}
+
+fn format_local(local: NaiveDateTime) -> String {
+    local.format("%Y%m%dT%H%M%S").to_string()
+}
+
+// A DTSTART-like property: a DATE for all-day events, otherwise local
+// times in the event's zone
+fn date_property(name: &str, event: &Event, values: &[NaiveDateTime]) -> String {
+    if event.all_day {
+        let dates: Vec<String> = values.iter().map(|value| value.format("%Y%m%d").to_string()).collect();
+        format!("{};VALUE=DATE:{}", name, dates.join(","))
+    } else {
+        let zone = time_zone::event_zone(event.time_zone.as_deref());
+        let times: Vec<String> = values.iter().map(|value| format_local(*value)).collect();
+        format!("{};TZID={}:{}", name, zone.name(), times.join(","))
+    }
+}
+
+fn local_start(event: &Event, date: NaiveDate, time: NaiveTime) -> NaiveDateTime {
+    NaiveDateTime::new(date, if event.all_day { NaiveTime::MIN } else { time })
+}
+
+fn format_utc(timestamp: NaiveDateTime) -> String {
+    timestamp.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_vevent(
+    lines: &mut Vec<String>,
+    event: &Event,
+    start: (NaiveDate, NaiveTime),
+    duration_minutes: Option<i32>,
+    title: &str,
+    address: Option<&str>,
+    description: Option<&str>,
+    recurrence_id: Option<NaiveDate>,
+    with_rule: bool,
+) {
+    let stamp = event.updated_at.unwrap_or_else(|| Utc::now().naive_utc());
+
+    lines.push("BEGIN:VEVENT".to_string());
+    lines.push(format!("UID:{}", event_uid(event.id)));
+    lines.push(format!("DTSTAMP:{}", format_utc(stamp)));
+    // Calendar apps only take changes with a higher SEQUENCE
+    lines.push(format!("SEQUENCE:{}", event.sequence));
+    // A cancelled occurrence is cancelled on its own, as sent to its attendees
+    let cancelled = event.status == "cancelled"
+        || recurrence_id.is_some_and(|date| event.recurrence_exceptions.contains(&date));
+    lines.push(if cancelled { "STATUS:CANCELLED" } else { "STATUS:CONFIRMED" }.to_string());
+    if let Some(recurrence_id) = recurrence_id {
+        lines.push(date_property("RECURRENCE-ID", event, &[local_start(event, recurrence_id, event.event_time)]));
+    }
+    let start = local_start(event, start.0, start.1);
+    lines.push(date_property("DTSTART", event, &[start]));
+    // Without DTEND a timed event has no length and an all-day event lasts one day
+    if let Some(minutes) = duration_minutes {
+        let minutes = if event.all_day {
+            (minutes + MINUTES_PER_DAY - 1) / MINUTES_PER_DAY * MINUTES_PER_DAY
+        } else {
+            minutes
+        };
+        if let Some(end) = start.checked_add_signed(Duration::minutes(minutes as i64)) {
+            lines.push(date_property("DTEND", event, &[end]));
+        }
+    }
+    lines.push(format!("SUMMARY:{}", escape_text(title)));
+    if let Some(address) = address {
+        lines.push(format!("LOCATION:{}", escape_text(address)));
+    }
+    if let Some(description) = description {
+        lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+    }
+    // The category first, then the tags
+    let categories: Vec<String> = event
+        .category
+        .iter()
+        .chain(event.tags.iter())
+        .map(|category| escape_text(category))
+        .collect();
+    if !categories.is_empty() {
+        lines.push(format!("CATEGORIES:{}", categories.join(",")));
+    }
+
+    if with_rule {
+        if let Some(rule) = &event.recurrence_rule {
+            lines.push(format!("RRULE:{}", rule));
+            if !event.recurrence_exceptions.is_empty() {
+                let dates: Vec<NaiveDateTime> = event
+                    .recurrence_exceptions
+                    .iter()
+                    .map(|date| local_start(event, *date, event.event_time))
+                    .collect();
+                lines.push(date_property("EXDATE", event, &dates));
+            }
+        }
+    }
+
+    if let Some(updated_at) = event.updated_at {
+        lines.push(format!("LAST-MODIFIED:{}", format_utc(updated_at)));
+    }
+    lines.push("END:VEVENT".to_string());
+}
+
+// The whole series: the main VEVENT plus one per changed occurrence
+fn push_event(lines: &mut Vec<String>, event: &Event, overrides: &[OccurrenceOverride]) {
+    push_vevent(
+        lines,
+        event,
+        (event.event_date, event.event_time),
+        event.duration_minutes,
+        &event.event_title,
+        event.address.as_deref(),
+        event.description.as_deref(),
+        None,
+        true,
+    );
+
+    if event.recurrence_rule.is_none() {
+        return;
+    }
+
+    for o in overrides.iter().filter(|o| o.event_id == event.id) {
+        if event.recurrence_exceptions.contains(&o.occurrence_date) {
+            continue;
+        }
+        push_occurrence(lines, event, o.occurrence_date, Some(o));
+    }
+}
+
+// A single occurrence of a recurring event
+fn push_occurrence(
+    lines: &mut Vec<String>,
+    event: &Event,
+    occurrence_date: NaiveDate,
+    override_: Option<&OccurrenceOverride>,
+) {
+    let title = override_.and_then(|o| o.event_title.as_deref()).unwrap_or(&event.event_title);
+    let date = override_.and_then(|o| o.event_date).unwrap_or(occurrence_date);
+    let time = override_.and_then(|o| o.event_time).unwrap_or(event.event_time);
+    let address = override_.and_then(|o| o.address.as_deref()).or(event.address.as_deref());
+    let description = override_.and_then(|o| o.description.as_deref()).or(event.description.as_deref());
+    let duration_minutes = override_.and_then(|o| o.duration_minutes).or(event.duration_minutes);
+
+    push_vevent(
+        lines,
+        event,
+        (date, time),
+        duration_minutes,
+        title,
+        address,
+        description,
+        Some(occurrence_date),
+        false,
+    );
+}
+
+fn format_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    let offset = format!("{}{:02}{:02}", sign, seconds / 3600, seconds % 3600 / 60);
+    if seconds % 60 == 0 {
+        offset
+    } else {
+        format!("{}{:02}", offset, seconds % 60)
+    }
+}
+
+fn utc_offset(zone: Tz, at: NaiveDateTime) -> i32 {
+    zone.offset_from_utc_datetime(&at).fix().local_minus_utc()
+}
+
+// A STANDARD or DAYLIGHT part of a VTIMEZONE
+#[derive(Debug)]
+struct Observance {
+    onset: NaiveDateTime, // Local time it starts at, in the offset before it
+    offset_from: i32,
+    offset_to: i32,
+    daylight: bool,
+    name: String,
+}
+
+// The observance starting at `at` (UTC), after `offset_from`
+fn observance(zone: Tz, at: NaiveDateTime, offset_from: i32) -> Observance {
+    let offset = zone.offset_from_utc_datetime(&at);
+    Observance {
+        onset: at + Duration::seconds(offset_from as i64),
+        offset_from,
+        offset_to: offset.fix().local_minus_utc(),
+        daylight: !offset.dst_offset().is_zero(),
+        name: offset.abbreviation().to_string(),
+    }
+}
+
+// The observances of a zone from the start of `first_year` to the end of
+// `last_year`: the one in effect first, then one per offset change. Changes
+// are looked for a day apart, then narrowed down to the second.
+fn observances(zone: Tz, first_year: i32, last_year: i32) -> Vec<Observance> {
+    let year_start = |year: i32| {
+        NaiveDate::from_ymd_opt(year, 1, 1).unwrap_or(NaiveDate::MAX).and_time(NaiveTime::MIN)
+    };
+    let start = year_start(first_year);
+    let end = year_start(last_year + 1);
+
+    let mut observances = vec![observance(zone, start, utc_offset(zone, start))];
+    let mut day = start;
+    while day < end {
+        let next = day + Duration::days(1);
+        let before = utc_offset(zone, day);
+        if utc_offset(zone, next) != before {
+            let (mut low, mut high) = (day, next);
+            while high - low > Duration::seconds(1) {
+                let middle = low + (high - low) / 2;
+                if utc_offset(zone, middle) == before {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            observances.push(observance(zone, high, before));
+        }
+        day = next;
+    }
+    observances
+}
+
+// Yearly rule of a change on `date`: the weekday counted from the start of
+// the month, or as the last one in the month's last week
+fn yearly_rule(date: NaiveDate) -> String {
+    const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+    let last_week = date
+        .checked_add_signed(Duration::days(7))
+        .is_none_or(|week_later| week_later.month() != date.month());
+    let week = if last_week { -1 } else { (date.day() as i32 - 1) / 7 + 1 };
+    format!(
+        "RRULE:FREQ=YEARLY;BYMONTH={};BYDAY={}{}",
+        date.month(),
+        week,
+        WEEKDAYS[date.weekday().num_days_from_monday() as usize]
+    )
+}
+
+fn push_vtimezone(lines: &mut Vec<String>, zone: Tz, first_year: i32) {
+    let last_year = first_year.max(time_zone::today(zone).year() + 1);
+
+    lines.push("BEGIN:VTIMEZONE".to_string());
+    lines.push(format!("TZID:{}", zone.name()));
+    for (index, observance) in observances(zone, first_year, last_year).iter().enumerate() {
+        let kind = if observance.daylight { "DAYLIGHT" } else { "STANDARD" };
+        lines.push(format!("BEGIN:{}", kind));
+        lines.push(format!("DTSTART:{}", format_local(observance.onset)));
+        lines.push(format!("TZOFFSETFROM:{}", format_offset(observance.offset_from)));
+        lines.push(format!("TZOFFSETTO:{}", format_offset(observance.offset_to)));
+        // The last year's changes go on for the years after it
+        if index > 0 && observance.onset.year() == last_year {
+            lines.push(yearly_rule(observance.onset.date()));
+        }
+        lines.push(format!("TZNAME:{}", escape_text(&observance.name)));
+        lines.push(format!("END:{}", kind));
+    }
+    lines.push("END:VTIMEZONE".to_string());
+}
+
+// Zones the timed events are written in, by name, with the first year each
+// is needed from
+fn zones_used<'a>(
+    events: impl IntoIterator<Item = &'a Event>,
+    overrides: &[OccurrenceOverride],
+) -> BTreeMap<String, (Tz, i32)> {
+    let mut zones = BTreeMap::new();
+    for event in events.into_iter().filter(|event| !event.all_day) {
+        let zone = time_zone::event_zone(event.time_zone.as_deref());
+        let first = overrides
+            .iter()
+            .filter(|o| o.event_id == event.id)
+            .filter_map(|o| o.event_date)
+            .fold(event.event_date, NaiveDate::min);
+        zones
+            .entry(zone.name().to_string())
+            .and_modify(|(_, year): &mut (Tz, i32)| *year = (*year).min(first.year()))
+            .or_insert((zone, first.year()));
+    }
+    zones
+}
+
+fn wrap_calendar<'a>(
+    events: impl IntoIterator<Item = &'a Event>,
+    overrides: &[OccurrenceOverride],
+    vevents: Vec<String>,
+) -> String {
+    let name = env::var("CALENDAR_NAME").unwrap_or_else(|_| "Church Events".to_string());
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Church App//Events//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        format!("X-WR-CALNAME:{}", escape_text(&name)),
+        format!("X-WR-TIMEZONE:{}", time_zone::church_zone().name()),
+    ];
+    for (zone, first_year) in zones_used(events, overrides).into_values() {
+        push_vtimezone(&mut lines, zone, first_year);
+    }
+    lines.extend(vevents);
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+/// Calendar with the given events and their changed occurrences
+pub fn calendar(events: &[Event], overrides: &[OccurrenceOverride]) -> String {
+    let mut vevents = Vec::new();
+    for event in events {
+        push_event(&mut vevents, event, overrides);
+    }
+    wrap_calendar(events, overrides, vevents)
+}
+
+/// Calendar with a single event, or with one occurrence of a recurring event
+pub fn event_calendar(event: &Event, overrides: &[OccurrenceOverride], occurrence_date: Option<NaiveDate>) -> String {
+    let mut vevents = Vec::new();
+    match occurrence_date {
+        Some(date) if event.recurrence_rule.is_some() => {
+            let override_ = overrides.iter().find(|o| o.event_id == event.id && o.occurrence_date == date);
+            push_occurrence(&mut vevents, event, date, override_);
+        }
+        _ => push_event(&mut vevents, event, overrides),
+    }
+    wrap_calendar([event], overrides, vevents)
+}
+
+fn calendar_response(body: String, filename: Option<&str>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response.content_type(CONTENT_TYPE);
+    if let Some(filename) = filename {
+        response.insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        ));
+    }
+    response.body(body)
+}
+
+// Subscribable feed of all events, optionally for one category or home group
+pub async fn get_calendar_feed(
+    pool: web::Data<PgPool>,
+    params: web::Query<CalendarFeedParams>,
+) -> impl Responder {
+    let since = Utc::now().date_naive() - chrono::Duration::days(FEED_HISTORY_DAYS);
+
+    let mut query = QueryBuilder::new(format!("SELECT {} FROM events WHERE (recurrence_rule IS NOT NULL OR event_date >= ", EVENT_COLUMNS));
+    query.push_bind(since);
+    query.push(")");
+
+    if let Some(category) = &params.category {
+        query.push(" AND category = ");
+        query.push_bind(category.clone());
+    }
+
+    if let Some(home_group_id) = params.home_group_id {
+        query.push(" AND home_group_id = ");
+        query.push_bind(home_group_id);
+    }
+
+    query.push(" ORDER BY event_date ASC, event_time ASC");
+
+    let mut deleted_query = QueryBuilder::new(
+        "SELECT id, event_title, event_date, event_time, recurrence_rule, category, all_day, time_zone, sequence, deleted_at
+         FROM deleted_events WHERE deleted_at >= ",
+    );
+    deleted_query.push_bind(since);
+
+    if let Some(category) = &params.category {
+        deleted_query.push(" AND category = ");
+        deleted_query.push_bind(category.clone());
+    }
+
+    if let Some(home_group_id) = params.home_group_id {
+        deleted_query.push(" AND home_group_id = ");
+        deleted_query.push_bind(home_group_id);
+    }
+
+    deleted_query.push(" ORDER BY event_date ASC, event_time ASC");
+
+    let result: Result<(Vec<Event>, Vec<OccurrenceOverride>), sqlx::Error> = async {
+        let mut events = query.build_query_as::<Event>().fetch_all(pool.get_ref()).await?;
+        let series_ids: Vec<i32> = events
+            .iter()
+            .filter(|event| event.recurrence_rule.is_some())
+            .map(|event| event.id)
+            .collect();
+        let overrides = events::fetch_overrides(pool.get_ref(), &series_ids).await?;
+        let deleted = deleted_query.build_query_as::<DeletedEvent>().fetch_all(pool.get_ref()).await?;
+        events.extend(deleted.into_iter().map(DeletedEvent::into_event));
+        Ok((events, overrides))
+    }
+    .await;
+
+    match result {
+        Ok((events, overrides)) => calendar_response(calendar(&events, &overrides), None),
+        Err(e) => {
+            eprintln!("Failed to build calendar feed: {}", e);
+            HttpResponse::InternalServerError().json("Failed to build calendar feed")
+        }
+    }
+}
+
+// Download a single event as .ics
+pub async fn get_event_ics(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+
+    let event = match events::fetch_event(pool.get_ref(), event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound().json("Event not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch event");
+        }
+    };
+
+    let overrides = match events::fetch_overrides(pool.get_ref(), &[event_id]).await {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("Failed to fetch overrides of event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch event");
+        }
+    };
+
+    let filename = format!("event-{}.ics", event_id);
+    calendar_response(event_calendar(&event, &overrides, None), Some(&filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn event(all_day: bool, time_zone: &str) -> Event {
+        Event {
+            id: 1,
+            event_title: "Service".to_string(),
+            event_date: date(2024, 6, 2),
+            event_time: NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+            address: None,
+            description: None,
+            recurrence_rule: Some("FREQ=WEEKLY".to_string()),
+            recurrence_exceptions: Vec::new(),
+            category: None,
+            home_group_id: None,
+            duration_minutes: Some(90),
+            all_day,
+            time_zone: Some(time_zone.to_string()),
+            capacity: None,
+            tags: Vec::new(),
+            status: "scheduled".to_string(),
+            status_reason: None,
+            sequence: 0,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn offsets_are_signed_hours_and_minutes() {
+        assert_eq!(format_offset(-5 * 3600), "-0500");
+        assert_eq!(format_offset(5 * 3600 + 30 * 60), "+0530");
+        assert_eq!(format_offset(0), "+0000");
+    }
+
+    #[test]
+    fn observances_follow_the_clock_changes() {
+        let observances = observances(chrono_tz::America::New_York, 2024, 2024);
+        let changes: Vec<(String, i32, i32, bool)> = observances
+            .iter()
+            .map(|o| (format_local(o.onset), o.offset_from, o.offset_to, o.daylight))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("20231231T190000".to_string(), -5 * 3600, -5 * 3600, false),
+                ("20240310T020000".to_string(), -5 * 3600, -4 * 3600, true),
+                ("20241103T020000".to_string(), -4 * 3600, -5 * 3600, false),
+            ]
+        );
+        assert_eq!(observances[1].name, "EDT");
+    }
+
+    #[test]
+    fn zone_without_changes_has_one_observance() {
+        let observances = observances(chrono_tz::Asia::Tokyo, 2024, 2025);
+        assert_eq!(observances.len(), 1);
+        assert_eq!(observances[0].offset_to, 9 * 3600);
+    }
+
+    #[test]
+    fn yearly_rules_count_weeks_from_either_end() {
+        assert_eq!(yearly_rule(date(2025, 3, 9)), "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=2SU");
+        assert_eq!(yearly_rule(date(2025, 11, 2)), "RRULE:FREQ=YEARLY;BYMONTH=11;BYDAY=1SU");
+        assert_eq!(yearly_rule(date(2025, 3, 30)), "RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU");
+    }
+
+    #[test]
+    fn timed_events_define_their_zone() {
+        let timed = event(false, "Europe/London");
+        let ics = event_calendar(&timed, &[], None);
+        assert_eq!(ics.matches("BEGIN:VTIMEZONE").count(), 1);
+        assert!(ics.contains("TZID:Europe/London\r\n"));
+        assert!(ics.contains("DTSTART;TZID=Europe/London:20240602T100000"));
+        // The changes of the year after this one repeat from then on
+        assert!(ics.contains("RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU"));
+        assert!(ics.contains("RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU"));
+
+        let all_day = event(true, "Europe/London");
+        assert!(!event_calendar(&all_day, &[], None).contains("VTIMEZONE"));
+    }
+}
@@ -0,0 +1,557 @@
+use actix_web::{http::header, web, HttpResponse, Responder};
+use chrono::{NaiveDate, NaiveDateTime};
+use data_encoding::BASE64URL_NOPAD;
+use hmac::{Hmac, Mac};
+use image::Luma;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{FromRow, PgPool};
+use std::env;
+
+use crate::eventrsvp::{self, ServingStatusType};
+use crate::event_status;
+use crate::events;
+use crate::user::Claims;
+use crate::waitlist;
+
+// Attendance on the day. Every confirmed RSVP has a check-in code, the RSVP
+// id signed with CHECKIN_SECRET, which its confirmation email carries as a
+// QR code. Door volunteers scan it to check the party in; a code can be
+// used once, later scans report when it was used. People without an RSVP
+// are registered as walk-ins and checked in at once.
+
+// Size of the rendered QR codes, in pixels
+const QR_SIZE: u32 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Deserialize)]
+pub struct CheckInRequest {
+    token: String,
+    event_id: Option<i32>, // The event being checked in, to catch codes for another one
+    occurrence_date: Option<NaiveDate>,
+}
+
+#[derive(Deserialize)]
+pub struct WalkInRequest {
+    event_id: i32,
+    occurrence_date: Option<NaiveDate>, // Required for recurring events
+    email: String,
+    user_id: Option<i32>,
+    #[serde(default = "default_adults")]
+    adults: i32,
+    #[serde(default)]
+    children: i32,
+}
+
+fn default_adults() -> i32 {
+    1
+}
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    occurrence_date: Option<NaiveDate>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct CheckedIn {
+    id: i32,
+    email: String,
+    event_id: i32,
+    occurrence_date: Option<NaiveDate>,
+    adults: i32,
+    children: i32,
+    walk_in: bool,
+    checked_in_at: Option<NaiveDateTime>,
+}
+
+#[derive(FromRow)]
+struct CheckInCandidate {
+    event_id: i32,
+    event_status: String,
+    occurrence_date: Option<NaiveDate>,
+    rsvp_status: ServingStatusType,
+    waitlisted_at: Option<NaiveDateTime>,
+}
+
+/// Attendance of an event occurrence
+#[derive(Serialize, FromRow)]
+pub struct AttendanceStats {
+    occurrence_date: Option<NaiveDate>,
+    expected: i64,        // Confirmed RSVPs with a seat
+    expected_people: i64, // Adults and children in them
+    checked_in: i64,
+    checked_in_people: i64,
+    walk_ins: i64,
+    not_checked_in: i64, // Confirmed RSVPs not seen yet
+}
+
+const CHECKED_IN_COLUMNS: &str =
+    "id, email, event_id, occurrence_date, adults, children, walk_in, checked_in_at";
+
+fn checkin_secret() -> Vec<u8> {
+    env::var("CHECKIN_SECRET")
+        .expect("CHECKIN_SECRET must be set")
+        .into_bytes()
+}
+
+// The purpose keeps a token for one use from passing for another
+fn signer(secret: &[u8], purpose: &str, rsvp_id: i32) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}", purpose, rsvp_id).as_bytes());
+    mac
+}
+
+fn sign(secret: &[u8], purpose: &str, rsvp_id: i32) -> String {
+    let signature = signer(secret, purpose, rsvp_id).finalize().into_bytes();
+    format!("{}.{}", rsvp_id, BASE64URL_NOPAD.encode(&signature))
+}
+
+// verify_slice compares in constant time
+fn verify_with(secret: &[u8], purpose: &str, token: &str) -> Option<i32> {
+    let (rsvp_id, signature) = token.trim().split_once('.')?;
+    let rsvp_id = rsvp_id.parse::<i32>().ok()?;
+    let signature = BASE64URL_NOPAD.decode(signature.as_bytes()).ok()?;
+    signer(secret, purpose, rsvp_id).verify_slice(&signature).ok()?;
+    Some(rsvp_id)
+}
+
+/// An RSVP id and its signature for `purpose`, e.g. for links in emails
+pub fn signed_token(purpose: &str, rsvp_id: i32) -> String {
+    sign(&checkin_secret(), purpose, rsvp_id)
+}
+
+/// The RSVP a token is for, if it was signed for `purpose`
+pub fn verify_signed(purpose: &str, token: &str) -> Option<i32> {
+    verify_with(&checkin_secret(), purpose, token)
+}
+
+/// Check-in code of an RSVP: its id and signature
+pub fn token(rsvp_id: i32) -> String {
+    signed_token("checkin", rsvp_id)
+}
+
+/// The RSVP a check-in code is for, if the signature is valid
+fn verify(token: &str) -> Option<i32> {
+    verify_signed("checkin", token)
+}
+
+/// A check-in code as a PNG QR code
+pub fn qr_png(token: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = QrCode::new(token.as_bytes())?
+        .render::<Luma<u8>>()
+        .min_dimensions(QR_SIZE, QR_SIZE)
+        .build();
+
+    let mut png = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut png).encode(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        image::ColorType::L8,
+    )?;
+    Ok(png)
+}
+
+/// A check-in code as an SVG QR code
+pub fn qr_svg(token: &str) -> Result<String, qrcode::types::QrError> {
+    Ok(QrCode::new(token.as_bytes())?
+        .render::<svg::Color>()
+        .min_dimensions(QR_SIZE, QR_SIZE)
+        .build())
+}
+
+/// Whether an RSVP should get a check-in code
+pub async fn has_checkin_code(pool: &PgPool, rsvp_id: i32) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM eventrsvp
+         WHERE id = $1 AND rsvp_status = 'confirmed' AND waitlisted_at IS NULL)"
+    )
+    .bind(rsvp_id)
+    .fetch_one(pool)
+    .await
+}
+
+// QR code of a check-in code, for the RSVP's own page. The code is only
+// accepted when its signature is valid.
+async fn qr_code(pool: &PgPool, token: &str, svg: bool) -> HttpResponse {
+    let rsvp_id = match verify(token) {
+        Some(rsvp_id) => rsvp_id,
+        None => return HttpResponse::NotFound().json("Check-in code not found"),
+    };
+
+    match has_checkin_code(pool, rsvp_id).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json("This RSVP is not confirmed"),
+        Err(e) => {
+            eprintln!("Failed to fetch RSVP {}: {}", rsvp_id, e);
+            return HttpResponse::InternalServerError().json("Failed to render check-in code");
+        }
+    }
+
+    let rendered = if svg {
+        qr_svg(token)
+            .map(|image| ("image/svg+xml", image.into_bytes()))
+            .map_err(|e| e.to_string())
+    } else {
+        qr_png(token)
+            .map(|image| ("image/png", image))
+            .map_err(|e| e.to_string())
+    };
+
+    match rendered {
+        Ok((content_type, image)) => HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, content_type))
+            .insert_header((header::CACHE_CONTROL, "private, max-age=86400"))
+            .body(image),
+        Err(e) => {
+            eprintln!("Failed to render check-in code of RSVP {}: {}", rsvp_id, e);
+            HttpResponse::InternalServerError().json("Failed to render check-in code")
+        }
+    }
+}
+
+pub async fn get_qr_png(pool: web::Data<PgPool>, token: web::Path<String>) -> impl Responder {
+    qr_code(pool.get_ref(), &token, false).await
+}
+
+pub async fn get_qr_svg(pool: web::Data<PgPool>, token: web::Path<String>) -> impl Responder {
+    qr_code(pool.get_ref(), &token, true).await
+}
+
+async fn fetch_checked_in(pool: &PgPool, rsvp_id: i32) -> Result<Option<CheckedIn>, sqlx::Error> {
+    sqlx::query_as::<_, CheckedIn>(&format!("SELECT {} FROM eventrsvp WHERE id = $1", CHECKED_IN_COLUMNS))
+        .bind(rsvp_id)
+        .fetch_optional(pool)
+        .await
+}
+
+// Check in the party of a scanned code (door volunteers)
+pub async fn check_in(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    request: web::Json<CheckInRequest>,
+) -> impl Responder {
+    let rsvp_id = match verify(&request.token) {
+        Some(rsvp_id) => rsvp_id,
+        None => {
+            return HttpResponse::BadRequest().json(json!({
+                "message": "Invalid check-in code"
+            }));
+        }
+    };
+
+    let candidate = sqlx::query_as::<_, CheckInCandidate>(
+        "SELECT r.event_id, e.status AS event_status, r.occurrence_date, r.rsvp_status, r.waitlisted_at
+         FROM eventrsvp r JOIN events e ON e.id = r.event_id
+         WHERE r.id = $1"
+    )
+    .bind(rsvp_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    let candidate = match candidate {
+        Ok(Some(candidate)) => candidate,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "message": "This RSVP no longer exists"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Failed to fetch RSVP {}: {}", rsvp_id, e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to check in"
+            }));
+        }
+    };
+
+    let wrong_event = request.event_id.map_or(false, |event_id| event_id != candidate.event_id)
+        || request.occurrence_date.map_or(false, |date| Some(date) != candidate.occurrence_date);
+    if wrong_event {
+        return HttpResponse::Conflict().json(json!({
+            "message": "This code is for another event or date",
+            "event_id": candidate.event_id,
+            "occurrence_date": candidate.occurrence_date,
+        }));
+    }
+
+    if candidate.event_status == event_status::CANCELLED {
+        return HttpResponse::Conflict().json(json!({ "message": "This event has been cancelled" }));
+    }
+
+    if candidate.rsvp_status != ServingStatusType::Confirmed || candidate.waitlisted_at.is_some() {
+        let reason = match (&candidate.rsvp_status, candidate.waitlisted_at) {
+            (_, Some(_)) => "This RSVP is on the waitlist",
+            (ServingStatusType::Declined, _) => "This RSVP was declined",
+            _ => "This RSVP is not confirmed yet",
+        };
+        return HttpResponse::Conflict().json(json!({ "message": reason }));
+    }
+
+    // Only the first scan checks in
+    let result = sqlx::query_as::<_, CheckedIn>(&format!(
+        "UPDATE eventrsvp SET checked_in_at = NOW(), checked_in_by = $2
+         WHERE id = $1 AND checked_in_at IS NULL
+         RETURNING {}",
+        CHECKED_IN_COLUMNS
+    ))
+    .bind(rsvp_id)
+    .bind(claims.sub)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(Some(checked_in)) => HttpResponse::Ok().json(json!({
+            "message": "Checked in",
+            "rsvp": checked_in,
+        })),
+        Ok(None) => match fetch_checked_in(pool.get_ref(), rsvp_id).await {
+            Ok(Some(checked_in)) => HttpResponse::Conflict().json(json!({
+                "message": "Already checked in",
+                "duplicate": true,
+                "rsvp": checked_in,
+            })),
+            Ok(None) => HttpResponse::NotFound().json(json!({
+                "message": "This RSVP no longer exists"
+            })),
+            Err(e) => {
+                eprintln!("Failed to fetch RSVP {}: {}", rsvp_id, e);
+                HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to check in"
+                }))
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to check in RSVP {}: {}", rsvp_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to check in"
+            }))
+        }
+    }
+}
+
+// Register and check in someone who arrives without an RSVP. An existing
+// RSVP of the same email is checked in instead. Walk-ins are let in even
+// when the event is full, as they are already at the door, but their seats
+// count from then on; they answer no registration questions.
+pub async fn walk_in(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    request: web::Json<WalkInRequest>,
+) -> impl Responder {
+    let email = request.email.trim().to_lowercase();
+    if email.is_empty() || !email.contains('@') {
+        return HttpResponse::BadRequest().json(json!({ "message": "A valid email is required" }));
+    }
+    if let Err(message) = eventrsvp::party_size(request.adults, request.children) {
+        return HttpResponse::BadRequest().json(json!({ "message": message }));
+    }
+
+    let event = match events::fetch_event(pool.get_ref(), request.event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "message": "Event not found" })),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to register walk-in" }));
+        }
+    };
+
+    let occurrence_valid = match (&event.recurrence_rule, request.occurrence_date) {
+        (None, None) => true,
+        (Some(_), Some(date)) => events::is_occurrence(&event, date),
+        _ => false,
+    };
+    if !occurrence_valid {
+        return HttpResponse::BadRequest().json(json!({
+            "message": "occurrence_date must be one of the event's dates, and only for recurring events"
+        }));
+    }
+
+    let result: Result<Option<(CheckedIn, bool)>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        waitlist::lock_event(&mut tx, request.event_id).await?;
+        if waitlist::is_cancelled(&mut tx, request.event_id).await? {
+            return Ok(None);
+        }
+
+        // Someone who RSVP'd but lost their code
+        let existing = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM eventrsvp
+             WHERE LOWER(email) = $1 AND event_id = $2 AND occurrence_date IS NOT DISTINCT FROM $3"
+        )
+        .bind(&email)
+        .bind(request.event_id)
+        .bind(request.occurrence_date)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let (checked_in, duplicate) = match existing {
+            Some(rsvp_id) => {
+                let already = sqlx::query_as::<_, CheckedIn>(&format!(
+                    "SELECT {} FROM eventrsvp WHERE id = $1 AND checked_in_at IS NOT NULL",
+                    CHECKED_IN_COLUMNS
+                ))
+                .bind(rsvp_id)
+                .fetch_optional(&mut tx)
+                .await?;
+
+                match already {
+                    Some(checked_in) => (checked_in, true),
+                    None => {
+                        let checked_in = sqlx::query_as::<_, CheckedIn>(&format!(
+                            "UPDATE eventrsvp SET rsvp_status = 'confirmed', waitlisted_at = NULL,
+                                adults = $2, children = $3, checked_in_at = NOW(), checked_in_by = $4
+                             WHERE id = $1
+                             RETURNING {}",
+                            CHECKED_IN_COLUMNS
+                        ))
+                        .bind(rsvp_id)
+                        .bind(request.adults)
+                        .bind(request.children)
+                        .bind(claims.sub)
+                        .fetch_one(&mut tx)
+                        .await?;
+                        (checked_in, false)
+                    }
+                }
+            }
+            None => {
+                let checked_in = sqlx::query_as::<_, CheckedIn>(&format!(
+                    "INSERT INTO eventrsvp (email, event_id, user_id, rsvp_date, rsvp_status, occurrence_date,
+                        adults, children, walk_in, checked_in_at, checked_in_by)
+                     VALUES ($1, $2, $3, CURRENT_DATE, 'confirmed', $4, $5, $6, TRUE, NOW(), $7)
+                     RETURNING {}",
+                    CHECKED_IN_COLUMNS
+                ))
+                .bind(&email)
+                .bind(request.event_id)
+                .bind(request.user_id)
+                .bind(request.occurrence_date)
+                .bind(request.adults)
+                .bind(request.children)
+                .bind(claims.sub)
+                .fetch_one(&mut tx)
+                .await?;
+                (checked_in, false)
+            }
+        };
+
+        tx.commit().await?;
+        Ok(Some((checked_in, duplicate)))
+    }
+    .await;
+
+    match result {
+        Ok(None) => HttpResponse::Conflict().json(json!({ "message": "This event has been cancelled" })),
+        Ok(Some((checked_in, false))) => HttpResponse::Ok().json(json!({
+            "message": "Checked in",
+            "rsvp": checked_in,
+        })),
+        Ok(Some((checked_in, true))) => HttpResponse::Conflict().json(json!({
+            "message": "Already checked in",
+            "duplicate": true,
+            "rsvp": checked_in,
+        })),
+        Err(e) => {
+            eprintln!("Failed to register walk-in for event {}: {}", request.event_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to register walk-in"
+            }))
+        }
+    }
+}
+
+// Attendance of an event, per occurrence for recurring events
+pub async fn get_attendance_stats(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+    params: web::Query<StatsParams>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+
+    let result = sqlx::query_as::<_, AttendanceStats>(
+        "SELECT occurrence_date,
+                COUNT(*) FILTER (WHERE expected) AS expected,
+                COALESCE(SUM(adults + children) FILTER (WHERE expected), 0)::BIGINT AS expected_people,
+                COUNT(*) FILTER (WHERE checked_in_at IS NOT NULL) AS checked_in,
+                COALESCE(SUM(adults + children) FILTER (WHERE checked_in_at IS NOT NULL), 0)::BIGINT AS checked_in_people,
+                COUNT(*) FILTER (WHERE walk_in) AS walk_ins,
+                COUNT(*) FILTER (WHERE expected AND checked_in_at IS NULL) AS not_checked_in
+         FROM (
+             SELECT *, rsvp_status = 'confirmed' AND waitlisted_at IS NULL AND NOT walk_in AS expected
+             FROM eventrsvp
+             WHERE event_id = $1 AND ($2::DATE IS NULL OR occurrence_date = $2)
+         ) r
+         GROUP BY occurrence_date
+         ORDER BY occurrence_date"
+    )
+    .bind(event_id)
+    .bind(params.occurrence_date)
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(stats) => HttpResponse::Ok().json(json!({
+            "event_id": event_id,
+            "occurrences": stats,
+        })),
+        Err(e) => {
+            eprintln!("Failed to fetch attendance of event {}: {}", event_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch attendance"
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"test secret";
+
+    #[test]
+    fn tokens_are_the_id_and_its_signature() {
+        let token = sign(SECRET, "checkin", 42);
+        let (rsvp_id, signature) = token.split_once('.').unwrap();
+        assert_eq!(rsvp_id, "42");
+        assert_eq!(BASE64URL_NOPAD.decode(signature.as_bytes()).unwrap().len(), 32);
+        assert_eq!(verify_with(SECRET, "checkin", &token), Some(42));
+        assert_eq!(verify_with(SECRET, "checkin", &format!(" {}\n", token)), Some(42));
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let token = sign(SECRET, "checkin", 42);
+        let (_, signature) = token.split_once('.').unwrap();
+        let mut bytes = BASE64URL_NOPAD.decode(signature.as_bytes()).unwrap();
+        bytes[0] ^= 1;
+        let tampered = format!("42.{}", BASE64URL_NOPAD.encode(&bytes));
+        assert_eq!(verify_with(SECRET, "checkin", &tampered), None);
+
+        let truncated = format!("42.{}", BASE64URL_NOPAD.encode(&bytes[..16]));
+        assert_eq!(verify_with(SECRET, "checkin", &truncated), None);
+        assert_eq!(verify_with(SECRET, "checkin", "42."), None);
+        assert_eq!(verify_with(SECRET, "checkin", "42"), None);
+        assert_eq!(verify_with(SECRET, "checkin", "x.y"), None);
+        assert_eq!(verify_with(SECRET, "checkin", ""), None);
+    }
+
+    #[test]
+    fn signatures_are_bound_to_the_id() {
+        let token = sign(SECRET, "checkin", 42);
+        let (_, signature) = token.split_once('.').unwrap();
+        assert_eq!(verify_with(SECRET, "checkin", &format!("43.{}", signature)), None);
+        assert_eq!(verify_with(SECRET, "checkin", &format!("-42.{}", signature)), None);
+    }
+
+    #[test]
+    fn signatures_are_bound_to_the_purpose_and_secret() {
+        let token = sign(SECRET, "checkin", 42);
+        assert_eq!(verify_with(SECRET, "homegroup_offer", &token), None);
+        assert_eq!(verify_with(b"other secret", "checkin", &token), None);
+    }
+}
@@ -2,13 +2,21 @@ use lettre::{
     transport::smtp::authentication::Credentials,
     AsyncSmtpTransport, AsyncTransport,
     Message,
-    message::{header, MultiPart, SinglePart},
+    message::{header, Attachment, MultiPart, SinglePart},
 };
 use sqlx::PgPool;
 use actix_web::{web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 use std::env;
 
+use crate::calendar;
+use crate::checkin;
+use crate::event_status;
+use crate::events;
+use crate::homegroup_waitlist;
+use crate::permission::{self, Resource};
+use crate::user::Claims;
+
 
 #[derive(Debug, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "email_status", rename_all = "lowercase")]
@@ -62,6 +70,39 @@ async fn get_event_details(pool: &PgPool, event_id: i32) -> Result<(String, Stri
     ))
 }
 
+// Title, date and time of what an RSVP is for: the event, or the RSVP'd
+// occurrence of a recurring event as changed for that date
+async fn get_rsvp_details(pool: &PgPool, rsvp_id: i32, event_id: i32) -> Result<(String, String, String), sqlx::Error> {
+    let (event_title, event_date, event_time) = get_event_details(pool, event_id).await?;
+    let occurrence_date = sqlx::query_scalar::<_, Option<chrono::NaiveDate>>(
+        "SELECT occurrence_date FROM eventrsvp WHERE id = $1"
+    )
+    .bind(rsvp_id)
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+    let occurrence_date = match occurrence_date {
+        Some(date) => date,
+        None => return Ok((event_title, event_date, event_time)),
+    };
+
+    let override_ = sqlx::query_as::<_, (Option<String>, Option<chrono::NaiveDate>, Option<chrono::NaiveTime>)>(
+        "SELECT event_title, event_date, event_time FROM event_occurrence_overrides
+         WHERE event_id = $1 AND occurrence_date = $2"
+    )
+    .bind(event_id)
+    .bind(occurrence_date)
+    .fetch_optional(pool)
+    .await?;
+    let (title, date, time) = override_.unwrap_or((None, None, None));
+
+    Ok((
+        title.unwrap_or(event_title),
+        date.unwrap_or(occurrence_date).to_string(),
+        time.map_or(event_time, |time| time.to_string()),
+    ))
+}
+
 async fn create_mailer(
     config: &EmailConfig,
 ) -> Result<AsyncSmtpTransport<lettre::Tokio1Executor>, lettre::transport::smtp::Error> {
@@ -75,6 +116,27 @@ async fn create_mailer(
         .build())
 }
 
+// Calendar entry for an RSVP: the event, or the RSVP'd occurrence of a
+// recurring event
+async fn get_rsvp_calendar(pool: &PgPool, rsvp_id: i32, event_id: i32) -> Result<Option<String>, sqlx::Error> {
+    let event = match events::fetch_event(pool, event_id).await? {
+        Some(event) => event,
+        None => return Ok(None),
+    };
+
+    let occurrence_date = sqlx::query_scalar::<_, Option<chrono::NaiveDate>>(
+        "SELECT occurrence_date FROM eventrsvp WHERE id = $1"
+    )
+    .bind(rsvp_id)
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let overrides = events::fetch_overrides(pool, &[event_id]).await?;
+
+    Ok(Some(calendar::event_calendar(&event, &overrides, occurrence_date)))
+}
+
 async fn send_confirmation_email_internal(
     pool: &PgPool,
     rsvp_id: i32,
@@ -87,6 +149,22 @@ async fn send_confirmation_email_internal(
     let config = EmailConfig::from_env();
     let mailer = create_mailer(&config).await?;
 
+    // Confirmed RSVPs get their check-in code, scanned at the door
+    let checkin_token = if checkin::has_checkin_code(pool, rsvp_id).await? {
+        Some(checkin::token(rsvp_id))
+    } else {
+        None
+    };
+    let checkin_html = match &checkin_token {
+        Some(_) => r#"<p>Show this code at the door to check in:</p>
+                <p><img src="cid:checkin-code" alt="Check-in code" width="300" height="300"></p>"#,
+        None => "",
+    };
+    let checkin_text = match &checkin_token {
+        Some(token) => format!("Your check-in code: {}\n\n", token),
+        None => String::new(),
+    };
+
     // Create email content
     let html_content = format!(
         r#"
@@ -99,6 +177,7 @@ async fn send_confirmation_email_internal(
                     <li>Date: {event_date}</li>
                     <li>Time: {event_time}</li>
                 </ul>
+                {checkin_html}
                 <p>We look forward to seeing you there!</p>
                 <p>Best regards,<br>Church Events Team</p>
             </body>
@@ -111,17 +190,116 @@ async fn send_confirmation_email_internal(
         Event Details:\n\
         Date: {}\n\
         Time: {}\n\n\
+        {}\
         We look forward to seeing you there!\n\n\
         Best regards,\n\
         Church Events Team",
-        event_title, event_date, event_time
+        event_title, event_date, event_time, checkin_text
     );
 
+    let text_part = SinglePart::builder()
+        .header(header::ContentType::TEXT_PLAIN)
+        .body(text_content);
+    let html_part = SinglePart::builder()
+        .header(header::ContentType::TEXT_HTML)
+        .body(html_content.clone());
+
+    // The QR code is shown inline in the HTML part
+    let body = match &checkin_token {
+        Some(token) => MultiPart::alternative().singlepart(text_part).multipart(
+            MultiPart::related().singlepart(html_part).singlepart(
+                Attachment::new_inline("checkin-code".to_string())
+                    .body(checkin::qr_png(token)?, header::ContentType::parse("image/png")?)
+            )
+        ),
+        None => MultiPart::alternative().singlepart(text_part).singlepart(html_part),
+    };
+
+    // Attach the event so it can be added to any calendar app
+    let body = match get_rsvp_calendar(pool, rsvp_id, event_id).await? {
+        Some(ics) => MultiPart::mixed().multipart(body).singlepart(
+            Attachment::new("event.ics".to_string())
+                .body(ics, header::ContentType::parse(calendar::CONTENT_TYPE)?)
+        ),
+        None => body,
+    };
+
     // Create the email message
     let email_message = Message::builder()
         .from(config.from_email.parse()?)
         .to(email.parse()?)
         .subject(format!("Confirmation: {}", event_title))
+        .multipart(body)?;
+
+    // Send the email
+    mailer.send(email_message).await?;
+
+    // After sending the email successfully, log it
+    sqlx::query!(
+        r#"
+        INSERT INTO email_logs
+        (rsvp_id, email_to, email_from, subject, body, status, sent_at)
+        VALUES ($1, $2, $3, $4, $5, 'sent', CURRENT_TIMESTAMP)
+        "#,
+        rsvp_id,
+        email,
+        config.from_email,
+        format!("Confirmation: {}", event_title),
+        html_content,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn send_decline_email_internal(
+    pool: &PgPool,
+    rsvp_id: i32,
+    email: &str,
+    event_id: i32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Get event details
+    let (event_title, event_date, event_time) = get_event_details(pool, event_id).await?;
+
+    let config = EmailConfig::from_env();
+    let mailer = create_mailer(&config).await?;
+
+    // Create email content
+    let html_content = format!(
+        r#"
+        <html>
+            <body>
+                <h2>Event Response Received</h2>
+                <p>We've received your response that you won't be able to attend {event_title}.</p>
+                <p>Event Details:</p>
+                <ul>
+                    <li>Date: {event_date}</li>
+                    <li>Time: {event_time}</li>
+                </ul>
+                <p>We hope to see you at future events!</p>
+                <p>Best regards,<br>Church Events Team</p>
+            </body>
+        </html>
+        "#
+    );
+
+    let text_content = format!(
+        "We've received your response that you won't be able to attend {}.\n\n\
+        Event Details:\n\
+        Date: {}\n\
+        Time: {}\n\n\
+        We hope to see you at future events!\n\n\
+        Best regards,\n\
+        Church Events Team",
+        event_title, event_date, event_time
+    );
+
+    // Create the email message
+    let email_message = Message::builder()
+        .from(config.from_email.parse()?)
+        .to(email.parse()?)
+        .subject(format!("Response Received: {}", event_title))
         .multipart(
             MultiPart::alternative()
                 .singlepart(
@@ -149,7 +327,7 @@ async fn send_confirmation_email_internal(
         rsvp_id,
         email,
         config.from_email,
-        format!("Confirmation: {}", event_title),
+        format!("Response Received: {}", event_title),
         html_content,
     )
     .execute(pool)
@@ -158,14 +336,17 @@ async fn send_confirmation_email_internal(
     Ok(())
 }
 
-async fn send_decline_email_internal(
+
+
+// Tell someone on the waitlist of an event that they now have a seat
+pub async fn send_waitlist_promotion_email(
     pool: &PgPool,
     rsvp_id: i32,
     email: &str,
     event_id: i32,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Get event details
-    let (event_title, event_date, event_time) = get_event_details(pool, event_id).await?;
+    // Get event details, on the RSVP'd date for recurring events
+    let (event_title, event_date, event_time) = get_rsvp_details(pool, rsvp_id, event_id).await?;
 
     let config = EmailConfig::from_env();
     let mailer = create_mailer(&config).await?;
@@ -175,14 +356,14 @@ async fn send_decline_email_internal(
         r#"
         <html>
             <body>
-                <h2>Event Response Received</h2>
-                <p>We've received your response that you won't be able to attend {event_title}.</p>
+                <h2>A Seat Is Now Available</h2>
+                <p>Good news! A seat has opened up for {event_title} and you have been moved off the waitlist.</p>
                 <p>Event Details:</p>
                 <ul>
                     <li>Date: {event_date}</li>
                     <li>Time: {event_time}</li>
                 </ul>
-                <p>We hope to see you at future events!</p>
+                <p>If you can no longer attend, please decline your RSVP so the next person in line can have the seat.</p>
                 <p>Best regards,<br>Church Events Team</p>
             </body>
         </html>
@@ -190,11 +371,11 @@ async fn send_decline_email_internal(
     );
 
     let text_content = format!(
-        "We've received your response that you won't be able to attend {}.\n\n\
+        "Good news! A seat has opened up for {} and you have been moved off the waitlist.\n\n\
         Event Details:\n\
         Date: {}\n\
         Time: {}\n\n\
-        We hope to see you at future events!\n\n\
+        If you can no longer attend, please decline your RSVP so the next person in line can have the seat.\n\n\
         Best regards,\n\
         Church Events Team",
         event_title, event_date, event_time
@@ -204,7 +385,7 @@ async fn send_decline_email_internal(
     let email_message = Message::builder()
         .from(config.from_email.parse()?)
         .to(email.parse()?)
-        .subject(format!("Response Received: {}", event_title))
+        .subject(format!("You're in: {}", event_title))
         .multipart(
             MultiPart::alternative()
                 .singlepart(
@@ -232,7 +413,7 @@ async fn send_decline_email_internal(
         rsvp_id,
         email,
         config.from_email,
-        format!("Response Received: {}", event_title),
+        format!("You're in: {}", event_title),
         html_content,
     )
     .execute(pool)
@@ -242,6 +423,207 @@ async fn send_decline_email_internal(
 }
 
 
+// Free text written by admins, such as the reason an event was cancelled
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\n' => escaped.push_str("<br>"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Tell someone who RSVP'd that the event is cancelled, attaching the
+// cancelled entry so calendar apps remove it
+pub async fn send_event_cancelled_email(
+    pool: &PgPool,
+    rsvp_id: i32,
+    email: &str,
+    event_id: i32,
+    reason: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (event_title, event_date, _) = get_rsvp_details(pool, rsvp_id, event_id).await?;
+
+    let config = EmailConfig::from_env();
+    let mailer = create_mailer(&config).await?;
+
+    let reason_html = escape_html(reason);
+    let html_content = format!(
+        r#"
+        <html>
+            <body>
+                <h2>Event Cancelled</h2>
+                <p>We are sorry to let you know that {event_title} on {event_date} has been cancelled.</p>
+                <p>{reason_html}</p>
+                <p>Your RSVP has been kept, so there is nothing you need to do.</p>
+                <p>Best regards,<br>Church Events Team</p>
+            </body>
+        </html>
+        "#
+    );
+
+    let text_content = format!(
+        "We are sorry to let you know that {} on {} has been cancelled.\n\n\
+        {}\n\n\
+        Your RSVP has been kept, so there is nothing you need to do.\n\n\
+        Best regards,\n\
+        Church Events Team",
+        event_title, event_date, reason
+    );
+
+    let body = MultiPart::alternative()
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_PLAIN)
+                .body(text_content)
+        )
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(html_content.clone())
+        );
+
+    let body = match get_rsvp_calendar(pool, rsvp_id, event_id).await? {
+        Some(ics) => MultiPart::mixed().multipart(body).singlepart(
+            Attachment::new("event.ics".to_string())
+                .body(ics, header::ContentType::parse(calendar::CONTENT_TYPE)?)
+        ),
+        None => body,
+    };
+
+    let subject = format!("Cancelled: {}", event_title);
+    let email_message = Message::builder()
+        .from(config.from_email.parse()?)
+        .to(email.parse()?)
+        .subject(subject.clone())
+        .multipart(body)?;
+
+    mailer.send(email_message).await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_logs
+        (rsvp_id, email_to, email_from, subject, body, status, sent_at)
+        VALUES ($1, $2, $3, $4, $5, 'sent', CURRENT_TIMESTAMP)
+        "#,
+        rsvp_id,
+        email,
+        config.from_email,
+        subject,
+        html_content,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Tell someone who RSVP'd that the event has moved, with the updated
+// calendar entry and links to say whether they can still come
+pub async fn send_event_rescheduled_email(
+    pool: &PgPool,
+    rsvp_id: i32,
+    email: &str,
+    event_id: i32,
+    reason: &str,
+    previous: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (event_title, event_date, event_time) = get_rsvp_details(pool, rsvp_id, event_id).await?;
+
+    let config = EmailConfig::from_env();
+    let mailer = create_mailer(&config).await?;
+
+    let attending_link = event_status::reconfirm_link(rsvp_id, true);
+    let declining_link = event_status::reconfirm_link(rsvp_id, false);
+
+    let reason_html = escape_html(reason);
+    let html_content = format!(
+        r#"
+        <html>
+            <body>
+                <h2>Event Rescheduled</h2>
+                <p>{event_title}, previously on {previous}, has moved.</p>
+                <p>{reason_html}</p>
+                <p>New Event Details:</p>
+                <ul>
+                    <li>Date: {event_date}</li>
+                    <li>Time: {event_time}</li>
+                </ul>
+                <p>Can you still come?</p>
+                <p><a href="{attending_link}">Yes, I'll be there</a> &nbsp; <a href="{declining_link}">No, decline my RSVP</a></p>
+                <p>Best regards,<br>Church Events Team</p>
+            </body>
+        </html>
+        "#
+    );
+
+    let text_content = format!(
+        "{}, previously on {}, has moved.\n\n\
+        {}\n\n\
+        New Event Details:\n\
+        Date: {}\n\
+        Time: {}\n\n\
+        Can you still come?\n\
+        Yes, I'll be there: {}\n\
+        No, decline my RSVP: {}\n\n\
+        Best regards,\n\
+        Church Events Team",
+        event_title, previous, reason, event_date, event_time, attending_link, declining_link
+    );
+
+    let body = MultiPart::alternative()
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_PLAIN)
+                .body(text_content)
+        )
+        .singlepart(
+            SinglePart::builder()
+                .header(header::ContentType::TEXT_HTML)
+                .body(html_content.clone())
+        );
+
+    let body = match get_rsvp_calendar(pool, rsvp_id, event_id).await? {
+        Some(ics) => MultiPart::mixed().multipart(body).singlepart(
+            Attachment::new("event.ics".to_string())
+                .body(ics, header::ContentType::parse(calendar::CONTENT_TYPE)?)
+        ),
+        None => body,
+    };
+
+    let subject = format!("Rescheduled: {}", event_title);
+    let email_message = Message::builder()
+        .from(config.from_email.parse()?)
+        .to(email.parse()?)
+        .subject(subject.clone())
+        .multipart(body)?;
+
+    mailer.send(email_message).await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_logs
+        (rsvp_id, email_to, email_from, subject, body, status, sent_at)
+        VALUES ($1, $2, $3, $4, $5, 'sent', CURRENT_TIMESTAMP)
+        "#,
+        rsvp_id,
+        email,
+        config.from_email,
+        subject,
+        html_content,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
 
 // Send confirmation email to EVENT RSVP
 pub async fn send_confirmation_email(
@@ -386,8 +768,19 @@ async fn send_homegroup_rsvp_email_internal(
 // Send confirmation email to home group RSVP
 pub async fn send_homegroup_rsvp_email(
     pool: web::Data<PgPool>,
+    claims: Claims,
     req: web::Json<HomeGroupEmailRequest>,
 ) -> impl Responder {
+    // Leaders may only email registrants of their own home group
+    if let Err(response) = permission::require_permission(
+        &pool,
+        &claims,
+        permission::HOMEGROUP_APPROVE,
+        Some(Resource::HomeGroup(req.home_group_id)),
+    ).await {
+        return response;
+    }
+
     match send_homegroup_rsvp_email_internal(
         &pool,
         &req.email,
@@ -401,6 +794,108 @@ pub async fn send_homegroup_rsvp_email(
     }
 }
 
+// Tell someone on the waitlist of a home group that a place opened up and
+// their registration is approved
+pub async fn send_homegroup_place_offered_email(
+    pool: &PgPool,
+    registration_id: i32,
+    email: &str,
+    name: &str,
+    home_group_id: i32,
+    expires_at: chrono::NaiveDateTime,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Get home group details
+    let home_group = sqlx::query!(
+        r#"
+        SELECT name, location
+        FROM homegroups
+        WHERE id = $1
+        "#,
+        home_group_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let config = EmailConfig::from_env();
+    let mailer = create_mailer(&config).await?;
+
+    let location = home_group.location.unwrap_or_else(|| "TBA".to_string());
+    let expires = expires_at.format("%Y-%m-%d %H:%M UTC");
+    let accept_link = homegroup_waitlist::offer_link(registration_id, true);
+    let decline_link = homegroup_waitlist::offer_link(registration_id, false);
+
+    // Create email content
+    let html_content = format!(
+        r#"
+        <html>
+            <body>
+                <h2>A Place Is Now Available</h2>
+                <p>Dear {}</p>
+                <p>Good news! A place has opened up in the home group {} and it is held for you until {}.</p>
+                <p>Location: {}</p>
+                <p>Do you want to join?</p>
+                <p><a href="{}">Yes, I'll take the place</a> &nbsp; <a href="{}">No, give it to the next person</a></p>
+                <p>If you do not answer in time, the place goes to the next person in line.</p>
+                <p>Best regards,<br>Church Events Team</p>
+            </body>
+        </html>
+        "#,
+        name, home_group.name, expires, location, accept_link, decline_link
+    );
+
+    let text_content = format!(
+        "Dear {},\n\n\
+        Good news! A place has opened up in the home group {} and it is held for you until {}.\n\n\
+        Location: {}\n\n\
+        Do you want to join?\n\
+        Yes, I'll take the place: {}\n\
+        No, give it to the next person: {}\n\n\
+        If you do not answer in time, the place goes to the next person in line.\n\n\
+        Best regards,\n\
+        Church Events Team",
+        name, home_group.name, expires, location, accept_link, decline_link
+    );
+
+    // Create the email message
+    let email_message = Message::builder()
+        .from(config.from_email.parse()?)
+        .to(email.parse()?)
+        .subject(format!("A place for you: {}", home_group.name))
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_PLAIN)
+                        .body(text_content)
+                )
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_HTML)
+                        .body(html_content.clone())
+                ),
+        )?;
+
+    // Send the email
+    mailer.send(email_message).await?;
+
+    // After sending the email successfully, log it
+    sqlx::query!(
+        r#"
+        INSERT INTO email_logs
+        (email_to, email_from, subject, body, status, sent_at)
+        VALUES ($1, $2, $3, $4, 'sent', CURRENT_TIMESTAMP)
+        "#,
+        email,
+        config.from_email,
+        format!("A place for you: {}", home_group.name),
+        html_content,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
 async fn send_homegroup_decline_email_internal(
     pool: &PgPool,
     email: &str,
@@ -497,8 +992,18 @@ async fn send_homegroup_decline_email_internal(
 // Send decline email to home group RSVP
 pub async fn send_homegroup_decline_email(
     pool: web::Data<PgPool>,
+    claims: Claims,
     req: web::Json<HomeGroupEmailRequest>,
 ) -> impl Responder {
+    if let Err(response) = permission::require_permission(
+        &pool,
+        &claims,
+        permission::HOMEGROUP_APPROVE,
+        Some(Resource::HomeGroup(req.home_group_id)),
+    ).await {
+        return response;
+    }
+
     match send_homegroup_decline_email_internal(
         &pool,
         &req.email,
@@ -781,4 +1286,163 @@ async fn rsvp_for_serving(
     .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// PASSWORD RESET EMAIL
+
+// Send the password reset link to a user
+pub async fn send_password_reset_email(
+    pool: &PgPool,
+    email: &str,
+    username: &str,
+    reset_link: &str,
+    expires_in_minutes: i64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = EmailConfig::from_env();
+    let mailer = create_mailer(&config).await?;
+
+    // Create email content
+    let html_content = format!(
+        r#"
+        <html>
+            <body>
+                <h2>Password Reset</h2>
+                <p>Dear {username}</p>
+                <p>We received a request to reset your password.</p>
+                <p><a href="{reset_link}">Reset your password</a></p>
+                <p>This link expires in {expires_in_minutes} minutes and can only be used once.</p>
+                <p>If you did not request a password reset, you can ignore this email.</p>
+                <p>Best regards,<br>Church Events Team</p>
+            </body>
+        </html>
+        "#
+    );
+
+    let text_content = format!(
+        "Dear {},\n\n\
+        We received a request to reset your password.\n\n\
+        Reset your password: {}\n\n\
+        This link expires in {} minutes and can only be used once.\n\n\
+        If you did not request a password reset, you can ignore this email.\n\n\
+        Best regards,\n\
+        Church Events Team",
+        username, reset_link, expires_in_minutes
+    );
+
+    // Create the email message
+    let email_message = Message::builder()
+        .from(config.from_email.parse()?)
+        .to(email.parse()?)
+        .subject("Reset your password")
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_PLAIN)
+                        .body(text_content)
+                )
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_HTML)
+                        .body(html_content)
+                ),
+        )?;
+
+    // Send the email
+    mailer.send(email_message).await?;
+
+    // Log without the body so the reset link is not stored in plain text
+    sqlx::query!(
+        r#"
+        INSERT INTO email_logs
+        (email_to, email_from, subject, body, status, sent_at)
+        VALUES ($1, $2, $3, $4, 'sent', CURRENT_TIMESTAMP)
+        "#,
+        email,
+        config.from_email,
+        "Reset your password",
+        "[password reset link omitted]",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// EMAIL VERIFICATION
+
+// Send the account verification link to a newly registered user
+pub async fn send_verification_email(
+    pool: &PgPool,
+    email: &str,
+    username: &str,
+    verification_link: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = EmailConfig::from_env();
+    let mailer = create_mailer(&config).await?;
+
+    // Create email content
+    let html_content = format!(
+        r#"
+        <html>
+            <body>
+                <h2>Welcome to the Church App</h2>
+                <p>Dear {username}</p>
+                <p>Thank you for registering! Please confirm your email address to activate your account.</p>
+                <p><a href="{verification_link}">Verify your email</a></p>
+                <p>If you did not create an account, you can ignore this email.</p>
+                <p>Best regards,<br>Church Events Team</p>
+            </body>
+        </html>
+        "#
+    );
+
+    let text_content = format!(
+        "Dear {},\n\n\
+        Thank you for registering! Please confirm your email address to activate your account.\n\n\
+        Verify your email: {}\n\n\
+        If you did not create an account, you can ignore this email.\n\n\
+        Best regards,\n\
+        Church Events Team",
+        username, verification_link
+    );
+
+    // Create the email message
+    let email_message = Message::builder()
+        .from(config.from_email.parse()?)
+        .to(email.parse()?)
+        .subject("Verify your email")
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_PLAIN)
+                        .body(text_content)
+                )
+                .singlepart(
+                    SinglePart::builder()
+                        .header(header::ContentType::TEXT_HTML)
+                        .body(html_content)
+                ),
+        )?;
+
+    // Send the email
+    mailer.send(email_message).await?;
+
+    // Log without the body so the verification link is not stored in plain text
+    sqlx::query!(
+        r#"
+        INSERT INTO email_logs
+        (email_to, email_from, subject, body, status, sent_at)
+        VALUES ($1, $2, $3, $4, 'sent', CURRENT_TIMESTAMP)
+        "#,
+        email,
+        config.from_email,
+        "Verify your email",
+        "[verification link omitted]",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
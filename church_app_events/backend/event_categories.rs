@@ -0,0 +1,299 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+
+// Event categories, managed by admins. Events refer to a category by name
+// (renames cascade, deleting a category leaves its events uncategorized),
+// so feeds and imports can keep using the name. Tags are free-form and
+// stored on the events themselves.
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_TAG_LENGTH: usize = 50;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct EventCategory {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub color: Option<String>, // Hex color for filter chips, e.g. #3366ff
+    pub event_count: i64,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryRequest {
+    name: String,
+    description: Option<String>,
+    color: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCategoryRequest {
+    name: Option<String>,
+    description: Option<String>,
+    color: Option<String>,
+}
+
+const CATEGORY_COLUMNS: &str = "c.id, c.name, c.description, c.color, \
+     (SELECT COUNT(*) FROM events e WHERE e.category = c.name) AS event_count, \
+     c.created_at, c.updated_at";
+
+fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Category name is required".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!("Category name is longer than {} characters", MAX_NAME_LENGTH));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_color(color: &str) -> Result<(), String> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if valid {
+        Ok(())
+    } else {
+        Err("Color must be a hex color such as #3366ff".to_string())
+    }
+}
+
+/// Tags as stored: trimmed, lowercase and without duplicates
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || normalized.contains(&tag) {
+            continue;
+        }
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!("Tag is longer than {} characters: {}", MAX_TAG_LENGTH, tag));
+        }
+        normalized.push(tag);
+    }
+    Ok(normalized)
+}
+
+/// Tags from a comma-separated query parameter
+pub fn parse_tags(tags: Option<&str>) -> Vec<String> {
+    let tags: Vec<String> = tags
+        .unwrap_or_default()
+        .split(',')
+        .map(str::to_string)
+        .collect();
+    normalize_tags(&tags).unwrap_or_default()
+}
+
+/// Name of the category matching `name` case-insensitively, if it exists
+pub async fn find_category(pool: &PgPool, name: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>("SELECT name FROM event_categories WHERE LOWER(name) = LOWER($1)")
+        .bind(name.trim())
+        .fetch_optional(pool)
+        .await
+}
+
+/// Resolve the category of an event request; empty means none
+pub async fn resolve_category(pool: &PgPool, category: Option<&str>) -> Result<Result<Option<String>, String>, sqlx::Error> {
+    match category.map(str::trim) {
+        None | Some("") => Ok(Ok(None)),
+        Some(name) => Ok(find_category(pool, name)
+            .await?
+            .map(Some)
+            .ok_or_else(|| format!("Unknown category: {}", name))),
+    }
+}
+
+async fn fetch_category(pool: &PgPool, id: i32) -> Result<Option<EventCategory>, sqlx::Error> {
+    sqlx::query_as::<_, EventCategory>(&format!(
+        "SELECT {} FROM event_categories c WHERE c.id = $1",
+        CATEGORY_COLUMNS
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+// List categories with the number of events in each
+pub async fn get_categories(pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as::<_, EventCategory>(&format!(
+        "SELECT {} FROM event_categories c ORDER BY c.name",
+        CATEGORY_COLUMNS
+    ))
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(categories) => HttpResponse::Ok().json(categories),
+        Err(e) => {
+            eprintln!("Failed to fetch event categories: {}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch categories"
+            }))
+        }
+    }
+}
+
+pub async fn add_category(
+    pool: web::Data<PgPool>,
+    request: web::Json<CategoryRequest>,
+) -> impl Responder {
+    let name = match validate_name(&request.name) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+    };
+    if let Some(Err(e)) = request.color.as_deref().map(validate_color) {
+        return HttpResponse::BadRequest().json(json!({ "message": e }));
+    }
+
+    match find_category(pool.get_ref(), &name).await {
+        Ok(Some(existing)) => {
+            return HttpResponse::Conflict().json(json!({
+                "message": format!("Category {} already exists", existing)
+            }));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to add category"
+            }));
+        }
+    }
+
+    let result = sqlx::query_scalar::<_, i32>(
+        "INSERT INTO event_categories (name, description, color) VALUES ($1, $2, $3) RETURNING id"
+    )
+    .bind(&name)
+    .bind(&request.description)
+    .bind(&request.color)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(id) => match fetch_category(pool.get_ref(), id).await {
+            Ok(Some(category)) => HttpResponse::Created().json(category),
+            Ok(None) => HttpResponse::NotFound().json(json!({ "message": "Category not found" })),
+            Err(e) => {
+                eprintln!("Failed to fetch category {}: {}", id, e);
+                HttpResponse::InternalServerError().json(json!({ "message": "Failed to add category" }))
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to add category {}: {}", name, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to add category"
+            }))
+        }
+    }
+}
+
+// Rename or describe a category; renames carry over to its events
+pub async fn update_category(
+    pool: web::Data<PgPool>,
+    category_id: web::Path<i32>,
+    request: web::Json<UpdateCategoryRequest>,
+) -> impl Responder {
+    let category_id = category_id.into_inner();
+
+    let name = match request.name.as_deref().map(validate_name) {
+        Some(Ok(name)) => Some(name),
+        Some(Err(e)) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+        None => None,
+    };
+    // An empty color removes it
+    let color = request.color.as_deref().map(str::trim);
+    if let Some(Err(e)) = color.filter(|color| !color.is_empty()).map(validate_color) {
+        return HttpResponse::BadRequest().json(json!({ "message": e }));
+    }
+
+    if let Some(name) = &name {
+        let taken = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM event_categories WHERE LOWER(name) = LOWER($1) AND id <> $2)"
+        )
+        .bind(name)
+        .bind(category_id)
+        .fetch_one(pool.get_ref())
+        .await;
+
+        match taken {
+            Ok(false) => {}
+            Ok(true) => {
+                return HttpResponse::Conflict().json(json!({
+                    "message": format!("Category {} already exists", name)
+                }));
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to update category"
+                }));
+            }
+        }
+    }
+
+    let result = sqlx::query(
+        "UPDATE event_categories SET
+            name = COALESCE($1, name),
+            description = COALESCE($2, description),
+            color = NULLIF(COALESCE($3, color), ''),
+            updated_at = NOW()
+         WHERE id = $4"
+    )
+    .bind(&name)
+    .bind(&request.description)
+    .bind(color)
+    .bind(category_id)
+    .execute(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            HttpResponse::NotFound().json(json!({ "message": "Category not found" }))
+        }
+        Ok(_) => match fetch_category(pool.get_ref(), category_id).await {
+            Ok(Some(category)) => HttpResponse::Ok().json(category),
+            Ok(None) => HttpResponse::NotFound().json(json!({ "message": "Category not found" })),
+            Err(e) => {
+                eprintln!("Failed to fetch category {}: {}", category_id, e);
+                HttpResponse::InternalServerError().json(json!({ "message": "Failed to update category" }))
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to update category {}: {}", category_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to update category"
+            }))
+        }
+    }
+}
+
+// Delete a category; its events become uncategorized
+pub async fn delete_category(
+    pool: web::Data<PgPool>,
+    category_id: web::Path<i32>,
+) -> impl Responder {
+    let category_id = category_id.into_inner();
+
+    let result = sqlx::query("DELETE FROM event_categories WHERE id = $1")
+        .bind(category_id)
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(done) if done.rows_affected() > 0 => HttpResponse::Ok().json(json!({
+            "message": "Category deleted"
+        })),
+        Ok(_) => HttpResponse::NotFound().json(json!({ "message": "Category not found" })),
+        Err(e) => {
+            eprintln!("Failed to delete category {}: {}", category_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to delete category"
+            }))
+        }
+    }
+}
@@ -0,0 +1,825 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+use crate::event_categories::normalize_tags;
+use crate::events::{normalize_rule, normalize_time_zone, resolve_duration, NewEvent};
+
+// Bulk creation of events from an uploaded .ics or CSV file. The file is the
+// raw request body; `dry_run=true` only returns the preview. A real import
+// runs in one transaction and is refused while any row has errors. Events
+// that already exist (same title on the same date) are skipped. CSV
+// categories must be managed ones; iCalendar CATEGORIES become tags, except
+// one naming a managed category, which becomes the event's category.
+
+pub const MAX_IMPORT_BYTES: usize = 5 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct ImportParams {
+    format: Option<String>, // "ics" or "csv", by default from the Content-Type
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// One CSV line; headers match NewEvent with a few common aliases
+#[derive(Debug, Deserialize)]
+struct CsvEvent {
+    #[serde(alias = "title")]
+    event_title: Option<String>,
+    #[serde(alias = "date")]
+    event_date: Option<String>,
+    #[serde(alias = "time")]
+    event_time: Option<String>,
+    #[serde(alias = "location")]
+    address: Option<String>,
+    description: Option<String>,
+    category: Option<String>,
+    #[serde(alias = "rrule")]
+    recurrence_rule: Option<String>,
+    end_date: Option<String>,
+    end_time: Option<String>,
+    #[serde(alias = "duration")]
+    duration_minutes: Option<String>,
+    all_day: Option<String>,
+    #[serde(alias = "timezone")]
+    time_zone: Option<String>,
+    capacity: Option<String>,
+    tags: Option<String>, // Comma-separated
+}
+
+#[derive(Serialize)]
+pub struct ImportRow {
+    row: usize, // CSV line or position of the VEVENT in the file
+    event: Option<NewEvent>,
+    errors: Vec<String>,
+    duplicate_of: Option<i32>, // Existing event with the same title and date
+}
+
+impl ImportRow {
+    fn invalid(row: usize, error: String) -> Self {
+        ImportRow {
+            row,
+            event: None,
+            errors: vec![error],
+            duplicate_of: None,
+        }
+    }
+}
+
+// Fields of an event before validation
+#[derive(Default)]
+struct RawEvent {
+    title: Option<String>,
+    date: Option<String>,
+    time: Option<String>,
+    address: Option<String>,
+    description: Option<String>,
+    category: Option<String>,
+    recurrence_rule: Option<String>,
+    exceptions: Vec<NaiveDate>,
+    end_date: Option<String>,
+    end_time: Option<String>,
+    duration_minutes: Option<String>,
+    all_day: bool,
+    time_zone: Option<String>,
+    capacity: Option<String>,
+    tags: Vec<String>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    ["%Y-%m-%d", "%Y%m%d", "%m/%d/%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    ["%H:%M:%S", "%H:%M", "%I:%M %p", "%I:%M%p"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(value, format).ok())
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+// Parse an optional field, recording an error when it is unreadable
+fn parse_optional<T>(
+    value: Option<String>,
+    parse: impl Fn(&str) -> Option<T>,
+    label: &str,
+    errors: &mut Vec<String>,
+) -> Option<T> {
+    let value = non_empty(value)?;
+    let parsed = parse(&value);
+    if parsed.is_none() {
+        errors.push(format!("Invalid {}: {}", label, value));
+    }
+    parsed
+}
+
+fn validate(row: usize, raw: RawEvent) -> ImportRow {
+    let mut errors = Vec::new();
+
+    let title = non_empty(raw.title);
+    match &title {
+        None => errors.push("Title is required".to_string()),
+        Some(title) if title.chars().count() > 255 => errors.push("Title is longer than 255 characters".to_string()),
+        _ => {}
+    }
+
+    let date = match non_empty(raw.date) {
+        None => {
+            errors.push("Date is required".to_string());
+            None
+        }
+        Some(date) => {
+            let parsed = parse_date(&date);
+            if parsed.is_none() {
+                errors.push(format!("Invalid date: {}", date));
+            }
+            parsed
+        }
+    };
+
+    // Events without a time (e.g. all-day entries) start at midnight
+    let time = match non_empty(raw.time) {
+        None => Some(NaiveTime::MIN),
+        Some(time) => {
+            let parsed = parse_time(&time);
+            if parsed.is_none() {
+                errors.push(format!("Invalid time: {}", time));
+            }
+            parsed
+        }
+    };
+
+    let recurrence_rule = match normalize_rule(raw.recurrence_rule.as_deref()) {
+        Ok(rule) => rule,
+        Err(e) => {
+            errors.push(format!("Invalid recurrence rule: {}", e));
+            None
+        }
+    };
+
+    let time_zone = match normalize_time_zone(raw.time_zone.as_deref()) {
+        Ok(time_zone) => time_zone,
+        Err(e) => {
+            errors.push(e);
+            None
+        }
+    };
+
+    let end_date = parse_optional(raw.end_date, parse_date, "end date", &mut errors);
+    let end_time = parse_optional(raw.end_time, parse_time, "end time", &mut errors);
+    let duration = parse_optional(raw.duration_minutes, |value| value.parse::<i32>().ok(), "duration", &mut errors);
+    let capacity = parse_optional(
+        raw.capacity,
+        |value| value.parse::<i32>().ok().filter(|capacity| *capacity > 0),
+        "capacity",
+        &mut errors,
+    );
+
+    let tags = match normalize_tags(&raw.tags) {
+        Ok(tags) => tags,
+        Err(e) => {
+            errors.push(e);
+            Vec::new()
+        }
+    };
+
+    let duration_minutes = match (date, time) {
+        (Some(date), Some(time)) => match resolve_duration(date, time, raw.all_day, duration, end_date, end_time) {
+            Ok(duration) => duration,
+            Err(e) => {
+                errors.push(e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let event = match (title, date, time) {
+        (Some(event_title), Some(event_date), Some(event_time)) if errors.is_empty() => Some(NewEvent {
+            event_title,
+            event_date,
+            event_time: if raw.all_day { NaiveTime::MIN } else { event_time },
+            address: non_empty(raw.address),
+            description: non_empty(raw.description),
+            recurrence_rule,
+            recurrence_exceptions: raw.exceptions,
+            category: non_empty(raw.category),
+            home_group_id: None,
+            duration_minutes,
+            end_date: None,
+            end_time: None,
+            all_day: raw.all_day,
+            time_zone,
+            capacity,
+            tags,
+        }),
+        _ => None,
+    };
+
+    ImportRow {
+        row,
+        event,
+        errors,
+        duplicate_of: None,
+    }
+}
+
+fn parse_csv(body: &[u8]) -> Vec<ImportRow> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+    let mut rows = Vec::new();
+
+    for (index, record) in reader.deserialize::<CsvEvent>().enumerate() {
+        // Line 1 is the header
+        let row = index + 2;
+        match record {
+            Ok(record) => {
+                let all_day = record.all_day.as_deref().map(str::trim).map_or(Some(false), parse_flag);
+                let all_day = match all_day {
+                    Some(all_day) => all_day,
+                    None => {
+                        rows.push(ImportRow::invalid(row, format!("Invalid all_day: {}", record.all_day.unwrap_or_default())));
+                        continue;
+                    }
+                };
+
+                rows.push(validate(
+                    row,
+                    RawEvent {
+                        title: record.event_title,
+                        date: record.event_date,
+                        time: record.event_time,
+                        address: record.address,
+                        description: record.description,
+                        category: record.category,
+                        recurrence_rule: record.recurrence_rule,
+                        exceptions: Vec::new(),
+                        end_date: record.end_date,
+                        end_time: record.end_time,
+                        duration_minutes: record.duration_minutes,
+                        all_day,
+                        time_zone: record.time_zone,
+                        capacity: record.capacity,
+                        tags: record
+                            .tags
+                            .as_deref()
+                            .map(|tags| tags.split(',').map(str::to_string).collect())
+                            .unwrap_or_default(),
+                    },
+                ))
+            }
+            Err(e) => rows.push(ImportRow::invalid(row, format!("Unreadable row: {}", e))),
+        }
+    }
+
+    rows
+}
+
+// Split a list value on the commas that are not escaped
+fn split_list(value: &str) -> Vec<&str> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                values.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    values.push(&value[start..]);
+
+    values
+}
+
+// Undo TEXT escaping (RFC 5545 section 3.3.11)
+fn unescape_text(value: &str) -> String {
+    let mut text = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => text.push('\n'),
+            Some(other) => text.push(other),
+            None => {}
+        }
+    }
+
+    text
+}
+
+// DATE or DATE-TIME value, kept as local time; the zone comes from the
+// TZID parameter, or is UTC for times ending in Z
+fn split_date_time(value: &str) -> (String, Option<String>) {
+    let value = value.trim_end_matches('Z');
+    match value.split_once('T') {
+        Some((date, time)) => {
+            let time = NaiveTime::parse_from_str(time, "%H%M%S")
+                .map(|time| time.format("%H:%M:%S").to_string())
+                .unwrap_or_else(|_| time.to_string());
+            (date.to_string(), Some(time))
+        }
+        None => (value.to_string(), None),
+    }
+}
+
+// Value of a property parameter, e.g. TZID in DTSTART;TZID=Europe/Berlin
+fn parameter(name_and_params: &str, parameter: &str) -> Option<String> {
+    name_and_params.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.eq_ignore_ascii_case(parameter).then(|| value.trim_matches('"').to_string())
+    })
+}
+
+// DURATION value (RFC 5545 section 3.3.6) in minutes, e.g. PT1H30M or P1D
+fn parse_duration(value: &str) -> Option<i32> {
+    let value = value.strip_prefix('+').unwrap_or(value).strip_prefix('P')?;
+    let mut minutes: i64 = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() => in_time = true,
+            unit => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+                minutes += match (unit, in_time) {
+                    ('W', false) => n * 7 * 24 * 60,
+                    ('D', false) => n * 24 * 60,
+                    ('H', true) => n * 60,
+                    ('M', true) => n,
+                    ('S', true) => n / 60,
+                    _ => return None,
+                };
+            }
+        }
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+    i32::try_from(minutes).ok()
+}
+
+fn parse_ics(body: &str) -> Vec<ImportRow> {
+    // Unfold continuation lines (RFC 5545 section 3.1)
+    let mut lines: Vec<String> = Vec::new();
+    for line in body.split('\n').map(|line| line.trim_end_matches('\r')) {
+        match line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')) {
+            Some(continuation) if !lines.is_empty() => lines.last_mut().unwrap().push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut rows = Vec::new();
+    let mut current: Option<(RawEvent, Vec<String>)> = None;
+    let mut position = 0;
+    // Depth of components nested in the VEVENT, such as VALARM
+    let mut nested = 0;
+
+    for line in lines {
+        let (name_and_params, value) = match line.split_once(':') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let name = name_and_params.split(';').next().unwrap_or_default().to_uppercase();
+        let is_vevent = value.eq_ignore_ascii_case("VEVENT");
+
+        if current.is_none() {
+            if name == "BEGIN" && is_vevent {
+                position += 1;
+                current = Some((RawEvent::default(), Vec::new()));
+            }
+            continue;
+        }
+
+        match name.as_str() {
+            "BEGIN" => nested += 1,
+            "END" if nested > 0 => nested -= 1,
+            "END" if is_vevent => {
+                if let Some((raw, skip_reasons)) = current.take() {
+                    match skip_reasons.into_iter().next() {
+                        Some(reason) => rows.push(ImportRow::invalid(position, reason)),
+                        None => rows.push(validate(position, raw)),
+                    }
+                }
+            }
+            _ if nested > 0 => {}
+            _ => {
+                let (raw, skip_reasons) = match current.as_mut() {
+                    Some((raw, skip_reasons)) => (raw, skip_reasons),
+                    None => continue,
+                };
+
+                match name.as_str() {
+                    "SUMMARY" => raw.title = Some(unescape_text(value)),
+                    "LOCATION" => raw.address = Some(unescape_text(value)),
+                    "DESCRIPTION" => raw.description = Some(unescape_text(value)),
+                    "CATEGORIES" => raw.tags.extend(split_list(value).into_iter().map(unescape_text)),
+                    "DTSTART" => {
+                        let (date, time) = split_date_time(value);
+                        raw.all_day = time.is_none();
+                        raw.date = Some(date);
+                        raw.time = time;
+                        raw.time_zone = match parameter(name_and_params, "TZID") {
+                            Some(tzid) => Some(tzid),
+                            None if value.ends_with('Z') => Some("UTC".to_string()),
+                            None => None,
+                        };
+                    }
+                    "DTEND" => {
+                        let (date, time) = split_date_time(value);
+                        // The end date of all-day events is exclusive in iCalendar
+                        let date = match time {
+                            Some(_) => date,
+                            None => parse_date(&date)
+                                .and_then(|date| date.pred_opt())
+                                .map_or(date, |date| date.format("%Y-%m-%d").to_string()),
+                        };
+                        raw.end_date = Some(date);
+                        raw.end_time = time;
+                    }
+                    "DURATION" => match parse_duration(value) {
+                        Some(minutes) => raw.duration_minutes = Some(minutes.to_string()),
+                        None => skip_reasons.push(format!("Invalid DURATION: {}", value)),
+                    },
+                    "RRULE" => raw.recurrence_rule = Some(value.to_string()),
+                    "EXDATE" => {
+                        for exception in value.split(',') {
+                            match parse_date(&split_date_time(exception).0) {
+                                Some(date) => raw.exceptions.push(date),
+                                None => skip_reasons.push(format!("Invalid EXDATE: {}", exception)),
+                            }
+                        }
+                    }
+                    "RECURRENCE-ID" => {
+                        skip_reasons.push("Changes to single occurrences cannot be imported".to_string());
+                    }
+                    "STATUS" if value.eq_ignore_ascii_case("CANCELLED") => {
+                        skip_reasons.push("Cancelled events are not imported".to_string());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    rows
+}
+
+// Match categories with the managed ones, by name in any case. A CSV row
+// naming an unknown category is an error; a calendar event takes the first
+// of its CATEGORIES that is a managed category.
+async fn resolve_categories(pool: &PgPool, rows: &mut [ImportRow], from_calendar: bool) -> Result<(), sqlx::Error> {
+    let categories: HashMap<String, String> = sqlx::query_scalar::<_, String>("SELECT name FROM event_categories")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|name| (name.to_lowercase(), name))
+        .collect();
+
+    for row in rows.iter_mut() {
+        let event = match row.event.as_mut() {
+            Some(event) => event,
+            None => continue,
+        };
+
+        if from_calendar {
+            if let Some(index) = event.tags.iter().position(|tag| categories.contains_key(tag)) {
+                let tag = event.tags.remove(index);
+                event.category = categories.get(&tag).cloned();
+            }
+            continue;
+        }
+
+        if let Some(category) = &event.category {
+            match categories.get(&category.to_lowercase()) {
+                Some(name) => event.category = Some(name.clone()),
+                None => {
+                    row.errors.push(format!("Unknown category: {}", category));
+                    row.event = None;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Mark rows matching an existing event, or an earlier row, by title and date
+async fn mark_duplicates(pool: &PgPool, rows: &mut [ImportRow]) -> Result<(), sqlx::Error> {
+    let dates: Vec<NaiveDate> = rows
+        .iter()
+        .filter_map(|row| row.event.as_ref().map(|event| event.event_date))
+        .collect();
+
+    let existing = sqlx::query_as::<_, (i32, String, NaiveDate)>(
+        "SELECT id, LOWER(event_title), event_date FROM events WHERE event_date = ANY($1)"
+    )
+    .bind(&dates)
+    .fetch_all(pool)
+    .await?;
+
+    let existing: HashMap<(String, NaiveDate), i32> = existing
+        .into_iter()
+        .map(|(id, title, date)| ((title, date), id))
+        .collect();
+
+    mark_repeated(rows, &existing);
+    Ok(())
+}
+
+// Mark rows matching one of `existing`, keyed by lowercase title and date,
+// or an earlier row of the file
+fn mark_repeated(rows: &mut [ImportRow], existing: &HashMap<(String, NaiveDate), i32>) {
+    let mut seen: HashMap<(String, NaiveDate), usize> = HashMap::new();
+    for row in rows.iter_mut() {
+        let key = match &row.event {
+            Some(event) => (event.event_title.to_lowercase(), event.event_date),
+            None => continue,
+        };
+
+        if let Some(id) = existing.get(&key) {
+            row.duplicate_of = Some(*id);
+        } else if let Some(earlier) = seen.get(&key) {
+            row.errors.push(format!("Same title and date as row {}", earlier));
+            row.event = None;
+        } else {
+            seen.insert(key, row.row);
+        }
+    }
+}
+
+async fn insert_events(pool: &PgPool, rows: &[ImportRow]) -> Result<Vec<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut created = Vec::new();
+
+    for event in rows.iter().filter(|row| row.duplicate_of.is_none()).filter_map(|row| row.event.as_ref()) {
+        let id = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO events (event_title, event_date, event_time, address, description,
+             recurrence_rule, recurrence_exceptions, category, duration_minutes, all_day, time_zone, capacity, tags)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+             RETURNING id"
+        )
+        .bind(&event.event_title)
+        .bind(event.event_date)
+        .bind(event.event_time)
+        .bind(&event.address)
+        .bind(&event.description)
+        .bind(&event.recurrence_rule)
+        .bind(&event.recurrence_exceptions)
+        .bind(&event.category)
+        .bind(event.duration_minutes)
+        .bind(event.all_day)
+        .bind(&event.time_zone)
+        .bind(event.capacity)
+        .bind(&event.tags)
+        .fetch_one(&mut tx)
+        .await?;
+
+        created.push(id);
+    }
+
+    tx.commit().await?;
+    Ok(created)
+}
+
+// Preview or import events from an .ics or CSV upload (admin only)
+pub async fn import_events(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    params: web::Query<ImportParams>,
+    body: web::Bytes,
+) -> impl Responder {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let format = match params.format.as_deref() {
+        Some(format) => format.to_lowercase(),
+        None if content_type.starts_with("text/calendar") => "ics".to_string(),
+        None if content_type.starts_with("text/csv") => "csv".to_string(),
+        None => String::new(),
+    };
+
+    let mut rows = match format.as_str() {
+        "csv" => parse_csv(&body),
+        "ics" => match std::str::from_utf8(&body) {
+            Ok(text) => parse_ics(text),
+            Err(_) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "message": "The calendar file must be UTF-8"
+                }));
+            }
+        },
+        _ => {
+            return HttpResponse::BadRequest().json(json!({
+                "message": "Upload a text/calendar or text/csv file, or pass format=ics|csv"
+            }));
+        }
+    };
+
+    if rows.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "message": "No events found in the file"
+        }));
+    }
+
+    if let Err(e) = resolve_categories(pool.get_ref(), &mut rows, format == "ics").await {
+        eprintln!("Failed to check event categories: {:?}", e);
+        return HttpResponse::InternalServerError().json(json!({
+            "message": "Failed to import events"
+        }));
+    }
+
+    if let Err(e) = mark_duplicates(pool.get_ref(), &mut rows).await {
+        eprintln!("Failed to check for duplicate events: {:?}", e);
+        return HttpResponse::InternalServerError().json(json!({
+            "message": "Failed to import events"
+        }));
+    }
+
+    let invalid = rows.iter().filter(|row| !row.errors.is_empty()).count();
+    let duplicates = rows.iter().filter(|row| row.duplicate_of.is_some()).count();
+    let summary = json!({
+        "total": rows.len(),
+        "valid": rows.len() - invalid,
+        "invalid": invalid,
+        "duplicates": duplicates,
+    });
+
+    if params.dry_run {
+        return HttpResponse::Ok().json(json!({
+            "dry_run": true,
+            "summary": summary,
+            "rows": rows,
+        }));
+    }
+
+    if invalid > 0 {
+        return HttpResponse::UnprocessableEntity().json(json!({
+            "message": "Fix the rows with errors before importing",
+            "summary": summary,
+            "rows": rows,
+        }));
+    }
+
+    match insert_events(pool.get_ref(), &rows).await {
+        Ok(created) => HttpResponse::Ok().json(json!({
+            "dry_run": false,
+            "summary": summary,
+            "imported": created.len(),
+            "created_ids": created,
+        })),
+        Err(e) => {
+            eprintln!("Failed to import events: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to import events, nothing was imported"
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar(vevents: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{}END:VCALENDAR\r\n", vevents)
+    }
+
+    #[test]
+    fn folded_lines_are_joined() {
+        let ics = calendar(
+            "BEGIN:VEVENT\r\nSUMMARY:Sunday \r\n Service\r\nDESCRIPTION:Bring a \r\n\tfriend\\, or two\r\n\
+             DTSTART;TZID=Europe/Berlin:20250302T100000\r\nDURATION:PT1H30M\r\nEND:VEVENT\r\n",
+        );
+        let rows = parse_ics(&ics);
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].errors.is_empty(), "{:?}", rows[0].errors);
+
+        let event = rows[0].event.as_ref().unwrap();
+        assert_eq!(event.event_title, "Sunday Service");
+        assert_eq!(event.description.as_deref(), Some("Bring a friend, or two"));
+        assert_eq!(event.event_time, NaiveTime::from_hms_opt(10, 0, 0).unwrap());
+        assert_eq!(event.time_zone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(event.duration_minutes, Some(90));
+    }
+
+    #[test]
+    fn all_day_end_dates_are_exclusive() {
+        let ics = calendar(
+            "BEGIN:VEVENT\nSUMMARY:Retreat\nDTSTART;VALUE=DATE:20250301\nDTEND;VALUE=DATE:20250303\nEND:VEVENT\n\
+             BEGIN:VEVENT\nSUMMARY:Fair\nDTSTART;VALUE=DATE:20250310\nDTEND;VALUE=DATE:20250311\nEND:VEVENT\n",
+        );
+        let rows = parse_ics(&ics);
+        let retreat = rows[0].event.as_ref().unwrap();
+        assert!(retreat.all_day);
+        assert_eq!(retreat.event_date, NaiveDate::from_ymd_opt(2025, 3, 1).unwrap());
+        assert_eq!(retreat.duration_minutes, Some(2 * 24 * 60));
+        assert_eq!(rows[1].event.as_ref().unwrap().duration_minutes, Some(24 * 60));
+    }
+
+    #[test]
+    fn durations_are_read_in_minutes() {
+        assert_eq!(parse_duration("PT1H30M"), Some(90));
+        assert_eq!(parse_duration("P1D"), Some(24 * 60));
+        assert_eq!(parse_duration("+P1W"), Some(7 * 24 * 60));
+        assert_eq!(parse_duration("P1DT2H"), Some(26 * 60));
+        assert_eq!(parse_duration("PT90S"), Some(1));
+    }
+
+    #[test]
+    fn invalid_durations_are_refused() {
+        for value in ["1H", "P1H", "PT1D", "PT5", "PTXM", "P99999999W"] {
+            assert_eq!(parse_duration(value), None, "{}", value);
+        }
+
+        let rows = parse_ics(&calendar(
+            "BEGIN:VEVENT\nSUMMARY:Choir\nDTSTART:20250302T180000\nDURATION:PT1Q\nEND:VEVENT\n",
+        ));
+        assert!(rows[0].event.is_none());
+        assert_eq!(rows[0].errors, vec!["Invalid DURATION: PT1Q"]);
+    }
+
+    #[test]
+    fn changed_and_cancelled_occurrences_are_skipped() {
+        let rows = parse_ics(&calendar(
+            "BEGIN:VEVENT\nSUMMARY:Study\nDTSTART:20250302T180000\nRECURRENCE-ID:20250302T180000\nEND:VEVENT\n\
+             BEGIN:VEVENT\nSUMMARY:Picnic\nDTSTART:20250309T120000\nSTATUS:CANCELLED\nEND:VEVENT\n\
+             BEGIN:VEVENT\nSUMMARY:Prayer\nDTSTART:20250316T070000\nSTATUS:CONFIRMED\n\
+             BEGIN:VALARM\nSTATUS:CANCELLED\nEND:VALARM\nEND:VEVENT\n",
+        ));
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].errors, vec!["Changes to single occurrences cannot be imported"]);
+        assert_eq!(rows[1].errors, vec!["Cancelled events are not imported"]);
+        assert_eq!(rows[1].row, 2);
+        // Properties of nested components are not the event's
+        assert!(rows[2].event.is_some(), "{:?}", rows[2].errors);
+    }
+
+    #[test]
+    fn csv_rows_are_numbered_by_line() {
+        let csv = "title,date,time,duration,all_day\n\
+                   Worship,2025-03-02,10:00,90,no\n\
+                   ,2025-03-09,10:00,,\n\
+                   Worship,2025-03-16,10:00,,maybe\n";
+        let rows = parse_csv(csv.as_bytes());
+        assert_eq!(rows.len(), 3);
+
+        let event = rows[0].event.as_ref().unwrap();
+        assert_eq!(rows[0].row, 2);
+        assert_eq!(event.event_title, "Worship");
+        assert_eq!(event.duration_minutes, Some(90));
+
+        assert_eq!(rows[1].errors, vec!["Title is required"]);
+        assert_eq!(rows[2].row, 4);
+        assert_eq!(rows[2].errors, vec!["Invalid all_day: maybe"]);
+    }
+
+    #[test]
+    fn repeated_rows_of_the_file_are_refused() {
+        let csv = "title,date\n\
+                   Worship,2025-03-02\n\
+                   worship,2025-03-02\n\
+                   Worship,2025-03-09\n\
+                   Potluck,2025-03-02\n";
+        let mut rows = parse_csv(csv.as_bytes());
+        let existing = HashMap::from([(("potluck".to_string(), NaiveDate::from_ymd_opt(2025, 3, 2).unwrap()), 7)]);
+        mark_repeated(&mut rows, &existing);
+
+        assert!(rows[0].event.is_some());
+        assert!(rows[1].event.is_none());
+        assert_eq!(rows[1].errors, vec!["Same title and date as row 2"]);
+        assert!(rows[2].event.is_some());
+        assert_eq!(rows[3].duplicate_of, Some(7));
+    }
+}
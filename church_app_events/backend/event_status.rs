@@ -0,0 +1,551 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{NaiveDate, NaiveTime};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+use std::env;
+
+use crate::checkin;
+use crate::email;
+use crate::eventrsvp;
+use crate::events::{self, Event};
+use crate::waitlist;
+
+// Cancelling and rescheduling events. The event is kept with its new status
+// and the reason given, so its RSVPs stay attached, and everyone who has not
+// declined is emailed with an updated .ics. After a reschedule attendees are
+// asked whether they can still come, through signed links in the email;
+// answering no declines the RSVP and frees the seat for the waitlist.
+// Occurrences of a recurring event are cancelled and rescheduled one at a
+// time, their attendees told the same way.
+
+pub const CANCELLED: &str = "cancelled";
+pub const RESCHEDULED: &str = "rescheduled";
+
+const MAX_REASON_LENGTH: usize = 1000;
+
+// Signs the RSVP ids of reconfirmation links
+const RECONFIRM_PURPOSE: &str = "reconfirm";
+
+#[derive(Debug, Deserialize)]
+pub struct CancelRequest {
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RescheduleRequest {
+    event_date: NaiveDate,
+    event_time: Option<NaiveTime>, // Keeps the time when omitted
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReconfirmRequest {
+    token: String,
+    attending: bool,
+}
+
+// Someone who RSVP'd and has not declined
+#[derive(Debug, FromRow)]
+struct Attendee {
+    id: i32,
+    email: String,
+}
+
+fn check_reason(reason: &str) -> Result<String, String> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        return Err("Give a reason, it is sent to attendees".to_string());
+    }
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(format!("The reason is longer than {} characters", MAX_REASON_LENGTH));
+    }
+    Ok(reason.to_string())
+}
+
+// When something started, as told to attendees
+fn describe_start(event: &Event, (date, time): (NaiveDate, NaiveTime)) -> String {
+    if event.all_day {
+        date.to_string()
+    } else {
+        format!("{} at {}", date, time.format("%H:%M"))
+    }
+}
+
+/// Link in the reschedule email answering whether the attendee can still come
+pub fn reconfirm_link(rsvp_id: i32, attending: bool) -> String {
+    let url = env::var("RSVP_RECONFIRM_URL").expect("RSVP_RECONFIRM_URL must be set");
+    format!(
+        "{}?token={}&attending={}",
+        url,
+        checkin::signed_token(RECONFIRM_PURPOSE, rsvp_id),
+        attending
+    )
+}
+
+// Look up an event for a status change; the error is the response to give
+async fn find_event(pool: &PgPool, event_id: i32) -> Result<Event, HttpResponse> {
+    match events::fetch_event(pool, event_id).await {
+        Ok(Some(event)) => Ok(event),
+        Ok(None) => Err(HttpResponse::NotFound().json(json!({ "message": "Event not found" }))),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            Err(HttpResponse::InternalServerError().json(json!({ "message": "Failed to fetch event" })))
+        }
+    }
+}
+
+// The event as it is now, with how many attendees are being emailed
+async fn status_response(pool: &PgPool, event_id: i32, message: &str, notified: usize) -> HttpResponse {
+    match events::fetch_event(pool, event_id).await {
+        Ok(event) => HttpResponse::Ok().json(json!({
+            "message": message,
+            "event": event,
+            "notified": notified,
+        })),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            HttpResponse::InternalServerError().json(json!({ "message": "Failed to fetch event" }))
+        }
+    }
+}
+
+// Cancel an event and tell everyone who RSVP'd
+pub async fn cancel_event(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+    request: web::Json<CancelRequest>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+
+    let reason = match check_reason(&request.reason) {
+        Ok(reason) => reason,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+    };
+
+    let event = match find_event(pool.get_ref(), event_id).await {
+        Ok(event) => event,
+        Err(response) => return response,
+    };
+    if event.status == CANCELLED {
+        return HttpResponse::Conflict().json(json!({ "message": "The event is already cancelled" }));
+    }
+
+    let result: Result<Option<Vec<Attendee>>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        let cancelled = sqlx::query(
+            "UPDATE events
+             SET status = $1, status_reason = $2, status_changed_at = NOW(), updated_at = NOW()
+             WHERE id = $3 AND status <> $1"
+        )
+        .bind(CANCELLED)
+        .bind(&reason)
+        .bind(event_id)
+        .execute(&mut tx)
+        .await?;
+        if cancelled.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        // Nothing is left to reconfirm
+        let attendees = sqlx::query_as::<_, Attendee>(
+            "UPDATE eventrsvp SET reconfirm_requested_at = NULL
+             WHERE event_id = $1 AND rsvp_status <> 'declined'
+             RETURNING id, email"
+        )
+        .bind(event_id)
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(attendees))
+    }
+    .await;
+
+    let attendees = match result {
+        Ok(Some(attendees)) => attendees,
+        Ok(None) => {
+            return HttpResponse::Conflict().json(json!({ "message": "The event is already cancelled" }));
+        }
+        Err(e) => {
+            eprintln!("Failed to cancel event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to cancel event" }));
+        }
+    };
+
+    let notified = attendees.len();
+    for attendee in attendees {
+        let pool = pool.get_ref().clone();
+        let reason = reason.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) =
+                email::send_event_cancelled_email(&pool, attendee.id, &attendee.email, event_id, &reason).await
+            {
+                eprintln!("Failed to send cancellation email to {}: {}", attendee.email, e);
+            }
+        });
+    }
+
+    status_response(pool.get_ref(), event_id, "Event cancelled", notified).await
+}
+
+// Cancel one occurrence of a recurring event and tell everyone who RSVP'd to it
+pub async fn cancel_occurrence(
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, NaiveDate)>,
+    request: web::Json<CancelRequest>,
+) -> impl Responder {
+    let (event_id, occurrence_date) = path.into_inner();
+
+    let reason = match check_reason(&request.reason) {
+        Ok(reason) => reason,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+    };
+
+    let event = match find_event(pool.get_ref(), event_id).await {
+        Ok(event) => event,
+        Err(response) => return response,
+    };
+    if event.recurrence_exceptions.contains(&occurrence_date) {
+        return HttpResponse::Conflict().json(json!({ "message": "The occurrence is already cancelled" }));
+    }
+    if !events::is_occurrence(&event, occurrence_date) {
+        return HttpResponse::BadRequest().json(json!({ "message": "The event has no occurrence on this date" }));
+    }
+
+    let result: Result<Option<Vec<Attendee>>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        // No RSVP for the occurrence arrives while it is cancelled
+        waitlist::lock_event(&mut tx, event_id).await?;
+
+        let cancelled = sqlx::query(
+            "UPDATE events
+             SET recurrence_exceptions = array_append(recurrence_exceptions, $1), updated_at = NOW()
+             WHERE id = $2 AND NOT ($1 = ANY(recurrence_exceptions))"
+        )
+        .bind(occurrence_date)
+        .bind(event_id)
+        .execute(&mut tx)
+        .await?;
+        if cancelled.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let attendees = sqlx::query_as::<_, Attendee>(
+            "UPDATE eventrsvp SET reconfirm_requested_at = NULL
+             WHERE event_id = $1 AND occurrence_date = $2 AND rsvp_status <> 'declined'
+             RETURNING id, email"
+        )
+        .bind(event_id)
+        .bind(occurrence_date)
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(attendees))
+    }
+    .await;
+
+    let attendees = match result {
+        Ok(Some(attendees)) => attendees,
+        Ok(None) => {
+            return HttpResponse::Conflict().json(json!({ "message": "The occurrence is already cancelled" }));
+        }
+        Err(e) => {
+            eprintln!("Failed to cancel occurrence {} of event {}: {}", occurrence_date, event_id, e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to cancel occurrence" }));
+        }
+    };
+
+    let notified = attendees.len();
+    for attendee in attendees {
+        let pool = pool.get_ref().clone();
+        let reason = reason.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) =
+                email::send_event_cancelled_email(&pool, attendee.id, &attendee.email, event_id, &reason).await
+            {
+                eprintln!("Failed to send cancellation email to {}: {}", attendee.email, e);
+            }
+        });
+    }
+
+    status_response(pool.get_ref(), event_id, "Occurrence cancelled", notified).await
+}
+
+// Move a one-off event to another date or time, tell everyone who RSVP'd
+// and ask them to confirm they can still come
+pub async fn reschedule_event(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+    request: web::Json<RescheduleRequest>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+
+    let reason = match check_reason(&request.reason) {
+        Ok(reason) => reason,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+    };
+
+    let event = match find_event(pool.get_ref(), event_id).await {
+        Ok(event) => event,
+        Err(response) => return response,
+    };
+    if event.recurrence_rule.is_some() {
+        return HttpResponse::BadRequest().json(json!({
+            "message": "Occurrences of a recurring event are rescheduled one at a time"
+        }));
+    }
+    if event.status == CANCELLED {
+        return HttpResponse::Conflict().json(json!({ "message": "A cancelled event cannot be rescheduled" }));
+    }
+
+    let event_time = request.event_time.unwrap_or(event.event_time);
+    if request.event_date == event.event_date && event_time == event.event_time {
+        return HttpResponse::BadRequest().json(json!({ "message": "The event is already at this date and time" }));
+    }
+
+    let previous = describe_start(&event, (event.event_date, event.event_time));
+
+    let result: Result<Option<Vec<Attendee>>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        // The event may have been cancelled since it was read
+        let rescheduled = sqlx::query(
+            "UPDATE events
+             SET event_date = $1, event_time = $2, status = $3, status_reason = $4,
+                 status_changed_at = NOW(), updated_at = NOW()
+             WHERE id = $5 AND status <> $6"
+        )
+        .bind(request.event_date)
+        .bind(event_time)
+        .bind(RESCHEDULED)
+        .bind(&reason)
+        .bind(event_id)
+        .bind(CANCELLED)
+        .execute(&mut tx)
+        .await?;
+        if rescheduled.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        // Attendees keep their seats until they answer
+        let attendees = sqlx::query_as::<_, Attendee>(
+            "UPDATE eventrsvp SET reconfirm_requested_at = NOW(), reconfirmed_at = NULL
+             WHERE event_id = $1 AND rsvp_status <> 'declined'
+             RETURNING id, email"
+        )
+        .bind(event_id)
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(attendees))
+    }
+    .await;
+
+    let attendees = match result {
+        Ok(Some(attendees)) => attendees,
+        Ok(None) => {
+            return HttpResponse::Conflict().json(json!({ "message": "A cancelled event cannot be rescheduled" }));
+        }
+        Err(e) => {
+            eprintln!("Failed to reschedule event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to reschedule event" }));
+        }
+    };
+
+    let notified = attendees.len();
+    for attendee in attendees {
+        let pool = pool.get_ref().clone();
+        let reason = reason.clone();
+        let previous = previous.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = email::send_event_rescheduled_email(
+                &pool,
+                attendee.id,
+                &attendee.email,
+                event_id,
+                &reason,
+                &previous,
+            )
+            .await
+            {
+                eprintln!("Failed to send reschedule email to {}: {}", attendee.email, e);
+            }
+        });
+    }
+
+    status_response(pool.get_ref(), event_id, "Event rescheduled", notified).await
+}
+
+// Move one occurrence of a recurring event, tell everyone who RSVP'd to it
+// and ask them to confirm they can still come
+pub async fn reschedule_occurrence(
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, NaiveDate)>,
+    request: web::Json<RescheduleRequest>,
+) -> impl Responder {
+    let (event_id, occurrence_date) = path.into_inner();
+
+    let reason = match check_reason(&request.reason) {
+        Ok(reason) => reason,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+    };
+
+    let event = match find_event(pool.get_ref(), event_id).await {
+        Ok(event) => event,
+        Err(response) => return response,
+    };
+    if event.status == CANCELLED {
+        return HttpResponse::Conflict().json(json!({ "message": "A cancelled event cannot be rescheduled" }));
+    }
+    if !events::is_occurrence(&event, occurrence_date) {
+        return HttpResponse::BadRequest().json(json!({ "message": "The event has no occurrence on this date" }));
+    }
+
+    // The start before the change, read with the event locked
+    let result: Result<Result<(String, Vec<Attendee>), &str>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        waitlist::lock_event(&mut tx, event_id).await?;
+
+        let current = events::fetch_override(&mut tx, event_id, occurrence_date).await?;
+        let previous = events::occurrence_start(&event, occurrence_date, current.as_ref());
+        let start = (request.event_date, request.event_time.unwrap_or(previous.1));
+        if start == previous {
+            return Ok(Err("The occurrence is already at this date and time"));
+        }
+
+        sqlx::query(
+            "INSERT INTO event_occurrence_overrides (event_id, occurrence_date, event_date, event_time)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (event_id, occurrence_date) DO UPDATE SET
+                event_date = EXCLUDED.event_date,
+                event_time = EXCLUDED.event_time,
+                updated_at = NOW()"
+        )
+        .bind(event_id)
+        .bind(occurrence_date)
+        .bind(start.0)
+        .bind(start.1)
+        .execute(&mut tx)
+        .await?;
+
+        // Attendees keep their seats until they answer
+        let attendees = sqlx::query_as::<_, Attendee>(
+            "UPDATE eventrsvp SET reconfirm_requested_at = NOW(), reconfirmed_at = NULL
+             WHERE event_id = $1 AND occurrence_date = $2 AND rsvp_status <> 'declined'
+             RETURNING id, email"
+        )
+        .bind(event_id)
+        .bind(occurrence_date)
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Ok((describe_start(&event, previous), attendees)))
+    }
+    .await;
+
+    let (previous, attendees) = match result {
+        Ok(Ok(rescheduled)) => rescheduled,
+        Ok(Err(e)) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+        Err(e) => {
+            eprintln!("Failed to reschedule occurrence {} of event {}: {}", occurrence_date, event_id, e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to reschedule occurrence" }));
+        }
+    };
+
+    let notified = attendees.len();
+    for attendee in attendees {
+        let pool = pool.get_ref().clone();
+        let reason = reason.clone();
+        let previous = previous.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = email::send_event_rescheduled_email(
+                &pool,
+                attendee.id,
+                &attendee.email,
+                event_id,
+                &reason,
+                &previous,
+            )
+            .await
+            {
+                eprintln!("Failed to send reschedule email to {}: {}", attendee.email, e);
+            }
+        });
+    }
+
+    status_response(pool.get_ref(), event_id, "Occurrence rescheduled", notified).await
+}
+
+// Answer a reschedule email: still coming, or decline the RSVP
+pub async fn reconfirm_rsvp(
+    pool: web::Data<PgPool>,
+    request: web::Json<ReconfirmRequest>,
+) -> impl Responder {
+    let rsvp_id = match checkin::verify_signed(RECONFIRM_PURPOSE, &request.token) {
+        Some(rsvp_id) => rsvp_id,
+        None => return HttpResponse::BadRequest().json(json!({ "message": "Invalid link" })),
+    };
+
+    let requested = sqlx::query_scalar::<_, bool>(
+        "SELECT reconfirm_requested_at IS NOT NULL FROM eventrsvp WHERE id = $1"
+    )
+    .bind(rsvp_id)
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match requested {
+        Ok(Some(true)) => {}
+        Ok(Some(false)) => {
+            return HttpResponse::Conflict().json(json!({
+                "message": "This RSVP has already been answered or needs no answer"
+            }));
+        }
+        Ok(None) => return HttpResponse::NotFound().json(json!({ "message": "RSVP not found" })),
+        Err(e) => {
+            eprintln!("Failed to fetch RSVP {}: {}", rsvp_id, e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to update RSVP" }));
+        }
+    }
+
+    if request.attending {
+        let result = sqlx::query(
+            "UPDATE eventrsvp SET reconfirm_requested_at = NULL, reconfirmed_at = NOW() WHERE id = $1"
+        )
+        .bind(rsvp_id)
+        .execute(pool.get_ref())
+        .await;
+
+        return match result {
+            Ok(_) => HttpResponse::Ok().json(json!({ "message": "Thank you, see you there!" })),
+            Err(e) => {
+                eprintln!("Failed to reconfirm RSVP {}: {}", rsvp_id, e);
+                HttpResponse::InternalServerError().json(json!({ "message": "Failed to update RSVP" }))
+            }
+        };
+    }
+
+    let declined = match eventrsvp::decline(pool.get_ref(), rsvp_id).await {
+        Ok(true) => {
+            sqlx::query("UPDATE eventrsvp SET reconfirm_requested_at = NULL WHERE id = $1")
+                .bind(rsvp_id)
+                .execute(pool.get_ref())
+                .await
+                .map(|_| true)
+        }
+        other => other,
+    };
+
+    match declined {
+        Ok(true) => HttpResponse::Ok().json(json!({ "message": "Your RSVP has been declined" })),
+        Ok(false) => HttpResponse::NotFound().json(json!({ "message": "RSVP not found" })),
+        Err(e) => {
+            eprintln!("Failed to decline RSVP {}: {}", rsvp_id, e);
+            HttpResponse::InternalServerError().json(json!({ "message": "Failed to update RSVP" }))
+        }
+    }
+}
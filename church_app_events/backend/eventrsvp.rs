@@ -2,9 +2,20 @@ use std::collections::HashMap;
 
 use actix_web::{web::{self}, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, FromRow, Row};
-use chrono::{NaiveDate, NaiveTime};
-use serde_json::json;
+use sqlx::{postgres::PgRow, PgPool, FromRow, Postgres, QueryBuilder, Row};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::event_status;
+use crate::events;
+use crate::export::{self, Column, ExportParams};
+use crate::pagination::{Listing, Page, PageParams, SortField, Sorting};
+use crate::registration_form;
+use crate::user::Claims;
+use crate::waitlist::{self, Promotion};
+
+// Adults and children in one RSVP, each taking a seat
+const MAX_PARTY_SIZE: i32 = 20;
 
 
 // Define RSVP status enum
@@ -46,6 +57,25 @@ pub struct EventRSVPRequest {
     event_id: i32,
     user_id: Option<i32>,
     rsvp_status: ServingStatusType,
+    occurrence_date: Option<NaiveDate>, // Required for recurring events
+    #[serde(default = "default_adults")]
+    adults: i32,
+    #[serde(default)]
+    children: i32,
+    #[serde(default)]
+    answers: Map<String, Value>, // Answers to the event's questions, by question id
+}
+
+fn default_adults() -> i32 {
+    1
+}
+
+// Change the party size or answers of an RSVP
+#[derive(Deserialize)]
+pub struct RegistrationRequest {
+    adults: Option<i32>,
+    children: Option<i32>,
+    answers: Option<Map<String, Value>>, // Replaces all answers
 }
 
 #[derive(Serialize)]
@@ -56,6 +86,11 @@ pub struct RSVPResponse {
     user_id: Option<i32>,
     rsvp_status: ServingStatusType,
     rsvp_date: NaiveDate,
+    occurrence_date: Option<NaiveDate>,
+    adults: i32,
+    children: i32,
+    answers: Value,
+    waitlist_position: Option<i64>, // Set while the event is full
 }
 
 // Add these response structs
@@ -67,6 +102,7 @@ pub struct RSVPListResponse {
     user_id: Option<i32>,
     rsvp_status: ServingStatusType,
     rsvp_date: NaiveDate,
+    occurrence_date: Option<NaiveDate>,
 }
 
 #[derive(Serialize)]
@@ -77,11 +113,97 @@ pub struct RSVPWithEventResponse {
     user_id: Option<i32>,
     rsvp_status: ServingStatusType,
     rsvp_date: NaiveDate,
+    occurrence_date: Option<NaiveDate>,
+    adults: i32,
+    children: i32,
+    answers: Value,
     event_title: String,
     event_date: NaiveDate,
     event_time: NaiveTime,
 }
 
+impl<'r> FromRow<'r, PgRow> for RSVPWithEventResponse {
+    fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
+        Ok(RSVPWithEventResponse {
+            id: row.try_get("id")?,
+            email: row.try_get("email")?,
+            event_id: row.try_get("event_id")?,
+            user_id: row.try_get("user_id")?,
+            rsvp_status: row.try_get("rsvp_status")?,
+            rsvp_date: row.try_get("rsvp_date")?,
+            occurrence_date: row.try_get("occurrence_date")?,
+            adults: row.try_get("adults")?,
+            children: row.try_get("children")?,
+            answers: row.try_get("answers")?,
+            event_title: row.try_get("event_title")?,
+            event_date: row.try_get("event_date")?,
+            event_time: row.try_get("event_time")?,
+        })
+    }
+}
+
+const RSVP_COLUMNS: &str = "r.id, r.email, r.event_id, r.user_id, r.rsvp_status, \
+     r.rsvp_date, r.occurrence_date, r.adults, r.children, r.answers, e.event_title, \
+     COALESCE(r.occurrence_date, e.event_date) AS event_date, e.event_time";
+
+// Sorts of RSVP listings, latest occurrence first by default
+static RSVP_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("event_date", "COALESCE(r.occurrence_date, e.event_date) + e.event_time", "TIMESTAMP"),
+        SortField::new("rsvp_date", "r.rsvp_date", "DATE"),
+        SortField::new("email", "LOWER(r.email)", "TEXT"),
+        SortField::new("status", "r.rsvp_status::TEXT", "TEXT"),
+        SortField::new("event_title", "LOWER(e.event_title)", "TEXT"),
+    ],
+    descending: true,
+    id: "r.id",
+};
+
+// A page of the RSVPs passing `filter`, on eventrsvp r joined with events e
+async fn fetch_rsvps(
+    pool: &PgPool,
+    page: &Page,
+    filter: impl Fn(&mut QueryBuilder<'_, Postgres>),
+) -> Result<Listing<RSVPWithEventResponse>, sqlx::Error> {
+    let mut count_query = QueryBuilder::new(
+        "SELECT COUNT(*) FROM eventrsvp r JOIN events e ON r.event_id = e.id WHERE 1=1"
+    );
+    filter(&mut count_query);
+    let (total,): (i64,) = count_query.build_query_as().fetch_one(pool).await?;
+
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {}, {} FROM eventrsvp r JOIN events e ON r.event_id = e.id WHERE 1=1",
+        RSVP_COLUMNS,
+        page.key_columns()
+    ));
+    filter(&mut query);
+    page.push_to(&mut query);
+
+    let rows = query.build().fetch_all(pool).await?;
+    page.finish(rows, total, |row| RSVPWithEventResponse::from_row(row))
+}
+
+// RSVPs passing `filter` per status, across all pages
+async fn count_by_status(
+    pool: &PgPool,
+    filter: impl Fn(&mut QueryBuilder<'_, Postgres>),
+) -> Result<HashMap<String, i64>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT r.rsvp_status::TEXT, COUNT(*) FROM eventrsvp r JOIN events e ON r.event_id = e.id WHERE 1=1"
+    );
+    filter(&mut query);
+    query.push(" GROUP BY r.rsvp_status");
+
+    let mut status_counts: HashMap<String, i64> = ["confirmed", "pending", "declined"]
+        .iter()
+        .map(|status| (status.to_string(), 0))
+        .collect();
+    for (status, count) in query.build_query_as::<(String, i64)>().fetch_all(pool).await? {
+        status_counts.insert(status, count);
+    }
+    Ok(status_counts)
+}
+
 // Add this response struct for search results
 #[derive(Serialize, FromRow, Clone)]
 pub struct RSVPSearchResponse {
@@ -91,6 +213,7 @@ pub struct RSVPSearchResponse {
     user_id: Option<i32>,
     rsvp_status: ServingStatusType,
     rsvp_date: NaiveDate,
+    occurrence_date: Option<NaiveDate>,
     event_title: String,
     event_date: NaiveDate,
     event_time: NaiveTime,
@@ -104,127 +227,301 @@ pub struct SearchResponse {
     status_counts: HashMap<String, usize>,
 }
 
+// Number of seats a party takes
+pub(crate) fn party_size(adults: i32, children: i32) -> Result<i32, String> {
+    if adults < 0 || children < 0 {
+        return Err("The number of adults and children cannot be negative".to_string());
+    }
+    let too_many = || format!("An RSVP is for at most {} people", MAX_PARTY_SIZE);
+    if adults > MAX_PARTY_SIZE || children > MAX_PARTY_SIZE {
+        return Err(too_many());
+    }
+    match adults.checked_add(children) {
+        Some(0) => Err("An RSVP is for at least one person".to_string()),
+        Some(size) if size <= MAX_PARTY_SIZE => Ok(size),
+        _ => Err(too_many()),
+    }
+}
+
+// Answers checked against the event's questions, or the response to send
+async fn checked_answers(pool: &PgPool, event_id: i32, answers: &Map<String, Value>) -> Result<Value, HttpResponse> {
+    let form = match registration_form::fetch_form(pool, event_id).await {
+        Ok(Some(form)) => form,
+        Ok(None) => return Err(HttpResponse::NotFound().json("Event not found")),
+        Err(e) => {
+            eprintln!("Failed to fetch questions of event {}: {}", event_id, e);
+            return Err(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    registration_form::check_answers(&form, answers).map_err(|errors| {
+        HttpResponse::BadRequest().json(json!({
+            "message": "Some answers are missing or invalid",
+            "errors": errors
+        }))
+    })
+}
+
 // Create a new RSVP
 pub async fn create_rsvp(
     pool: web::Data<PgPool>,
     rsvp_data: web::Json<EventRSVPRequest>) -> impl Responder {
-        // Check if the event exists for this email and event
-        let existing = sqlx::query!(
-            "SELECT user_id FROM EventRSVP WHERE email = $1 AND event_id = $2",
-            rsvp_data.email,
-            rsvp_data.event_id
-        )
-        .fetch_optional(pool.get_ref())
-        .await;
+        // RSVPs to a recurring event are for one of its occurrences
+        let event = match events::fetch_event(pool.get_ref(), rsvp_data.event_id).await {
+            Ok(Some(event)) => event,
+            Ok(None) => return HttpResponse::NotFound().json("Event not found"),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json("Internal server error");
+            }
+        };
+        if event.status == event_status::CANCELLED {
+            return HttpResponse::Conflict().json("This event has been cancelled");
+        }
 
-    match existing {
-        Ok(Some(_)) => {
-            HttpResponse::BadRequest().json("This email has already RSVP'd to this event")
+        match (&event.recurrence_rule, rsvp_data.occurrence_date) {
+            (None, Some(_)) => {
+                return HttpResponse::BadRequest().json("This event does not repeat, omit occurrence_date");
+            }
+            (Some(_), None) => {
+                return HttpResponse::BadRequest().json("occurrence_date is required for a recurring event");
+            }
+            (Some(_), Some(date)) if !events::is_occurrence(&event, date) => {
+                return HttpResponse::BadRequest().json("The event has no occurrence on this date");
+            }
+            _ => {}
         }
-        Ok(None) => {
+
+        let party = match party_size(rsvp_data.adults, rsvp_data.children) {
+            Ok(party) => party,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        };
+        if let Some(capacity) = event.capacity.filter(|capacity| party > *capacity) {
+            return HttpResponse::BadRequest().json(format!(
+                "A party of {} does not fit in this event's {} seats", party, capacity
+            ));
+        }
+
+        let answers = match checked_answers(pool.get_ref(), rsvp_data.event_id, &rsvp_data.answers).await {
+            Ok(answers) => answers,
+            Err(response) => return response,
+        };
+
+        // Seats are counted with the event locked, so concurrent RSVPs cannot overbook
+        let result: Result<Option<_>, sqlx::Error> = async {
+            let mut tx = pool.begin().await?;
+            let capacity = waitlist::lock_event(&mut tx, rsvp_data.event_id).await?.flatten();
+
+            // Check if the event exists for this email and event
+            let existing = sqlx::query!(
+                "SELECT user_id FROM EventRSVP WHERE email = $1 AND event_id = $2
+                 AND occurrence_date IS NOT DISTINCT FROM $3",
+                rsvp_data.email,
+                rsvp_data.event_id,
+                rsvp_data.occurrence_date
+            )
+            .fetch_optional(&mut tx)
+            .await?;
+
+            if existing.is_some() {
+                return Ok(None);
+            }
+
             // Determine initial status based on the request
             let initial_status = match rsvp_data.rsvp_status {
                 ServingStatusType::Declined => ServingStatusType::Declined,
                 _ => ServingStatusType::Pending
             };
 
-            let result = sqlx::query!(
+            // Join the waitlist when the party does not fit in the seats left
+            let waitlisted = initial_status != ServingStatusType::Declined
+                && !waitlist::has_seats_for(&mut tx, rsvp_data.event_id, rsvp_data.occurrence_date, capacity, party).await?;
+
+            let rsvp = sqlx::query!(
                 r#"
-                INSERT INTO eventrsvp (email, event_id, user_id, rsvp_date, rsvp_status)
-                VALUES ($1, $2, $3, CURRENT_DATE, $4)
-                RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date
+                INSERT INTO eventrsvp (email, event_id, user_id, rsvp_date, rsvp_status, occurrence_date, waitlisted_at,
+                    adults, children, answers)
+                VALUES ($1, $2, $3, CURRENT_DATE, $4, $5, CASE WHEN $6 THEN NOW() END, $7, $8, $9)
+                RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date, occurrence_date,
+                    adults, children, answers
                 "#,
                 rsvp_data.email,
                 rsvp_data.event_id,
                 rsvp_data.user_id,
                 initial_status as ServingStatusType,
+                rsvp_data.occurrence_date,
+                waitlisted,
+                rsvp_data.adults,
+                rsvp_data.children,
+                answers,
             )
-            .fetch_one(pool.get_ref())
-            .await;
-
-
-            match result {
-                Ok(rsvp) => HttpResponse::Ok().json(RSVPResponse {
-                    id: rsvp.id,
-                    email: rsvp.email,
-                    event_id: rsvp.event_id,
-                    user_id: rsvp.user_id,
-                    rsvp_status: rsvp.rsvp_status,
-                    rsvp_date: rsvp.rsvp_date,
-                }),
-                Err(e) =>
-                {
-                    eprintln!("Failed to create RSVP: {}", e);
-                    HttpResponse::InternalServerError().json("Failed to insert RSVP")
-                },
-            }
+            .fetch_one(&mut tx)
+            .await?;
+
+            tx.commit().await?;
+            Ok(Some(rsvp))
+        }
+        .await;
+
+    match result {
+        Ok(Some(rsvp)) => {
+            let waitlist_position = waitlist::position(pool.get_ref(), rsvp.id).await.unwrap_or_else(|e| {
+                eprintln!("Failed to get waitlist position of RSVP {}: {}", rsvp.id, e);
+                None
+            });
+
+            HttpResponse::Ok().json(RSVPResponse {
+                id: rsvp.id,
+                email: rsvp.email,
+                event_id: rsvp.event_id,
+                user_id: rsvp.user_id,
+                rsvp_status: rsvp.rsvp_status,
+                rsvp_date: rsvp.rsvp_date,
+                occurrence_date: rsvp.occurrence_date,
+                adults: rsvp.adults,
+                children: rsvp.children,
+                answers: rsvp.answers,
+                waitlist_position,
+            })
+        }
+        Ok(None) => {
+            HttpResponse::BadRequest().json("This email has already RSVP'd to this event")
         }
         Err(e) => {
-            eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json("Internal server error")
+            eprintln!("Failed to create RSVP: {}", e);
+            HttpResponse::InternalServerError().json("Failed to insert RSVP")
         }
     }
 }
 
+enum StatusChange {
+    NotFound,
+    Waitlisted,
+    Cancelled,
+    Changed(RSVPResponse, Vec<Promotion>),
+}
+
+// Change the status of an RSVP with its event locked. A decline frees the
+// seat for the first in line on the waitlist; taking part again after a
+// decline needs a free seat or joins the end of the waitlist. RSVPs on the
+// waitlist stay pending until they get a seat.
+async fn change_status(
+    pool: &PgPool,
+    rsvp_id: i32,
+    status: ServingStatusType,
+) -> Result<StatusChange, sqlx::Error> {
+    let event_id = match sqlx::query_scalar::<_, i32>("SELECT event_id FROM eventrsvp WHERE id = $1")
+        .bind(rsvp_id)
+        .fetch_optional(pool)
+        .await?
+    {
+        Some(event_id) => event_id,
+        None => return Ok(StatusChange::NotFound),
+    };
+
+    let mut tx = pool.begin().await?;
+    let capacity = waitlist::lock_event(&mut tx, event_id).await?.flatten();
+
+    let current = sqlx::query_as::<_, (Option<NaiveDate>, ServingStatusType, Option<NaiveDateTime>, i32)>(
+        "SELECT occurrence_date, rsvp_status, waitlisted_at, adults + children FROM eventrsvp WHERE id = $1"
+    )
+    .bind(rsvp_id)
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (occurrence_date, current_status, waitlisted_at, party) = match current {
+        Some(current) => current,
+        None => return Ok(StatusChange::NotFound),
+    };
+
+    // Only declining is left once the event is cancelled
+    if status != ServingStatusType::Declined && waitlist::is_cancelled(&mut tx, event_id).await? {
+        return Ok(StatusChange::Cancelled);
+    }
+
+    let held_seat = current_status != ServingStatusType::Declined && waitlisted_at.is_none();
+
+    let waitlisted_at = if status == ServingStatusType::Declined {
+        None
+    } else if current_status != ServingStatusType::Declined {
+        // Keeps its seat or its place in line
+        waitlisted_at
+    } else if waitlist::has_seats_for(&mut tx, event_id, occurrence_date, capacity, party).await? {
+        None
+    } else {
+        Some(Utc::now().naive_utc())
+    };
+
+    let status = match (waitlisted_at, status) {
+        (Some(_), ServingStatusType::Confirmed) if current_status != ServingStatusType::Declined => {
+            return Ok(StatusChange::Waitlisted);
+        }
+        (Some(_), _) => ServingStatusType::Pending,
+        (None, status) => status,
+    };
+
+    let rsvp = sqlx::query!(
+        r#"
+        UPDATE eventrsvp
+        SET rsvp_status = $1, waitlisted_at = $2
+        WHERE id = $3
+        RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date, occurrence_date,
+            adults, children, answers
+        "#,
+        status.clone() as ServingStatusType,
+        waitlisted_at,
+        rsvp_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let promotions = if held_seat && status == ServingStatusType::Declined {
+        waitlist::promote(&mut tx, event_id, occurrence_date, capacity).await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await?;
+
+    let waitlist_position = waitlist::position(pool, rsvp_id).await?;
+
+    Ok(StatusChange::Changed(
+        RSVPResponse {
+            id: rsvp.id,
+            email: rsvp.email,
+            event_id: rsvp.event_id,
+            user_id: rsvp.user_id,
+            rsvp_status: rsvp.rsvp_status,
+            rsvp_date: rsvp.rsvp_date,
+            occurrence_date: rsvp.occurrence_date,
+            adults: rsvp.adults,
+            children: rsvp.children,
+            answers: rsvp.answers,
+            waitlist_position,
+        },
+        promotions,
+    ))
+}
+
 // Get all RSVPs with event details
 pub async fn get_all_rsvps(
     pool: web::Data<PgPool>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        SELECT r.id, r.email, r.event_id, r.user_id,
-               r.rsvp_status as "rsvp_status!: ServingStatusType",
-               r.rsvp_date,
-               e.event_title, e.event_date, e.event_time
-        FROM eventrsvp r
-        JOIN events e ON r.event_id = e.id
-        ORDER BY e.event_date DESC, e.event_time DESC
-        "#
-    )
-    .fetch_all(pool.get_ref())
+    let page = match Page::new(&page, &RSVP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        let rsvps = fetch_rsvps(pool.get_ref(), &page, |_| {}).await?;
+        let status_counts = count_by_status(pool.get_ref(), |_| {}).await?;
+        Ok::<_, sqlx::Error>(rsvps.with("status_counts", json!(status_counts)))
+    }
     .await;
 
     match result {
-        Ok(rsvps) => {
-            // Calculate status counts
-            let status_counts = HashMap::from([
-                ("confirmed".to_string(),
-                    rsvps.iter()
-                    .filter(|r| r.rsvp_status == ServingStatusType::Confirmed)
-                    .count()),
-                ("pending".to_string(),
-                    rsvps.iter()
-                    .filter(|r| r.rsvp_status == ServingStatusType::Pending)
-                    .count()),
-                ("declined".to_string(),
-                    rsvps.iter()
-                    .filter(|r| r.rsvp_status == ServingStatusType::Declined)
-                    .count()),
-            ]);
-
-            let converted_rsvps: Vec<RSVPWithEventResponse> = rsvps
-                .iter()
-                .map(|r| RSVPWithEventResponse {
-                    id: r.id,
-                    email: r.email.clone(),
-                    event_id: r.event_id,
-                    user_id: r.user_id,
-                    rsvp_status: r.rsvp_status.clone(),
-                    rsvp_date: r.rsvp_date,
-                    event_title: r.event_title.clone(),
-                    event_date: r.event_date,
-                    event_time: r.event_time.clone(),
-                })
-                .collect();
-
-            let response = serde_json::json!({
-                "rsvps": converted_rsvps,
-                "total": rsvps.len(),
-                "status_counts": status_counts,
-            });
-
-            HttpResponse::Ok().json(response)
-        }
+        Ok(rsvps) => HttpResponse::Ok().json(rsvps),
         Err(e) => {
             eprintln!("Failed to fetch RSVPs: {}", e);
             HttpResponse::InternalServerError().json("Failed to fetch RSVPs")
@@ -232,61 +529,76 @@ pub async fn get_all_rsvps(
     }
 }
 
-// Get RSVPs for a specific event with counts
+// Get RSVPs for a specific event with counts and the event's questions,
+// so answers can be shown under their labels
 pub async fn get_rsvps_by_event(
     pool: web::Data<PgPool>,
-    event_id: web::Path<i32>
+    event_id: web::Path<i32>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query!(
+    let event_id = event_id.into_inner();
+    let page = match Page::new(&page, &RSVP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let questions = match registration_form::fetch_form(pool.get_ref(), event_id).await {
+        Ok(questions) => questions.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Failed to fetch questions of event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch RSVPs");
+        }
+    };
+
+    let counts = sqlx::query!(
         r#"
-        WITH status_counts AS (
-            SELECT
-                COUNT(CASE WHEN rsvp_status = 'confirmed' THEN 1 END) as confirmed_count,
-                COUNT(CASE WHEN rsvp_status = 'pending' THEN 1 END) as pending_count,
-                COUNT(CASE WHEN rsvp_status = 'declined' THEN 1 END) as declined_count
-            FROM eventrsvp
-            WHERE event_id = $1
-        )
-        SELECT er.id, er.email, er.event_id, er.user_id,
-               er.rsvp_status as "rsvp_status!: ServingStatusType", er.rsvp_date,
-               e.event_title, e.event_date, e.event_time,
-               sc.confirmed_count, sc.pending_count, sc.declined_count
-        FROM eventrsvp er
-        JOIN events e ON er.event_id = e.id
-        CROSS JOIN status_counts sc
-        WHERE er.event_id = $1
-        ORDER BY er.rsvp_date DESC
+        SELECT
+            COUNT(CASE WHEN rsvp_status = 'confirmed' THEN 1 END) as confirmed_count,
+            COUNT(CASE WHEN rsvp_status = 'pending' THEN 1 END) as pending_count,
+            COUNT(CASE WHEN rsvp_status = 'declined' THEN 1 END) as declined_count,
+            COUNT(CASE WHEN waitlisted_at IS NOT NULL THEN 1 END) as waitlisted_count,
+            SUM(CASE WHEN rsvp_status <> 'declined' AND waitlisted_at IS NULL THEN adults END) as adults_count,
+            SUM(CASE WHEN rsvp_status <> 'declined' AND waitlisted_at IS NULL THEN children END) as children_count
+        FROM eventrsvp
+        WHERE event_id = $1
         "#,
-        event_id.into_inner()
+        event_id
     )
-    .fetch_all(pool.get_ref())
+    .fetch_one(pool.get_ref())
+    .await;
+
+    let counts = match counts {
+        Ok(counts) => counts,
+        Err(e) => {
+            eprintln!("Failed to count RSVPs for event: {}", e);
+            return HttpResponse::InternalServerError().json("Failed to fetch RSVPs");
+        }
+    };
+
+    let result = fetch_rsvps(pool.get_ref(), &page, |query| {
+        query.push(" AND r.event_id = ");
+        query.push_bind(event_id);
+    })
     .await;
 
     match result {
         Ok(rsvps) => {
-            if rsvps.is_empty() {
+            if rsvps.total == 0 {
                 return HttpResponse::NotFound().json("No RSVPs found for this event");
             }
 
-            // Create a response that includes both the RSVP list and the counts
-            let response = serde_json::json!({
-                "counts": {
-                    "confirmed": rsvps[0].confirmed_count,
-                    "pending": rsvps[0].pending_count,
-                    "declined": rsvps[0].declined_count,
-                },
-                "rsvps": rsvps.iter().map(|r| RSVPWithEventResponse {
-                    id: r.id,
-                    email: r.email.clone(),
-                    event_id: r.event_id,
-                    user_id: r.user_id,
-                    rsvp_status: r.rsvp_status.clone(),
-                    rsvp_date: r.rsvp_date,
-                    event_title: r.event_title.clone(),
-                    event_date: r.event_date,
-                    event_time: r.event_time.clone(),
-                }).collect::<Vec<_>>()
-            });
+            // Include the counts and questions next to the page of RSVPs
+            let response = rsvps
+                .with("counts", json!({
+                    "confirmed": counts.confirmed_count,
+                    "pending": counts.pending_count,
+                    "declined": counts.declined_count,
+                    "waitlisted": counts.waitlisted_count,
+                    // People holding seats
+                    "adults": counts.adults_count.unwrap_or_default(),
+                    "children": counts.children_count.unwrap_or_default(),
+                }))
+                .with("questions", json!(questions));
 
             HttpResponse::Ok().json(response)
         }
@@ -300,41 +612,23 @@ pub async fn get_rsvps_by_event(
 // Get all RSVPs by email
 pub async fn get_rsvps_by_email(
     pool: web::Data<PgPool>,
-    email: web::Path<String>
-) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        SELECT er.id, er.email, er.event_id, er.user_id,
-               er.rsvp_status as "rsvp_status!: ServingStatusType", er.rsvp_date,
-               e.event_title, e.event_date, e.event_time
-        FROM eventrsvp er
-        JOIN events e ON er.event_id = e.id
-        WHERE er.email = $1
-        ORDER BY e.event_date DESC
-        "#,
-        email.into_inner()
-    )
-    .fetch_all(pool.get_ref())
+    email: web::Path<String>,
+    page: web::Query<PageParams>,
+) -> HttpResponse {
+    let email = email.into_inner();
+    let page = match Page::new(&page, &RSVP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let result = fetch_rsvps(pool.get_ref(), &page, |query| {
+        query.push(" AND r.email = ");
+        query.push_bind(email.clone());
+    })
     .await;
 
     match result {
-        Ok(rsvps) => {
-            let response: Vec<RSVPWithEventResponse> = rsvps
-                .iter()
-                .map(|r| RSVPWithEventResponse {
-                    id: r.id,
-                    email: r.email.clone(),
-                    event_id: r.event_id,
-                    user_id: r.user_id,
-                    rsvp_status: r.rsvp_status.clone(),
-                    rsvp_date: r.rsvp_date,
-                    event_title: r.event_title.clone(),
-                    event_date: r.event_date,
-                    event_time: r.event_time.clone(),
-                })
-                .collect();
-            HttpResponse::Ok().json(response)
-        }
+        Ok(rsvps) => HttpResponse::Ok().json(rsvps),
         Err(e) => {
             eprintln!("Failed to fetch RSVPs: {}", e);
             HttpResponse::InternalServerError().json("Failed to fetch RSVPs")
@@ -346,32 +640,168 @@ pub async fn get_rsvps_by_email(
 pub async fn update_rsvp(
     pool: web::Data<PgPool>,
     rsvp_id: web::Path<i32>,
-    status: web::Json<ServingStatusType>) -> impl Responder {
-        let result = sqlx::query!(
-            r#"
-            UPDATE eventrsvp
-            SET rsvp_status = $1
-            WHERE id = $2
-            RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date
-            "#,
-            status.into_inner() as ServingStatusType,
-            rsvp_id.into_inner()
-        )
-        .fetch_one(pool.get_ref())
-        .await;
+    status: web::Json<ServingStatusType>) -> HttpResponse {
+    match change_status(pool.get_ref(), rsvp_id.into_inner(), status.into_inner()).await {
+        Ok(StatusChange::Changed(rsvp, promotions)) => {
+            waitlist::notify(pool.get_ref(), promotions);
+            HttpResponse::Ok().json(rsvp)
+        }
+        Ok(StatusChange::Waitlisted) => {
+            HttpResponse::Conflict().json("This RSVP is on the waitlist and cannot be confirmed yet")
+        }
+        Ok(StatusChange::Cancelled) => HttpResponse::Conflict().json("The event has been cancelled"),
+        Ok(StatusChange::NotFound) => HttpResponse::NotFound().json("RSVP not found"),
+        Err(e) => {
+            eprintln!("Failed to update RSVP status: {}", e);
+            HttpResponse::InternalServerError().json("Failed to update RSVP status")
+        }
+    }
+}
 
-    match result {
-        Ok(rsvp) => HttpResponse::Ok().json(RSVPResponse {
+enum RegistrationChange {
+    NotFound,
+    Cancelled,
+    NoSeats(i32),
+    Changed(RSVPResponse, Vec<Promotion>),
+}
+
+// Change the party size and answers of an RSVP with its event locked. A
+// larger party needs the extra seats to be free, unless it is waiting or
+// declined; seats given up by a smaller party go to the first in line.
+async fn change_registration(
+    pool: &PgPool,
+    rsvp_id: i32,
+    event_id: i32,
+    adults: Option<i32>,
+    children: Option<i32>,
+    answers: Option<Value>,
+) -> Result<RegistrationChange, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let capacity = waitlist::lock_event(&mut tx, event_id).await?.flatten();
+
+    let current = sqlx::query_as::<_, (Option<NaiveDate>, ServingStatusType, Option<NaiveDateTime>, i32, i32)>(
+        "SELECT occurrence_date, rsvp_status, waitlisted_at, adults, children FROM eventrsvp
+         WHERE id = $1 AND event_id = $2"
+    )
+    .bind(rsvp_id)
+    .bind(event_id)
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (occurrence_date, status, waitlisted_at, current_adults, current_children) = match current {
+        Some(current) => current,
+        None => return Ok(RegistrationChange::NotFound),
+    };
+
+    if waitlist::is_cancelled(&mut tx, event_id).await? {
+        return Ok(RegistrationChange::Cancelled);
+    }
+
+    let held_seat = status != ServingStatusType::Declined && waitlisted_at.is_none();
+    let current_party = current_adults + current_children;
+    let party = adults.unwrap_or(current_adults) + children.unwrap_or(current_children);
+    if capacity.is_some_and(|capacity| party > capacity) {
+        return Ok(RegistrationChange::NoSeats(party));
+    }
+
+    if held_seat
+        && party > current_party
+        && !waitlist::has_seats_for(&mut tx, event_id, occurrence_date, capacity, party - current_party).await?
+    {
+        return Ok(RegistrationChange::NoSeats(party));
+    }
+
+    let rsvp = sqlx::query!(
+        r#"
+        UPDATE eventrsvp
+        SET adults = COALESCE($1, adults), children = COALESCE($2, children), answers = COALESCE($3, answers)
+        WHERE id = $4
+        RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date, occurrence_date,
+            adults, children, answers
+        "#,
+        adults,
+        children,
+        answers,
+        rsvp_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    let promotions = if held_seat && party < current_party {
+        waitlist::promote(&mut tx, event_id, occurrence_date, capacity).await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await?;
+
+    let waitlist_position = waitlist::position(pool, rsvp_id).await?;
+
+    Ok(RegistrationChange::Changed(
+        RSVPResponse {
             id: rsvp.id,
             email: rsvp.email,
             event_id: rsvp.event_id,
             user_id: rsvp.user_id,
             rsvp_status: rsvp.rsvp_status,
             rsvp_date: rsvp.rsvp_date,
-        }),
+            occurrence_date: rsvp.occurrence_date,
+            adults: rsvp.adults,
+            children: rsvp.children,
+            answers: rsvp.answers,
+            waitlist_position,
+        },
+        promotions,
+    ))
+}
+
+// Update the party size or answers of an RSVP
+pub async fn update_registration(
+    pool: web::Data<PgPool>,
+    rsvp_id: web::Path<i32>,
+    request: web::Json<RegistrationRequest>,
+) -> HttpResponse {
+    let rsvp_id = rsvp_id.into_inner();
+
+    let current = sqlx::query_as::<_, (i32, i32, i32)>("SELECT event_id, adults, children FROM eventrsvp WHERE id = $1")
+        .bind(rsvp_id)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    let (event_id, adults, children) = match current {
+        Ok(Some(current)) => current,
+        Ok(None) => return HttpResponse::NotFound().json("RSVP not found"),
         Err(e) => {
-            eprintln!("Failed to update RSVP status: {}", e);
-            HttpResponse::InternalServerError().json("Failed to update RSVP status")
+            eprintln!("Database error: {}", e);
+            return HttpResponse::InternalServerError().json("Internal server error");
+        }
+    };
+
+    if let Err(e) = party_size(request.adults.unwrap_or(adults), request.children.unwrap_or(children)) {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let answers = match &request.answers {
+        Some(answers) => match checked_answers(pool.get_ref(), event_id, answers).await {
+            Ok(answers) => Some(answers),
+            Err(response) => return response,
+        },
+        None => None,
+    };
+
+    match change_registration(pool.get_ref(), rsvp_id, event_id, request.adults, request.children, answers).await {
+        Ok(RegistrationChange::Changed(rsvp, promotions)) => {
+            waitlist::notify(pool.get_ref(), promotions);
+            HttpResponse::Ok().json(rsvp)
+        }
+        Ok(RegistrationChange::NoSeats(party)) => {
+            HttpResponse::Conflict().json(format!("Not enough seats left for a party of {}", party))
+        }
+        Ok(RegistrationChange::Cancelled) => HttpResponse::Conflict().json("The event has been cancelled"),
+        Ok(RegistrationChange::NotFound) => HttpResponse::NotFound().json("RSVP not found"),
+        Err(e) => {
+            eprintln!("Failed to update RSVP registration: {}", e);
+            HttpResponse::InternalServerError().json("Failed to update RSVP")
         }
     }
 }
@@ -382,23 +812,96 @@ pub struct SearchQuery {
     pub event_title: Option<String>,
     pub status: Option<ServingStatusType>,
     pub user_id: Option<i32>,
+    pub event_id: Option<i32>,
+}
+
+// Filters of a search, on eventrsvp r joined with events e
+fn push_filters(query: &mut QueryBuilder<'_, Postgres>, params: &SearchQuery) {
+    // Apply status filter if provided
+    if let Some(status) = &params.status {
+        query.push(" AND r.rsvp_status = ");
+        query.push_bind(status.clone());
+    }
+
+    // Email filter with improved pattern matching
+    if let Some(email) = &params.email {
+        let search_pattern = format!("%{}%", email.trim().to_lowercase());
+        query.push(" AND LOWER(r.email) LIKE ");
+        query.push_bind(search_pattern);
+    }
+
+    // Event title filter with improved pattern matching
+    if let Some(event_title) = &params.event_title {
+        let search_pattern = format!("%{}%", event_title.trim().to_lowercase());
+        query.push(" AND LOWER(e.event_title) LIKE ");
+        query.push_bind(search_pattern);
+    }
+
+    // User ID filter
+    if let Some(user_id) = params.user_id {
+        query.push(" AND r.user_id = ");
+        query.push_bind(user_id);
+    }
+
+    // Event filter
+    if let Some(event_id) = params.event_id {
+        query.push(" AND r.event_id = ");
+        query.push_bind(event_id);
+    }
 }
 
 
-// Delete an RSVP
+// Delete an RSVP; its seat goes to the first in line
 pub async fn delete_rsvp(
     pool: web::Data<PgPool>,
     rsvp_id: web::Path<i32>
-) -> impl Responder {
-    let result = sqlx::query!(
-        "DELETE FROM eventrsvp WHERE id = $1 RETURNING id",
-        rsvp_id.into_inner()
-    )
-    .fetch_optional(pool.get_ref())
+) -> HttpResponse {
+    let id = rsvp_id.into_inner();
+
+    let result: Result<Option<Vec<Promotion>>, sqlx::Error> = async {
+        let event_id = match sqlx::query_scalar::<_, i32>("SELECT event_id FROM eventrsvp WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool.get_ref())
+            .await?
+        {
+            Some(event_id) => event_id,
+            None => return Ok(None),
+        };
+
+        let mut tx = pool.begin().await?;
+        let capacity = waitlist::lock_event(&mut tx, event_id).await?.flatten();
+
+        let deleted = sqlx::query!(
+            r#"
+            DELETE FROM eventrsvp WHERE id = $1
+            RETURNING occurrence_date, rsvp_status as "rsvp_status!: ServingStatusType", waitlisted_at
+            "#,
+            id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let deleted = match deleted {
+            Some(deleted) => deleted,
+            None => return Ok(None),
+        };
+
+        let promotions = if deleted.rsvp_status != ServingStatusType::Declined && deleted.waitlisted_at.is_none() {
+            waitlist::promote(&mut tx, event_id, deleted.occurrence_date, capacity).await?
+        } else {
+            Vec::new()
+        };
+
+        tx.commit().await?;
+        Ok(Some(promotions))
+    }
     .await;
 
     match result {
-        Ok(Some(_)) => HttpResponse::Ok().json("RSVP deleted successfully"),
+        Ok(Some(promotions)) => {
+            waitlist::notify(pool.get_ref(), promotions);
+            HttpResponse::Ok().json("RSVP deleted successfully")
+        }
         Ok(None) => HttpResponse::NotFound().json("RSVP not found"),
         Err(e) => {
             eprintln!("Failed to delete RSVP: {}", e);
@@ -407,6 +910,75 @@ pub async fn delete_rsvp(
     }
 }
 
+// Make sure the user may change an RSVP; the error is the response to give
+async fn check_owner(pool: &PgPool, claims: &Claims, rsvp_id: i32) -> Result<(), HttpResponse> {
+    let owner = sqlx::query_as::<_, (String, Option<i32>)>("SELECT email, user_id FROM eventrsvp WHERE id = $1")
+        .bind(rsvp_id)
+        .fetch_optional(pool)
+        .await;
+
+    match owner {
+        Ok(Some((email, user_id))) if claims.owns(&email, user_id) => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json("You can only change your own RSVPs")),
+        Ok(None) => Err(HttpResponse::NotFound().json("RSVP not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Get the RSVPs of the logged-in user
+pub async fn get_own_rsvps(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    email: web::Path<String>,
+    page: web::Query<PageParams>,
+) -> HttpResponse {
+    if !claims.owns(&email, None) {
+        return HttpResponse::Forbidden().json("You can only list your own RSVPs");
+    }
+    get_rsvps_by_email(pool, email, page).await
+}
+
+// Update an RSVP of the logged-in user
+pub async fn update_own_rsvp(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rsvp_id: web::Path<i32>,
+    status: web::Json<ServingStatusType>,
+) -> HttpResponse {
+    if let Err(response) = check_owner(pool.get_ref(), &claims, *rsvp_id).await {
+        return response;
+    }
+    update_rsvp(pool, rsvp_id, status).await
+}
+
+// Update the party size or answers of an RSVP of the logged-in user
+pub async fn update_own_registration(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rsvp_id: web::Path<i32>,
+    request: web::Json<RegistrationRequest>,
+) -> HttpResponse {
+    if let Err(response) = check_owner(pool.get_ref(), &claims, *rsvp_id).await {
+        return response;
+    }
+    update_registration(pool, rsvp_id, request).await
+}
+
+// Delete an RSVP of the logged-in user
+pub async fn delete_own_rsvp(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rsvp_id: web::Path<i32>,
+) -> HttpResponse {
+    if let Err(response) = check_owner(pool.get_ref(), &claims, *rsvp_id).await {
+        return response;
+    }
+    delete_rsvp(pool, rsvp_id).await
+}
+
 // Add or update the admin confirmation endpoint
 pub async fn confirm_rsvp(
     pool: web::Data<PgPool>,
@@ -416,8 +988,9 @@ pub async fn confirm_rsvp(
         r#"
         UPDATE eventrsvp
         SET rsvp_status = 'confirmed'
-        WHERE id = $1 AND rsvp_status = 'pending'
-        RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date
+        WHERE id = $1 AND rsvp_status = 'pending' AND waitlisted_at IS NULL
+        RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status!: ServingStatusType", rsvp_date, occurrence_date,
+            adults, children, answers
         "#,
         rsvp_id.into_inner()
     )
@@ -432,6 +1005,11 @@ pub async fn confirm_rsvp(
             user_id: rsvp.user_id,
             rsvp_status: rsvp.rsvp_status,
             rsvp_date: rsvp.rsvp_date,
+            occurrence_date: rsvp.occurrence_date,
+            adults: rsvp.adults,
+            children: rsvp.children,
+            answers: rsvp.answers,
+            waitlist_position: None,
         }),
         Err(e) => {
             eprintln!("Failed to confirm RSVP: {}", e);
@@ -440,6 +1018,18 @@ pub async fn confirm_rsvp(
     }
 }
 
+/// Decline an RSVP, e.g. from a link in an email, and give its seat to the
+/// waitlist. False if the RSVP does not exist.
+pub async fn decline(pool: &PgPool, rsvp_id: i32) -> Result<bool, sqlx::Error> {
+    match change_status(pool, rsvp_id, ServingStatusType::Declined).await? {
+        StatusChange::Changed(_, promotions) => {
+            waitlist::notify(pool, promotions);
+            Ok(true)
+        }
+        StatusChange::NotFound | StatusChange::Waitlisted | StatusChange::Cancelled => Ok(false),
+    }
+}
+
 pub async fn decline_rsvp(
     pool: web::Data<PgPool>,
     rsvp_id: web::Path<i32>
@@ -448,55 +1038,18 @@ pub async fn decline_rsvp(
     let id = rsvp_id.into_inner();
     println!("Attempting to decline RSVP with ID: {}", id);
 
-    // First check if the RSVP exists
-    let rsvp_exists = sqlx::query!(
-        "SELECT id FROM eventrsvp WHERE id = $1",
-        id  // Use the extracted id
-    )
-    .fetch_optional(pool.get_ref())
-    .await;
-
-    match rsvp_exists {
-        Ok(Some(_)) => {
-            // RSVP exists, proceed with update
-            let result = sqlx::query!(
-                r#"
-                UPDATE eventrsvp
-                SET rsvp_status = 'declined'::serving_status_type
-                WHERE id = $1
-                RETURNING id, email, event_id, user_id, rsvp_status as "rsvp_status: ServingStatusType", rsvp_date
-                "#,
-                id  // Use the extracted id
-            )
-            .fetch_one(pool.get_ref())
-            .await;
-
-            match result {
-                Ok(rsvp) => {
-                    println!("Successfully declined RSVP: {:?}", rsvp.id);
-                    HttpResponse::Ok().json(json!({
-                        "status": "success",
-                        "message": "RSVP declined successfully",
-                        "data": {
-                            "id": rsvp.id,
-                            "email": rsvp.email,
-                            "event_id": rsvp.event_id,
-                            "user_id": rsvp.user_id,
-                            "rsvp_status": rsvp.rsvp_status,
-                            "rsvp_date": rsvp.rsvp_date
-                        }
-                    }))
-                },
-                Err(e) => {
-                    eprintln!("Failed to decline RSVP {}: {}", id, e);
-                    HttpResponse::InternalServerError().json(json!({
-                        "status": "error",
-                        "message": format!("Failed to decline RSVP: {}", e)
-                    }))
-                }
-            }
+    match change_status(pool.get_ref(), id, ServingStatusType::Declined).await {
+        Ok(StatusChange::Changed(rsvp, promotions)) => {
+            println!("Successfully declined RSVP: {:?}", rsvp.id);
+            // The freed seat goes to the first in line
+            waitlist::notify(pool.get_ref(), promotions);
+            HttpResponse::Ok().json(json!({
+                "status": "success",
+                "message": "RSVP declined successfully",
+                "data": rsvp
+            }))
         },
-        Ok(None) => {
+        Ok(StatusChange::NotFound) | Ok(StatusChange::Waitlisted) | Ok(StatusChange::Cancelled) => {
             println!("RSVP with ID {} not found", id);
             HttpResponse::NotFound().json(json!({
                 "status": "error",
@@ -504,10 +1057,10 @@ pub async fn decline_rsvp(
             }))
         },
         Err(e) => {
-            eprintln!("Database error while checking RSVP {}: {}", id, e);
+            eprintln!("Failed to decline RSVP {}: {}", id, e);
             HttpResponse::InternalServerError().json(json!({
                 "status": "error",
-                "message": format!("Database error: {}", e)
+                "message": format!("Failed to decline RSVP: {}", e)
             }))
         }
     }
@@ -517,144 +1070,125 @@ pub async fn decline_rsvp(
 pub async fn search_rsvps(
     pool: web::Data<PgPool>,
     params: web::Query<SearchQuery>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let mut count_query = sqlx::QueryBuilder::new(
-        "SELECT COUNT(*) as count
-         FROM eventrsvp r
-         JOIN events e ON r.event_id = e.id
-         WHERE 1=1"
-    );
+    let page = match Page::new(&page, &RSVP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    // Apply status filter if provided
-    if let Some(status) = &params.status {
-        count_query.push(" AND r.rsvp_status = ");
-        count_query.push_bind(status);
+    let result = async {
+        let rsvps = fetch_rsvps(pool.get_ref(), &page, |query| push_filters(query, &params)).await?;
+        let status_counts = count_by_status(pool.get_ref(), |query| push_filters(query, &params)).await?;
+        Ok::<_, sqlx::Error>(rsvps.with("status_counts", json!(status_counts)))
     }
+    .await;
 
-    // Email filter with improved pattern matching
-    if let Some(email) = &params.email {
-        let search_pattern = format!("%{}%", email.trim().to_lowercase());
-        count_query.push(" AND LOWER(r.email) LIKE ");
-        count_query.push_bind(search_pattern);
+    match result {
+        Ok(rsvps) => HttpResponse::Ok().json(rsvps),
+        Err(err) => {
+            eprintln!("Search error: {}", err);
+            HttpResponse::InternalServerError().json("Failed to search RSVPs")
+        }
     }
+}
 
-    // Event title filter with improved pattern matching
-    if let Some(event_title) = &params.event_title {
-        let search_pattern = format!("%{}%", event_title.trim().to_lowercase());
-        count_query.push(" AND LOWER(e.event_title) LIKE ");
-        count_query.push_bind(search_pattern);
-    }
+// Columns of an RSVP export, with one per question when exporting an event
+fn export_columns(questions: &[registration_form::Question]) -> Vec<Column> {
+    let mut columns = vec![
+        Column::number("id", "r.id"),
+        Column::text("email", "r.email"),
+        Column::number("user_id", "r.user_id"),
+        Column::number("event_id", "r.event_id"),
+        Column::text("event_title", "e.event_title"),
+        Column::text("event_date", "COALESCE(r.occurrence_date, e.event_date)"),
+        Column::text("event_time", "e.event_time"),
+        Column::text("status", "r.rsvp_status"),
+        Column::text("rsvp_date", "r.rsvp_date"),
+        Column::text("waitlisted_at", "r.waitlisted_at"),
+        Column::number("adults", "r.adults"),
+        Column::number("children", "r.children"),
+        Column::text("checked_in_at", "r.checked_in_at"),
+        Column::text("walk_in", "r.walk_in"),
+    ];
 
-    // User ID filter
-    if let Some(user_id) = params.user_id {
-        count_query.push(" AND r.user_id = ");
-        count_query.push_bind(user_id);
+    // Question ids are lowercase letters, digits and underscores, so they
+    // can go into the SQL; lists of options are joined with semicolons
+    let safe_id = |id: &str| id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    for question in questions.iter().filter(|question| safe_id(&question.id)) {
+        let answer = format!("r.answers -> '{}'", question.id);
+        let sql = format!(
+            "CASE WHEN jsonb_typeof({0}) = 'array' \
+             THEN (SELECT string_agg(value, '; ') FROM jsonb_array_elements_text({0})) \
+             ELSE r.answers ->> '{1}' END",
+            answer, question.id
+        );
+        columns.push(Column::text(&format!("answer.{}", question.id), &sql).labelled(&question.label));
     }
 
-    // Execute count query first
-    let total_count = match count_query
-        .build()
-        .fetch_one(pool.get_ref())
-        .await {
-            Ok(row) => row.get::<i64, _>("count"),
+    columns
+}
+
+// Export RSVPs as CSV or XLSX with the search filters. With an event_id the
+// answers to its questions can be exported too, as answer.<question id>.
+pub async fn export_rsvps(
+    pool: web::Data<PgPool>,
+    params: web::Query<SearchQuery>,
+    export_params: web::Query<ExportParams>,
+) -> impl Responder {
+    let questions = match params.event_id {
+        Some(event_id) => match registration_form::fetch_form(pool.get_ref(), event_id).await {
+            Ok(Some(questions)) => questions,
+            Ok(None) => return HttpResponse::NotFound().json("Event not found"),
             Err(e) => {
-                eprintln!("Count query error: {}", e);
-                return HttpResponse::InternalServerError().json("Failed to count RSVPs");
+                eprintln!("Failed to fetch questions of event {}: {}", event_id, e);
+                return HttpResponse::InternalServerError().json("Failed to export RSVPs");
             }
+        },
+        None => Vec::new(),
     };
 
-    // Build main search query
-    let mut search_query = sqlx::QueryBuilder::new(
-        "SELECT r.id, r.email, r.event_id, r.user_id,
-                r.rsvp_status as \"rsvp_status\",
-                r.rsvp_date, e.event_title, e.event_date, e.event_time
-         FROM eventrsvp r
-         JOIN events e ON r.event_id = e.id
-         WHERE 1=1"
-    );
+    let (format, columns) = match export::parse_params(&export_params, export_columns(&questions)) {
+        Ok(selection) => selection,
+        Err(response) => return response,
+    };
 
-    // Apply the same filters to search query
-    if let Some(status) = &params.status {
-        search_query.push(" AND r.rsvp_status = ");
-        search_query.push_bind(status);
-    }
+    let mut query = export::select(&columns);
+    query.push(" FROM eventrsvp r JOIN events e ON r.event_id = e.id WHERE 1=1");
+    push_filters(&mut query, &params);
+    query.push(" ORDER BY r.rsvp_date DESC, r.id DESC");
 
-    if let Some(email) = &params.email {
-        let search_pattern = format!("%{}%", email.trim().to_lowercase());
-        search_query.push(" AND LOWER(r.email) LIKE ");
-        search_query.push_bind(search_pattern);
-    }
+    let filename = match params.event_id {
+        Some(event_id) => format!("event-{}-rsvps", event_id),
+        None => "event-rsvps".to_string(),
+    };
+    export::stream(pool.get_ref(), query, columns, format, &filename)
+}
 
-    if let Some(event_title) = &params.event_title {
-        let search_pattern = format!("%{}%", event_title.trim().to_lowercase());
-        search_query.push(" AND LOWER(e.event_title) LIKE ");
-        search_query.push_bind(search_pattern);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if let Some(user_id) = params.user_id {
-        search_query.push(" AND r.user_id = ");
-        search_query.push_bind(user_id);
+    #[test]
+    fn party_size_counts_adults_and_children() {
+        assert_eq!(party_size(1, 0), Ok(1));
+        assert_eq!(party_size(2, 3), Ok(5));
+        assert_eq!(party_size(0, MAX_PARTY_SIZE), Ok(MAX_PARTY_SIZE));
     }
 
-    // Execute the query
-    search_query.push(" ORDER BY e.event_date DESC, e.event_time DESC");
-
-    match search_query
-        .build()
-        .fetch_all(pool.get_ref())
-        .await {
-        Ok(rsvps) => {
-            // Calculate status counts
-            let status_counts = HashMap::from([
-                ("confirmed".to_string(),
-                    rsvps.iter()
-                    .filter(|r| {
-                        let status: ServingStatusType = r.try_get("rsvp_status").unwrap_or(ServingStatusType::Pending);
-                        status == ServingStatusType::Confirmed
-                    })
-                    .count()),
-                ("pending".to_string(),
-                    rsvps.iter()
-                    .filter(|r| {
-                        let status: ServingStatusType = r.try_get("rsvp_status").unwrap_or(ServingStatusType::Pending);
-                        status == ServingStatusType::Pending
-                    })
-                    .count()),
-                ("declined".to_string(),
-                    rsvps.iter()
-                    .filter(|r| {
-                        let status: ServingStatusType = r.try_get("rsvp_status").unwrap_or(ServingStatusType::Pending);
-                        status == ServingStatusType::Declined
-                    })
-                    .count()),
-            ]);
-
-            let converted_rsvps: Vec<RSVPWithEventResponse> = rsvps
-                .iter()
-                .map(|r| RSVPWithEventResponse {
-                    id: r.try_get("id").unwrap(),
-                    email: r.try_get("email").unwrap(),
-                    event_id: r.try_get("event_id").unwrap(),
-                    user_id: r.try_get("user_id").unwrap(),
-                    rsvp_status: r.try_get("rsvp_status").unwrap(),
-                    rsvp_date: r.try_get("rsvp_date").unwrap(),
-                    event_title: r.try_get("event_title").unwrap(),
-                    event_date: r.try_get("event_date").unwrap(),
-                    event_time: r.try_get("event_time").unwrap(),
-                })
-                .collect();
-
-            let response = serde_json::json!({
-                "rsvps": converted_rsvps,
-                "total": total_count,
-                "status_counts": status_counts,
-            });
+    #[test]
+    fn party_size_is_bounded() {
+        assert!(party_size(0, 0).is_err());
+        assert!(party_size(-1, 2).is_err());
+        assert!(party_size(2, -1).is_err());
+        assert!(party_size(MAX_PARTY_SIZE, 1).is_err());
+        assert!(party_size(MAX_PARTY_SIZE + 1, 0).is_err());
+    }
 
-            HttpResponse::Ok().json(response)
-        }
-        Err(err) => {
-            eprintln!("Search error: {}", err);
-            HttpResponse::InternalServerError().json("Failed to search RSVPs")
-        }
+    #[test]
+    fn party_size_does_not_overflow() {
+        assert!(party_size(i32::MAX, i32::MAX).is_err());
+        assert!(party_size(i32::MAX, 1).is_err());
+        assert!(party_size(1, i32::MAX).is_err());
     }
 }
@@ -1,37 +1,146 @@
 use actix_web::{web, HttpResponse, Responder};
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::HashMap;
 
+use crate::event_categories::{self, normalize_tags, parse_tags};
+use crate::pagination::{Listing, MemoryPage, Page, PageParams, SortField, Sorting};
+use crate::recurrence::RecurrenceRule;
+use crate::search::{self, Hit};
+use crate::time_zone;
+use crate::waitlist::{self, Availability};
+
+// How far ahead open-ended listings ("future", "upcoming") expand recurring events
+const RECURRENCE_HORIZON_DAYS: i64 = 365;
+
+// Events without an end are taken to last this long, or the whole day
+const DEFAULT_DURATION_MINUTES: i64 = 60;
+pub(crate) const MINUTES_PER_DAY: i32 = 24 * 60;
+
+// Events that started up to this many days ago are checked for being under
+// way; longer events only show as current once they are this close to the end
+const CURRENT_LOOKBACK_DAYS: i64 = 31;
+
+pub(crate) const EVENT_COLUMNS: &str = "id, event_title, event_date, event_time, address, description, \
+     recurrence_rule, recurrence_exceptions, category, home_group_id, duration_minutes, all_day, time_zone, \
+     capacity, tags, status, status_reason, sequence, created_at, updated_at";
+
+// Sorts of the event list; recurring events sort by their first occurrence
+static EVENT_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("id", "id", "INTEGER"),
+        SortField::new("event_date", "event_date + event_time", "TIMESTAMP"),
+        SortField::new("event_title", "LOWER(event_title)", "TEXT"),
+        SortField::new("created_at", "COALESCE(created_at, 'epoch')", "TIMESTAMP"),
+    ],
+    descending: false,
+    id: "id",
+};
+
+// Sorts of occurrence listings, which otherwise keep their own order
+const OCCURRENCE_SORTS: &[&str] = &["starts_at", "event_title"];
+const SEARCH_SORTS: &[&str] = &["relevance", "starts_at", "event_title"];
 
 #[derive(Debug, Deserialize, Serialize, FromRow)]
 pub struct Event {
     pub id: i32,
     pub event_title: String,
-    pub event_date: NaiveDate,
+    pub event_date: NaiveDate, // First occurrence of a recurring event
     pub event_time: NaiveTime,
     pub address: Option<String>,
     pub description: Option<String>,
+    pub recurrence_rule: Option<String>, // RFC 5545 RRULE, e.g. FREQ=WEEKLY;BYDAY=SU
+    pub recurrence_exceptions: Vec<NaiveDate>, // Cancelled occurrences (EXDATE)
+    pub category: Option<String>,
+    pub home_group_id: Option<i32>, // Set for home group events
+    pub duration_minutes: Option<i32>, // Wall-clock length; None when no end was given
+    pub all_day: bool,
+    pub time_zone: Option<String>, // IANA zone of event_date and event_time; None for the church's
+    pub capacity: Option<i32>, // Seats per occurrence; None for no limit
+    pub tags: Vec<String>,
+    pub status: String, // scheduled, cancelled or rescheduled, see event_status.rs
+    pub status_reason: Option<String>,
+    pub sequence: i32, // Raised by every change, for calendar apps
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
 }
 
+/// A listed event with its seats taken and left, see `waitlist::event_availability`
+#[derive(Debug, Serialize)]
+pub struct ListedEvent {
+    #[serde(flatten)]
+    pub event: Event,
+    pub availability: Availability,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct NewEvent {
     pub event_title: String,
     pub event_date: NaiveDate,
-    pub event_time: NaiveTime,
+    #[serde(default)]
+    pub event_time: NaiveTime, // Ignored for all-day events
     pub address: Option<String>,
     pub description: Option<String>,
+    pub recurrence_rule: Option<String>,
+    #[serde(default)]
+    pub recurrence_exceptions: Vec<NaiveDate>,
+    pub category: Option<String>,
+    pub home_group_id: Option<i32>,
+    // The end, either as a length or as a local end date and/or time
+    pub duration_minutes: Option<i32>,
+    #[serde(default, skip_serializing)]
+    pub end_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing)]
+    pub end_time: Option<NaiveTime>,
+    #[serde(default)]
+    pub all_day: bool,
+    pub time_zone: Option<String>,
+    pub capacity: Option<i32>,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
+/// A single occurrence of an event. One-off events have exactly one, with
+/// no `occurrence_date`; for recurring events `occurrence_date` is the date
+/// given by the rule, which RSVPs and overrides refer to, while `event_date`
+/// and the other fields reflect any override. `event_date` and `event_time`
+/// are local to `time_zone`; `starts_at` and `ends_at` are rendered in the
+/// zone the caller asked for.
 #[derive(Debug, Serialize, Clone)]
-struct EventRecord {
-    id: i32,
-    event_title: String,
-    event_date: NaiveDate,
-    event_time: NaiveTime,
-    address: Option<String>,
+pub struct EventOccurrence {
+    pub id: i32,
+    pub event_title: String,
+    pub event_date: NaiveDate,
+    pub event_time: NaiveTime,
+    pub address: Option<String>,
+    pub description: Option<String>,
+    pub recurrence_rule: Option<String>,
+    pub occurrence_date: Option<NaiveDate>,
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    pub home_group_id: Option<i32>,
+    pub all_day: bool,
+    pub status: String,
+    pub time_zone: String,
+    pub starts_at: DateTime<FixedOffset>,
+    pub ends_at: DateTime<FixedOffset>,
+    #[serde(flatten)]
+    pub availability: Availability,
+}
+
+/// Changes to one occurrence of a recurring event
+#[derive(Debug, Serialize, FromRow, Clone)]
+pub struct OccurrenceOverride {
+    pub event_id: i32,
+    pub occurrence_date: NaiveDate,
+    pub event_title: Option<String>,
+    pub event_date: Option<NaiveDate>,
+    pub event_time: Option<NaiveTime>,
+    pub address: Option<String>,
+    pub description: Option<String>,
+    pub duration_minutes: Option<i32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -42,6 +151,15 @@ struct EventResponse {
     event_time: NaiveTime,
     address: Option<String>,
     description: Option<String>,
+    recurrence_rule: Option<String>,
+    recurrence_exceptions: Vec<NaiveDate>,
+    category: Option<String>,
+    home_group_id: Option<i32>,
+    duration_minutes: Option<i32>,
+    all_day: bool,
+    time_zone: Option<String>,
+    capacity: Option<i32>,
+    tags: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,6 +169,69 @@ pub struct UpdateEventRequest {
     event_time: Option<NaiveTime>,
     address: Option<String>,
     description: Option<String>,
+    recurrence_rule: Option<String>, // Empty string makes the event one-off again
+    recurrence_exceptions: Option<Vec<NaiveDate>>,
+    category: Option<String>,
+    home_group_id: Option<i32>,
+    duration_minutes: Option<i32>, // 0 clears it, back to the default length
+    end_date: Option<NaiveDate>,
+    end_time: Option<NaiveTime>,
+    all_day: Option<bool>,
+    time_zone: Option<String>, // Empty string falls back to the church's zone
+    capacity: Option<i32>, // 0 removes the limit
+    tags: Option<Vec<String>>, // Replaces all tags
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OccurrenceOverrideRequest {
+    event_title: Option<String>,
+    event_date: Option<NaiveDate>,
+    event_time: Option<NaiveTime>,
+    address: Option<String>,
+    description: Option<String>,
+    duration_minutes: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OccurrenceRangeParams {
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    tz: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventListParams {
+    tz: Option<String>, // Zone to render start and end times in
+    category: Option<String>,
+    tags: Option<String>, // Comma-separated; events must have all of them
+}
+
+// Category and tag filters of the listings and the search
+#[derive(Debug, Default)]
+struct EventFilter {
+    category: Option<String>,
+    tags: Vec<String>,
+}
+
+impl EventFilter {
+    fn new(category: Option<&str>, tags: Option<&str>) -> Self {
+        EventFilter {
+            category: category.map(str::trim).filter(|category| !category.is_empty()).map(str::to_string),
+            tags: parse_tags(tags),
+        }
+    }
+
+    fn push_to(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        if let Some(category) = &self.category {
+            query.push(" AND LOWER(category) = LOWER(");
+            query.push_bind(category.clone());
+            query.push(")");
+        }
+        if !self.tags.is_empty() {
+            query.push(" AND tags @> ");
+            query.push_bind(self.tags.clone());
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,188 +242,879 @@ pub struct EventSearchParams{
     start_time: Option<NaiveTime>,
     end_time: Option<NaiveTime>,
     category: Option<String>,
+    tags: Option<String>, // Comma-separated; events must have all of them
     date_filter: Option<String>,
-    limit: Option<i32>,
-    offset: Option<i32>,
+    tz: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryFacet {
+    category: String,
+    color: Option<String>,
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagFacet {
+    tag: String,
+    count: usize,
+}
+
+// Occurrence counts per category and tag for filter chips
+#[derive(Debug, Serialize)]
+pub struct EventFacets {
+    categories: Vec<CategoryFacet>,
+    uncategorized: usize,
+    tags: Vec<TagFacet>,
+}
+
+impl EventOccurrence {
+    fn single(event: &Event) -> Self {
+        let zone = time_zone::event_zone(event.time_zone.as_deref());
+        let (starts_at, ends_at) = schedule(
+            zone,
+            event.event_date,
+            event.event_time,
+            event.all_day,
+            event.duration_minutes,
+        );
+
+        EventOccurrence {
+            id: event.id,
+            event_title: event.event_title.clone(),
+            event_date: event.event_date,
+            event_time: event.event_time,
+            address: event.address.clone(),
+            description: event.description.clone(),
+            recurrence_rule: None,
+            occurrence_date: None,
+            category: event.category.clone(),
+            tags: event.tags.clone(),
+            home_group_id: event.home_group_id,
+            all_day: event.all_day,
+            status: event.status.clone(),
+            time_zone: zone.name().to_string(),
+            starts_at: time_zone::in_zone(starts_at, zone),
+            ends_at: time_zone::in_zone(ends_at, zone),
+            availability: Availability::new(event.capacity, 0, 0),
+        }
+    }
+
+    fn of_series(event: &Event, occurrence_date: NaiveDate, override_: Option<&OccurrenceOverride>) -> Self {
+        let mut occurrence = EventOccurrence {
+            event_date: occurrence_date,
+            recurrence_rule: event.recurrence_rule.clone(),
+            occurrence_date: Some(occurrence_date),
+            ..EventOccurrence::single(event)
+        };
+        let mut duration_minutes = event.duration_minutes;
+
+        if let Some(o) = override_ {
+            if let Some(title) = &o.event_title {
+                occurrence.event_title = title.clone();
+            }
+            if let Some(date) = o.event_date {
+                occurrence.event_date = date;
+            }
+            if let Some(time) = o.event_time {
+                occurrence.event_time = time;
+            }
+            if o.address.is_some() {
+                occurrence.address = o.address.clone();
+            }
+            if o.description.is_some() {
+                occurrence.description = o.description.clone();
+            }
+            if o.duration_minutes.is_some() {
+                duration_minutes = o.duration_minutes;
+            }
+        }
+
+        let zone = time_zone::event_zone(event.time_zone.as_deref());
+        let (starts_at, ends_at) = schedule(
+            zone,
+            occurrence.event_date,
+            occurrence.event_time,
+            event.all_day,
+            duration_minutes,
+        );
+        occurrence.starts_at = time_zone::in_zone(starts_at, zone);
+        occurrence.ends_at = time_zone::in_zone(ends_at, zone);
+
+        occurrence
+    }
+
+    /// Whether the occurrence has started and not yet ended at `now`
+    pub fn is_under_way(&self, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now && now < self.ends_at
+    }
+
+    /// Start and end as local times in `zone`
+    pub fn in_zone(mut self, zone: Tz) -> Self {
+        self.starts_at = time_zone::in_zone(self.starts_at.with_timezone(&Utc), zone);
+        self.ends_at = time_zone::in_zone(self.ends_at.with_timezone(&Utc), zone);
+        self
+    }
+}
+
+// Start and end instants of an occurrence. The length is measured on the
+// wall clock, so an evening event keeps its local end time on the day the
+// clocks change; all-day events run from midnight to midnight.
+fn schedule(
+    zone: Tz,
+    date: NaiveDate,
+    time: NaiveTime,
+    all_day: bool,
+    duration_minutes: Option<i32>,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = NaiveDateTime::new(date, if all_day { NaiveTime::MIN } else { time });
+    let minutes = match duration_minutes {
+        Some(minutes) => minutes as i64,
+        None if all_day => MINUTES_PER_DAY as i64,
+        None => DEFAULT_DURATION_MINUTES,
+    };
+    let end = start.checked_add_signed(Duration::minutes(minutes)).unwrap_or(start);
+
+    (time_zone::localize(zone, start), time_zone::localize(zone, end))
+}
+
+/// Length of an event in minutes from a request giving either
+/// `duration_minutes` or a local end date and/or time. All-day events end
+/// on a date (inclusive). None when no end was given.
+pub(crate) fn resolve_duration(
+    event_date: NaiveDate,
+    event_time: NaiveTime,
+    all_day: bool,
+    duration_minutes: Option<i32>,
+    end_date: Option<NaiveDate>,
+    end_time: Option<NaiveTime>,
+) -> Result<Option<i32>, String> {
+    if let Some(minutes) = duration_minutes {
+        if end_date.is_some() || end_time.is_some() {
+            return Err("Give either a duration or an end, not both".to_string());
+        }
+        if minutes <= 0 {
+            return Err("Duration must be positive".to_string());
+        }
+        return Ok(Some(minutes));
+    }
+
+    if all_day {
+        if end_time.is_some() {
+            return Err("All-day events end on a date, not at a time".to_string());
+        }
+        return match end_date {
+            None => Ok(None),
+            Some(end_date) if end_date < event_date => Err("The event cannot end before it starts".to_string()),
+            Some(end_date) => i32::try_from((end_date - event_date).num_days() + 1)
+                .ok()
+                .and_then(|days| days.checked_mul(MINUTES_PER_DAY))
+                .map(Some)
+                .ok_or_else(|| "The event is too long".to_string()),
+        };
+    }
+
+    if end_date.is_none() && end_time.is_none() {
+        return Ok(None);
+    }
+
+    let start = NaiveDateTime::new(event_date, event_time);
+    let end = NaiveDateTime::new(end_date.unwrap_or(event_date), end_time.unwrap_or(event_time));
+    match i32::try_from((end - start).num_minutes()) {
+        Ok(minutes) if minutes > 0 => Ok(Some(minutes)),
+        Ok(_) => Err("The event must end after it starts".to_string()),
+        Err(_) => Err("The event is too long".to_string()),
+    }
+}
+
+// Validate a time zone from a request; empty means the church's zone
+pub(crate) fn normalize_time_zone(time_zone: Option<&str>) -> Result<Option<String>, String> {
+    match time_zone.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(name) => time_zone::parse(name).map(|zone| Some(zone.name().to_string())),
+    }
+}
+
+fn parse_rule(event: &Event) -> Option<RecurrenceRule> {
+    match event.recurrence_rule.as_deref()?.parse::<RecurrenceRule>() {
+        Ok(rule) => Some(rule),
+        Err(e) => {
+            eprintln!("Ignoring invalid recurrence rule of event {}: {}", event.id, e);
+            None
+        }
+    }
+}
+
+// Normalize a recurrence rule from a request; empty means none
+pub(crate) fn normalize_rule(rule: Option<&str>) -> Result<Option<String>, String> {
+    match rule.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(rule) => rule.parse::<RecurrenceRule>().map(|rule| Some(rule.to_string())),
+    }
+}
+
+/// Occurrences of `event` whose (possibly overridden) date is within `from..=to`
+pub fn expand(event: &Event, overrides: &[OccurrenceOverride], from: NaiveDate, to: NaiveDate) -> Vec<EventOccurrence> {
+    let rule = match parse_rule(event) {
+        Some(rule) => rule,
+        None if event.event_date >= from && event.event_date <= to => return vec![EventOccurrence::single(event)],
+        None => return Vec::new(),
+    };
+
+    let overrides: Vec<&OccurrenceOverride> = overrides.iter().filter(|o| o.event_id == event.id).collect();
+
+    let mut dates = rule.dates_between(event.event_date, from, to);
+
+    // Occurrences moved into the window from outside it
+    for o in &overrides {
+        let moved_in = o.event_date.map_or(false, |date| date >= from && date <= to);
+        if moved_in && !dates.contains(&o.occurrence_date) && rule.includes(event.event_date, o.occurrence_date) {
+            dates.push(o.occurrence_date);
+        }
+    }
+
+    dates
+        .into_iter()
+        .filter(|date| !event.recurrence_exceptions.contains(date))
+        .map(|date| {
+            let override_ = overrides.iter().find(|o| o.occurrence_date == date).copied();
+            EventOccurrence::of_series(event, date, override_)
+        })
+        .filter(|occurrence| occurrence.event_date >= from && occurrence.event_date <= to)
+        .collect()
+}
+
+/// The first occurrence of a recurring event from today on, in the event's
+/// zone; None for one-off events
+pub fn next_occurrence(event: &Event) -> Option<NaiveDate> {
+    let rule = parse_rule(event)?;
+    let today = time_zone::today(time_zone::event_zone(event.time_zone.as_deref()));
+    rule.dates_between(event.event_date, today, today + Duration::days(RECURRENCE_HORIZON_DAYS))
+        .into_iter()
+        .find(|date| !event.recurrence_exceptions.contains(date))
+}
+
+/// Whether `occurrence_date` is a valid (not cancelled) occurrence of a recurring event
+pub fn is_occurrence(event: &Event, occurrence_date: NaiveDate) -> bool {
+    match parse_rule(event) {
+        Some(rule) => {
+            !event.recurrence_exceptions.contains(&occurrence_date)
+                && rule.includes(event.event_date, occurrence_date)
+        }
+        None => false,
+    }
+}
+
+pub async fn fetch_event(pool: &PgPool, event_id: i32) -> Result<Option<Event>, sqlx::Error> {
+    sqlx::query_as::<_, Event>(&format!("SELECT {} FROM events WHERE id = $1", EVENT_COLUMNS))
+        .bind(event_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn fetch_overrides(pool: &PgPool, event_ids: &[i32]) -> Result<Vec<OccurrenceOverride>, sqlx::Error> {
+    sqlx::query_as::<_, OccurrenceOverride>(
+        "SELECT event_id, occurrence_date, event_title, event_date, event_time, address, description, duration_minutes
+         FROM event_occurrence_overrides WHERE event_id = ANY($1)"
+    )
+    .bind(event_ids)
+    .fetch_all(pool)
+    .await
+}
+
+/// The change made to one occurrence, read in a transaction
+pub async fn fetch_override(
+    tx: &mut Transaction<'_, Postgres>,
+    event_id: i32,
+    occurrence_date: NaiveDate,
+) -> Result<Option<OccurrenceOverride>, sqlx::Error> {
+    sqlx::query_as::<_, OccurrenceOverride>(
+        "SELECT event_id, occurrence_date, event_title, event_date, event_time, address, description, duration_minutes
+         FROM event_occurrence_overrides WHERE event_id = $1 AND occurrence_date = $2"
+    )
+    .bind(event_id)
+    .bind(occurrence_date)
+    .fetch_optional(&mut *tx)
+    .await
+}
+
+/// Date and time an occurrence starts at, once changed by its override
+pub fn occurrence_start(
+    event: &Event,
+    occurrence_date: NaiveDate,
+    override_: Option<&OccurrenceOverride>,
+) -> (NaiveDate, NaiveTime) {
+    (
+        override_.and_then(|o| o.event_date).unwrap_or(occurrence_date),
+        override_.and_then(|o| o.event_time).unwrap_or(event.event_time),
+    )
+}
+
+// Whether anyone who has not declined RSVP'd to an occurrence
+async fn occurrence_has_attendees(
+    tx: &mut Transaction<'_, Postgres>,
+    event_id: i32,
+    occurrence_date: NaiveDate,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM eventrsvp
+         WHERE event_id = $1 AND occurrence_date = $2 AND rsvp_status <> 'declined')"
+    )
+    .bind(event_id)
+    .bind(occurrence_date)
+    .fetch_one(&mut *tx)
+    .await
+}
+
+// Run an events query (already filtered by anything but dates) and expand
+// the matching events into their occurrences within `from..=to`, sorted by
+// start time. An open end lists one-off events without limit and expands
+// recurring events up to RECURRENCE_HORIZON_DAYS ahead.
+async fn fetch_occurrences(
+    pool: &PgPool,
+    mut query: QueryBuilder<'_, Postgres>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<Vec<EventOccurrence>, sqlx::Error> {
+    // One-off events inside the window, and every series starting before its end
+    query.push(" AND ((recurrence_rule IS NULL");
+    if let Some(from) = from {
+        query.push(" AND event_date >= ");
+        query.push_bind(from);
+    }
+    if let Some(to) = to {
+        query.push(" AND event_date <= ");
+        query.push_bind(to);
+    }
+    query.push(") OR (recurrence_rule IS NOT NULL");
+    if let Some(to) = to {
+        query.push(" AND event_date <= ");
+        query.push_bind(to);
+    }
+    query.push("))");
+
+    let events = query.build_query_as::<Event>().fetch_all(pool).await?;
+
+    let series_ids: Vec<i32> = events
+        .iter()
+        .filter(|event| event.recurrence_rule.is_some())
+        .map(|event| event.id)
+        .collect();
+    let overrides = if series_ids.is_empty() {
+        Vec::new()
+    } else {
+        fetch_overrides(pool, &series_ids).await?
+    };
+
+    let expand_from = from.unwrap_or(NaiveDate::MIN);
+    let expand_to = to.unwrap_or_else(|| Utc::now().date_naive() + chrono::Duration::days(RECURRENCE_HORIZON_DAYS));
+
+    let mut occurrences = Vec::new();
+    for event in &events {
+        match event.recurrence_rule {
+            None => occurrences.push(EventOccurrence::single(event)),
+            Some(_) => occurrences.extend(expand(event, &overrides, expand_from, expand_to)),
+        }
+    }
+
+    occurrences.sort_by_key(|occurrence| occurrence.starts_at);
+    waitlist::fill_availability(pool, &mut occurrences).await?;
+    Ok(occurrences)
+}
+
+async fn occurrences_between(
+    pool: &PgPool,
+    filter: &EventFilter,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<Vec<EventOccurrence>, sqlx::Error> {
+    let mut query = QueryBuilder::new(format!("SELECT {} FROM events WHERE 1=1", EVENT_COLUMNS));
+    filter.push_to(&mut query);
+    fetch_occurrences(pool, query, from, to).await
 }
 
 pub async fn add_event(
     pool: web::Data<PgPool>,
     new_event: web::Json<NewEvent>,
 ) -> impl Responder {
+    let recurrence_rule = match normalize_rule(new_event.recurrence_rule.as_deref()) {
+        Ok(rule) => rule,
+        Err(e) => return HttpResponse::BadRequest().json(format!("Invalid recurrence rule: {}", e)),
+    };
 
-    let result = sqlx::query!(
-        "INSERT INTO events (event_title, event_date, event_time, address, description)
-        VALUES ($1, $2, $3, $4, $5)
-        RETURNING id, event_title, event_date, event_time, address, description, created_at, updated_at",
+    let time_zone = match normalize_time_zone(new_event.time_zone.as_deref()) {
+        Ok(time_zone) => time_zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    if new_event.capacity.map_or(false, |capacity| capacity <= 0) {
+        return HttpResponse::BadRequest().json("Capacity must be positive");
+    }
+
+    let category = match event_categories::resolve_category(pool.get_ref(), new_event.category.as_deref()).await {
+        Ok(Ok(category)) => category,
+        Ok(Err(e)) => return HttpResponse::BadRequest().json(e),
+        Err(err) => return HttpResponse::InternalServerError().body(format!("Error: {}", err)),
+    };
+
+    let tags = match normalize_tags(&new_event.tags) {
+        Ok(tags) => tags,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    // All-day events start at midnight
+    let event_time = if new_event.all_day { NaiveTime::MIN } else { new_event.event_time };
+
+    let duration_minutes = match resolve_duration(
+        new_event.event_date,
+        event_time,
+        new_event.all_day,
+        new_event.duration_minutes,
+        new_event.end_date,
+        new_event.end_time,
+    ) {
+        Ok(duration) => duration,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let result = sqlx::query_as!(
+        Event,
+        "INSERT INTO events (event_title, event_date, event_time, address, description,
+        recurrence_rule, recurrence_exceptions, category, home_group_id, duration_minutes, all_day, time_zone, capacity,
+        tags)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+        RETURNING id, event_title, event_date, event_time, address, description,
+        recurrence_rule, recurrence_exceptions, category, home_group_id, duration_minutes, all_day, time_zone,
+        capacity, tags, status, status_reason, sequence, created_at, updated_at",
         new_event.event_title,
         new_event.event_date,
-        new_event.event_time,
+        event_time,
         new_event.address,
-        new_event.description
+        new_event.description,
+        recurrence_rule,
+        &new_event.recurrence_exceptions,
+        category,
+        new_event.home_group_id,
+        duration_minutes,
+        new_event.all_day,
+        time_zone,
+        new_event.capacity,
+        &tags
     )
     .fetch_one(pool.get_ref())
     .await;
 
     match result {
-        Ok(row) => {
-            // Map the row to Event and return it as JSON
-            let event = Event {
-                id: row.id,
-                event_title: row.event_title,
-                event_date: row.event_date,
-                event_time: row.event_time,
-                address: row.address,
-                description: row.description,
-                created_at: row.created_at,
-                updated_at: row.updated_at,
-            };
-            HttpResponse::Ok().json(event)
-        }
+        Ok(event) => HttpResponse::Ok().json(event),
         Err(err) => HttpResponse::InternalServerError().body(format!("Error: {}", err)),
     }
 }
 
-pub async fn get_all_events(pool: web::Data<PgPool>) -> impl Responder {
-    // Fetch all events from the database
-    let result = sqlx::query_as!(
-        Event,
-        "SELECT id, event_title, event_date, event_time, address, description, created_at, updated_at FROM events"
-    )
-    .fetch_all(pool.get_ref())
+pub async fn get_all_events(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventListParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &EVENT_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+
+    // Fetch a page of events from the database; recurring events are listed once
+    let result = async {
+        let mut count = QueryBuilder::new("SELECT COUNT(*) FROM events WHERE 1=1");
+        filter.push_to(&mut count);
+        let (total,) = count.build_query_as::<(i64,)>().fetch_one(pool.get_ref()).await?;
+
+        let mut query = QueryBuilder::new(format!(
+            "SELECT {}, {} FROM events WHERE 1=1",
+            EVENT_COLUMNS,
+            page.key_columns()
+        ));
+        filter.push_to(&mut query);
+        page.push_to(&mut query);
+        let rows = query.build().fetch_all(pool.get_ref()).await?;
+
+        let events = page.finish(rows, total, |row| Event::from_row(row))?;
+        let availability = waitlist::event_availability(pool.get_ref(), &events.items).await?;
+        Ok::<_, sqlx::Error>(events.map(|event| ListedEvent {
+            availability: availability.get(&event.id).copied().unwrap_or_default(),
+            event,
+        }))
+    }
     .await;
 
     match result {
         Ok(events) => {
-            // Return a list of events as JSON
+            // Return a page of events as JSON
             HttpResponse::Ok().json(events)
         }
         Err(err) => HttpResponse::InternalServerError().body(format!("Error: {}", err)),
     }
 }
 
-pub async fn get_past_events(pool: web::Data<PgPool>) -> impl Responder {
-    // Use `date_naive()` to get the current date as `NaiveDate`
-    let current_date = Utc::now().date_naive();
+// A page of occurrences, sorted as asked, with their start and end in `zone`
+fn render(mut occurrences: Vec<EventOccurrence>, page: &MemoryPage, zone: Tz) -> Listing<EventOccurrence> {
+    let sorted = match page.sort() {
+        Some("event_title") => {
+            occurrences.sort_by(|a, b| {
+                a.event_title
+                    .to_lowercase()
+                    .cmp(&b.event_title.to_lowercase())
+                    .then(a.starts_at.cmp(&b.starts_at))
+            });
+            true
+        }
+        Some("starts_at") => {
+            occurrences.sort_by_key(|occurrence| occurrence.starts_at);
+            true
+        }
+        _ => false, // Kept in the order given, e.g. by relevance
+    };
+    if sorted && page.descending() {
+        occurrences.reverse();
+    }
 
-    // Fetch past events, deserializing directly into `EventRecord`
-    let events = sqlx::query!(
-        "SELECT id, event_title, event_date, event_time, address FROM events WHERE event_date < $1 ORDER BY event_date DESC, event_time DESC",
-        current_date
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .unwrap_or_else(|_| Vec::new());
+    page.finish(occurrences).map(|occurrence| occurrence.in_zone(zone))
+}
+
+// Events are classified by their actual start and end; the date windows
+// below are a day wider on each side as events may be in any zone
+pub async fn get_past_events(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventListParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let page = match MemoryPage::new(&page, OCCURRENCE_SORTS) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+    let now = Utc::now();
+
+    let mut occurrences = occurrences_between(pool.get_ref(), &filter, None, now.date_naive().succ_opt())
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to fetch past events: {}", e);
+            Vec::new()
+        });
 
-    let event_records: Vec<EventRecord> = events.into_iter().map(|row| EventRecord {
-        id: row.id,
-        event_title: row.event_title,
-        event_date: row.event_date,
-        event_time: row.event_time,
-        address: row.address,
-        }).collect();
+    // Ended events, most recent first
+    occurrences.retain(|occurrence| occurrence.ends_at <= now);
+    occurrences.reverse();
 
-    HttpResponse::Ok().json(event_records)
+    HttpResponse::Ok().json(render(occurrences, &page, zone))
 }
 
 
-pub async fn get_current_events(pool: web::Data<PgPool>) -> impl Responder {
-    let current_date = chrono::Utc::now().date_naive();
-    let events = sqlx::query!(
-        "SELECT id, event_title, event_date, event_time, address FROM events WHERE event_date = $1 ORDER BY event_time DESC",
-        current_date
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .unwrap_or_else(|_| Vec::new());
+pub async fn get_current_events(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventListParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let page = match MemoryPage::new(&page, OCCURRENCE_SORTS) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+    let now = Utc::now();
+    let today = now.date_naive();
+
+    let from = today - Duration::days(CURRENT_LOOKBACK_DAYS);
+    let mut occurrences = occurrences_between(pool.get_ref(), &filter, Some(from), today.succ_opt())
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to fetch current events: {}", e);
+            Vec::new()
+        });
 
-    let event_records: Vec<EventRecord> = events.into_iter().map(|row| EventRecord {
-        id: row.id,
-        event_title: row.event_title,
-        event_date: row.event_date,
-        event_time: row.event_time,
-        address: row.address,
-        }).collect();
+    // Events under way, latest start first
+    occurrences.retain(|occurrence| occurrence.is_under_way(now));
+    occurrences.reverse();
 
-    HttpResponse::Ok().json(event_records)
+    HttpResponse::Ok().json(render(occurrences, &page, zone))
 }
 
-pub async fn get_future_events(pool: web::Data<PgPool>) -> impl Responder {
-    let current_date = chrono::Utc::now().date_naive();
-    let events = sqlx::query!(
-        "SELECT id, event_title, event_date, event_time, address FROM events WHERE event_date > $1 ORDER BY event_date ASC, event_time ASC",
-        current_date
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .unwrap_or_else(|_| Vec::new());
+pub async fn get_future_events(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventListParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let page = match MemoryPage::new(&page, OCCURRENCE_SORTS) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+    let now = Utc::now();
+
+    let mut occurrences = occurrences_between(pool.get_ref(), &filter, now.date_naive().pred_opt(), None)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to fetch future events: {}", e);
+            Vec::new()
+        });
 
-    let event_records: Vec<EventRecord> = events.into_iter().map(|row| EventRecord {
-        id: row.id,
-        event_title: row.event_title,
-        event_date: row.event_date,
-        event_time: row.event_time,
-        address: row.address,
-        }).collect();
+    occurrences.retain(|occurrence| occurrence.starts_at > now);
 
-    HttpResponse::Ok().json(event_records)
+    HttpResponse::Ok().json(render(occurrences, &page, zone))
 }
 
-pub async fn get_current_future_events(pool: web::Data<PgPool>) -> impl Responder {
-    let current_date = Utc::now().date_naive();
+pub async fn get_current_future_events(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventListParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let page = match MemoryPage::new(&page, OCCURRENCE_SORTS) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+    let now = Utc::now();
 
-    // Get current events
-    let current_events = sqlx::query!(
-        "SELECT id, event_title, event_date, event_time, address FROM events WHERE event_date = $1 ORDER BY event_time DESC",
-        current_date
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .unwrap_or_else(|_| Vec::new());
+    let from = now.date_naive() - Duration::days(CURRENT_LOOKBACK_DAYS);
+    let occurrences = occurrences_between(pool.get_ref(), &filter, Some(from), None)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to fetch current and future events: {}", e);
+            Vec::new()
+        });
 
-    // Get future events
-    let future_events = sqlx::query!(
-        "SELECT id, event_title, event_date, event_time, address FROM events WHERE event_date > $1 ORDER BY event_date ASC, event_time ASC",
-        current_date
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .unwrap_or_else(|_| Vec::new());
-
-    // Map the current events into EventRecord
-    let current_event_records: Vec<EventRecord> = current_events.into_iter().map(|row| EventRecord {
-        id: row.id,
-        event_title: row.event_title,
-        event_date: row.event_date,
-        event_time: row.event_time,
-        address: row.address,
-    }).collect();
-
-    // Map the future events into EventRecord
-    let future_event_records: Vec<EventRecord> = future_events.into_iter().map(|row| EventRecord {
-        id: row.id,
-        event_title: row.event_title,
-        event_date: row.event_date,
-        event_time: row.event_time,
-        address: row.address,
-    }).collect();
+    // Events under way latest first, then future events soonest first
+    let (mut current_events, future_events): (Vec<_>, Vec<_>) = occurrences
+        .into_iter()
+        .filter(|occurrence| occurrence.ends_at > now)
+        .partition(|occurrence| occurrence.is_under_way(now));
+    current_events.reverse();
 
     // Combine the events into one vector
-    let combined_events = [current_event_records, future_event_records].concat();
+    let combined_events = [current_events, future_events].concat();
 
     // Return the combined events as JSON
-    HttpResponse::Ok().json(combined_events)
+    HttpResponse::Ok().json(render(combined_events, &page, zone))
+}
+
+// List the occurrences of one event, by default for the coming year
+pub async fn get_event_occurrences(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+    params: web::Query<OccurrenceRangeParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let from = params.start_date.unwrap_or_else(|| time_zone::today(zone));
+    let to = params.end_date.unwrap_or(from + chrono::Duration::days(RECURRENCE_HORIZON_DAYS));
+
+    let page = match MemoryPage::new(&page, OCCURRENCE_SORTS) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    if to < from {
+        return HttpResponse::BadRequest().json("End date cannot be earlier than start date");
+    }
+
+    let event = match fetch_event(pool.get_ref(), event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound().json("Event not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch event");
+        }
+    };
+
+    let overrides = match fetch_overrides(pool.get_ref(), &[event_id]).await {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("Failed to fetch overrides of event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch event");
+        }
+    };
+
+    let mut occurrences = expand(&event, &overrides, from, to);
+    occurrences.sort_by_key(|occurrence| occurrence.starts_at);
+
+    if let Err(e) = waitlist::fill_availability(pool.get_ref(), &mut occurrences).await {
+        eprintln!("Failed to count seats of event {}: {}", event_id, e);
+        return HttpResponse::InternalServerError().json("Failed to fetch event");
+    }
+
+    HttpResponse::Ok().json(render(occurrences, &page, zone))
 }
 
+// Change a single occurrence of a recurring event
+pub async fn set_occurrence_override(
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, NaiveDate)>,
+    request: web::Json<OccurrenceOverrideRequest>,
+) -> impl Responder {
+    let (event_id, occurrence_date) = path.into_inner();
+
+    let event = match fetch_event(pool.get_ref(), event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound().json("Event not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch event");
+        }
+    };
+
+    if !is_occurrence(&event, occurrence_date) {
+        return HttpResponse::BadRequest().json("The event has no occurrence on this date");
+    }
+
+    if request.duration_minutes.map_or(false, |minutes| minutes <= 0) {
+        return HttpResponse::BadRequest().json("Duration must be positive");
+    }
+
+    // Moving an occurrence people are coming to is a reschedule, so they are told
+    let result: Result<Result<OccurrenceOverride, &str>, sqlx::Error> = async {
+        // RSVPs are made with the event locked, so none arrive before the change
+        let mut tx = pool.begin().await?;
+        waitlist::lock_event(&mut tx, event_id).await?;
 
+        let current = fetch_override(&mut tx, event_id, occurrence_date).await?;
+        let start = (
+            request.event_date.unwrap_or(occurrence_date),
+            request.event_time.unwrap_or(event.event_time),
+        );
+        if start != occurrence_start(&event, occurrence_date, current.as_ref())
+            && occurrence_has_attendees(&mut tx, event_id, occurrence_date).await?
+        {
+            return Ok(Err("The occurrence has RSVPs; reschedule it instead so attendees are told"));
+        }
+
+        let override_ = sqlx::query_as::<_, OccurrenceOverride>(
+            "INSERT INTO event_occurrence_overrides
+                (event_id, occurrence_date, event_title, event_date, event_time, address, description, duration_minutes)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (event_id, occurrence_date) DO UPDATE SET
+                event_title = EXCLUDED.event_title,
+                event_date = EXCLUDED.event_date,
+                event_time = EXCLUDED.event_time,
+                address = EXCLUDED.address,
+                description = EXCLUDED.description,
+                duration_minutes = EXCLUDED.duration_minutes,
+                updated_at = NOW()
+             RETURNING event_id, occurrence_date, event_title, event_date, event_time, address, description, duration_minutes"
+        )
+        .bind(event_id)
+        .bind(occurrence_date)
+        .bind(&request.event_title)
+        .bind(request.event_date)
+        .bind(request.event_time)
+        .bind(&request.address)
+        .bind(&request.description)
+        .bind(request.duration_minutes)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Ok(override_))
+    }
+    .await;
+
+    match result {
+        Ok(Ok(override_)) => HttpResponse::Ok().json(EventOccurrence::of_series(&event, occurrence_date, Some(&override_))),
+        Ok(Err(e)) => HttpResponse::Conflict().json(e),
+        Err(err) => {
+            eprintln!("Failed to save override of event {}: {}", event_id, err);
+            HttpResponse::InternalServerError().json("Failed to update occurrence")
+        }
+    }
+}
+
+// Restore an occurrence to the series defaults
+pub async fn delete_occurrence_override(
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, NaiveDate)>,
+) -> impl Responder {
+    let (event_id, occurrence_date) = path.into_inner();
+
+    let event = match fetch_event(pool.get_ref(), event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound().json("Event not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().json("Failed to fetch event");
+        }
+    };
+
+    // Moving an occurrence back is a reschedule too when people are coming
+    let result: Result<Result<bool, &str>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        waitlist::lock_event(&mut tx, event_id).await?;
+
+        let current = match fetch_override(&mut tx, event_id, occurrence_date).await? {
+            Some(current) => current,
+            None => return Ok(Ok(false)),
+        };
+        if occurrence_start(&event, occurrence_date, Some(&current)) != (occurrence_date, event.event_time)
+            && occurrence_has_attendees(&mut tx, event_id, occurrence_date).await?
+        {
+            return Ok(Err("The occurrence has RSVPs; reschedule it instead so attendees are told"));
+        }
+
+        sqlx::query("DELETE FROM event_occurrence_overrides WHERE event_id = $1 AND occurrence_date = $2")
+            .bind(event_id)
+            .bind(occurrence_date)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Ok(true))
+    }
+    .await;
+
+    match result {
+        Ok(Ok(true)) => HttpResponse::Ok().json("Occurrence reset successfully."),
+        Ok(Ok(false)) => HttpResponse::NotFound().json("No changes found for this occurrence"),
+        Ok(Err(e)) => HttpResponse::Conflict().json(e),
+        Err(err) => HttpResponse::InternalServerError().json(format!("Error: {}", err)),
+    }
+}
+
+
+// Events with RSVPs are cancelled instead, so attendees are told
 pub async fn delete_event(pool: web::Data<PgPool>, event_id: web::Path<i32>) -> impl Responder {
     let event_id = event_id.into_inner();
-    let result = sqlx::query!("DELETE FROM events WHERE id = $1", event_id)
-        .execute(pool.get_ref())
-        .await;
+    let result = sqlx::query!(
+        "DELETE FROM events WHERE id = $1
+         AND NOT EXISTS (SELECT 1 FROM eventrsvp WHERE event_id = $1 AND rsvp_status <> 'declined')",
+        event_id
+    )
+    .execute(pool.get_ref())
+    .await;
 
     match result {
-        Ok(_) => HttpResponse::Ok().json("Event deleted successfully."),
+        Ok(done) if done.rows_affected() > 0 => HttpResponse::Ok().json("Event deleted successfully."),
+        Ok(_) => match fetch_event(pool.get_ref(), event_id).await {
+            Ok(Some(_)) => HttpResponse::Conflict().json(
+                "The event has RSVPs; cancel it instead so attendees are told"
+            ),
+            Ok(None) => HttpResponse::NotFound().json("Event not found"),
+            Err(err) => HttpResponse::InternalServerError().json(format!("Error: {}", err)),
+        },
         Err(err) => HttpResponse::InternalServerError().json(format!("Error: {}", err)),
     }
 }
@@ -256,56 +1128,241 @@ pub async fn update_event(
     let event_id = event_id.into_inner();
     let update_request = update_request.into_inner();
 
-    // Prepare SQL query for updating the event
-    let result = sqlx::query!(
-        r#"
-        UPDATE events
-        SET
-            event_title = COALESCE($1, event_title),
-            event_date = COALESCE($2, event_date),
-            event_time = COALESCE($3, event_time),
-            address = COALESCE($4, address),
-            description = COALESCE($5, description),
-            updated_at = NOW()
-        WHERE id = $6
-        RETURNING id, event_title, event_date, event_time, address, description
-        "#,
-        update_request.event_title,
-        update_request.event_date,
-        update_request.event_time,
-        update_request.address,
-        update_request.description,
-        event_id
-    )
-    .fetch_one(pool.get_ref())
+    // Some("") clears the rule, None keeps it
+    let recurrence_rule = match normalize_rule(update_request.recurrence_rule.as_deref()) {
+        Ok(rule) => update_request.recurrence_rule.as_ref().map(|_| rule.unwrap_or_default()),
+        Err(e) => return HttpResponse::BadRequest().json(format!("Invalid recurrence rule: {}", e)),
+    };
+
+    // Some("") falls back to the church's zone, None keeps the event's
+    let time_zone = match normalize_time_zone(update_request.time_zone.as_deref()) {
+        Ok(time_zone) => update_request.time_zone.as_ref().map(|_| time_zone.unwrap_or_default()),
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    if update_request.capacity.map_or(false, |capacity| capacity < 0) {
+        return HttpResponse::BadRequest().json("Capacity cannot be negative");
+    }
+
+    // Some("") removes the category, None keeps it
+    let category = match event_categories::resolve_category(pool.get_ref(), update_request.category.as_deref()).await {
+        Ok(Ok(category)) => update_request.category.as_ref().map(|_| category.unwrap_or_default()),
+        Ok(Err(e)) => return HttpResponse::BadRequest().json(e),
+        Err(e) => {
+            eprintln!("Failed to look up category: {}", e);
+            return HttpResponse::InternalServerError().body("Error updating event");
+        }
+    };
+
+    let tags = match update_request.tags.as_deref().map(normalize_tags).transpose() {
+        Ok(tags) => tags,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    // A new end is measured from the start the event will have after the update
+    let event = match fetch_event(pool.get_ref(), event_id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => return HttpResponse::NotFound().json("Event not found"),
+        Err(e) => {
+            eprintln!("Failed to fetch event {}: {}", event_id, e);
+            return HttpResponse::InternalServerError().body("Error updating event");
+        }
+    };
+    let all_day = update_request.all_day.unwrap_or(event.all_day);
+    let event_time = if all_day { Some(NaiveTime::MIN) } else { update_request.event_time };
+
+    // Some(0) clears the duration, None keeps it
+    let clears_duration = update_request.duration_minutes == Some(0)
+        && update_request.end_date.is_none()
+        && update_request.end_time.is_none();
+    let duration_minutes = if clears_duration {
+        Some(0)
+    } else {
+        match resolve_duration(
+            update_request.event_date.unwrap_or(event.event_date),
+            event_time.unwrap_or(event.event_time),
+            all_day,
+            update_request.duration_minutes,
+            update_request.end_date,
+            update_request.end_time,
+        ) {
+            Ok(duration) => duration,
+            Err(e) => return HttpResponse::BadRequest().json(e),
+        }
+    };
+
+    // Moving an event with RSVPs, or cancelling occurrences people are coming
+    // to, goes through the status changes so attendees are told
+    let moves = update_request.event_date.is_some_and(|date| date != event.event_date)
+        || event_time.is_some_and(|time| time != event.event_time)
+        || all_day != event.all_day
+        || time_zone.as_deref().is_some_and(|zone| zone != event.time_zone.as_deref().unwrap_or(""));
+    let cancelled_dates: Vec<NaiveDate> = update_request
+        .recurrence_exceptions
+        .iter()
+        .flatten()
+        .filter(|date| !event.recurrence_exceptions.contains(date))
+        .copied()
+        .collect();
+    let today = time_zone::today(time_zone::event_zone(event.time_zone.as_deref()));
+    // A new rule or series start changes which dates are occurrences, and
+    // would leave RSVPs on dates the event no longer has
+    let changes_dates = recurrence_rule
+        .as_deref()
+        .is_some_and(|rule| rule != event.recurrence_rule.as_deref().unwrap_or(""))
+        || (event.recurrence_rule.is_some()
+            && update_request.event_date.is_some_and(|date| date != event.event_date));
+
+    let result: Result<Result<_, &str>, sqlx::Error> = async {
+        // RSVPs are made with the event locked, so none arrive before the update
+        let mut tx = pool.begin().await?;
+        waitlist::lock_event(&mut tx, event_id).await?;
+
+        if changes_dates {
+            let attending = sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM eventrsvp WHERE event_id = $1 AND rsvp_status <> 'declined'"
+            )
+            .bind(event_id)
+            .fetch_one(&mut tx)
+            .await?;
+            if attending > 0 {
+                return Ok(Err(
+                    "The event has RSVPs; its recurrence and start date cannot change, cancel or reschedule occurrences instead"
+                ));
+            }
+        }
+
+        if moves {
+            let attending = sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM eventrsvp WHERE event_id = $1 AND rsvp_status <> 'declined'
+                 AND (occurrence_date IS NULL OR occurrence_date >= $2)"
+            )
+            .bind(event_id)
+            .bind(today)
+            .fetch_one(&mut tx)
+            .await?;
+            if attending > 0 {
+                return Ok(Err(if event.recurrence_rule.is_some() {
+                    "Upcoming occurrences have RSVPs; reschedule them one at a time so attendees are told"
+                } else {
+                    "The event has RSVPs; reschedule it instead so attendees are told"
+                }));
+            }
+        }
+
+        if !cancelled_dates.is_empty() {
+            let attending = sqlx::query_scalar::<_, i64>(
+                "SELECT COUNT(*) FROM eventrsvp WHERE event_id = $1 AND rsvp_status <> 'declined'
+                 AND occurrence_date = ANY($2)"
+            )
+            .bind(event_id)
+            .bind(&cancelled_dates)
+            .fetch_one(&mut tx)
+            .await?;
+            if attending > 0 {
+                return Ok(Err("Occurrences with RSVPs are cancelled one at a time so attendees are told"));
+            }
+        }
+
+        let updated = sqlx::query!(
+            r#"
+            UPDATE events
+            SET
+                event_title = COALESCE($1, event_title),
+                event_date = COALESCE($2, event_date),
+                event_time = COALESCE($3, event_time),
+                address = COALESCE($4, address),
+                description = COALESCE($5, description),
+                recurrence_rule = NULLIF(COALESCE($6, recurrence_rule), ''),
+                recurrence_exceptions = COALESCE($7, recurrence_exceptions),
+                category = NULLIF(COALESCE($8, category), ''),
+                home_group_id = COALESCE($9, home_group_id),
+                duration_minutes = CASE WHEN $10::INTEGER IS NULL THEN duration_minutes ELSE NULLIF($10, 0) END,
+                all_day = $11,
+                time_zone = NULLIF(COALESCE($12, time_zone), ''),
+                capacity = CASE WHEN $13::INTEGER IS NULL THEN capacity ELSE NULLIF($13, 0) END,
+                tags = COALESCE($14, tags),
+                updated_at = NOW()
+            WHERE id = $15
+            RETURNING id, event_title, event_date, event_time, address, description,
+            recurrence_rule, recurrence_exceptions, category, home_group_id, duration_minutes, all_day, time_zone,
+            capacity, tags
+            "#,
+            update_request.event_title,
+            update_request.event_date,
+            event_time,
+            update_request.address,
+            update_request.description,
+            recurrence_rule,
+            update_request.recurrence_exceptions.as_deref(),
+            category,
+            update_request.home_group_id,
+            duration_minutes,
+            all_day,
+            time_zone,
+            update_request.capacity,
+            tags.as_deref(),
+            event_id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Ok(updated))
+    }
     .await;
 
+    // A larger capacity, or none, frees seats for those waiting
+    if matches!(result, Ok(Ok(_))) && update_request.capacity.is_some() {
+        if let Err(e) = waitlist::fill_free_seats(pool.get_ref(), event_id).await {
+            eprintln!("Failed to fill free seats of event {}: {}", event_id, e);
+        }
+    }
+
     match result {
-        Ok(event) => HttpResponse::Ok().json(EventResponse {
+        Ok(Ok(event)) => HttpResponse::Ok().json(EventResponse {
             id: event.id,
             event_title: event.event_title,
             event_date: event.event_date,
             event_time: event.event_time,
             address: event.address,
             description: event.description,
+            recurrence_rule: event.recurrence_rule,
+            recurrence_exceptions: event.recurrence_exceptions,
+            category: event.category,
+            home_group_id: event.home_group_id,
+            duration_minutes: event.duration_minutes,
+            all_day: event.all_day,
+            time_zone: event.time_zone,
+            capacity: event.capacity,
+            tags: event.tags,
         }),
+        Ok(Err(e)) => HttpResponse::Conflict().json(e),
         Err(_) => HttpResponse::InternalServerError().body("Error updating event"),
     }
 }
 
-pub async fn search_events(
-    pool: web::Data<PgPool>,
-    params: web::Query<EventSearchParams>
-) -> impl Responder {
-    // Get current date for relative date calculations
-    let current_date = chrono::Utc::now().date_naive();
+// Occurrences matching a search. The outer error is a database error,
+// the inner one a bad request.
+async fn search_occurrences(
+    pool: &PgPool,
+    params: &EventSearchParams,
+    filter: &EventFilter,
+) -> Result<Result<Vec<EventOccurrence>, String>, sqlx::Error> {
+    // Relative dates are days in the caller's zone, the church's by default
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return Ok(Err(e)),
+    };
+    let now = Utc::now();
+    let current_date = time_zone::today(zone);
 
     // Handle date range logic
     let (effective_start_date, effective_end_date) = match (params.start_date, params.end_date) {
         // Case 1: Both dates provided - validate and use them
         (Some(start), Some(end)) => {
             if end < start {
-                return HttpResponse::BadRequest().json("End date cannot be earlier than start date");
+                return Ok(Err("End date cannot be earlier than start date".to_string()));
             }
             (start, end)
         },
@@ -328,98 +1385,182 @@ pub async fn search_events(
         }
     };
 
-    let mut query = sqlx::QueryBuilder::new(
-        "SELECT id, event_title, event_date, event_time, address, description, created_at, updated_at
-         FROM events WHERE 1=1"
-    );
+    // Predefined date range filters, applied to each occurrence
+    let (from, to) = match params.date_filter.as_deref() {
+        Some("today") => (Some(current_date), Some(current_date)),
+        Some("tomorrow") => {
+            let tomorrow = current_date + chrono::Duration::days(1);
+            (Some(tomorrow), Some(tomorrow))
+        },
+        Some("this_week") => (Some(current_date), Some(current_date + chrono::Duration::days(7))),
+        Some("upcoming") => (current_date.pred_opt(), None),
+        Some("past") => (None, current_date.succ_opt()),
+        // Custom date range using effective dates
+        None => (Some(effective_start_date), Some(effective_end_date)),
+        _ => (None, None),
+    };
+
+    let mut query = QueryBuilder::new(format!("SELECT {} FROM events WHERE 1=1", EVENT_COLUMNS));
 
-    // Common search filters
+    // Full-text search of titles, categories, tags, addresses and descriptions
     if let Some(text) = &params.text {
-        let search_pattern = format!("%{}%", text);
-        query.push(" AND (event_title ILIKE ");
-        query.push_bind(search_pattern.clone());
-        query.push(" OR description ILIKE ");
-        query.push_bind(search_pattern.clone());
-        query.push(" OR address ILIKE ");
-        query.push_bind(search_pattern);
-        query.push(")");
+        search::EVENTS.push_match(&mut query, text);
     }
 
-    // Add predefined date range filters
+    // Category and tag filters
+    filter.push_to(&mut query);
+
+    let mut occurrences = fetch_occurrences(pool, query, from, to).await?;
+
+    // Time range if provided; overrides may move an occurrence's time
+    occurrences.retain(|occurrence| {
+        params.start_time.map_or(true, |start_time| occurrence.event_time >= start_time)
+            && params.end_time.map_or(true, |end_time| occurrence.event_time <= end_time)
+    });
+
+    // Upcoming includes events under way; past ones have ended
     match params.date_filter.as_deref() {
-        Some("today") => {
-            query.push(" AND event_date = ");
-            query.push_bind(current_date);
-        },
-        Some("tomorrow") => {
-            query.push(" AND event_date = ");
-            query.push_bind(current_date + chrono::Duration::days(1));
-        },
-        Some("this_week") => {
-            let week_end = current_date + chrono::Duration::days(7);
-            query.push(" AND event_date >= ");
-            query.push_bind(current_date);
-            query.push(" AND event_date <= ");
-            query.push_bind(week_end);
-        },
-        Some("upcoming") => {
-            query.push(" AND event_date >= ");
-            query.push_bind(current_date);
-        },
-        Some("past") => {
-            query.push(" AND event_date < ");
-            query.push_bind(current_date);
-        },
-        // Custom date range using effective dates
-        None => {
-            query.push(" AND event_date >= ");
-            query.push_bind(effective_start_date);
-            query.push(" AND event_date <= ");
-            query.push_bind(effective_end_date);
-        },
+        Some("upcoming") => occurrences.retain(|occurrence| occurrence.ends_at > now),
+        Some("past") => occurrences.retain(|occurrence| occurrence.ends_at <= now),
         _ => {}
     }
 
-    // Time range if provided
-    if let Some(start_time) = params.start_time {
-        query.push(" AND event_time >= ");
-        query.push_bind(start_time);
-    }
+    Ok(Ok(occurrences))
+}
 
-    if let Some(end_time) = params.end_time {
-        query.push(" AND event_time <= ");
-        query.push_bind(end_time);
-    }
+pub async fn search_events(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventSearchParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let zone = match time_zone::requested(params.tz.as_deref()) {
+        Ok(zone) => zone,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+    let page = match MemoryPage::new(&page, SEARCH_SORTS) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    // Category filter
-    if let Some(category) = &params.category {
-        query.push(" AND category = ");
-        query.push_bind(category.clone());
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+    let mut occurrences = match search_occurrences(pool.get_ref(), &params, &filter).await {
+        Ok(Ok(occurrences)) => occurrences,
+        Ok(Err(e)) => return HttpResponse::BadRequest().json(e),
+        Err(err) => {
+            eprintln!("Search error: {}", err);
+            return HttpResponse::InternalServerError().json("Failed to search events");
+        }
+    };
+
+    // Relevance and snippets of the matched events
+    let text = params.text.as_deref().map(str::trim).filter(|text| !text.is_empty());
+    let rankings = match text {
+        Some(text) if !occurrences.is_empty() => {
+            let mut ids: Vec<i32> = occurrences.iter().map(|occurrence| occurrence.id).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            match search::EVENTS.rankings(pool.get_ref(), text, &ids).await {
+                Ok(rankings) => rankings,
+                Err(err) => {
+                    eprintln!("Search error: {}", err);
+                    return HttpResponse::InternalServerError().json("Failed to search events");
+                }
+            }
+        }
+        _ => HashMap::new(),
+    };
+    let relevance = |occurrence: &EventOccurrence| {
+        rankings.get(&occurrence.id).map_or(0.0, |ranking| ranking.relevance)
+    };
+
+    // Best matches first when searching for text, earliest first among equals
+    if text.is_some() && matches!(page.sort(), None | Some("relevance")) {
+        occurrences.sort_by(|a, b| relevance(b).total_cmp(&relevance(a)));
+    } else if params.date_filter == Some("past".to_string()) {
+        occurrences.reverse(); // Past events newest first
     }
 
-    // Add smart sorting based on search context
-    if params.date_filter == Some("past".to_string()) {
-        query.push(" ORDER BY event_date DESC, event_time DESC"); // Past events newest first
-    } else {
-        query.push(" ORDER BY event_date ASC, event_time ASC"); // Future events soonest first
+    let results = render(occurrences, &page, zone).map(|occurrence| {
+        let ranking = rankings.get(&occurrence.id);
+        Hit::new(occurrence, ranking)
+    });
+    HttpResponse::Ok().json(results)
+}
+
+// Facets of a search: how many occurrences each category and tag would
+// match. Category counts ignore the selected category, so every category
+// shows what choosing it would give; tag counts are within the selection,
+// since tags narrow it further.
+pub async fn get_event_facets(
+    pool: web::Data<PgPool>,
+    params: web::Query<EventSearchParams>
+) -> impl Responder {
+    let filter = EventFilter::new(params.category.as_deref(), params.tags.as_deref());
+    let any_category = EventFilter { category: None, tags: filter.tags.clone() };
+
+    let categories = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT name, color FROM event_categories ORDER BY name"
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    let categories = match categories {
+        Ok(categories) => categories,
+        Err(err) => {
+            eprintln!("Failed to fetch event categories: {}", err);
+            return HttpResponse::InternalServerError().json("Failed to fetch facets");
+        }
+    };
+
+    let mut searches = Vec::new();
+    for filter in [&any_category, &filter] {
+        match search_occurrences(pool.get_ref(), &params, filter).await {
+            Ok(Ok(occurrences)) => searches.push(occurrences),
+            Ok(Err(e)) => return HttpResponse::BadRequest().json(e),
+            Err(err) => {
+                eprintln!("Search error: {}", err);
+                return HttpResponse::InternalServerError().json("Failed to fetch facets");
+            }
+        }
     }
+    let selected = searches.pop().unwrap_or_default();
+    let any_category = searches.pop().unwrap_or_default();
 
-    // Add pagination
-    if let (Some(limit), Some(offset)) = (params.limit, params.offset) {
-        query.push(" LIMIT ");
-        query.push_bind(limit);
-        query.push(" OFFSET ");
-        query.push_bind(offset);
+    let mut category_counts: HashMap<&str, usize> = HashMap::new();
+    let mut uncategorized = 0;
+    for occurrence in &any_category {
+        match occurrence.category.as_deref() {
+            Some(category) => *category_counts.entry(category).or_default() += 1,
+            None => uncategorized += 1,
+        }
     }
 
-    // Execute query
-    let query = query.build_query_as::<Event>();
-    match query.fetch_all(pool.get_ref()).await {
-        Ok(events) => HttpResponse::Ok().json(events),
-        Err(err) => {
-            eprintln!("Search error: {}", err);
-            HttpResponse::InternalServerError().json("Failed to search events")
+    let categories = categories
+        .iter()
+        .map(|(name, color)| CategoryFacet {
+            category: name.clone(),
+            color: color.clone(),
+            count: category_counts.get(name.as_str()).copied().unwrap_or_default(),
+        })
+        .collect();
+
+    let mut tag_counts: HashMap<&str, usize> = HashMap::new();
+    for occurrence in &selected {
+        for tag in &occurrence.tags {
+            *tag_counts.entry(tag).or_default() += 1;
         }
     }
-}
 
+    // Most used tags first
+    let mut tags: Vec<TagFacet> = tag_counts
+        .into_iter()
+        .map(|(tag, count)| TagFacet { tag: tag.to_string(), count })
+        .collect();
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+
+    HttpResponse::Ok().json(EventFacets {
+        categories,
+        uncategorized,
+        tags,
+    })
+}
@@ -0,0 +1,728 @@
+use actix_web::{http::header, web::Bytes, HttpResponse};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use futures::channel::mpsc;
+use futures::{SinkExt, TryStreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
+use std::borrow::Cow;
+use std::io::Write;
+
+// Downloads of long lists as CSV or XLSX. Rows are streamed from the
+// database and written out in chunks as they arrive, so an export never
+// holds the whole list in memory. The handlers choose the columns and
+// filters; every column is selected as text and written as a string cell,
+// or as a number for numeric columns. Text that a spreadsheet would read as
+// a formula is prefixed with a quote. Once streaming has started an error
+// can only cut the download short; the error is logged.
+
+// Bytes written before a chunk is sent, and chunks waiting to be sent
+const CHUNK_BYTES: usize = 64 * 1024;
+const CHANNEL_CHUNKS: usize = 8;
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    format: Option<String>,  // csv (default) or xlsx
+    columns: Option<String>, // Comma-separated column keys, by default all
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Xlsx,
+}
+
+impl Format {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Xlsx => "xlsx",
+        }
+    }
+}
+
+/// A column that can be exported
+#[derive(Debug, Clone)]
+pub struct Column {
+    key: String,
+    label: String,
+    sql: String, // Expression selected for the column
+    numeric: bool,
+}
+
+impl Column {
+    pub fn text(key: &str, sql: &str) -> Self {
+        Column {
+            key: key.to_string(),
+            label: key.to_string(),
+            sql: sql.to_string(),
+            numeric: false,
+        }
+    }
+
+    pub fn number(key: &str, sql: &str) -> Self {
+        Column {
+            numeric: true,
+            ..Column::text(key, sql)
+        }
+    }
+
+    /// Heading in the file, the key by default
+    pub fn labelled(self, label: &str) -> Self {
+        Column {
+            label: label.to_string(),
+            ..self
+        }
+    }
+}
+
+/// Format and columns of an export, or the response to a bad request
+#[allow(clippy::result_large_err)]
+pub fn parse_params(params: &ExportParams, available: Vec<Column>) -> Result<(Format, Vec<Column>), HttpResponse> {
+    let format = match params.format.as_deref().map(str::to_lowercase).as_deref() {
+        None | Some("csv") => Format::Csv,
+        Some("xlsx") => Format::Xlsx,
+        Some(other) => {
+            return Err(HttpResponse::BadRequest().json(json!({
+                "message": format!("Unsupported format: {}", other)
+            })));
+        }
+    };
+
+    let requested: Vec<&str> = params
+        .columns
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .collect();
+
+    if requested.is_empty() {
+        return Ok((format, available));
+    }
+
+    let mut columns = Vec::new();
+    for key in requested {
+        match available.iter().find(|column| column.key == key) {
+            Some(column) => columns.push(column.clone()),
+            None => {
+                return Err(HttpResponse::BadRequest().json(json!({
+                    "message": format!("Unknown column: {}", key),
+                    "columns": available.iter().map(|column| column.key.as_str()).collect::<Vec<_>>(),
+                })));
+            }
+        }
+    }
+
+    Ok((format, columns))
+}
+
+/// Start of the export query: the columns, as text
+pub fn select(columns: &[Column]) -> QueryBuilder<'static, Postgres> {
+    let selected: Vec<String> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| format!("({})::TEXT AS c{}", column.sql, index))
+        .collect();
+    QueryBuilder::new(format!("SELECT {}", selected.join(", ")))
+}
+
+/// Stream the rows of `query`, which must start with `select(columns)`
+pub fn stream(
+    pool: &PgPool,
+    mut query: QueryBuilder<'static, Postgres>,
+    columns: Vec<Column>,
+    format: Format,
+    filename: &str,
+) -> HttpResponse {
+    let (mut sender, receiver) = mpsc::channel::<Result<Bytes, actix_web::Error>>(CHANNEL_CHUNKS);
+    let pool = pool.clone();
+
+    actix_web::rt::spawn(async move {
+        let mut writer = TableWriter::new(format, &columns);
+        let mut rows = query.build().fetch(&pool);
+
+        loop {
+            let row = match rows.try_next().await {
+                Ok(Some(row)) => row,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Export query failed: {}", e);
+                    let _ = sender.send(Err(actix_web::error::ErrorInternalServerError("Export failed"))).await;
+                    return;
+                }
+            };
+
+            let cells: Vec<Option<String>> = (0..columns.len())
+                .map(|index| row.try_get::<Option<String>, _>(index).unwrap_or_default())
+                .collect();
+            if let Err(e) = writer.write_row(&cells) {
+                eprintln!("Export failed: {}", e);
+                let _ = sender.send(Err(actix_web::error::ErrorInternalServerError("Export failed"))).await;
+                return;
+            }
+
+            // Stop when the download was cancelled
+            if writer.buffered() >= CHUNK_BYTES && sender.send(Ok(writer.take())).await.is_err() {
+                return;
+            }
+        }
+
+        match writer.finish() {
+            Ok(bytes) => {
+                let _ = sender.send(Ok(bytes)).await;
+            }
+            Err(e) => {
+                eprintln!("Export failed: {}", e);
+                let _ = sender.send(Err(actix_web::error::ErrorInternalServerError("Export failed"))).await;
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.{}\"", filename, format.extension()),
+        ))
+        .streaming(receiver)
+}
+
+// Text as written to a cell: a quote keeps text starting like a formula
+// from being run by the spreadsheet, numbers of numeric columns are kept
+fn cell_text(value: &str, numeric: bool) -> Cow<'_, str> {
+    if numeric && value.parse::<f64>().is_ok_and(f64::is_finite) {
+        return Cow::Borrowed(value);
+    }
+    match value.chars().next() {
+        Some('=' | '+' | '-' | '@' | '\t' | '\r') => Cow::Owned(format!("'{}", value)),
+        _ => Cow::Borrowed(value),
+    }
+}
+
+enum TableWriter {
+    Csv(csv::Writer<Vec<u8>>, Vec<bool>), // With which columns are numeric
+    Xlsx(XlsxWriter),
+}
+
+impl TableWriter {
+    fn new(format: Format, columns: &[Column]) -> Self {
+        let labels: Vec<Option<String>> = columns.iter().map(|column| Some(column.label.clone())).collect();
+        let numeric = columns.iter().map(|column| column.numeric).collect();
+        let mut writer = match format {
+            Format::Csv => TableWriter::Csv(csv::Writer::from_writer(Vec::new()), numeric),
+            Format::Xlsx => TableWriter::Xlsx(XlsxWriter::new(numeric)),
+        };
+        // Nothing is written yet, so the heading fits in any archive
+        let _ = writer.write_heading(&labels);
+        writer
+    }
+
+    fn write_heading(&mut self, labels: &[Option<String>]) -> Result<(), String> {
+        match self {
+            TableWriter::Xlsx(writer) => writer.write_row(labels, true),
+            TableWriter::Csv(..) => self.write_row(labels),
+        }
+    }
+
+    fn write_row(&mut self, cells: &[Option<String>]) -> Result<(), String> {
+        match self {
+            TableWriter::Csv(writer, numeric) => {
+                let record = cells.iter().enumerate().map(|(index, cell)| {
+                    let numeric = numeric.get(index).copied().unwrap_or(false);
+                    cell_text(cell.as_deref().unwrap_or_default(), numeric).into_owned()
+                });
+                // Writing to memory cannot fail
+                let _ = writer.write_record(record);
+                Ok(())
+            }
+            TableWriter::Xlsx(writer) => writer.write_row(cells, false),
+        }
+    }
+
+    fn buffered(&mut self) -> usize {
+        match self {
+            TableWriter::Csv(writer, _) => {
+                let _ = writer.flush();
+                writer.get_ref().len()
+            }
+            TableWriter::Xlsx(writer) => writer.zip.out.len(),
+        }
+    }
+
+    // Bytes written so far
+    fn take(&mut self) -> Bytes {
+        match self {
+            TableWriter::Csv(writer, _) => {
+                // Carry on with a fresh writer over an empty buffer
+                let written = std::mem::replace(writer, csv::Writer::from_writer(Vec::new()));
+                Bytes::from(written.into_inner().unwrap_or_default())
+            }
+            TableWriter::Xlsx(writer) => Bytes::from(std::mem::take(&mut writer.zip.out)),
+        }
+    }
+
+    fn finish(mut self) -> Result<Bytes, String> {
+        if let TableWriter::Xlsx(writer) = &mut self {
+            writer.finish()?;
+        }
+        Ok(self.take())
+    }
+}
+
+// A single-sheet workbook with inline strings, written as it goes
+struct XlsxWriter {
+    zip: ZipStream,
+    numeric: Vec<bool>,
+    row: usize,
+}
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+
+const ROOT_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Export" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+const SHEET_START: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#;
+
+const SHEET_END: &str = "</sheetData></worksheet>";
+
+impl XlsxWriter {
+    fn new(numeric: Vec<bool>) -> Self {
+        let mut zip = ZipStream::default();
+        // A handful of small entries always fit in an archive
+        let _ = zip.add("[Content_Types].xml", CONTENT_TYPES.as_bytes())
+            .and_then(|_| zip.add("_rels/.rels", ROOT_RELS.as_bytes()))
+            .and_then(|_| zip.add("xl/workbook.xml", WORKBOOK.as_bytes()))
+            .and_then(|_| zip.add("xl/_rels/workbook.xml.rels", WORKBOOK_RELS.as_bytes()))
+            .and_then(|_| zip.start("xl/worksheets/sheet1.xml"))
+            .and_then(|_| zip.write(SHEET_START.as_bytes()));
+
+        XlsxWriter { zip, numeric, row: 0 }
+    }
+
+    fn write_row(&mut self, cells: &[Option<String>], heading: bool) -> Result<(), String> {
+        self.row += 1;
+        let mut xml = format!("<row r=\"{}\">", self.row);
+
+        for (index, cell) in cells.iter().enumerate() {
+            let value = match cell {
+                Some(value) => value,
+                None => continue,
+            };
+            let reference = format!("{}{}", column_name(index), self.row);
+            let numeric = !heading && self.numeric.get(index).copied().unwrap_or(false);
+
+            if numeric && value.parse::<f64>().is_ok_and(f64::is_finite) {
+                xml.push_str(&format!("<c r=\"{}\"><v>{}</v></c>", reference, value));
+            } else {
+                xml.push_str(&format!(
+                    "<c r=\"{}\" t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+                    reference,
+                    escape_xml(&cell_text(value, false))
+                ));
+            }
+        }
+
+        xml.push_str("</row>");
+        self.zip.write(xml.as_bytes())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.zip.write(SHEET_END.as_bytes())?;
+        self.zip.finish()
+    }
+}
+
+// Spreadsheet column name of a 0-based index: A, B, ..., Z, AA, ...
+fn column_name(index: usize) -> String {
+    let mut name = Vec::new();
+    let mut number = index + 1;
+    while number > 0 {
+        let remainder = (number - 1) % 26;
+        name.push(b'A' + remainder as u8);
+        number = (number - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+
+// Escape text for XML, dropping control characters XML cannot hold
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// A ZIP archive written front to back, for a body that cannot seek: each
+// entry is deflated as it is written and followed by a data descriptor
+// with its checksum and sizes. Without ZIP64, entries and the archive must
+// stay under 4 GiB and hold fewer than 65,535 entries; going past either is
+// an error.
+#[derive(Default)]
+struct ZipStream {
+    out: Vec<u8>,
+    written: u64, // Bytes written so far, including those already taken
+    entries: Vec<ZipEntry>,
+    current: Option<OpenEntry>,
+}
+
+struct ZipEntry {
+    name: String,
+    offset: u64,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+}
+
+struct OpenEntry {
+    name: String,
+    offset: u64,
+    crc: crc32fast::Hasher,
+    compressed_size: u64,
+    size: u64,
+    encoder: DeflateEncoder<Vec<u8>>,
+}
+
+// General purpose flags: sizes in a data descriptor, UTF-8 names
+const ZIP_FLAGS: u16 = 0x0008 | 0x0800;
+const ZIP_DEFLATE: u16 = 8;
+const ZIP_VERSION: u16 = 20;
+// 1980-01-01 00:00 in MS-DOS format
+const ZIP_TIME: u16 = 0;
+const ZIP_DATE: u16 = 0x21;
+
+// A size or offset of a ZIP without ZIP64; all ones marks a ZIP64 value
+fn zip_u32(value: u64, what: &str) -> Result<u32, String> {
+    u32::try_from(value)
+        .ok()
+        .filter(|value| *value != u32::MAX)
+        .ok_or_else(|| format!("The {} is too large for a ZIP file without ZIP64", what))
+}
+
+impl ZipStream {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.out.extend_from_slice(bytes);
+        self.written += bytes.len() as u64;
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn emit_u32(&mut self, value: u32) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        self.start(name)?;
+        self.write(data)?;
+        self.finish_entry()
+    }
+
+    fn start(&mut self, name: &str) -> Result<(), String> {
+        self.finish_entry()?;
+
+        if self.entries.len() + 1 >= u16::MAX as usize {
+            return Err("Too many entries for a ZIP file without ZIP64".to_string());
+        }
+        let offset = self.written;
+        zip_u32(offset, "archive")?;
+        self.emit_u32(0x04034b50);
+        self.emit_u16(ZIP_VERSION);
+        self.emit_u16(ZIP_FLAGS);
+        self.emit_u16(ZIP_DEFLATE);
+        self.emit_u16(ZIP_TIME);
+        self.emit_u16(ZIP_DATE);
+        self.emit_u32(0); // Checksum and sizes follow the data
+        self.emit_u32(0);
+        self.emit_u32(0);
+        self.emit_u16(name.len() as u16);
+        self.emit_u16(0);
+        self.emit(name.as_bytes());
+
+        self.current = Some(OpenEntry {
+            name: name.to_string(),
+            offset,
+            crc: crc32fast::Hasher::new(),
+            compressed_size: 0,
+            size: 0,
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+        });
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        let compressed = match self.current.as_mut() {
+            Some(entry) => {
+                entry.crc.update(data);
+                entry.size += data.len() as u64;
+                zip_u32(entry.size, "entry")?;
+                // Writing to memory cannot fail
+                let _ = entry.encoder.write_all(data);
+                let compressed = std::mem::take(entry.encoder.get_mut());
+                entry.compressed_size += compressed.len() as u64;
+                compressed
+            }
+            None => return Ok(()),
+        };
+        self.emit(&compressed);
+        Ok(())
+    }
+
+    fn finish_entry(&mut self) -> Result<(), String> {
+        let mut entry = match self.current.take() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        let _ = entry.encoder.try_finish();
+        let compressed = std::mem::take(entry.encoder.get_mut());
+        entry.compressed_size += compressed.len() as u64;
+        self.emit(&compressed);
+
+        let crc = entry.crc.finalize();
+        let compressed_size = zip_u32(entry.compressed_size, "entry")?;
+        let size = zip_u32(entry.size, "entry")?;
+        self.emit_u32(0x08074b50);
+        self.emit_u32(crc);
+        self.emit_u32(compressed_size);
+        self.emit_u32(size);
+
+        self.entries.push(ZipEntry {
+            name: entry.name,
+            offset: entry.offset,
+            crc,
+            compressed_size: entry.compressed_size,
+            size: entry.size,
+        });
+        Ok(())
+    }
+
+    // Write the central directory
+    fn finish(&mut self) -> Result<(), String> {
+        self.finish_entry()?;
+
+        let directory_offset = self.written;
+        let entries = std::mem::take(&mut self.entries);
+        for entry in &entries {
+            self.emit_u32(0x02014b50);
+            self.emit_u16(ZIP_VERSION);
+            self.emit_u16(ZIP_VERSION);
+            self.emit_u16(ZIP_FLAGS);
+            self.emit_u16(ZIP_DEFLATE);
+            self.emit_u16(ZIP_TIME);
+            self.emit_u16(ZIP_DATE);
+            self.emit_u32(entry.crc);
+            self.emit_u32(entry.compressed_size as u32);
+            self.emit_u32(entry.size as u32);
+            self.emit_u16(entry.name.len() as u16);
+            self.emit_u16(0); // Extra field
+            self.emit_u16(0); // Comment
+            self.emit_u16(0); // Disk
+            self.emit_u16(0); // Internal attributes
+            self.emit_u32(0); // External attributes
+            self.emit_u32(entry.offset as u32);
+            self.emit(entry.name.as_bytes());
+        }
+        let directory_size = zip_u32(self.written - directory_offset, "archive")?;
+        let directory_offset = zip_u32(directory_offset, "archive")?;
+
+        self.emit_u32(0x06054b50);
+        self.emit_u16(0);
+        self.emit_u16(0);
+        self.emit_u16(entries.len() as u16);
+        self.emit_u16(entries.len() as u16);
+        self.emit_u32(directory_size);
+        self.emit_u32(directory_offset);
+        self.emit_u16(0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    // Read an archive back through its central directory, checking every
+    // entry's local header, checksum, sizes and data descriptor
+    fn read_zip(bytes: &[u8]) -> Vec<(String, String)> {
+        let end = bytes.len() - 22;
+        assert_eq!(u32_at(bytes, end), 0x06054b50);
+        let count = u16_at(bytes, end + 10);
+        let mut at = u32_at(bytes, end + 16) as usize;
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(bytes, at), 0x02014b50);
+            let crc = u32_at(bytes, at + 16);
+            let compressed_size = u32_at(bytes, at + 20) as usize;
+            let size = u32_at(bytes, at + 24) as usize;
+            let name_length = u16_at(bytes, at + 28);
+            let offset = u32_at(bytes, at + 42) as usize;
+            let name = String::from_utf8(bytes[at + 46..at + 46 + name_length].to_vec()).unwrap();
+            at += 46 + name_length + u16_at(bytes, at + 30) + u16_at(bytes, at + 32);
+
+            assert_eq!(u32_at(bytes, offset), 0x04034b50);
+            assert_eq!(&bytes[offset + 30..offset + 30 + name_length], name.as_bytes());
+            let data_start = offset + 30 + u16_at(bytes, offset + 26) + u16_at(bytes, offset + 28);
+            let data_end = data_start + compressed_size;
+
+            let mut data = Vec::new();
+            DeflateDecoder::new(&bytes[data_start..data_end]).read_to_end(&mut data).unwrap();
+            assert_eq!(data.len(), size);
+            assert_eq!(crc32fast::hash(&data), crc);
+
+            assert_eq!(u32_at(bytes, data_end), 0x08074b50);
+            assert_eq!(u32_at(bytes, data_end + 4), crc);
+            assert_eq!(u32_at(bytes, data_end + 8) as usize, compressed_size);
+            assert_eq!(u32_at(bytes, data_end + 12) as usize, size);
+
+            entries.push((name, String::from_utf8(data).unwrap()));
+        }
+        entries
+    }
+
+    fn columns() -> Vec<Column> {
+        vec![Column::text("name", "name"), Column::number("adults", "adults")]
+    }
+
+    fn row(name: &str, adults: &str) -> Vec<Option<String>> {
+        vec![Some(name.to_string()), Some(adults.to_string())]
+    }
+
+    #[test]
+    fn column_names_continue_past_z() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+
+    #[test]
+    fn escape_xml_escapes_markup_and_drops_control_characters() {
+        assert_eq!(escape_xml(r#"<a href="x">Tom & Jerry</a>"#), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&lt;/a&gt;");
+        assert_eq!(escape_xml("one\ttwo\nthree\u{1}\u{7f}"), "one\ttwo\nthree");
+        assert_eq!(escape_xml("Zoë's"), "Zoë's");
+    }
+
+    #[test]
+    fn cell_text_quotes_formulas() {
+        for value in ["=1+1", "+1", "-1+1", "@SUM(A1)", "\t=1", "\r=1"] {
+            assert_eq!(cell_text(value, false), format!("'{}", value));
+        }
+        assert_eq!(cell_text("Smith", false), "Smith");
+        assert_eq!(cell_text("", false), "");
+        // Numbers of numeric columns stay numbers
+        assert_eq!(cell_text("-3", true), "-3");
+        assert_eq!(cell_text("-3", false), "'-3");
+        assert_eq!(cell_text("=1+1", true), "'=1+1");
+    }
+
+    #[test]
+    fn csv_rows_quote_formulas() {
+        let mut writer = TableWriter::new(Format::Csv, &columns());
+        writer.write_row(&row("=HYPERLINK(\"http://x\")", "-2")).unwrap();
+        writer.write_row(&[Some("Ann".to_string()), None]).unwrap();
+        let csv = String::from_utf8(writer.finish().unwrap().to_vec()).unwrap();
+
+        assert_eq!(csv, "name,adults\n\"'=HYPERLINK(\"\"http://x\"\")\",-2\nAnn,\n");
+    }
+
+    #[test]
+    fn xlsx_round_trips_through_a_zip_reader() {
+        let mut writer = TableWriter::new(Format::Xlsx, &columns());
+        writer.write_row(&row("Ann & Bob", "2")).unwrap();
+        writer.write_row(&row("@cmd", "not a number")).unwrap();
+        // Sent in chunks, as a download is
+        let mut bytes = writer.take().to_vec();
+        writer.write_row(&row("Cy", "-1")).unwrap();
+        bytes.extend_from_slice(&writer.finish().unwrap());
+
+        let entries = read_zip(&bytes);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["[Content_Types].xml", "_rels/.rels", "xl/workbook.xml", "xl/_rels/workbook.xml.rels", "xl/worksheets/sheet1.xml"]
+        );
+        assert_eq!(entries[2].1, WORKBOOK);
+
+        let sheet = &entries[4].1;
+        assert!(sheet.starts_with(SHEET_START) && sheet.ends_with(SHEET_END));
+        assert!(sheet.contains(r#"<c r="B1" t="inlineStr"><is><t xml:space="preserve">adults</t></is></c>"#));
+        assert!(sheet.contains(r#"<t xml:space="preserve">Ann &amp; Bob</t>"#));
+        assert!(sheet.contains(r#"<c r="B2"><v>2</v></c>"#));
+        assert!(sheet.contains(r#"<t xml:space="preserve">'@cmd</t>"#));
+        assert!(sheet.contains(r#"<c r="B3" t="inlineStr"><is><t xml:space="preserve">not a number</t></is></c>"#));
+        assert!(sheet.contains(r#"<c r="B4"><v>-1</v></c>"#));
+    }
+
+    #[test]
+    fn zip_refuses_too_many_entries() {
+        let entries = (0..u16::MAX as usize - 2)
+            .map(|index| ZipEntry {
+                name: index.to_string(),
+                offset: 0,
+                crc: 0,
+                compressed_size: 0,
+                size: 0,
+            })
+            .collect();
+        let mut zip = ZipStream { entries, ..ZipStream::default() };
+
+        assert!(zip.add("last", b"fits").is_ok());
+        assert!(zip.start("one too many").is_err());
+    }
+
+    #[test]
+    fn zip_refuses_more_than_4_gib() {
+        let mut zip = ZipStream::default();
+        zip.start("sheet").unwrap();
+        if let Some(entry) = zip.current.as_mut() {
+            entry.size = u32::MAX as u64 - 2;
+        }
+        assert!(zip.write(b"a").is_ok());
+        assert!(zip.write(b"b").is_err());
+
+        let mut zip = ZipStream { written: u32::MAX as u64, ..ZipStream::default() };
+        assert!(zip.start("past the end").is_err());
+    }
+}
@@ -1,7 +1,11 @@
 use actix_web::{web, HttpResponse, Responder};
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use serde::{Serialize, Deserialize};
-use sqlx::{PgPool, FromRow};
+use sqlx::{PgPool, FromRow, Postgres, QueryBuilder};
+
+use crate::homegroup_waitlist;
+use crate::pagination::{Listing, Page, PageParams, SortField, Sorting};
+use crate::waitlist::Availability;
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct HomeGroup {
@@ -19,6 +23,14 @@ pub struct HomeGroup {
     pub meeting_day: Option<NaiveDate>,
 }
 
+/// A listed home group with its places taken and left
+#[derive(Debug, Serialize)]
+pub struct ListedHomeGroup {
+    #[serde(flatten)]
+    pub home_group: HomeGroup,
+    pub availability: Availability,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateHomeGroupRequest {
     pub name: String,
@@ -44,13 +56,71 @@ pub struct UpdateHomeGroupRequest {
     pub meeting_day: Option<NaiveDate>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 pub struct SearchQuery {
     pub name: Option<String>,
     pub location: Option<String>,
     pub language: Option<String>,
 }
 
+// Sorts of home group listings, newest first by default
+static HOME_GROUP_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("created_at", "COALESCE(created_at, 'epoch')", "TIMESTAMP"),
+        SortField::new("name", "LOWER(name)", "TEXT"),
+        SortField::new("location", "LOWER(COALESCE(location, ''))", "TEXT"),
+        SortField::new("id", "id", "INTEGER"),
+    ],
+    descending: true,
+    id: "id",
+};
+
+fn push_filters(query: &mut QueryBuilder<'_, Postgres>, params: &SearchQuery) {
+    if let Some(name) = &params.name {
+        query.push(" AND LOWER(name) LIKE LOWER(");
+        query.push_bind(format!("%{}%", name));
+        query.push(")");
+    }
+
+    if let Some(location) = &params.location {
+        query.push(" AND LOWER(location) LIKE LOWER(");
+        query.push_bind(format!("%{}%", location));
+        query.push(")");
+    }
+
+    if let Some(language) = &params.language {
+        query.push(" AND LOWER(language) LIKE LOWER(");
+        query.push_bind(format!("%{}%", language));
+        query.push(")");
+    }
+}
+
+// A page of the home groups matching `params`, with their places
+async fn fetch_home_groups(pool: &PgPool, params: &SearchQuery, page: &Page) -> Result<Listing<ListedHomeGroup>, sqlx::Error> {
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM homegroups WHERE 1 = 1");
+    push_filters(&mut count_query, params);
+    let (total,): (i64,) = count_query.build_query_as().fetch_one(pool).await?;
+
+    let mut query = QueryBuilder::new(format!("SELECT *, {} FROM homegroups WHERE 1 = 1", page.key_columns()));
+    push_filters(&mut query, params);
+    page.push_to(&mut query);
+
+    let rows = query.build().fetch_all(pool).await?;
+    let home_groups = page.finish(rows, total, |row| HomeGroup::from_row(row))?;
+
+    let capacities: Vec<(i32, Option<i32>)> = home_groups
+        .items
+        .iter()
+        .map(|home_group| (home_group.id, home_group.max_capacity))
+        .collect();
+    let mut availability = homegroup_waitlist::availability(pool, &capacities).await?;
+
+    Ok(home_groups.map(|home_group| ListedHomeGroup {
+        availability: availability.remove(&home_group.id).unwrap_or_default(),
+        home_group,
+    }))
+}
+
 // Create a new home group
 pub async fn add_home_group(
     pool: web::Data<PgPool>,
@@ -63,7 +133,8 @@ pub async fn add_home_group(
          (name, description, location, language, profile_picture,
          max_capacity, meeting_time, meeting_day, created_by)
          VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-         RETURNING *"#,
+         RETURNING id, name, description, location, created_by, created_at, updated_at,
+         language, profile_picture, max_capacity, meeting_time, meeting_day"#,
         home_group.name,
         home_group.description,
         home_group.location,
@@ -89,14 +160,14 @@ pub async fn add_home_group(
 // Get all home groups
 pub async fn get_all_home_groups(
     pool: web::Data<PgPool>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
+    let page = match Page::new(&page, &HOME_GROUP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    let result = sqlx::query_as!(
-        HomeGroup,
-        r#"SELECT * FROM homegroups"#
-    )
-    .fetch_all(pool.get_ref())
-    .await;
+    let result = fetch_home_groups(pool.get_ref(), &SearchQuery::default(), &page).await;
 
     match result {
         Ok(home_groups) => HttpResponse::Ok().json(home_groups),
@@ -115,7 +186,9 @@ pub async fn get_home_group(
 
     let result = sqlx::query_as!(
         HomeGroup,
-        r#"SELECT * FROM homegroups WHERE id = $1"#,
+        r#"SELECT id, name, description, location, created_by, created_at, updated_at,
+         language, profile_picture, max_capacity, meeting_time, meeting_day
+         FROM homegroups WHERE id = $1"#,
         id.into_inner()
     )
     .fetch_optional(pool.get_ref())
@@ -150,7 +223,9 @@ pub async fn update_home_group(
             meeting_time = COALESCE($7, meeting_time),
             meeting_day = COALESCE($8, meeting_day),
             updated_at = CURRENT_TIMESTAMP
-        WHERE id = $9 RETURNING *"#,
+        WHERE id = $9
+        RETURNING id, name, description, location, created_by, created_at, updated_at,
+            language, profile_picture, max_capacity, meeting_time, meeting_day"#,
            request.name,
            request.description,
            request.location,
@@ -164,6 +239,13 @@ pub async fn update_home_group(
     .fetch_one(pool.get_ref())
     .await;
 
+    // A larger capacity frees places for those waiting
+    if let (Ok(home_group), Some(_)) = (&result, request.max_capacity) {
+        if let Err(e) = homegroup_waitlist::fill_free_places(pool.get_ref(), home_group.id).await {
+            eprintln!("Failed to fill free places of home group {}: {}", home_group.id, e);
+        }
+    }
+
     match result {
         Ok(home_group) => HttpResponse::Ok().json(home_group),
         Err(e) => {
@@ -200,45 +282,14 @@ pub async fn delete_home_group(
 pub async fn search_home_groups(
     pool: web::Data<PgPool>,
     query: web::Query<SearchQuery>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
+    let page = match Page::new(&page, &HOME_GROUP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    let mut sql = String::from(
-        r#"
-        SELECT * FROM homegroups WHERE 1 = 1
-        "#
-    );
-
-    let mut bindings = vec![];
-
-
-    if let Some(name) = &query.name {
-        sql.push_str(" AND LOWER(name) LIKE LOWER($1)");
-        bindings.push(format!("%{}%", name));
-    }
-
-
-    if let Some(location) = &query.location {
-        let param_num = bindings.len() + 1;
-        sql.push_str(&format!(" AND LOWER(location) LIKE LOWER(${})", param_num));
-        bindings.push(format!("%{}%", location));
-
-    }
-
-    if let Some(language) = &query.language {
-        let param_num = bindings.len() + 1;
-        sql.push_str(&format!(" AND LOWER(language) LIKE LOWER(${})", param_num));
-        bindings.push(format!("%{}%", language));
-    }
-
-    sql.push_str(" ORDER BY created_at DESC");
-
-    let mut query = sqlx::query_as::<_, HomeGroup>(&sql);
-
-    for binding in bindings {
-        query = query.bind(binding);
-    }
-
-    let result = query.fetch_all(pool.get_ref()).await;
+    let result = fetch_home_groups(pool.get_ref(), &query, &page).await;
 
     match result {
         Ok(home_groups) => HttpResponse::Ok().json(home_groups),
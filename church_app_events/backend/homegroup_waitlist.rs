@@ -0,0 +1,231 @@
+use chrono::NaiveDateTime;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::env;
+
+use crate::checkin;
+use crate::email;
+use crate::waitlist::Availability;
+
+// Capacity limits and waitlists for home group registrations. A home group
+// may have a max_capacity; every approved registration holds a place in it.
+// Once the places are taken, approval is refused and new registrations join
+// a waitlist ordered by when they joined. A place freed by a decline, a
+// deleted registration or a larger capacity is offered to the first in line,
+// who is emailed and holds the place until they accept it, which approves
+// the registration, or the offer expires, which declines it and passes the
+// place on. Every change to places locks the home group row first, so
+// concurrent approvals cannot overfill it.
+
+// How long an offered place is held
+const OFFER_TTL_HOURS: i32 = 48;
+
+// Signs the registration ids of offer links
+const OFFER_PURPOSE: &str = "homegroup_offer";
+
+/// A waitlisted registration offered a place
+#[derive(Debug, sqlx::FromRow)]
+pub struct Offer {
+    pub registration_id: i32,
+    pub email: String,
+    pub name: String,
+    pub home_group_id: i32,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Link in the offer email accepting or turning down the place
+pub fn offer_link(registration_id: i32, accept: bool) -> String {
+    let url = env::var("HOME_GROUP_OFFER_URL").expect("HOME_GROUP_OFFER_URL must be set");
+    format!(
+        "{}?token={}&accept={}",
+        url,
+        checkin::signed_token(OFFER_PURPOSE, registration_id),
+        accept
+    )
+}
+
+/// The registration an offer link is for, if the signature is valid
+pub fn verify_offer(token: &str) -> Option<i32> {
+    checkin::verify_signed(OFFER_PURPOSE, token)
+}
+
+/// Lock a home group for place changes and return its capacity.
+/// None if the home group does not exist.
+pub async fn lock_group(
+    tx: &mut Transaction<'_, Postgres>,
+    home_group_id: i32,
+) -> Result<Option<Option<i32>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<i32>>("SELECT max_capacity FROM homegroups WHERE id = $1 FOR UPDATE")
+        .bind(home_group_id)
+        .fetch_optional(&mut *tx)
+        .await
+}
+
+async fn places_taken(tx: &mut Transaction<'_, Postgres>, home_group_id: i32) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM homegroupregistrations
+         WHERE home_group_id = $1
+           AND (registration_status = 'approved' OR offer_expires_at > NOW())"
+    )
+    .bind(home_group_id)
+    .fetch_one(&mut *tx)
+    .await
+}
+
+/// Whether a home group has a place left; the group must be locked
+pub async fn has_place(
+    tx: &mut Transaction<'_, Postgres>,
+    home_group_id: i32,
+    capacity: Option<i32>,
+) -> Result<bool, sqlx::Error> {
+    match capacity {
+        None => Ok(true),
+        Some(capacity) => Ok(places_taken(tx, home_group_id).await? < capacity as i64),
+    }
+}
+
+/// Offer the free places of a home group to the first in line, once the
+/// expired offers are declined; the group must be locked
+pub async fn offer_places(
+    tx: &mut Transaction<'_, Postgres>,
+    home_group_id: i32,
+    capacity: Option<i32>,
+) -> Result<Vec<Offer>, sqlx::Error> {
+    sqlx::query(
+        "UPDATE homegroupregistrations SET registration_status = 'declined', offer_expires_at = NULL
+         WHERE home_group_id = $1 AND offer_expires_at <= NOW()"
+    )
+    .bind(home_group_id)
+    .execute(&mut *tx)
+    .await?;
+
+    // No limit once the group has no capacity any more
+    let free_places = match capacity {
+        Some(capacity) => {
+            let free = capacity as i64 - places_taken(tx, home_group_id).await?;
+            if free <= 0 {
+                return Ok(Vec::new());
+            }
+            Some(free)
+        }
+        None => None,
+    };
+
+    sqlx::query_as::<_, Offer>(
+        "UPDATE homegroupregistrations
+         SET waitlisted_at = NULL, offer_expires_at = NOW() + make_interval(hours => $3)
+         WHERE id IN (
+             SELECT id FROM homegroupregistrations
+             WHERE home_group_id = $1 AND waitlisted_at IS NOT NULL
+             ORDER BY waitlisted_at, id
+             LIMIT $2
+         )
+         RETURNING id AS registration_id, email, name, home_group_id, offer_expires_at AS expires_at"
+    )
+    .bind(home_group_id)
+    .bind(free_places)
+    .bind(OFFER_TTL_HOURS)
+    .fetch_all(&mut *tx)
+    .await
+}
+
+/// Fill the free places of a home group, e.g. after its capacity was
+/// raised, and email those offered a place
+pub async fn fill_free_places(pool: &PgPool, home_group_id: i32) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let capacity = match lock_group(&mut tx, home_group_id).await? {
+        Some(capacity) => capacity,
+        None => return Ok(()),
+    };
+    let offers = offer_places(&mut tx, home_group_id, capacity).await?;
+
+    tx.commit().await?;
+    notify(pool, offers);
+    Ok(())
+}
+
+/// Pass on the places of expired offers in every home group
+pub async fn expire_offers(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let home_group_ids = sqlx::query_scalar::<_, i32>(
+        "SELECT DISTINCT home_group_id FROM homegroupregistrations WHERE offer_expires_at <= NOW()"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for home_group_id in home_group_ids {
+        fill_free_places(pool, home_group_id).await?;
+    }
+    Ok(())
+}
+
+/// Email those offered a place, in the background
+pub fn notify(pool: &PgPool, offers: Vec<Offer>) {
+    for offer in offers {
+        let pool = pool.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = email::send_homegroup_place_offered_email(
+                &pool,
+                offer.registration_id,
+                &offer.email,
+                &offer.name,
+                offer.home_group_id,
+                offer.expires_at,
+            )
+            .await
+            {
+                eprintln!("Failed to send home group place email to {}: {}", offer.email, e);
+            }
+        });
+    }
+}
+
+/// 1-based place of a registration on its waitlist, None if it is not
+/// waitlisted
+pub async fn position(pool: &PgPool, registration_id: i32) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM homegroupregistrations w
+         JOIN homegroupregistrations r ON w.home_group_id = r.home_group_id
+         WHERE r.id = $1 AND r.waitlisted_at IS NOT NULL AND w.waitlisted_at IS NOT NULL
+           AND (w.waitlisted_at, w.id) <= (r.waitlisted_at, r.id)"
+    )
+    .bind(registration_id)
+    .fetch_one(pool)
+    .await
+    .map(|position| (position > 0).then_some(position))
+}
+
+/// Places taken and left of home groups, given their ids and capacities
+pub async fn availability(
+    pool: &PgPool,
+    home_groups: &[(i32, Option<i32>)],
+) -> Result<HashMap<i32, Availability>, sqlx::Error> {
+    let ids: Vec<i32> = home_groups.iter().map(|(id, _)| *id).collect();
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let counts = sqlx::query_as::<_, (i32, i64, i64)>(
+        "SELECT home_group_id,
+                COUNT(*) FILTER (WHERE registration_status = 'approved' OR offer_expires_at > NOW()),
+                COUNT(*) FILTER (WHERE waitlisted_at IS NOT NULL)
+         FROM homegroupregistrations WHERE home_group_id = ANY($1)
+         GROUP BY home_group_id"
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?;
+
+    let counts: HashMap<i32, (i64, i64)> = counts
+        .into_iter()
+        .map(|(home_group_id, taken, waiting)| (home_group_id, (taken, waiting)))
+        .collect();
+
+    Ok(home_groups
+        .iter()
+        .map(|(id, capacity)| {
+            let (taken, waiting) = counts.get(id).copied().unwrap_or_default();
+            (*id, Availability::new(*capacity, taken, waiting))
+        })
+        .collect())
+}
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 use actix_web::{web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, postgres::PgRow, Row};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder, postgres::PgRow, Row};
 use chrono::NaiveDateTime;
-use serde_json;
+use serde_json::{self, json};
+
+use crate::export::{self, Column, ExportParams};
+use crate::homegroup_waitlist::{self, Offer};
+use crate::pagination::{Listing, Page, PageParams, SortField, Sorting};
+use crate::permission::{self, Resource};
+use crate::user::Claims;
 
 // Define Registration status enum
 #[derive(Debug, Deserialize, Serialize, Clone, sqlx::Type, PartialEq)]
@@ -56,8 +62,15 @@ pub struct RegistrationResponse {
     user_id: Option<i32>,
     registration_status: RegistrationStatus,
     registration_date: NaiveDateTime,
+    offer_expires_at: Option<NaiveDateTime>, // Set while a freed place is held for it
+    #[sqlx(default)]
+    waitlist_position: Option<i64>, // Set while the home group is full
 }
 
+// Columns of a RegistrationResponse
+const RESPONSE_COLUMNS: &str =
+    "id, email, name, home_group_id, user_id, registration_status, registration_date, offer_expires_at";
+
 #[derive(Serialize)]
 pub struct RegistrationWithGroupResponse {
     id: i32,
@@ -66,6 +79,8 @@ pub struct RegistrationWithGroupResponse {
     user_id: Option<i32>,
     registration_status: RegistrationStatus,
     registration_date: NaiveDateTime,
+    waitlisted_at: Option<NaiveDateTime>,
+    offer_expires_at: Option<NaiveDateTime>,
     group_name: String,
     group_location: Option<String>,
 }
@@ -79,76 +94,190 @@ impl<'r> sqlx::FromRow<'r, PgRow> for RegistrationWithGroupResponse {
             user_id: row.try_get("user_id")?,
             registration_status: row.try_get("registration_status")?,
             registration_date: row.try_get("registration_date")?,
+            waitlisted_at: row.try_get("waitlisted_at")?,
+            offer_expires_at: row.try_get("offer_expires_at")?,
             group_name: row.try_get("group_name")?,
             group_location: row.try_get("group_location")?,
         })
     }
 }
 
-// Create a new registration
+// Home group leaders may hold homegroup.approve for their own groups only
+async fn authorize_group(pool: &PgPool, claims: &Claims, home_group_id: i32) -> Result<(), HttpResponse> {
+    permission::require_permission(
+        pool,
+        claims,
+        permission::HOMEGROUP_APPROVE,
+        Some(Resource::HomeGroup(home_group_id)),
+    ).await
+}
+
+// Same check, for the home group a registration belongs to
+async fn authorize_registration(pool: &PgPool, claims: &Claims, registration_id: i32) -> Result<(), HttpResponse> {
+    let home_group_id = sqlx::query_scalar::<_, i32>(
+        "SELECT home_group_id FROM homegroupregistrations WHERE id = $1"
+    )
+    .bind(registration_id)
+    .fetch_optional(pool)
+    .await;
+
+    match home_group_id {
+        Ok(Some(home_group_id)) => authorize_group(pool, claims, home_group_id).await,
+        Ok(None) => Err(HttpResponse::NotFound().json("Registration not found")),
+        Err(e) => {
+            eprintln!("Failed to fetch registration: {}", e);
+            Err(HttpResponse::InternalServerError().json("Failed to fetch registration"))
+        }
+    }
+}
+
+// Listing across groups needs the permission for every group
+async fn authorize_all_groups(pool: &PgPool, claims: &Claims) -> Result<(), HttpResponse> {
+    permission::require_permission(pool, claims, permission::HOMEGROUP_APPROVE, None).await
+}
+
+const REGISTRATION_COLUMNS: &str = "hr.id, hr.email, hr.home_group_id, hr.user_id, hr.registration_status, \
+     hr.registration_date, hr.waitlisted_at, hr.offer_expires_at, hg.name AS group_name, hg.location AS group_location";
+
+// Sorts of registration listings, newest first by default
+static REGISTRATION_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("registration_date", "COALESCE(hr.registration_date, 'epoch')", "TIMESTAMP"),
+        SortField::new("email", "LOWER(hr.email)", "TEXT"),
+        SortField::new("status", "hr.registration_status::TEXT", "TEXT"),
+        SortField::new("group_name", "LOWER(hg.name)", "TEXT"),
+    ],
+    descending: true,
+    id: "hr.id",
+};
+
+// A page of the registrations passing `filter`, on homegroupregistrations hr
+// joined with homegroups hg
+async fn fetch_registrations(
+    pool: &PgPool,
+    page: &Page,
+    filter: impl Fn(&mut QueryBuilder<'_, Postgres>),
+) -> Result<Listing<RegistrationWithGroupResponse>, sqlx::Error> {
+    let mut count_query = QueryBuilder::new(
+        "SELECT COUNT(*) FROM homegroupregistrations hr JOIN homegroups hg ON hr.home_group_id = hg.id WHERE 1=1"
+    );
+    filter(&mut count_query);
+    let (total,): (i64,) = count_query.build_query_as().fetch_one(pool).await?;
+
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {}, {} FROM homegroupregistrations hr JOIN homegroups hg ON hr.home_group_id = hg.id WHERE 1=1",
+        REGISTRATION_COLUMNS,
+        page.key_columns()
+    ));
+    filter(&mut query);
+    page.push_to(&mut query);
+
+    let rows = query.build().fetch_all(pool).await?;
+    page.finish(rows, total, |row| RegistrationWithGroupResponse::from_row(row))
+}
+
+// Registrations passing `filter` per status, across all pages
+async fn count_by_status(
+    pool: &PgPool,
+    filter: impl Fn(&mut QueryBuilder<'_, Postgres>),
+) -> Result<HashMap<String, i64>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT hr.registration_status::TEXT, COUNT(*)
+         FROM homegroupregistrations hr JOIN homegroups hg ON hr.home_group_id = hg.id WHERE 1=1"
+    );
+    filter(&mut query);
+    query.push(" GROUP BY hr.registration_status");
+
+    let mut status_counts: HashMap<String, i64> = ["approved", "pending", "declined"]
+        .iter()
+        .map(|status| (status.to_string(), 0))
+        .collect();
+    for (status, count) in query.build_query_as::<(String, i64)>().fetch_all(pool).await? {
+        status_counts.insert(status, count);
+    }
+    Ok(status_counts)
+}
+
+// Create a new registration; it joins the waitlist when the group is full
 pub async fn create_registration(
     pool: web::Data<PgPool>,
+    claims: Claims,
     registration_data: web::Json<HomeGroupRegistrationRequest>,
 ) -> impl Responder {
-    // Check if the user is already registered for this home group
-    let existing = sqlx::query!(
-        "SELECT user_id FROM homegroupregistrations WHERE email = $1 AND home_group_id = $2",
-        registration_data.email,
-        registration_data.home_group_id
-    )
-    .fetch_optional(pool.get_ref())
-    .await;
+    if let Err(response) = authorize_group(&pool, &claims, registration_data.home_group_id).await {
+        return response;
+    }
 
-    match existing {
-        Ok(Some(_)) => {
-            HttpResponse::BadRequest().json("This email has already registered for this home group")
+    // Places are counted with the group locked, so concurrent approvals cannot overfill it
+    let result: Result<Result<RegistrationResponse, HttpResponse>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let capacity = match homegroup_waitlist::lock_group(&mut tx, registration_data.home_group_id).await? {
+            Some(capacity) => capacity,
+            None => return Ok(Err(HttpResponse::NotFound().json("Home group not found"))),
+        };
+
+        // Check if the user is already registered for this home group
+        let existing = sqlx::query!(
+            "SELECT user_id FROM homegroupregistrations WHERE email = $1 AND home_group_id = $2",
+            registration_data.email,
+            registration_data.home_group_id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        if existing.is_some() {
+            return Ok(Err(
+                HttpResponse::BadRequest().json("This email has already registered for this home group")
+            ));
         }
-        Ok(None) => {
-            // Determine initial status based on the request
-            let initial_status =
-            match registration_data.registration_status {
-                RegistrationStatus::Declined => RegistrationStatus::Declined,
-                _ => RegistrationStatus::Pending,
-            };
 
-            let result = sqlx::query!(
-                r#"
-                INSERT INTO homegroupregistrations
-                (email, name, home_group_id, user_id,
-                registration_date, registration_status)
-                VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP, $5)
-                RETURNING id, email, name, home_group_id, user_id,
-                registration_status as "registration_status!: RegistrationStatus",
-                registration_date
-                "#,
-                registration_data.email,
-                registration_data.name,
-                registration_data.home_group_id,
-                registration_data.user_id,
-                initial_status as RegistrationStatus,
-            )
-            .fetch_one(pool.get_ref())
-            .await;
-
-            match result {
-                Ok(registration) => HttpResponse::Ok().json(RegistrationResponse {
-                    id: registration.id,
-                    email: registration.email,
-                    name: registration.name,
-                    home_group_id: registration.home_group_id,
-                    user_id: registration.user_id,
-                    registration_status: registration.registration_status,
-                    registration_date: registration.registration_date.expect("Registration date should be present"),
-                }),
-                Err(e) => {
-                    eprintln!("Failed to create registration: {}", e);
-                    HttpResponse::InternalServerError().json(format!("Failed to create registration: {}", e))
-                }
-            }
+        // Determine initial status based on the request
+        let initial_status =
+        match registration_data.registration_status {
+            RegistrationStatus::Declined => RegistrationStatus::Declined,
+            _ => RegistrationStatus::Pending,
+        };
+
+        // Join the waitlist when every place is taken
+        let waitlisted = initial_status != RegistrationStatus::Declined
+            && !homegroup_waitlist::has_place(&mut tx, registration_data.home_group_id, capacity).await?;
+
+        let registration = sqlx::query_as::<_, RegistrationResponse>(&format!(
+            "INSERT INTO homegroupregistrations
+             (email, name, home_group_id, user_id,
+             registration_date, registration_status, waitlisted_at)
+             VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP, $5, CASE WHEN $6 THEN NOW() END)
+             RETURNING {}",
+            RESPONSE_COLUMNS
+        ))
+        .bind(&registration_data.email)
+        .bind(&registration_data.name)
+        .bind(registration_data.home_group_id)
+        .bind(registration_data.user_id)
+        .bind(initial_status)
+        .bind(waitlisted)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Ok(registration))
+    }
+    .await;
+
+    match result {
+        Ok(Ok(mut registration)) => {
+            registration.waitlist_position = homegroup_waitlist::position(pool.get_ref(), registration.id)
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Failed to get waitlist position of registration {}: {}", registration.id, e);
+                    None
+                });
+            HttpResponse::Ok().json(registration)
         }
+        Ok(Err(response)) => response,
         Err(e) => {
-            eprintln!("Database error: {}", e);
-            HttpResponse::InternalServerError().json(format!("Database error: {}", e))
+            eprintln!("Failed to create registration: {}", e);
+            HttpResponse::InternalServerError().json(format!("Failed to create registration: {}", e))
         }
     }
 }
@@ -156,38 +285,19 @@ pub async fn create_registration(
 // Get all registrations with group details
 pub async fn get_all_registrations(
     pool: web::Data<PgPool>,
+    claims: Claims,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        SELECT hr.id, hr.email, hr.home_group_id, hr.user_id,
-               hr.registration_status as "registration_status!: RegistrationStatus",
-               hr.registration_date,
-               hg.name as group_name, hg.location as group_location
-        FROM homegroupregistrations hr
-        JOIN homegroups hg ON hr.home_group_id = hg.id
-        ORDER BY hr.registration_date DESC
-        "#
-    )
-    .fetch_all(pool.get_ref())
-    .await;
+    if let Err(response) = authorize_all_groups(&pool, &claims).await {
+        return response;
+    }
+    let page = match Page::new(&page, &REGISTRATION_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    match result {
-        Ok(registrations) => {
-            let response: Vec<RegistrationWithGroupResponse> = registrations
-                .iter()
-                .map(|r| RegistrationWithGroupResponse {
-                    id: r.id,
-                    email: r.email.clone(),
-                    home_group_id: r.home_group_id,
-                    user_id: r.user_id,
-                    registration_status: r.registration_status.clone(),
-                    registration_date: r.registration_date.expect("Registration date should be present"),
-                    group_name: r.group_name.clone(),
-                    group_location: r.group_location.clone(),
-                })
-                .collect();
-            HttpResponse::Ok().json(response)
-        }
+    match fetch_registrations(pool.get_ref(), &page, |_| {}).await {
+        Ok(registrations) => HttpResponse::Ok().json(registrations),
         Err(e) => {
             eprintln!("Failed to fetch registrations: {}", e);
             HttpResponse::InternalServerError().json("Failed to fetch registrations")
@@ -198,60 +308,38 @@ pub async fn get_all_registrations(
 // Get registrations for a specific home group with counts
 pub async fn get_registrations_by_group(
     pool: web::Data<PgPool>,
+    claims: Claims,
     home_group_id: web::Path<i32>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        WITH status_counts AS (
-            SELECT
-                COUNT(CASE WHEN registration_status = 'approved' THEN 1 END) as approved_count,
-                COUNT(CASE WHEN registration_status = 'pending' THEN 1 END) as pending_count,
-                COUNT(CASE WHEN registration_status = 'declined' THEN 1 END) as declined_count
-            FROM homegroupregistrations
-            WHERE home_group_id = $1
-        )
-        SELECT hr.id, hr.email, hr.home_group_id, hr.user_id,
-               hr.registration_status as "registration_status!: RegistrationStatus",
-               hr.registration_date,
-               hg.name as group_name, hg.location as group_location,
-               sc.approved_count, sc.pending_count, sc.declined_count
-        FROM homegroupregistrations hr
-        JOIN homegroups hg ON hr.home_group_id = hg.id
-        CROSS JOIN status_counts sc
-        WHERE hr.home_group_id = $1
-        ORDER BY hr.registration_date DESC
-        "#,
-        home_group_id.into_inner()
-    )
-    .fetch_all(pool.get_ref())
+    let home_group_id = home_group_id.into_inner();
+    if let Err(response) = authorize_group(&pool, &claims, home_group_id).await {
+        return response;
+    }
+    let page = match Page::new(&page, &REGISTRATION_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let in_group = |query: &mut QueryBuilder<'_, Postgres>| {
+        query.push(" AND hr.home_group_id = ");
+        query.push_bind(home_group_id);
+    };
+    let result = async {
+        let registrations = fetch_registrations(pool.get_ref(), &page, in_group).await?;
+        let counts = count_by_status(pool.get_ref(), in_group).await?;
+        Ok::<_, sqlx::Error>((registrations, counts))
+    }
     .await;
 
     match result {
-        Ok(registrations) => {
-            if registrations.is_empty() {
+        Ok((registrations, counts)) => {
+            if registrations.total == 0 {
                 return HttpResponse::NotFound().json("No registrations found for this home group");
             }
 
-            // Create a response that includes both the registration list and the counts
-            let response = serde_json::json!({
-                "counts": {
-                    "approved": registrations[0].approved_count,
-                    "pending": registrations[0].pending_count,
-                    "declined": registrations[0].declined_count,
-                },
-                "registrations": registrations.iter().map(|r| RegistrationWithGroupResponse {
-                    id: r.id,
-                    email: r.email.clone(),
-                    home_group_id: r.home_group_id,
-                    user_id: r.user_id,
-                    registration_status: r.registration_status.clone(),
-                    registration_date: r.registration_date.expect("Registration date should be present"),
-                    group_name: r.group_name.clone(),
-                    group_location: r.group_location.clone(),
-                }).collect::<Vec<_>>()
-            });
-
-            HttpResponse::Ok().json(response)
+            // Include the counts next to the page of registrations
+            HttpResponse::Ok().json(registrations.with("counts", json!(counts)))
         }
         Err(e) => {
             eprintln!("Failed to fetch registrations for home group: {}", e);
@@ -263,41 +351,27 @@ pub async fn get_registrations_by_group(
 // Get all registrations by email
 pub async fn get_registrations_by_email(
     pool: web::Data<PgPool>,
+    claims: Claims,
     email: web::Path<String>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        SELECT hr.id, hr.email, hr.home_group_id, hr.user_id,
-               hr.registration_status as "registration_status!: RegistrationStatus",
-               hr.registration_date,
-               hg.name as group_name, hg.location as group_location
-        FROM homegroupregistrations hr
-        JOIN homegroups hg ON hr.home_group_id = hg.id
-        WHERE hr.email = $1
-        ORDER BY hr.registration_date DESC
-        "#,
-        email.into_inner()
-    )
-    .fetch_all(pool.get_ref())
+    if let Err(response) = authorize_all_groups(&pool, &claims).await {
+        return response;
+    }
+    let email = email.into_inner();
+    let page = match Page::new(&page, &REGISTRATION_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let result = fetch_registrations(pool.get_ref(), &page, |query| {
+        query.push(" AND hr.email = ");
+        query.push_bind(email.clone());
+    })
     .await;
 
     match result {
-        Ok(registrations) => {
-            let response: Vec<RegistrationWithGroupResponse> = registrations
-                .iter()
-                .map(|r| RegistrationWithGroupResponse {
-                    id: r.id,
-                    email: r.email.clone(),
-                    home_group_id: r.home_group_id,
-                    user_id: r.user_id,
-                    registration_status: r.registration_status.clone(),
-                    registration_date: r.registration_date.expect("Registration date should be present"),
-                    group_name: r.group_name.clone(),
-                    group_location: r.group_location.clone(),
-                })
-                .collect();
-            HttpResponse::Ok().json(response)
-        }
+        Ok(registrations) => HttpResponse::Ok().json(registrations),
         Err(e) => {
             eprintln!("Failed to fetch registrations: {}", e);
             HttpResponse::InternalServerError().json("Failed to fetch registrations")
@@ -305,58 +379,190 @@ pub async fn get_registrations_by_email(
     }
 }
 
+enum StatusChange {
+    NotFound,
+    Full(Option<i64>), // The registration's place on the waitlist
+    Changed(RegistrationResponse, Vec<Offer>),
+}
+
+// Change the status of a registration with its home group locked, if its
+// status is `from`. Approval needs a free place, or puts the registration
+// on the waitlist; taking an approved or offered registration out frees its
+// place for the first in line. Any offer ends with the change.
+async fn change_status(
+    pool: &PgPool,
+    registration_id: i32,
+    from: Option<RegistrationStatus>,
+    status: RegistrationStatus,
+) -> Result<StatusChange, sqlx::Error> {
+    let home_group_id = match sqlx::query_scalar::<_, i32>(
+        "SELECT home_group_id FROM homegroupregistrations WHERE id = $1"
+    )
+    .bind(registration_id)
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(home_group_id) => home_group_id,
+        None => return Ok(StatusChange::NotFound),
+    };
+
+    let mut tx = pool.begin().await?;
+    let capacity = homegroup_waitlist::lock_group(&mut tx, home_group_id).await?.flatten();
+
+    let current = sqlx::query_as::<_, (RegistrationStatus, Option<NaiveDateTime>, bool)>(
+        "SELECT registration_status, waitlisted_at, COALESCE(offer_expires_at > NOW(), FALSE)
+         FROM homegroupregistrations WHERE id = $1"
+    )
+    .bind(registration_id)
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (current_status, waitlisted_at, offered) = match current {
+        Some((current_status, _, _)) if from.as_ref().is_some_and(|from| *from != current_status) => {
+            return Ok(StatusChange::NotFound);
+        }
+        Some(current) => current,
+        None => return Ok(StatusChange::NotFound),
+    };
+
+    let held_place = current_status == RegistrationStatus::Approved || offered;
+
+    if status == RegistrationStatus::Approved
+        && !held_place
+        && !homegroup_waitlist::has_place(&mut tx, home_group_id, capacity).await?
+    {
+        // Keeps its place in line, or joins the end of it
+        sqlx::query(
+            "UPDATE homegroupregistrations
+             SET registration_status = 'pending', waitlisted_at = COALESCE(waitlisted_at, NOW()),
+                 offer_expires_at = NULL
+             WHERE id = $1"
+        )
+        .bind(registration_id)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        return Ok(StatusChange::Full(homegroup_waitlist::position(pool, registration_id).await?));
+    }
+
+    // Only pending registrations wait
+    let waitlisted_at = match status {
+        RegistrationStatus::Pending => waitlisted_at,
+        _ => None,
+    };
+
+    let mut registration = sqlx::query_as::<_, RegistrationResponse>(&format!(
+        "UPDATE homegroupregistrations SET registration_status = $1, waitlisted_at = $2, offer_expires_at = NULL
+         WHERE id = $3
+         RETURNING {}",
+        RESPONSE_COLUMNS
+    ))
+    .bind(status.clone())
+    .bind(waitlisted_at)
+    .bind(registration_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    let offers = if held_place && status != RegistrationStatus::Approved {
+        homegroup_waitlist::offer_places(&mut tx, home_group_id, capacity).await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await?;
+
+    registration.waitlist_position = homegroup_waitlist::position(pool, registration_id).await?;
+    Ok(StatusChange::Changed(registration, offers))
+}
+
+// Response to a status change made by `action`, e.g. "update registration status"
+fn status_response(pool: &PgPool, result: Result<StatusChange, sqlx::Error>, action: &str) -> HttpResponse {
+    match result {
+        Ok(StatusChange::Changed(registration, offers)) => {
+            // A freed place goes to the first in line
+            homegroup_waitlist::notify(pool, offers);
+            HttpResponse::Ok().json(registration)
+        }
+        Ok(StatusChange::Full(waitlist_position)) => HttpResponse::Conflict().json(json!({
+            "message": "The home group is full; the registration is on the waitlist",
+            "waitlist_position": waitlist_position,
+        })),
+        Ok(StatusChange::NotFound) => HttpResponse::NotFound().json("Registration not found"),
+        Err(e) => {
+            eprintln!("Failed to {}: {}", action, e);
+            HttpResponse::InternalServerError().json(format!("Failed to {}", action))
+        }
+    }
+}
+
 // Update a registration
 pub async fn update_registration(
     pool: web::Data<PgPool>,
+    claims: Claims,
     registration_id: web::Path<i32>,
     status: web::Json<RegistrationStatus>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        UPDATE homegroupregistrations
-        SET registration_status = $1
-        WHERE id = $2
-        RETURNING id, email, name, home_group_id, user_id,
-        registration_status as "registration_status!: RegistrationStatus",
-        registration_date
-        "#,
-        status.into_inner() as RegistrationStatus,
-        registration_id.into_inner()
-    )
-    .fetch_one(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(registration) => HttpResponse::Ok().json(RegistrationResponse {
-            id: registration.id,
-            email: registration.email,
-            name: registration.name,
-            home_group_id: registration.home_group_id,
-            user_id: registration.user_id,
-            registration_status: registration.registration_status,
-            registration_date: registration.registration_date.expect("Registration date should be present"),
-        }),
-        Err(e) => {
-            eprintln!("Failed to update registration status: {}", e);
-            HttpResponse::InternalServerError().json("Failed to update registration status")
-        }
+    let registration_id = registration_id.into_inner();
+    if let Err(response) = authorize_registration(&pool, &claims, registration_id).await {
+        return response;
     }
+
+    let result = change_status(pool.get_ref(), registration_id, None, status.into_inner()).await;
+    status_response(pool.get_ref(), result, "update registration status")
 }
 
 // Delete a registration
 pub async fn delete_registration(
     pool: web::Data<PgPool>,
+    claims: Claims,
     registration_id: web::Path<i32>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        "DELETE FROM homegroupregistrations WHERE id = $1 RETURNING id",
-        registration_id.into_inner()
-    )
-    .fetch_optional(pool.get_ref())
+    let registration_id = registration_id.into_inner();
+    if let Err(response) = authorize_registration(&pool, &claims, registration_id).await {
+        return response;
+    }
+
+    // A deleted approved or offered registration frees its place for the first in line
+    let result: Result<Option<Vec<Offer>>, sqlx::Error> = async {
+        let home_group_id = match sqlx::query_scalar::<_, i32>(
+            "SELECT home_group_id FROM homegroupregistrations WHERE id = $1"
+        )
+        .bind(registration_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        {
+            Some(home_group_id) => home_group_id,
+            None => return Ok(None),
+        };
+
+        let mut tx = pool.begin().await?;
+        let capacity = homegroup_waitlist::lock_group(&mut tx, home_group_id).await?.flatten();
+
+        let deleted = sqlx::query_scalar::<_, bool>(
+            "DELETE FROM homegroupregistrations WHERE id = $1
+             RETURNING registration_status = 'approved' OR COALESCE(offer_expires_at > NOW(), FALSE)"
+        )
+        .bind(registration_id)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let offers = match deleted {
+            Some(true) => homegroup_waitlist::offer_places(&mut tx, home_group_id, capacity).await?,
+            Some(false) => Vec::new(),
+            None => return Ok(None),
+        };
+
+        tx.commit().await?;
+        Ok(Some(offers))
+    }
     .await;
 
     match result {
-        Ok(Some(_)) => HttpResponse::Ok().json("Registration deleted successfully"),
+        Ok(Some(offers)) => {
+            homegroup_waitlist::notify(pool.get_ref(), offers);
+            HttpResponse::Ok().json("Registration deleted successfully")
+        }
         Ok(None) => HttpResponse::NotFound().json("Registration not found"),
         Err(e) => {
             eprintln!("Failed to delete registration: {}", e);
@@ -365,74 +571,126 @@ pub async fn delete_registration(
     }
 }
 
-// Confirm a registration
+// Confirm a pending registration, if the group has a place left
 pub async fn confirm_registration(
     pool: web::Data<PgPool>,
+    claims: Claims,
     registration_id: web::Path<i32>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        UPDATE homegroupregistrations
-        SET registration_status = 'approved'
-        WHERE id = $1 AND registration_status = 'pending'
-        RETURNING id, email, name, home_group_id, user_id,
-        registration_status as "registration_status!: RegistrationStatus",
-        registration_date
-        "#,
-        registration_id.into_inner()
+    let registration_id = registration_id.into_inner();
+    if let Err(response) = authorize_registration(&pool, &claims, registration_id).await {
+        return response;
+    }
+
+    let result = change_status(
+        pool.get_ref(),
+        registration_id,
+        Some(RegistrationStatus::Pending),
+        RegistrationStatus::Approved,
     )
-    .fetch_one(pool.get_ref())
     .await;
-
-    match result {
-        Ok(registration) => HttpResponse::Ok().json(RegistrationResponse {
-            id: registration.id,
-            email: registration.email,
-            name: registration.name,
-            home_group_id: registration.home_group_id,
-            user_id: registration.user_id,
-            registration_status: registration.registration_status,
-            registration_date: registration.registration_date.expect("Registration date should be present"),
-        }),
-        Err(e) => {
-            eprintln!("Failed to confirm registration: {}", e);
-            HttpResponse::InternalServerError().json("Failed to confirm registration")
-        }
-    }
+    status_response(pool.get_ref(), result, "confirm registration")
 }
 
-// Decline a registration
+// Decline a pending registration
 pub async fn decline_registration(
     pool: web::Data<PgPool>,
+    claims: Claims,
     registration_id: web::Path<i32>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        UPDATE homegroupregistrations
-        SET registration_status = 'declined'
-        WHERE id = $1 AND registration_status = 'pending'
-        RETURNING id, email, name, home_group_id, user_id,
-        registration_status as "registration_status!: RegistrationStatus",
-        registration_date
-        "#,
-        registration_id.into_inner()
+    let registration_id = registration_id.into_inner();
+    if let Err(response) = authorize_registration(&pool, &claims, registration_id).await {
+        return response;
+    }
+
+    let result = change_status(
+        pool.get_ref(),
+        registration_id,
+        Some(RegistrationStatus::Pending),
+        RegistrationStatus::Declined,
     )
-    .fetch_one(pool.get_ref())
+    .await;
+    status_response(pool.get_ref(), result, "decline registration")
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OfferAnswer {
+    token: String,
+    accept: bool,
+}
+
+// Answer a place offered from the waitlist, from the links in the offer
+// email: accepting approves the registration, turning it down declines it
+// and offers the place to the next in line
+pub async fn answer_offer(
+    pool: web::Data<PgPool>,
+    request: web::Json<OfferAnswer>,
+) -> impl Responder {
+    let registration_id = match homegroup_waitlist::verify_offer(&request.token) {
+        Some(registration_id) => registration_id,
+        None => return HttpResponse::BadRequest().json(json!({ "message": "Invalid link" })),
+    };
+
+    let result: Result<Option<(RegistrationResponse, Vec<Offer>)>, sqlx::Error> = async {
+        let home_group_id = match sqlx::query_scalar::<_, i32>(
+            "SELECT home_group_id FROM homegroupregistrations WHERE id = $1"
+        )
+        .bind(registration_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        {
+            Some(home_group_id) => home_group_id,
+            None => return Ok(None),
+        };
+
+        let mut tx = pool.begin().await?;
+        let capacity = homegroup_waitlist::lock_group(&mut tx, home_group_id).await?.flatten();
+
+        // Only an offer still open can be answered
+        let status = if request.accept { RegistrationStatus::Approved } else { RegistrationStatus::Declined };
+        let registration = sqlx::query_as::<_, RegistrationResponse>(&format!(
+            "UPDATE homegroupregistrations SET registration_status = $1, offer_expires_at = NULL
+             WHERE id = $2 AND offer_expires_at > NOW()
+             RETURNING {}",
+            RESPONSE_COLUMNS
+        ))
+        .bind(status)
+        .bind(registration_id)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let registration = match registration {
+            Some(registration) => registration,
+            None => return Ok(None),
+        };
+
+        let offers = if request.accept {
+            Vec::new()
+        } else {
+            homegroup_waitlist::offer_places(&mut tx, home_group_id, capacity).await?
+        };
+
+        tx.commit().await?;
+        Ok(Some((registration, offers)))
+    }
     .await;
 
     match result {
-        Ok(registration) => HttpResponse::Ok().json(RegistrationResponse {
-            id: registration.id,
-            email: registration.email,
-            name: registration.name,
-            home_group_id: registration.home_group_id,
-            user_id: registration.user_id,
-            registration_status: registration.registration_status,
-            registration_date: registration.registration_date.expect("Registration date should be present"),
-        }),
+        Ok(Some((registration, offers))) => {
+            homegroup_waitlist::notify(pool.get_ref(), offers);
+            let message = if request.accept {
+                "Welcome to the home group!"
+            } else {
+                "Thank you for letting us know"
+            };
+            HttpResponse::Ok().json(json!({ "message": message, "registration": registration }))
+        }
+        Ok(None) => HttpResponse::Conflict().json(json!({
+            "message": "This offer has expired or was already answered"
+        })),
         Err(e) => {
-            eprintln!("Failed to decline registration: {}", e);
-            HttpResponse::InternalServerError().json("Failed to decline registration")
+            eprintln!("Failed to answer offer for registration {}: {}", registration_id, e);
+            HttpResponse::InternalServerError().json(json!({ "message": "Failed to answer the offer" }))
         }
     }
 }
@@ -443,149 +701,68 @@ pub struct SearchQuery {
     pub group_name: Option<String>,
     pub status: Option<RegistrationStatus>,
     pub user_id: Option<i32>,
+    pub home_group_id: Option<i32>,
 }
 
-// Search registrations
-pub async fn search_registrations(
-    pool: web::Data<PgPool>,
-    params: web::Query<SearchQuery>,
-) -> impl Responder {
-    let mut count_query = sqlx::QueryBuilder::new(
-        "SELECT COUNT (*) as count
-         FROM homegroupregistrations hr
-         JOIN homegroups hg ON hr.home_group_id = hg.id
-         WHERE 1=1"
-    );
-
+// Filters of a search, on homegroupregistrations hr joined with homegroups hg
+fn push_filters(query: &mut QueryBuilder<'_, Postgres>, params: &SearchQuery) {
     // Apply status filter if provided
     if let Some(status) = &params.status {
-        count_query.push(" AND hr.registration_status = ");
-        count_query.push_bind(status);
+        query.push(" AND hr.registration_status = ");
+        query.push_bind(status.clone());
     }
 
     // Email filter with improved pattern matching
     if let Some(email) = &params.email {
         let search_pattern = format!("%{}%", email.trim().to_lowercase());
-        count_query.push(" AND LOWER(hr.email) LIKE ");
-        count_query.push_bind(search_pattern);
+        query.push(" AND LOWER(hr.email) LIKE ");
+        query.push_bind(search_pattern);
     }
 
     // Group name filter with improved pattern matching
     if let Some(group_name) = &params.group_name {
         let search_pattern = format!("%{}%", group_name.trim().to_lowercase());
-        count_query.push(" AND LOWER(hg.name) LIKE ");
-        count_query.push_bind(search_pattern);
+        query.push(" AND LOWER(hg.name) LIKE ");
+        query.push_bind(search_pattern);
     }
 
     // User ID filter
     if let Some(user_id) = params.user_id {
-        count_query.push(" AND hr.user_id = ");
-        count_query.push_bind(user_id);
-    }
-
-    // Execute count query first
-    let total_count = match count_query
-        .build()
-        .fetch_one(pool.get_ref())
-        .await {
-            Ok(row) => row.get::<i64, _>("count"),
-            Err(e) => {
-                eprintln!("Count query error: {}", e);
-                return HttpResponse::InternalServerError().json("Failed to count registrations");
-            }
-        };
-
-    // Default sorting by registration date
-    let mut search_query = sqlx::QueryBuilder::new(
-        "SELECT hr.id, hr.email, hr.home_group_id, hr.user_id,
-                hr.registration_status as \"registration_status\",
-                hr.registration_date, hg.name as group_name, hg.location as group_location
-         FROM homegroupregistrations hr
-         JOIN homegroups hg ON hr.home_group_id = hg.id
-         WHERE 1=1"
-    );
-
-    // Apply status filter if provided
-    if let Some(status) = &params.status {
-        search_query.push(" AND hr.registration_status = ");
-        search_query.push_bind(status);
+        query.push(" AND hr.user_id = ");
+        query.push_bind(user_id);
     }
 
-    // Apply email filter if provided
-    if let Some(email) = &params.email {
-        let search_pattern = format!("%{}%", email.trim().to_lowercase());
-        search_query.push(" AND LOWER(hr.email) LIKE ");
-        search_query.push_bind(search_pattern);
+    // Home group filter
+    if let Some(home_group_id) = params.home_group_id {
+        query.push(" AND hr.home_group_id = ");
+        query.push_bind(home_group_id);
     }
+}
 
-    // Apply group name filter if provided
-    if let Some(group_name) = &params.group_name {
-        let search_pattern = format!("%{}%", group_name.trim().to_lowercase());
-        search_query.push(" AND LOWER(hg.name) LIKE ");
-        search_query.push_bind(search_pattern);
+// Search registrations
+pub async fn search_registrations(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    params: web::Query<SearchQuery>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    if let Err(response) = authorize_all_groups(&pool, &claims).await {
+        return response;
     }
+    let page = match Page::new(&page, &REGISTRATION_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let result = async {
+        let registrations = fetch_registrations(pool.get_ref(), &page, |query| push_filters(query, &params)).await?;
+        let status_counts = count_by_status(pool.get_ref(), |query| push_filters(query, &params)).await?;
+        Ok::<_, sqlx::Error>(registrations.with("status_counts", json!(status_counts)))
+    }
+    .await;
 
-    // Apply user ID filter if provided
-    if let Some(user_id) = params.user_id {
-        search_query.push(" AND hr.user_id = ");
-        search_query.push_bind(user_id);
-    }
-
-    // Execute the query
-    search_query.push(" ORDER BY hr.registration_date DESC");
-
-    match search_query
-        .build()
-        .fetch_all(pool.get_ref())
-        .await {
-        Ok(registrations) => {
-            // Calculate status counts
-            let status_counts = HashMap::from([
-                ("approved".to_string(),
-                    registrations.iter()
-                    .filter(|r| {
-                        let status: RegistrationStatus = r.try_get("registration_status").unwrap_or(RegistrationStatus::Pending);
-                        status == RegistrationStatus::Approved
-                    })
-                    .count()),
-                ("pending".to_string(),
-                    registrations.iter()
-                    .filter(|r| {
-                        let status: RegistrationStatus = r.try_get("registration_status").unwrap_or(RegistrationStatus::Pending);
-                        status == RegistrationStatus::Pending
-                    })
-                    .count()),
-                ("declined".to_string(),
-                    registrations.iter()
-                    .filter(|r| {
-                        let status: RegistrationStatus = r.try_get("registration_status").unwrap_or(RegistrationStatus::Pending);
-                        status == RegistrationStatus::Declined
-                    })
-                    .count()),
-            ]);
-
-            let converted_registrations: Vec<RegistrationWithGroupResponse> = registrations
-                .iter()
-                .map(|r| RegistrationWithGroupResponse {
-                    id: r.try_get("id").unwrap(),
-                    email: r.try_get("email").unwrap(),
-                    home_group_id: r.try_get("home_group_id").unwrap(),
-                    user_id: r.try_get("user_id").unwrap(),
-                    registration_status: r.try_get("registration_status").unwrap(),
-                    registration_date: r.try_get("registration_date").unwrap(),
-                    group_name: r.try_get("group_name").unwrap(),
-                    group_location: r.try_get("group_location").unwrap(),
-                })
-                .collect();
-
-            let response = serde_json::json!({
-                "registrations": converted_registrations,
-                "total": total_count as usize,
-                "status_counts": status_counts,
-            });
-
-            HttpResponse::Ok().json(response)
-        }
+    match result {
+        Ok(registrations) => HttpResponse::Ok().json(registrations),
         Err(err) => {
             eprintln!("Search error: {}", err);
             HttpResponse::InternalServerError().json("Failed to search registrations")
@@ -593,3 +770,50 @@ pub async fn search_registrations(
     }
 }
 
+// Columns of a registration export
+fn export_columns() -> Vec<Column> {
+    vec![
+        Column::number("id", "hr.id"),
+        Column::text("email", "hr.email"),
+        Column::number("user_id", "hr.user_id"),
+        Column::number("home_group_id", "hr.home_group_id"),
+        Column::text("group_name", "hg.name"),
+        Column::text("group_location", "hg.location"),
+        Column::text("status", "hr.registration_status"),
+        Column::text("registration_date", "hr.registration_date"),
+        Column::text("waitlisted_at", "hr.waitlisted_at"),
+    ]
+}
+
+// Export registrations as CSV or XLSX with the search filters. Leaders can
+// export the registrations of their own group with its home_group_id.
+pub async fn export_registrations(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    params: web::Query<SearchQuery>,
+    export_params: web::Query<ExportParams>,
+) -> impl Responder {
+    let authorized = match params.home_group_id {
+        Some(home_group_id) => authorize_group(&pool, &claims, home_group_id).await,
+        None => authorize_all_groups(&pool, &claims).await,
+    };
+    if let Err(response) = authorized {
+        return response;
+    }
+
+    let (format, columns) = match export::parse_params(&export_params, export_columns()) {
+        Ok(selection) => selection,
+        Err(response) => return response,
+    };
+
+    let mut query = export::select(&columns);
+    query.push(" FROM homegroupregistrations hr JOIN homegroups hg ON hr.home_group_id = hg.id WHERE 1=1");
+    push_filters(&mut query, &params);
+    query.push(" ORDER BY hr.registration_date DESC, hr.id DESC");
+
+    let filename = match params.home_group_id {
+        Some(home_group_id) => format!("home-group-{}-registrations", home_group_id),
+        None => "home-group-registrations".to_string(),
+    };
+    export::stream(pool.get_ref(), query, columns, format, &filename)
+}
@@ -0,0 +1,310 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use lazy_static::lazy_static;
+use redis::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use std::env;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::media::rate_limiter::RateLimiter;
+use crate::pagination::{Page, PageParams, SortField, Sorting};
+
+// Password reset links asked for within an hour, per email and per client IP
+const RESET_WINDOW: Duration = Duration::from_secs(3600);
+const RESET_MAX_PER_EMAIL: i32 = 3;
+const RESET_MAX_PER_IP: i32 = 20;
+
+/// LoginGuard throttles failed password attempts per identifier and per client IP.
+/// An identifier that reaches its limit is locked until the window expires.
+/// Requests for password reset links are limited the same way, so the
+/// endpoint cannot be used to flood an inbox.
+pub struct LoginGuard {
+    per_identifier: RateLimiter,
+    per_ip: RateLimiter,
+    resets_per_email: RateLimiter,
+    resets_per_ip: RateLimiter,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct FailedLoginAttempt {
+    pub id: i32,
+    pub identifier: String,
+    pub user_id: Option<i32>,
+    pub ip_address: String,
+    pub reason: String,
+    pub attempted_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FailedLoginQuery {
+    pub identifier: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_id: Option<i32>,
+}
+
+// Sorts of failed login attempts, most recent first by default
+static ATTEMPT_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("attempted_at", "COALESCE(attempted_at, TIMESTAMP 'epoch')", "TIMESTAMP"),
+        SortField::new("identifier", "identifier", "TEXT"),
+        SortField::new("ip_address", "ip_address", "TEXT"),
+    ],
+    descending: true,
+    id: "id",
+};
+
+impl LoginGuard {
+    /// Creates a new LoginGuard instance
+    ///
+    /// # Arguments
+    /// * `client` - Redis client instance
+    /// * `lockout` - Window in which failures are counted, and lock duration once exceeded
+    /// * `max_identifier_failures` - Failures allowed per email/username within the window
+    /// * `max_ip_failures` - Failures allowed per client IP within the window
+    pub fn new_with_client(
+        client: Client,
+        lockout: Duration,
+        max_identifier_failures: i32,
+        max_ip_failures: i32,
+    ) -> Self {
+        Self {
+            per_identifier: RateLimiter::new_with_prefix(
+                client.clone(), "login:identifier", lockout, max_identifier_failures,
+            ),
+            per_ip: RateLimiter::new_with_prefix(client.clone(), "login:ip", lockout, max_ip_failures),
+            resets_per_email: RateLimiter::new_with_prefix(
+                client.clone(), "password_reset:email", RESET_WINDOW, RESET_MAX_PER_EMAIL,
+            ),
+            resets_per_ip: RateLimiter::new_with_prefix(client, "password_reset:ip", RESET_WINDOW, RESET_MAX_PER_IP),
+        }
+    }
+
+    /// Returns the seconds to wait if the identifier or the IP is throttled
+    pub async fn retry_after(&self, identifier: &str, ip: &str) -> Result<Option<u64>> {
+        let by_identifier = self.per_identifier.retry_after(identifier).await?;
+        let by_ip = self.per_ip.retry_after(ip).await?;
+        Ok(by_identifier.max(by_ip))
+    }
+
+    /// Counts a failed attempt and records it for admins
+    pub async fn record_failure(
+        &self,
+        pool: &PgPool,
+        identifier: &str,
+        ip: &str,
+        user_id: Option<i32>,
+        reason: &str,
+    ) -> Result<()> {
+        self.per_identifier.hit(identifier).await?;
+        self.per_ip.hit(ip).await?;
+
+        sqlx::query(
+            "INSERT INTO failed_login_attempts (identifier, user_id, ip_address, reason)
+             VALUES ($1, $2, $3, $4)"
+        )
+        .bind(identifier)
+        .bind(user_id)
+        .bind(ip)
+        .bind(reason)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Clears the identifier's failures after a successful login
+    pub async fn record_success(&self, identifier: &str) -> Result<()> {
+        self.per_identifier.reset(identifier).await
+    }
+
+    /// Counts a request for a password reset link, unless the email or the
+    /// IP is throttled; then returns the seconds to wait
+    pub async fn record_reset_request(&self, email: &str, ip: &str) -> Result<Option<u64>> {
+        let by_email = self.resets_per_email.retry_after(email).await?;
+        let by_ip = self.resets_per_ip.retry_after(ip).await?;
+        if let Some(retry_after) = by_email.max(by_ip) {
+            return Ok(Some(retry_after));
+        }
+
+        self.resets_per_email.hit(email).await?;
+        self.resets_per_ip.hit(ip).await?;
+        Ok(None)
+    }
+}
+
+// Identifiers are counted case-insensitively so "Admin" and "admin" share a limit
+pub fn normalize_identifier(identifier: &str) -> String {
+    identifier.trim().to_lowercase()
+}
+
+lazy_static! {
+    // Reverse proxies in front of the server, from the comma-separated
+    // TRUSTED_PROXIES. Only their X-Forwarded-For headers are believed.
+    static ref TRUSTED_PROXIES: Vec<IpAddr> = env::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|proxy| !proxy.is_empty())
+        .filter_map(|proxy| match proxy.parse() {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                eprintln!("Ignoring invalid TRUSTED_PROXIES entry {}", proxy);
+                None
+            }
+        })
+        .collect();
+}
+
+// The client behind a chain of proxies: the peer, unless it is a trusted
+// proxy, then the last X-Forwarded-For hop not added by a trusted proxy.
+// Hops further left are written by the client and can be anything.
+fn forwarded_client(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+// Client IP for throttling and audit: the peer address, or the address a
+// trusted proxy forwarded for. Client-supplied headers are never believed,
+// so rotating them cannot dodge the per-IP limit.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let peer = match req.peer_addr() {
+        Some(peer) => peer.ip(),
+        None => return "unknown".to_string(),
+    };
+    let forwarded_for = req
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok());
+
+    forwarded_client(peer, forwarded_for, &TRUSTED_PROXIES).to_string()
+}
+
+pub fn too_many_attempts(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(json!({
+            "message": "Too many failed attempts, please try again later",
+            "retry_after": retry_after
+        }))
+}
+
+// List recent failed login attempts (admin only)
+pub async fn get_failed_login_attempts(
+    pool: web::Data<PgPool>,
+    params: web::Query<FailedLoginQuery>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &ATTEMPT_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let filter = |query_builder: &mut QueryBuilder<'_, Postgres>| {
+        if let Some(identifier) = &params.identifier {
+            query_builder.push(" AND identifier = ");
+            query_builder.push_bind(normalize_identifier(identifier));
+        }
+
+        if let Some(ip_address) = &params.ip_address {
+            query_builder.push(" AND ip_address = ");
+            query_builder.push_bind(ip_address.clone());
+        }
+
+        if let Some(user_id) = params.user_id {
+            query_builder.push(" AND user_id = ");
+            query_builder.push_bind(user_id);
+        }
+    };
+
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM failed_login_attempts WHERE 1=1");
+    filter(&mut count_query);
+
+    let total: i64 = match count_query.build_query_as::<(i64,)>().fetch_one(pool.get_ref()).await {
+        Ok((total,)) => total,
+        Err(e) => {
+            eprintln!("Error counting failed login attempts: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch failed login attempts"
+            }));
+        }
+    };
+
+    let mut query_builder = QueryBuilder::new(format!(
+        "SELECT id, identifier, user_id, ip_address, reason, attempted_at, {}
+         FROM failed_login_attempts WHERE 1=1",
+        page.key_columns()
+    ));
+    filter(&mut query_builder);
+    page.push_to(&mut query_builder);
+
+    let result = match query_builder.build().fetch_all(pool.get_ref()).await {
+        Ok(rows) => page.finish(rows, total, |row| FailedLoginAttempt::from_row(row)),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(attempts) => HttpResponse::Ok().json(attempts),
+        Err(e) => {
+            eprintln!("Error fetching failed login attempts: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch failed login attempts"
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let client = forwarded_client(ip("203.0.113.9"), Some("198.51.100.1"), &[ip("10.0.0.1")]);
+        assert_eq!(client, ip("203.0.113.9"));
+
+        let client = forwarded_client(ip("203.0.113.9"), Some("198.51.100.1"), &[]);
+        assert_eq!(client, ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn trusted_proxy_forwards_the_client() {
+        let client = forwarded_client(ip("10.0.0.1"), Some("198.51.100.1"), &[ip("10.0.0.1")]);
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn hops_spoofed_by_the_client_are_ignored() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        let client = forwarded_client(ip("10.0.0.1"), Some("1.2.3.4, 198.51.100.1, 10.0.0.2"), &trusted);
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn trusted_proxy_without_header_is_the_client() {
+        assert_eq!(forwarded_client(ip("10.0.0.1"), None, &[ip("10.0.0.1")]), ip("10.0.0.1"));
+        assert_eq!(forwarded_client(ip("10.0.0.1"), Some("garbage"), &[ip("10.0.0.1")]), ip("10.0.0.1"));
+    }
+}
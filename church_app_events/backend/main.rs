@@ -10,15 +10,42 @@ use crate::media::youtube_service::YouTubeService;
 use std::sync::Arc;
 use crate::media::rate_limiter::RateLimiter;
 use crate::media::cache::Cache;
+use crate::revocation::RevocationStore;
+use crate::login_guard::LoginGuard;
+use crate::oidc::OidcProvider;
 use std::time::Duration;
 use anyhow::Result;
 use std::collections::HashMap;
 mod events; // Events for the events module
+mod event_status; // Cancelling and rescheduling events for the events module
+mod recurrence; // Recurrence rules for the events module
+mod time_zone; // Event time zones for the events module
+mod event_categories; // Event categories and tags for the events module
+mod calendar; // iCalendar feed and export for the events module
+mod event_import; // Bulk event import from .ics and CSV files
 mod eventrsvp; // Event RSVPs for the events module
+mod waitlist; // Event capacity and waitlists for event RSVPs
+mod registration_form; // Custom registration questions for event RSVPs
+mod checkin; // QR code check-in and attendance for event RSVPs
 mod email; // Email for the events, homegroup, andserving modules
 mod homegroup; // Home group for the homegroup modules
 mod homegrouprsvp; // Home group RSVPs for the homegroupmodules
+mod homegroup_waitlist; // Home group capacity and waitlists for home group RSVPs
 mod user; // User for the events, homegroup, serving, and media modules
+mod session; // JWT claims and request authentication for the user module
+mod revocation; // Token revocation for the user module
+mod refresh_token; // Refresh token rotation for the user module
+mod password_reset; // Password reset by email for the user module
+mod registration; // Public self-registration for the user module
+mod login_guard; // Login brute-force protection for the user module
+mod two_factor; // TOTP two-factor authentication for the user module
+mod permission; // Fine-grained permissions for the user module
+mod audit; // Audit log of admin mutations
+mod export; // CSV and XLSX exports of RSVPs, registrations and serving sign-ups
+mod pagination; // Pagination and sorting for list and search endpoints
+mod search; // Full-text search of events, media, home groups and servings
+mod oidc; // Sign in with Google (OpenID Connect) for the user module
+mod profile; // Self-service profile for the user module
 mod serving; // Serving for the serving modules
 mod servingrsvp; // Serving RSVPs for the serving module
 mod media {
@@ -109,6 +136,47 @@ let pool = PgPool::connect(&database_url).await.unwrap();
 
     println!("Cache created successfully");
 
+    // Revoked tokens live in Redis so logout is shared across backend instances
+    let revocations = web::Data::new(RevocationStore::new_with_client(client.clone()));
+
+    // Failed password attempts are counted in Redis, per identifier and per client IP
+    let login_lockout_secs = env::var("LOGIN_LOCKOUT_SECS")
+        .unwrap_or_else(|_| "900".to_string())
+        .parse::<u64>()
+        .expect("LOGIN_LOCKOUT_SECS must be a valid number");
+
+    let login_max_failures = env::var("LOGIN_MAX_FAILURES")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse::<i32>()
+        .expect("LOGIN_MAX_FAILURES must be a valid number");
+
+    let login_ip_max_failures = env::var("LOGIN_IP_MAX_FAILURES")
+        .unwrap_or_else(|_| "20".to_string())
+        .parse::<i32>()
+        .expect("LOGIN_IP_MAX_FAILURES must be a valid number");
+
+    let login_guard = web::Data::new(LoginGuard::new_with_client(
+        client.clone(),
+        Duration::from_secs(login_lockout_secs),
+        login_max_failures,
+        login_ip_max_failures,
+    ));
+
+    // Sign in with Google; disabled unless OIDC_ISSUER_URL is set
+    let oidc = web::Data::new(OidcProvider::from_env(client.clone()));
+
+    // Places offered from home group waitlists and not taken in time go to the next in line
+    let offers_pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(15 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = homegroup_waitlist::expire_offers(&offers_pool).await {
+                eprintln!("Failed to expire home group offers: {}", e);
+            }
+        }
+    });
+
     HttpServer::new(move || {
         // Create a single YouTube service instance
         let youtube_service = web::Data::new(YouTubeService::get_instance(
@@ -140,13 +208,74 @@ let pool = PgPool::connect(&database_url).await.unwrap();
         App::new()
             .app_data(web::Data::new(pool.clone()))
             .app_data(youtube_service.clone())
-            .wrap(cors)
+            .app_data(revocations.clone())
+            .app_data(login_guard.clone())
+            .app_data(oidc.clone())
+            // Innermost, so the claims set by AuthMiddleware are available
+            .wrap(audit::AuditMiddleware)
+            // Registered before CORS so CORS stays outermost and also
+            // decorates the 401/403 responses produced by the middleware
             .wrap(user::AuthMiddleware)
+            .wrap(cors)
             // Authentication routes
             .service(
                 web::scope("/auth")
+                    .route("/register", web::post().to(registration::register))
+                    .route("/verify-email", web::get().to(registration::verify_email))
+                    .route("/verify-email/resend", web::post().to(registration::resend_verification))
                     .route("/login", web::post().to(user::login))
+                    .route("/2fa/verify", web::post().to(two_factor::verify_challenge))
+                    .route("/refresh", web::post().to(user::refresh))
+                    .route("/password/forgot", web::post().to(password_reset::forgot_password))
+                    .route("/password/reset", web::post().to(password_reset::reset_password))
                     .route("/logout", web::post().to(user::logout))
+                    .route("/oidc/authorize", web::get().to(oidc::oidc_authorize))
+                    .route("/oidc/callback", web::post().to(oidc::oidc_callback))
+            )
+            // Account settings of the logged-in user
+            .service(
+                web::scope("/me/two-factor")
+                    .route("", web::get().to(two_factor::get_two_factor_status))
+                    .route("/setup", web::post().to(two_factor::setup_two_factor))
+                    .route("/confirm", web::post().to(two_factor::confirm_two_factor))
+                    .route("/disable", web::post().to(two_factor::disable_two_factor))
+                    .route("/recovery-codes", web::post().to(two_factor::regenerate_recovery_codes))
+            )
+            // Registered after /me/two-factor, which it would otherwise shadow
+            .service(
+                web::scope("/me")
+                    .route("", web::get().to(profile::get_profile))
+                    .route("", web::put().to(profile::update_profile))
+                    .route("", web::delete().to(profile::delete_account))
+                    .route("/password", web::post().to(profile::change_password))
+                    .route("/activity", web::get().to(profile::get_activity))
+                    .route("/reauth/oidc", web::get().to(oidc::oidc_reauthorize))
+            )
+            // Security policy
+            .service(
+                web::scope("/admin/security")
+                    .route("/two-factor-policy", web::get().to(two_factor::get_two_factor_policy))
+                    .route("/two-factor-policy", web::put().to(two_factor::update_two_factor_policy))
+            )
+            // Audit log
+            .service(
+                web::scope("/admin/audit")
+                    .route("", web::get().to(audit::get_audit_log))
+                    .route("/export", web::get().to(audit::export_audit_log))
+            )
+            // Roles, permissions and grants
+            .service(
+                web::scope("/admin/permissions")
+                    .route("", web::get().to(permission::get_permissions))
+                    .route("/roles", web::get().to(permission::get_roles))
+                    .route("/roles", web::post().to(permission::create_role))
+                    .route("/roles/{name}", web::put().to(permission::update_role))
+                    .route("/roles/{name}", web::delete().to(permission::delete_role))
+                    .route("/users/{id}", web::get().to(permission::get_user_permissions))
+                    .route("/users/{id}/roles", web::post().to(permission::assign_role))
+                    .route("/users/{id}/roles/{role}", web::delete().to(permission::remove_role))
+                    .route("/users/{id}/grants", web::post().to(permission::add_grant))
+                    .route("/grants/{id}", web::delete().to(permission::delete_grant))
             )
             // Authentication routes (existing admin routes)
             .service(
@@ -155,11 +284,21 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                     .route("/edit/{id}", web::put().to(user::update_user))
                     .route("/{id}", web::delete().to(user::delete_user))
                     .route("/search", web::get().to(user::search_users))
+                    .route("/failed-logins", web::get().to(login_guard::get_failed_login_attempts))
                     .route("/list", web::get().to(user::get_all_users))
                     .route("/{id}", web::get().to(user::get_user))
                     .route("/email/{email}", web::get().to(user::get_user_by_email))
                     .route("/username/{username}", web::get().to(user::get_user_by_username))
                     .route("/verify-password", web::post().to(user::verify_password))
+                    .route("/{id}/revoke-sessions", web::post().to(user::revoke_user_sessions))
+            )
+            // Event categories
+            .service(
+                web::scope("/admin/event-categories")
+                    .route("", web::get().to(event_categories::get_categories))
+                    .route("/add", web::post().to(event_categories::add_category))
+                    .route("/edit/{id}", web::put().to(event_categories::update_category))
+                    .route("/{id}", web::delete().to(event_categories::delete_category))
             )
             // Events
             .service(
@@ -167,6 +306,26 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                     .route("/add", web::post().to(events::add_event))
                     .route("/edit/{id}", web::put().to(events::update_event))
                     .route("/{id}", web::delete().to(events::delete_event))
+                    .route("/{id}/cancel", web::post().to(event_status::cancel_event))
+                    .route("/{id}/reschedule", web::post().to(event_status::reschedule_event))
+                    .service(
+                        web::resource("/import")
+                            .app_data(web::PayloadConfig::new(event_import::MAX_IMPORT_BYTES))
+                            .route(web::post().to(event_import::import_events))
+                    )
+                    .route("/{id}/occurrences", web::get().to(events::get_event_occurrences))
+                    .route("/{id}/occurrences/{date}", web::put().to(events::set_occurrence_override))
+                    .route("/{id}/occurrences/{date}", web::delete().to(events::delete_occurrence_override))
+                    .route("/{id}/occurrences/{date}/cancel", web::post().to(event_status::cancel_occurrence))
+                    .route("/{id}/occurrences/{date}/reschedule", web::post().to(event_status::reschedule_occurrence))
+                    .service(
+                        web::scope("/checkin")
+                            .route("", web::post().to(checkin::check_in))
+                            .route("/walk-in", web::post().to(checkin::walk_in))
+                            .route("/stats/{event_id}", web::get().to(checkin::get_attendance_stats))
+                    )
+                    .route("/{id}/questions", web::get().to(registration_form::get_questions))
+                    .route("/{id}/questions", web::put().to(registration_form::set_questions))
                     .route("/search", web::get().to(events::search_events))
                     .route("/list", web::get().to(events::get_all_events))
                     .route("/past", web::get().to(events::get_past_events))
@@ -181,8 +340,10 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                             .route("/confirm/{id}", web::post().to(eventrsvp::confirm_rsvp))
                             .route("/decline/{id}", web::post().to(eventrsvp::decline_rsvp))
                             .route("/search", web::get().to(eventrsvp::search_rsvps))
+                            .route("/export", web::get().to(eventrsvp::export_rsvps))
                             .route("/add", web::post().to(eventrsvp::create_rsvp))
                             .route("/edit/{id}", web::put().to(eventrsvp::update_rsvp))
+                            .route("/registration/{id}", web::put().to(eventrsvp::update_registration))
                             .route("/{id}", web::delete().to(eventrsvp::delete_rsvp))
                             .route("/list", web::get().to(eventrsvp::get_all_rsvps))
                             .route("/email/{email}", web::get().to(eventrsvp::get_rsvps_by_email))
@@ -214,6 +375,7 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                             .route("/confirm/{id}", web::post().to(homegrouprsvp::confirm_registration))
                             .route("/decline/{id}", web::post().to(homegrouprsvp::decline_registration))
                             .route("/search", web::get().to(homegrouprsvp::search_registrations))
+                            .route("/export", web::get().to(homegrouprsvp::export_registrations))
                             .service(
                                 web::scope("/email")
                                     .route("/send-confirmation", web::post().to(email::send_homegroup_rsvp_email))
@@ -221,15 +383,37 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                             )
                     )
             )
+            // Events and media for members, also shown before logging in
+            .route("/events/list", web::get().to(events::get_all_events))
+            .route("/events/search", web::get().to(events::search_events))
+            .route("/media/list", web::get().to(media::media::get_all_media))
+            .route("/media/search", web::get().to(media::media::search_media))
+            .route("/media/youtube/videos", web::get().to(|service: web::Data<YouTubeService>| async move {
+                service.get_channel_videos(None).await
+            }))
+            .route("/media/{id}", web::get().to(media::media::get_media))
+            // Calendar subscriptions and downloads
+            .route("/events/calendar.ics", web::get().to(calendar::get_calendar_feed))
+            .route("/events/categories", web::get().to(event_categories::get_categories))
+            .route("/events/facets", web::get().to(events::get_event_facets))
+            .route("/events/{id}/questions", web::get().to(registration_form::get_questions))
+            .route("/events/checkin/{token}.png", web::get().to(checkin::get_qr_png))
+            .route("/events/checkin/{token}.svg", web::get().to(checkin::get_qr_svg))
+            .route("/events/{id}.ics", web::get().to(calendar::get_event_ics))
+            // Search across events, media, home groups and servings
+            .route("/search", web::get().to(search::search))
+            // Answers to places offered from home group waitlists
+            .route("/home_group/offer", web::post().to(homegrouprsvp::answer_offer))
             // User Event RSVPs
             .service(
                 web::scope("events/rsvp")
                     .route("/add", web::post().to(eventrsvp::create_rsvp))
-                    .route("/edit/{id}", web::put().to(eventrsvp::update_rsvp))
-                    .route("/{id}", web::delete().to(eventrsvp::delete_rsvp))
-                    .route("/list", web::get().to(eventrsvp::get_all_rsvps))
-                    .route("/email/{email}", web::get().to(eventrsvp::get_rsvps_by_email))
-                    .route("/event/{event_id}", web::get().to(eventrsvp::get_rsvps_by_event))
+                    .route("/reconfirm", web::post().to(event_status::reconfirm_rsvp))
+                    // Logged-in users, on their own RSVPs only
+                    .route("/edit/{id}", web::put().to(eventrsvp::update_own_rsvp))
+                    .route("/registration/{id}", web::put().to(eventrsvp::update_own_registration))
+                    .route("/{id}", web::delete().to(eventrsvp::delete_own_rsvp))
+                    .route("/email/{email}", web::get().to(eventrsvp::get_own_rsvps))
             )
             // Serving routes
             .service(
@@ -244,6 +428,11 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                         web::scope("/rsvp")
                         .route("/confirm/{id}", web::post().to(servingrsvp::confirm_serving_rsvp))
                         .route("/decline/{id}", web::post().to(servingrsvp::decline_serving_rsvp))
+                        .route("/export", web::get().to(servingrsvp::export_serving_rsvps))
+                        .route("/edit/{id}", web::put().to(servingrsvp::update_serving_rsvp))
+                        .route("/{id}", web::delete().to(servingrsvp::delete_serving_rsvp))
+                        .route("/list", web::get().to(servingrsvp::get_all_serving_rsvps))
+                        .route("/search", web::get().to(servingrsvp::search_serving_rsvp))
                         .service(
                             web::scope("/email")
                             .route("/send-confirmation", web::post().to(email::send_serving_rsvp_email))
@@ -255,11 +444,9 @@ let pool = PgPool::connect(&database_url).await.unwrap();
                 // User Serving RSVPs
                 web::scope("/servings/rsvp")
                     .route("/add", web::post().to(servingrsvp::create_serving_rsvp))
-                    .route("/edit/{id}", web::put().to(servingrsvp::update_serving_rsvp))
-                    .route("/{id}", web::delete().to(servingrsvp::delete_serving_rsvp))
-                    .route("/list", web::get().to(servingrsvp::get_all_serving_rsvps))
-                    .route("/search", web::get().to(servingrsvp::search_serving_rsvp))
-
+                    // Logged-in users, on their own RSVPs only
+                    .route("/edit/{id}", web::put().to(servingrsvp::update_own_serving_rsvp))
+                    .route("/{id}", web::delete().to(servingrsvp::delete_own_serving_rsvp))
             )
             // Media routes
             .service(
@@ -2,6 +2,7 @@ use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
 use actix_web::{web, HttpResponse, Responder};
+use crate::pagination::{Page, PageParams, SortField, Sorting};
 use crate::media::{
     models::{Media, MediaType, MediaStatus, MediaUpdateRequest},
     media_repository::MediaRepository,
@@ -30,14 +31,36 @@ pub struct MediaResponse {
     pub series_order: Option<i32>,
 }
 
-/// Search request parameters for media
+/// Search request parameters for media; pages are chosen with `PageParams`
 #[derive(Debug, Deserialize, Serialize, Clone, FromRow, PartialEq)]
 pub struct SearchMediaRequest {
     pub query: String,
-    pub page: Option<i32>,
-    pub page_size: Option<i32>,
 }
 
+/// Sorts of media listings, newest first by default
+static MEDIA_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("created_at", "created_at", "TIMESTAMP"),
+        SortField::new("title", "LOWER(title)", "TEXT"),
+        SortField::new("views_count", "COALESCE(views_count, 0)", "INTEGER"),
+        SortField::new("series_order", "COALESCE(series_order, 0)", "INTEGER"),
+    ],
+    descending: true,
+    id: "id",
+};
+
+/// Sorts of media searches, best matches first by default
+static MEDIA_SEARCH_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("relevance", "relevance", "REAL"),
+        SortField::new("created_at", "created_at", "TIMESTAMP"),
+        SortField::new("title", "LOWER(title)", "TEXT"),
+        SortField::new("views_count", "COALESCE(views_count, 0)", "INTEGER"),
+    ],
+    descending: true,
+    id: "id",
+};
+
 // ===== Media CRUD Operations =====
 
 /// Creates a new media entry
@@ -78,19 +101,31 @@ pub async fn get_media(pool: web::Data<PgPool>, id: web::Path<i32>) -> impl Resp
     }
 }
 
-/// Retrieves all media entries
-pub async fn get_all_media(pool: web::Data<PgPool>) -> impl Responder {
+/// Retrieves a page of media entries
+pub async fn get_all_media(pool: web::Data<PgPool>, page: web::Query<PageParams>) -> impl Responder {
+    let page = match Page::new(&page, &MEDIA_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
     let media_repository = MediaRepository::new(pool.get_ref().clone());
-    match media_repository.get_all_media().await {
+    match media_repository.get_all_media(&page).await {
         Ok(media) => HttpResponse::Ok().json(media),
         Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
     }
 }
 
-/// Searches media based on query parameters
-pub async fn search_media(pool: web::Data<PgPool>, request: web::Query<SearchMediaRequest>) -> impl Responder {
+/// Searches media with full-text search, a page at a time
+pub async fn search_media(
+    pool: web::Data<PgPool>,
+    request: web::Query<SearchMediaRequest>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &MEDIA_SEARCH_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
     let media_repository = MediaRepository::new(pool.get_ref().clone());
-    match media_repository.search_media(request.query.clone()).await {
+    match media_repository.search_media(request.query.clone(), &page).await {
         Ok(media) => HttpResponse::Ok().json(media),
         Err(e) => HttpResponse::InternalServerError().json(e.to_string()),
     }
@@ -1,9 +1,11 @@
-use sqlx::{PgPool, Row};
+use sqlx::{FromRow, PgPool, QueryBuilder, Row};
 use std::str::FromStr;
 use chrono::NaiveDateTime;
 
 use crate::media::models::{Media, MediaStatus, MediaType, MediaUpdateRequest};
 use crate::media::error::{AppError, ErrorMessage};
+use crate::pagination::{Listing, Page};
+use crate::search::{Hit, Ranking, MEDIA};
 
 #[derive(sqlx::FromRow)]
 struct MediaRow {
@@ -36,7 +38,7 @@ impl MediaRepository {
     }
 
     /// Helper function to convert database row to Media struct
-    fn row_to_media(&self, row: sqlx::postgres::PgRow) -> Result<Media, AppError> {
+    fn row_to_media(&self, row: &sqlx::postgres::PgRow) -> Result<Media, AppError> {
         Ok(Media {
             id: row.get("id"),
             title: row.get("title"),
@@ -87,7 +89,7 @@ impl MediaRepository {
         .await
         .map_err(|e| AppError::DatabaseError(e))?;
 
-        self.row_to_media(row)
+        self.row_to_media(&row)
             .map_err(|e| AppError::BadRequest(ErrorMessage::from(e.to_string())))
     }
 
@@ -98,7 +100,7 @@ impl MediaRepository {
             .fetch_optional(&self.pool)
             .await
         {
-            Ok(Some(row)) => self.row_to_media(row),
+            Ok(Some(row)) => self.row_to_media(&row),
             Ok(None) => Err(AppError::NotFound(ErrorMessage::from("Media not found"))),
             Err(e) => Err(AppError::DatabaseError(e)),
         }
@@ -134,7 +136,7 @@ impl MediaRepository {
         .await
         .map_err(|e| AppError::DatabaseError(e))?;
 
-        self.row_to_media(row)
+        self.row_to_media(&row)
             .map_err(|e| AppError::BadRequest(ErrorMessage::from(e.to_string())))
     }
 
@@ -149,39 +151,52 @@ impl MediaRepository {
         }
     }
 
-    /// Retrieves all media entries, ordered by creation date
-    pub async fn get_all_media(&self) -> Result<Vec<Media>, AppError> {
-        match sqlx::query("SELECT * FROM media ORDER BY created_at DESC")
+    /// Retrieves a page of media entries
+    pub async fn get_all_media(&self, page: &Page) -> Result<Listing<Media>, AppError> {
+        let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM media")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        let mut query = QueryBuilder::new(format!("SELECT *, {} FROM media WHERE 1=1", page.key_columns()));
+        page.push_to(&mut query);
+
+        let rows = query
+            .build()
             .fetch_all(&self.pool)
             .await
-        {
-            Ok(rows) => {
-                let media_result: Result<Vec<Media>, _> = rows
-                    .into_iter()
-                    .map(|row| self.row_to_media(row))
-                    .collect();
-                media_result
-            }
-            Err(e) => Err(AppError::DatabaseError(e))
-        }
+            .map_err(AppError::DatabaseError)?;
+        page.finish(rows, total, |row| self.row_to_media(row))
     }
 
-    /// Searches for media entries by title
-    pub async fn search_media(&self, search_query: String) -> Result<Vec<Media>, AppError> {
-        let search_pattern = format!("%{}%", search_query);
-        match sqlx::query("SELECT * FROM media WHERE title ILIKE $1 ORDER BY created_at DESC")
-            .bind(search_pattern)
+    /// Searches media entries by their title and description, a page at a
+    /// time. Pages may be sorted by `relevance`.
+    pub async fn search_media(&self, search_query: String, page: &Page) -> Result<Listing<Hit<Media>>, AppError> {
+        let text = search_query.trim();
+
+        let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM media WHERE 1=1");
+        MEDIA.push_match(&mut count_query, text);
+        let (total,): (i64,) = count_query
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        let mut query = QueryBuilder::new(format!("SELECT *, {} FROM (SELECT *", page.key_columns()));
+        MEDIA.push_ranking(&mut query, text);
+        query.push(" FROM media WHERE 1=1");
+        MEDIA.push_match(&mut query, text);
+        query.push(") AS hits WHERE 1=1");
+        page.push_to(&mut query);
+
+        let rows = query
+            .build()
             .fetch_all(&self.pool)
             .await
-        {
-            Ok(rows) => {
-                let media_result: Result<Vec<Media>, _> = rows
-                    .into_iter()
-                    .map(|row| self.row_to_media(row))
-                    .collect();
-                media_result
-            }
-            Err(e) => Err(AppError::DatabaseError(e))
-        }
+            .map_err(AppError::DatabaseError)?;
+        page.finish(rows, total, |row| {
+            let ranking = Ranking::from_row(row).map_err(AppError::DatabaseError)?;
+            Ok(Hit::new(self.row_to_media(row)?, Some(&ranking)))
+        })
     }
 }
@@ -5,6 +5,7 @@ use std::time::Duration;
 /// RateLimiter handles request rate limiting using Redis
 pub struct RateLimiter {
     client: Client,
+    prefix: String,
     window: Duration,
     max_requests: i32,
 }
@@ -17,7 +18,27 @@ impl RateLimiter {
     /// * `window` - Time window for rate limiting
     /// * `max_requests` - Maximum allowed requests within window
     pub fn new_with_client(client: Client, window: Duration, max_requests: i32) -> Self {
-        Self { client, window, max_requests }
+        Self::new_with_prefix(client, "", window, max_requests)
+    }
+
+    /// Creates a new RateLimiter whose Redis keys are namespaced by `prefix`,
+    /// so several limiters can share one Redis instance
+    ///
+    /// # Arguments
+    /// * `client` - Redis client instance
+    /// * `prefix` - Namespace prepended to every key
+    /// * `window` - Time window for rate limiting
+    /// * `max_requests` - Maximum allowed requests within window
+    pub fn new_with_prefix(client: Client, prefix: &str, window: Duration, max_requests: i32) -> Self {
+        Self { client, prefix: prefix.to_string(), window, max_requests }
+    }
+
+    fn key(&self, id: &str) -> String {
+        if self.prefix.is_empty() {
+            id.to_string()
+        } else {
+            format!("{}:{}", self.prefix, id)
+        }
     }
 
     /// Checks if a request from an IP should be rate limited
@@ -30,24 +51,69 @@ impl RateLimiter {
     /// * `Ok(false)` if rate limit exceeded
     pub async fn check_rate_limit(&self, ip: &str) -> Result<bool> {
         let mut conn = self.client.get_async_connection().await?;
+        let key = self.key(ip);
 
-        match conn.get::<_, Option<String>>(ip).await? {
+        match conn.get::<_, Option<String>>(&key).await? {
             Some(count) => {
                 let count: i32 = count.parse()?;
                 if count >= self.max_requests {
                     Ok(false)
                 } else {
                     let _ : i64 =
-                    conn.incr(ip, 1).await?;
+                    conn.incr(&key, 1).await?;
                     Ok(true)
                 }
             }
             None => {
                let _ : () =
-                conn.set_ex(ip, "0", self.window.as_secs() as usize).await?;
+                conn.set_ex(&key, "0", self.window.as_secs() as usize).await?;
                 Ok(true)
             }
         }
     }
-}
 
+    /// Checks whether an id is over the limit without counting a request
+    ///
+    /// # Arguments
+    /// * `id` - Identifier to check (IP address, username, ...)
+    ///
+    /// # Returns
+    /// * `Ok(Some(secs))` with the seconds until the id is allowed again
+    /// * `Ok(None)` if the id is under the limit
+    pub async fn retry_after(&self, id: &str) -> Result<Option<u64>> {
+        let mut conn = self.client.get_async_connection().await?;
+        let key = self.key(id);
+
+        match conn.get::<_, Option<i32>>(&key).await? {
+            Some(count) if count >= self.max_requests => {
+                let ttl: i64 = conn.ttl(&key).await?;
+                Ok(Some(ttl.max(1) as u64))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Counts one event (e.g. a failed login) for an id.
+    /// Reaching the limit restarts the window, so the id stays blocked
+    /// for a full window after its last counted event.
+    ///
+    /// # Returns
+    /// * `Ok(count)` - Number of events counted in the current window
+    pub async fn hit(&self, id: &str) -> Result<i32> {
+        let mut conn = self.client.get_async_connection().await?;
+        let key = self.key(id);
+
+        let count: i32 = conn.incr(&key, 1).await?;
+        if count == 1 || count >= self.max_requests {
+            let _ : bool = conn.expire(&key, self.window.as_secs() as usize).await?;
+        }
+        Ok(count)
+    }
+
+    /// Clears the counter of an id
+    pub async fn reset(&self, id: &str) -> Result<()> {
+        let mut conn = self.client.get_async_connection().await?;
+        let _ : i64 = conn.del(self.key(id)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,588 @@
+use actix_web::{web, HttpResponse, Responder};
+use anyhow::{anyhow, bail, Result};
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use redis::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::env;
+use tokio::sync::RwLock;
+
+use crate::refresh_token::generate_token;
+use crate::two_factor;
+use crate::user::{token_response, Claims, User, UserRole};
+
+// "Sign in with Google" (or any OpenID Connect provider) using the
+// authorization code flow with PKCE:
+//
+// 1. GET /auth/oidc/authorize returns the provider URL to send the user to.
+// 2. The provider redirects to OIDC_REDIRECT_URI (a frontend route) with
+//    `code` and `state`, which the frontend posts to /auth/oidc/callback.
+// 3. The code is exchanged for an ID token, verified against the issuer's
+//    JWKS, and our own tokens are issued as for a password login.
+//
+// Signed-in users who have no password of their own confirm who they are
+// for account changes the same way, starting at /me/reauth/oidc; the
+// callback then returns a short-lived reauth_token instead of a session.
+//
+// The issuer is discovered from OIDC_ISSUER_URL/.well-known/openid-configuration,
+// so a local mock provider (e.g. mock-oauth2-server) works by pointing
+// OIDC_ISSUER_URL at it.
+
+// How long a started sign-in may take
+const PENDING_LOGIN_TTL_SECS: usize = 600;
+
+// How long a re-authentication stands in for the password
+const REAUTH_TTL_SECS: usize = 300;
+
+// Asymmetric algorithms accepted for ID tokens; HMAC would let anyone who
+// knows the client secret forge tokens
+const ACCEPTED_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+struct OidcConfig {
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+}
+
+#[derive(Deserialize, Clone)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+// Stored in Redis between the authorize and callback steps, keyed by `state`
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    code_verifier: String,
+    nonce: String,
+    #[serde(default)]
+    reauth_user_id: Option<i32>, // Set when a signed-in user re-authenticates
+}
+
+#[derive(Deserialize)]
+struct TokenEndpointResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    // Some providers send "true" as a string
+    email_verified: Option<Value>,
+    nonce: Option<String>,
+    picture: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+/// OidcProvider talks to the configured OpenID Connect issuer and caches
+/// its metadata and signing keys
+pub struct OidcProvider {
+    config: Option<OidcConfig>,
+    redis: Client,
+    http: reqwest::Client,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl OidcProvider {
+    /// Creates a new OidcProvider from the OIDC_* environment variables.
+    /// Sign-in stays disabled when OIDC_ISSUER_URL is not set.
+    ///
+    /// # Arguments
+    /// * `redis` - Redis client instance, used for pending sign-ins
+    pub fn from_env(redis: Client) -> Self {
+        let config = env::var("OIDC_ISSUER_URL").ok().map(|issuer_url| OidcConfig {
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id: env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID must be set"),
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: env::var("OIDC_REDIRECT_URI").expect("OIDC_REDIRECT_URI must be set"),
+        });
+
+        Self {
+            config,
+            redis,
+            http: reqwest::Client::new(),
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    fn config(&self) -> Result<&OidcConfig> {
+        self.config.as_ref().ok_or_else(|| anyhow!("OIDC is not configured"))
+    }
+
+    async fn metadata(&self) -> Result<ProviderMetadata> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", self.config()?.issuer_url);
+        let metadata: ProviderMetadata = self.http.get(&url).send().await?.error_for_status()?.json().await?;
+
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    // Signing key for `kid`; the JWKS is reloaded once when the key is
+    // unknown, which is how providers roll their keys
+    async fn decoding_key(&self, kid: &str) -> Result<DecodingKey> {
+        if let Some(jwks) = self.jwks.read().await.as_ref() {
+            if let Some(jwk) = jwks.find(kid) {
+                return Ok(DecodingKey::from_jwk(jwk)?);
+            }
+        }
+
+        let metadata = self.metadata().await?;
+        let jwks: JwkSet = self.http.get(&metadata.jwks_uri).send().await?.error_for_status()?.json().await?;
+
+        let key = match jwks.find(kid) {
+            Some(jwk) => DecodingKey::from_jwk(jwk)?,
+            None => bail!("No signing key with kid {}", kid),
+        };
+
+        *self.jwks.write().await = Some(jwks);
+        Ok(key)
+    }
+
+    /// Starts a sign-in, or a re-authentication of a signed-in user, and
+    /// returns the provider URL to redirect the user to
+    pub async fn authorization_url(&self, reauth_user_id: Option<i32>) -> Result<String> {
+        let config = self.config()?;
+        let metadata = self.metadata().await?;
+
+        let state = generate_token();
+        let pending = PendingLogin {
+            code_verifier: generate_token(),
+            nonce: generate_token(),
+            reauth_user_id,
+        };
+        let code_challenge = BASE64URL_NOPAD.encode(&Sha256::digest(pending.code_verifier.as_bytes()));
+
+        let mut conn = self.redis.get_async_connection().await?;
+        let _: () = redis::cmd("SET")
+            .arg(format!("oidc:state:{}", state))
+            .arg(serde_json::to_string(&pending)?)
+            .arg("EX")
+            .arg(PENDING_LOGIN_TTL_SECS)
+            .query_async(&mut conn)
+            .await?;
+
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &config.client_id)
+            .append_pair("redirect_uri", &config.redirect_uri)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", &state)
+            .append_pair("nonce", &pending.nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+
+    // Pending sign-ins are single use
+    async fn take_pending(&self, state: &str) -> Result<Option<PendingLogin>> {
+        let mut conn = self.redis.get_async_connection().await?;
+        let key = format!("oidc:state:{}", state);
+
+        let (pending, _): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .query_async(&mut conn)
+            .await?;
+
+        match pending {
+            Some(pending) => Ok(Some(serde_json::from_str(&pending)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn issue_reauth(&self, user_id: i32) -> Result<String> {
+        let token = generate_token();
+        let mut conn = self.redis.get_async_connection().await?;
+        let _: () = redis::cmd("SET")
+            .arg(format!("oidc:reauth:{}", token))
+            .arg(user_id)
+            .arg("EX")
+            .arg(REAUTH_TTL_SECS)
+            .query_async(&mut conn)
+            .await?;
+        Ok(token)
+    }
+
+    /// Whether `token` is a re-authentication of `user_id`; tokens are single use
+    pub async fn take_reauth(&self, token: &str, user_id: i32) -> Result<bool> {
+        let mut conn = self.redis.get_async_connection().await?;
+        let key = format!("oidc:reauth:{}", token);
+
+        let (reauth_user_id, _): (Option<i32>, i64) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .del(&key)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(reauth_user_id == Some(user_id))
+    }
+
+    async fn exchange_code(&self, code: &str, code_verifier: &str) -> Result<String> {
+        let config = self.config()?;
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let response = self.http.post(&metadata.token_endpoint).form(&form).send().await?;
+        if !response.status().is_success() {
+            bail!("Token endpoint returned {}: {}", response.status(), response.text().await.unwrap_or_default());
+        }
+
+        let tokens: TokenEndpointResponse = response.json().await?;
+        Ok(tokens.id_token)
+    }
+
+    async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<IdTokenClaims> {
+        let config = self.config()?;
+        let metadata = self.metadata().await?;
+
+        let header = decode_header(id_token)?;
+        if !ACCEPTED_ALGORITHMS.contains(&header.alg) {
+            bail!("Unsupported ID token algorithm {:?}", header.alg);
+        }
+        let kid = header.kid.ok_or_else(|| anyhow!("ID token has no kid"))?;
+        let key = self.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)?.claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            bail!("ID token nonce does not match");
+        }
+
+        Ok(claims)
+    }
+}
+
+fn email_is_verified(claims: &IdTokenClaims) -> bool {
+    match &claims.email_verified {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    }
+}
+
+// Derive a free username (3-20 characters of [a-zA-Z0-9_-]) from an email
+async fn available_username(pool: &PgPool, email: &str) -> Result<String, sqlx::Error> {
+    let mut base: String = email
+        .split('@')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(15)
+        .collect();
+    while base.len() < 3 {
+        base.push('_');
+    }
+
+    let mut candidate = base.clone();
+    loop {
+        let taken = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE LOWER(username) = LOWER($1))"
+        )
+        .bind(&candidate)
+        .fetch_one(pool)
+        .await?;
+
+        if !taken {
+            return Ok(candidate);
+        }
+
+        let suffix = &generate_token()[..4];
+        candidate = format!("{}{}", base, suffix);
+    }
+}
+
+// Find the user linked to the provider account, or link the account to the
+// user with the same verified email, or create a new user. The caller has
+// checked the provider verified the email. None if a local account holds
+// the email without having verified it: whoever registered it may not own
+// the address, so it must not gain a session of the provider's user.
+async fn find_or_create_user(pool: &PgPool, issuer: &str, claims: &IdTokenClaims, email: &str) -> Result<Option<i32>> {
+    let linked = sqlx::query_scalar::<_, i32>(
+        "SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2"
+    )
+    .bind(issuer)
+    .bind(&claims.sub)
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(user_id) = linked {
+        return Ok(Some(user_id));
+    }
+
+    let existing = sqlx::query_as::<_, (i32, bool)>("SELECT id, email_verified FROM users WHERE LOWER(email) = LOWER($1)")
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+
+    let user_id = match existing {
+        Some((user_id, true)) => user_id,
+        Some((_, false)) => return Ok(None),
+        None => {
+            // No usable password: the user can set one through password reset
+            let password_hash = bcrypt::hash(generate_token(), bcrypt::DEFAULT_COST)?;
+            let username = available_username(pool, email).await?;
+
+            sqlx::query_scalar::<_, i32>(
+                "INSERT INTO users (email, password_hash, username, role, profile_picture, email_verified)
+                 VALUES ($1, $2, $3, $4, $5, TRUE)
+                 RETURNING id"
+            )
+            .bind(email)
+            .bind(password_hash)
+            .bind(username)
+            .bind(UserRole::User)
+            .bind(&claims.picture)
+            .fetch_one(pool)
+            .await?
+        }
+    };
+
+    sqlx::query(
+        "INSERT INTO user_identities (user_id, issuer, subject, email)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (issuer, subject) DO NOTHING"
+    )
+    .bind(user_id)
+    .bind(issuer)
+    .bind(&claims.sub)
+    .bind(email)
+    .execute(pool)
+    .await?;
+
+    Ok(Some(user_id))
+}
+
+// Start "Sign in with Google"
+pub async fn oidc_authorize(oidc: web::Data<OidcProvider>) -> impl Responder {
+    if oidc.config.is_none() {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "message": "Sign in with Google is not configured"
+        }));
+    }
+
+    match oidc.authorization_url(None).await {
+        Ok(authorization_url) => HttpResponse::Ok().json(json!({
+            "authorization_url": authorization_url
+        })),
+        Err(e) => {
+            eprintln!("Failed to start OIDC sign-in: {:?}", e);
+            HttpResponse::BadGateway().json(json!({
+                "message": "Failed to start sign-in"
+            }))
+        }
+    }
+}
+
+// Start re-authenticating the caller with Google, for account changes that
+// ask for the password
+pub async fn oidc_reauthorize(oidc: web::Data<OidcProvider>, claims: Claims) -> impl Responder {
+    if oidc.config.is_none() {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "message": "Sign in with Google is not configured"
+        }));
+    }
+
+    match oidc.authorization_url(Some(claims.sub)).await {
+        Ok(authorization_url) => HttpResponse::Ok().json(json!({
+            "authorization_url": authorization_url
+        })),
+        Err(e) => {
+            eprintln!("Failed to start OIDC re-authentication: {:?}", e);
+            HttpResponse::BadGateway().json(json!({
+                "message": "Failed to start sign-in"
+            }))
+        }
+    }
+}
+
+// A re-authentication must come from an account at the provider that is
+// linked to the user who started it
+async fn finish_reauth(pool: &PgPool, oidc: &OidcProvider, user_id: i32, claims: &IdTokenClaims) -> HttpResponse {
+    let linked: Result<bool> = async {
+        let issuer = oidc.metadata().await?.issuer;
+        Ok(sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM user_identities WHERE issuer = $1 AND subject = $2 AND user_id = $3)"
+        )
+        .bind(issuer)
+        .bind(&claims.sub)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?)
+    }
+    .await;
+
+    let reauth_token = match linked {
+        Ok(true) => oidc.issue_reauth(user_id).await,
+        Ok(false) => {
+            return HttpResponse::Forbidden().json(json!({
+                "message": "This account at the identity provider is not linked to yours"
+            }));
+        }
+        Err(e) => Err(e),
+    };
+
+    match reauth_token {
+        Ok(reauth_token) => HttpResponse::Ok().json(json!({
+            "reauth_token": reauth_token,
+            "expires_in": REAUTH_TTL_SECS
+        })),
+        Err(e) => {
+            eprintln!("Failed to re-authenticate user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to complete sign-in"
+            }))
+        }
+    }
+}
+
+// Finish "Sign in with Google" and issue our tokens
+pub async fn oidc_callback(
+    pool: web::Data<PgPool>,
+    oidc: web::Data<OidcProvider>,
+    request: web::Json<OidcCallbackRequest>,
+) -> impl Responder {
+    if oidc.config.is_none() {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "message": "Sign in with Google is not configured"
+        }));
+    }
+
+    let pending = match oidc.take_pending(&request.state).await {
+        Ok(Some(pending)) => pending,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "message": "Invalid or expired sign-in, please try again"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Failed to load pending OIDC sign-in: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to complete sign-in"
+            }));
+        }
+    };
+
+    let claims = match oidc.exchange_code(&request.code, &pending.code_verifier).await {
+        Ok(id_token) => oidc.verify_id_token(&id_token, &pending.nonce).await,
+        Err(e) => Err(e),
+    };
+
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(e) => {
+            eprintln!("OIDC sign-in rejected: {:?}", e);
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Sign-in with the identity provider failed"
+            }));
+        }
+    };
+
+    if let Some(user_id) = pending.reauth_user_id {
+        return finish_reauth(pool.get_ref(), oidc.get_ref(), user_id, &claims).await;
+    }
+
+    let email = match (&claims.email, email_is_verified(&claims)) {
+        (Some(email), true) => email.clone(),
+        _ => {
+            return HttpResponse::Forbidden().json(json!({
+                "message": "Your account at the identity provider has no verified email"
+            }));
+        }
+    };
+
+    let issuer = match oidc.metadata().await {
+        Ok(metadata) => metadata.issuer,
+        Err(e) => {
+            eprintln!("Failed to load OIDC metadata: {:?}", e);
+            return HttpResponse::BadGateway().json(json!({
+                "message": "Failed to complete sign-in"
+            }));
+        }
+    };
+
+    let user_id = match find_or_create_user(pool.get_ref(), &issuer, &claims, &email).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            return HttpResponse::Conflict().json(json!({
+                "message": "An account with this email exists but its email was never verified. \
+                            Verify it from the email we sent, then sign in with Google"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Failed to link OIDC account for {}: {:?}", email, e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to complete sign-in"
+            }));
+        }
+    };
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, email, password_hash, username, role as "role!: UserRole",
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
+        FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match user {
+        // The identity provider replaces the password, not the second factor
+        Ok(user) if user.totp_enabled => two_factor::challenge_response(pool.get_ref(), user.id).await,
+        Ok(user) => token_response(pool.get_ref(), &user, None).await,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch user"
+            }))
+        }
+    }
+}
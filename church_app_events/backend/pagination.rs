@@ -0,0 +1,556 @@
+use actix_web::HttpResponse;
+use chrono::{NaiveDate, NaiveDateTime};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::postgres::PgRow;
+use sqlx::{Postgres, QueryBuilder, Row};
+
+// Pagination and sorting of list and search endpoints. Listings take a
+// `limit` and either an `offset` (or `page` and `page_size`) or the
+// `cursor` of the previous page, and `sort` and `order` among the fields
+// each endpoint allows. They answer with the items, the total across all
+// pages and the cursor of the next page, if there is one.
+//
+// Cursors of database listings hold the sort value and id of the last item
+// seen, so pages do not shift when rows are added or removed before them.
+// Listings computed in memory, like event occurrences, hold a position.
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    page: Option<i64>, // 1-based, with page_size instead of offset and limit
+    page_size: Option<i64>,
+    cursor: Option<String>, // next_cursor of the previous page
+    sort: Option<String>,
+    order: Option<String>, // asc or desc
+}
+
+/// A field a listing can be sorted by
+pub struct SortField {
+    name: &'static str,
+    sql: &'static str,      // Expression to sort on, never NULL
+    sql_type: &'static str, // Its type, to read cursor values back
+}
+
+impl SortField {
+    pub const fn new(name: &'static str, sql: &'static str, sql_type: &'static str) -> Self {
+        SortField { name, sql, sql_type }
+    }
+
+    // Whether a cursor key reads back as the field's type, so a tampered
+    // cursor is refused instead of failing the query
+    fn accepts(&self, key: &str) -> bool {
+        match self.sql_type {
+            "DATE" => NaiveDate::parse_from_str(key, "%Y-%m-%d").is_ok(),
+            "TIMESTAMP" => NaiveDateTime::parse_from_str(key, "%Y-%m-%d %H:%M:%S%.f").is_ok(),
+            "INTEGER" => key.parse::<i32>().is_ok(),
+            "REAL" => key.parse::<f32>().is_ok(),
+            _ => true,
+        }
+    }
+}
+
+/// Sorts allowed by a database listing; the first one is the default
+pub struct Sorting {
+    pub fields: &'static [SortField],
+    pub descending: bool, // Default order
+    pub id: &'static str, // Unique integer expression breaking ties
+}
+
+/// A page of a listing
+#[derive(Debug, Serialize)]
+pub struct Listing<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl<T> Listing<T> {
+    /// Add a field next to the items, e.g. counts per status
+    pub fn with(mut self, key: &str, value: Value) -> Self {
+        self.extra.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Listing<U> {
+        Listing {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            next_cursor: self.next_cursor,
+            extra: self.extra,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    desc: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(&serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str) -> Option<Cursor> {
+        let bytes = BASE64URL_NOPAD.decode(cursor.trim().as_bytes()).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+fn bad_request(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "message": message }))
+}
+
+// Limit, offset, sort and order asked for, before the sort is resolved
+struct Request {
+    limit: i64,
+    offset: i64,
+    sort: Option<String>,
+    descending: Option<bool>,
+    cursor: Option<Cursor>,
+}
+
+impl Request {
+    #[allow(clippy::result_large_err)]
+    fn parse(params: &PageParams) -> Result<Request, HttpResponse> {
+        let descending = match params.order.as_deref().map(str::to_lowercase).as_deref() {
+            None => None,
+            Some("asc") => Some(false),
+            Some("desc") => Some(true),
+            Some(_) => return Err(bad_request("Order must be asc or desc")),
+        };
+
+        let (limit, offset) = match (params.page, params.page_size) {
+            (None, None) => (params.limit.unwrap_or(DEFAULT_LIMIT), params.offset.unwrap_or(0)),
+            (page, page_size) => {
+                let page_size = page_size.or(params.limit).unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+                match (page.unwrap_or(1).max(1) - 1).checked_mul(page_size) {
+                    Some(offset) => (page_size, offset),
+                    None => return Err(bad_request("Page is out of range")),
+                }
+            }
+        };
+        if offset < 0 {
+            return Err(bad_request("Offset cannot be negative"));
+        }
+
+        let cursor = match params.cursor.as_deref().filter(|cursor| !cursor.trim().is_empty()) {
+            None => None,
+            Some(_) if offset > 0 => return Err(bad_request("Use either a cursor or an offset")),
+            Some(cursor) => match Cursor::decode(cursor) {
+                Some(cursor) => Some(cursor),
+                None => return Err(bad_request("Invalid cursor")),
+            },
+        };
+
+        // A cursor continues the sort it was made for
+        let sort = params.sort.as_deref().map(str::trim).filter(|sort| !sort.is_empty());
+        if let Some(cursor) = &cursor {
+            if sort.is_some_and(|sort| sort != cursor.sort) || descending.is_some_and(|desc| desc != cursor.desc) {
+                return Err(bad_request("The cursor belongs to a different sort"));
+            }
+        }
+
+        Ok(Request {
+            limit: limit.clamp(1, MAX_LIMIT),
+            offset,
+            sort: sort.map(str::to_string),
+            descending,
+            cursor,
+        })
+    }
+}
+
+fn unknown_sort(sort: &str, allowed: &[&str]) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "message": format!("Cannot sort by {}", sort),
+        "sorts": allowed,
+    }))
+}
+
+/// A page of a database listing
+pub struct Page {
+    limit: i64,
+    offset: i64,
+    after: Option<(String, i64)>,
+    sort: &'static SortField,
+    descending: bool,
+    id: &'static str,
+}
+
+impl Page {
+    #[allow(clippy::result_large_err)]
+    pub fn new(params: &PageParams, sorting: &'static Sorting) -> Result<Page, HttpResponse> {
+        let request = Request::parse(params)?;
+
+        let name = request
+            .cursor
+            .as_ref()
+            .map(|cursor| cursor.sort.as_str())
+            .or(request.sort.as_deref());
+        let sort = match name {
+            None => &sorting.fields[0],
+            Some(name) => match sorting.fields.iter().find(|field| field.name == name) {
+                Some(field) => field,
+                None => {
+                    let allowed: Vec<&str> = sorting.fields.iter().map(|field| field.name).collect();
+                    return Err(unknown_sort(name, &allowed));
+                }
+            },
+        };
+
+        let after = match &request.cursor {
+            None => None,
+            Some(Cursor { key: Some(key), id: Some(id), .. }) if sort.accepts(key) => Some((key.clone(), *id)),
+            Some(_) => return Err(bad_request("Invalid cursor")),
+        };
+
+        Ok(Page {
+            limit: request.limit,
+            offset: request.offset,
+            after,
+            sort,
+            descending: request
+                .cursor
+                .as_ref()
+                .map(|cursor| cursor.desc)
+                .or(request.descending)
+                .unwrap_or(sorting.descending),
+            id: sorting.id,
+        })
+    }
+
+    /// Columns to select next to the item's, for the next cursor
+    pub fn key_columns(&self) -> String {
+        format!("({})::TEXT AS page_key, ({})::BIGINT AS page_id", self.sort.sql, self.id)
+    }
+
+    /// Cursor condition, ORDER BY, LIMIT and OFFSET, after the filters
+    pub fn push_to(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+
+        if let Some((key, id)) = &self.after {
+            let comparison = if self.descending { "<" } else { ">" };
+            query.push(format!(" AND (({}), ({})) {} (CAST(", self.sort.sql, self.id, comparison));
+            query.push_bind(key.clone());
+            query.push(format!(" AS {}), ", self.sort.sql_type));
+            query.push_bind(*id);
+            query.push(")");
+        }
+
+        query.push(format!(" ORDER BY {} {}, {} {}", self.sort.sql, direction, self.id, direction));
+        // One more row than asked for tells whether there is a next page
+        query.push(" LIMIT ");
+        query.push_bind(self.limit + 1);
+        if self.offset > 0 {
+            query.push(" OFFSET ");
+            query.push_bind(self.offset);
+        }
+    }
+
+    /// The page of `rows`, fetched with `key_columns` and `push_to`
+    pub fn finish<T, E>(
+        &self,
+        mut rows: Vec<PgRow>,
+        total: i64,
+        item: impl Fn(&PgRow) -> Result<T, E>,
+    ) -> Result<Listing<T>, E> {
+        let more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let next_cursor = match rows.last() {
+            Some(row) if more => {
+                let key = row.try_get::<String, _>("page_key").ok();
+                let id = row.try_get::<i64, _>("page_id").ok();
+                Some(
+                    Cursor {
+                        sort: self.sort.name.to_string(),
+                        desc: self.descending,
+                        key,
+                        id,
+                        position: None,
+                    }
+                    .encode(),
+                )
+            }
+            _ => None,
+        };
+
+        Ok(Listing {
+            items: rows.iter().map(item).collect::<Result<Vec<T>, E>>()?,
+            total,
+            next_cursor,
+            extra: Map::new(),
+        })
+    }
+}
+
+/// A page of a listing computed in memory. Without a sort the listing
+/// keeps its own order.
+pub struct MemoryPage {
+    limit: usize,
+    start: usize,
+    sort: Option<&'static str>,
+    descending: bool,
+}
+
+impl MemoryPage {
+    #[allow(clippy::result_large_err)]
+    pub fn new(params: &PageParams, sorts: &[&'static str]) -> Result<MemoryPage, HttpResponse> {
+        let request = Request::parse(params)?;
+
+        let name = match &request.cursor {
+            Some(cursor) => Some(cursor.sort.as_str()).filter(|sort| !sort.is_empty()),
+            None => request.sort.as_deref(),
+        };
+        let sort = match name {
+            None => None,
+            Some(name) => match sorts.iter().find(|sort| **sort == name) {
+                Some(sort) => Some(*sort),
+                None => return Err(unknown_sort(name, sorts)),
+            },
+        };
+
+        let start = match &request.cursor {
+            None => request.offset as usize,
+            Some(Cursor { position: Some(position), .. }) => *position,
+            Some(_) => return Err(bad_request("Invalid cursor")),
+        };
+
+        Ok(MemoryPage {
+            limit: request.limit as usize,
+            start,
+            sort,
+            descending: request
+                .cursor
+                .as_ref()
+                .map(|cursor| cursor.desc)
+                .or(request.descending)
+                .unwrap_or(false),
+        })
+    }
+
+    pub fn sort(&self) -> Option<&'static str> {
+        self.sort
+    }
+
+    pub fn descending(&self) -> bool {
+        self.descending
+    }
+
+    /// The page of `items`, already in order
+    pub fn finish<T>(&self, items: Vec<T>) -> Listing<T> {
+        let total = items.len();
+        let end = self.start.saturating_add(self.limit);
+
+        let next_cursor = (end < total).then(|| {
+            Cursor {
+                sort: self.sort.unwrap_or_default().to_string(),
+                desc: self.descending,
+                key: None,
+                id: None,
+                position: Some(end),
+            }
+            .encode()
+        });
+
+        Listing {
+            items: items.into_iter().skip(self.start).take(self.limit).collect(),
+            total: total as i64,
+            next_cursor,
+            extra: Map::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    static SORTING: Sorting = Sorting {
+        fields: &[
+            SortField::new("starts", "e.starts", "TIMESTAMP"),
+            SortField::new("day", "e.day", "DATE"),
+            SortField::new("seats", "e.seats", "INTEGER"),
+            SortField::new("score", "e.score", "REAL"),
+            SortField::new("title", "e.title", "TEXT"),
+        ],
+        descending: false,
+        id: "e.id",
+    };
+
+    fn params() -> PageParams {
+        PageParams {
+            limit: None,
+            offset: None,
+            page: None,
+            page_size: None,
+            cursor: None,
+            sort: None,
+            order: None,
+        }
+    }
+
+    fn cursor(sort: &str, key: &str) -> String {
+        Cursor { sort: sort.to_string(), desc: false, key: Some(key.to_string()), id: Some(7), position: None }.encode()
+    }
+
+    fn status<T>(result: Result<T, HttpResponse>) -> StatusCode {
+        match result {
+            Ok(_) => StatusCode::OK,
+            Err(response) => response.status(),
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let encoded = Cursor {
+            sort: "starts".to_string(),
+            desc: true,
+            key: Some("2024-05-01 10:00:00".to_string()),
+            id: Some(42),
+            position: None,
+        }
+        .encode();
+
+        let decoded = Cursor::decode(&encoded).unwrap();
+        assert_eq!(decoded.sort, "starts");
+        assert!(decoded.desc);
+        assert_eq!(decoded.key.as_deref(), Some("2024-05-01 10:00:00"));
+        assert_eq!(decoded.id, Some(42));
+        assert_eq!(decoded.position, None);
+    }
+
+    #[test]
+    fn garbage_cursor_is_refused() {
+        assert!(Cursor::decode("not a cursor!").is_none());
+        assert!(Cursor::decode(&BASE64URL_NOPAD.encode(b"{\"sort\":1}")).is_none());
+
+        let mut params = params();
+        params.cursor = Some("not a cursor!".to_string());
+        assert_eq!(status(Request::parse(&params)), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn page_and_page_size_give_the_offset() {
+        let mut params = params();
+        params.page = Some(3);
+        params.page_size = Some(20);
+        let request = Request::parse(&params).unwrap();
+        assert_eq!((request.limit, request.offset), (20, 40));
+
+        params.page = Some(0);
+        let request = Request::parse(&params).unwrap();
+        assert_eq!((request.limit, request.offset), (20, 0));
+
+        params.page = Some(2);
+        params.page_size = Some(10_000);
+        let request = Request::parse(&params).unwrap();
+        assert_eq!((request.limit, request.offset), (MAX_LIMIT, MAX_LIMIT));
+    }
+
+    #[test]
+    fn limit_and_offset_are_bounded() {
+        let request = Request::parse(&params()).unwrap();
+        assert_eq!((request.limit, request.offset), (DEFAULT_LIMIT, 0));
+
+        let mut params = params();
+        params.limit = Some(0);
+        params.offset = Some(5);
+        let request = Request::parse(&params).unwrap();
+        assert_eq!((request.limit, request.offset), (1, 5));
+
+        params.offset = Some(-1);
+        assert_eq!(status(Request::parse(&params)), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn huge_page_is_refused() {
+        let mut params = params();
+        params.page = Some(i64::MAX);
+        params.page_size = Some(MAX_LIMIT);
+        assert_eq!(status(Request::parse(&params)), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn cursor_and_offset_are_exclusive() {
+        let mut params = params();
+        params.offset = Some(10);
+        params.cursor = Some(cursor("title", "a"));
+        assert_eq!(status(Request::parse(&params)), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn cursor_keeps_its_sort() {
+        let mut params = params();
+        params.cursor = Some(cursor("title", "a"));
+        params.sort = Some("seats".to_string());
+        assert_eq!(status(Request::parse(&params)), StatusCode::BAD_REQUEST);
+
+        params.sort = Some("title".to_string());
+        assert_eq!(status(Page::new(&params, &SORTING)), StatusCode::OK);
+    }
+
+    #[test]
+    fn cursor_keys_must_match_the_sort_type() {
+        let valid = [
+            ("starts", "2024-05-01 10:00:00"),
+            ("starts", "2024-05-01 10:00:00.123456"),
+            ("day", "2024-05-01"),
+            ("seats", "12"),
+            ("score", "0.25"),
+            ("title", "'); DROP TABLE events; --"),
+        ];
+        for (sort, key) in valid {
+            let mut params = params();
+            params.cursor = Some(cursor(sort, key));
+            assert_eq!(status(Page::new(&params, &SORTING)), StatusCode::OK, "{} {}", sort, key);
+        }
+
+        let invalid = [
+            ("starts", "yesterday"),
+            ("day", "2024-13-01"),
+            ("seats", "99999999999"),
+            ("seats", "1.5"),
+            ("score", "lots"),
+        ];
+        for (sort, key) in invalid {
+            let mut params = params();
+            params.cursor = Some(cursor(sort, key));
+            assert_eq!(status(Page::new(&params, &SORTING)), StatusCode::BAD_REQUEST, "{} {}", sort, key);
+        }
+    }
+
+    #[test]
+    fn memory_pages_continue_from_their_position() {
+        let page = MemoryPage::new(&PageParams { limit: Some(2), ..params() }, &[]).unwrap();
+        let first = page.finish(vec![1, 2, 3, 4, 5]);
+        assert_eq!(first.items, vec![1, 2]);
+        assert_eq!(first.total, 5);
+
+        let page = MemoryPage::new(&PageParams { limit: Some(2), cursor: first.next_cursor, ..params() }, &[]).unwrap();
+        let second = page.finish(vec![1, 2, 3, 4, 5]);
+        assert_eq!(second.items, vec![3, 4]);
+
+        let page = MemoryPage::new(&PageParams { limit: Some(2), cursor: second.next_cursor, ..params() }, &[]).unwrap();
+        let last = page.finish(vec![1, 2, 3, 4, 5]);
+        assert_eq!(last.items, vec![5]);
+        assert!(last.next_cursor.is_none());
+    }
+}
@@ -0,0 +1,217 @@
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use std::env;
+
+use crate::email;
+use crate::login_guard::{client_ip, normalize_identifier, LoginGuard};
+use crate::refresh_token::{self, generate_token, hash_token};
+use crate::revocation::RevocationStore;
+use crate::user::{validate_password, ACCESS_TOKEN_TTL_SECS};
+
+// How long a reset link stays valid
+const RESET_TOKEN_TTL_MINUTES: i64 = 60;
+
+#[derive(Deserialize, Debug)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+// Create a reset token for the email (if registered) and mail the link
+async fn create_reset_token(pool: &PgPool, email: &str, reset_url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let user = sqlx::query!(
+        "SELECT id, username FROM users WHERE LOWER(email) = LOWER($1)",
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    // Only the most recent link stays valid
+    sqlx::query(
+        "UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP
+         WHERE user_id = $1 AND used_at IS NULL"
+    )
+    .bind(user.id)
+    .execute(pool)
+    .await?;
+
+    let token = generate_token();
+    let expires_at = Utc::now().naive_utc() + ChronoDuration::minutes(RESET_TOKEN_TTL_MINUTES);
+
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+         VALUES ($1, $2, $3)"
+    )
+    .bind(user.id)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    let reset_link = format!("{}?token={}", reset_url, token);
+
+    email::send_password_reset_email(
+        pool,
+        email,
+        &user.username,
+        &reset_link,
+        RESET_TOKEN_TTL_MINUTES,
+    ).await
+}
+
+// Consume a valid token and store the new password hash.
+// Returns the user id, or None if the token is unknown, used or expired.
+async fn apply_reset(
+    pool: &PgPool,
+    token: &str,
+    password_hash: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let reset = sqlx::query_as::<_, (i32, i32)>(
+        "SELECT id, user_id FROM password_reset_tokens
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+         FOR UPDATE"
+    )
+    .bind(hash_token(token))
+    .bind(Utc::now().naive_utc())
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (reset_id, user_id) = match reset {
+        Some(reset) => reset,
+        None => return Ok(None),
+    };
+
+    sqlx::query(
+        "UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2"
+    )
+    .bind(password_hash)
+    .bind(user_id)
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(reset_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(user_id))
+}
+
+fn too_many_requests(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(json!({
+            "message": "Too many reset requests, please try again later",
+            "retry_after": retry_after
+        }))
+}
+
+// Request a password reset link
+pub async fn forgot_password(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    login_guard: web::Data<LoginGuard>,
+    request: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    let reset_url = match env::var("PASSWORD_RESET_URL") {
+        Ok(reset_url) => reset_url,
+        Err(_) => {
+            eprintln!("PASSWORD_RESET_URL must be set to send reset links");
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to process password reset"
+            }));
+        }
+    };
+
+    // Counted whether or not the email is registered, so the limit tells nothing
+    let email = request.into_inner().email;
+    let ip = client_ip(&req);
+    match login_guard.record_reset_request(&normalize_identifier(&email), &ip).await {
+        Ok(Some(retry_after)) => return too_many_requests(retry_after),
+        Ok(None) => {}
+        Err(e) => eprintln!("Password reset throttling unavailable: {:?}", e),
+    }
+
+    // Processed in the background so neither the response nor its timing
+    // reveals whether the email is registered
+    let pool = pool.get_ref().clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = create_reset_token(&pool, &email, &reset_url).await {
+            eprintln!("Failed to process password reset for {}: {}", email, e);
+        }
+    });
+
+    HttpResponse::Ok().json(json!({
+        "message": "If an account exists for this email, a reset link has been sent"
+    }))
+}
+
+// Set a new password using a reset token
+pub async fn reset_password(
+    pool: web::Data<PgPool>,
+    revocations: web::Data<RevocationStore>,
+    request: web::Json<ResetPasswordRequest>,
+) -> impl Responder {
+    if let Err(e) = validate_password(&request.new_password) {
+        return HttpResponse::BadRequest().json(json!({
+            "message": e
+        }));
+    }
+
+    let password_hash = match bcrypt::hash(&request.new_password, bcrypt::DEFAULT_COST) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to hash password: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to reset password"
+            }));
+        }
+    };
+
+    let user_id = match apply_reset(pool.get_ref(), &request.token, &password_hash).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "message": "Invalid or expired reset token"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Database error while resetting password: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to reset password"
+            }));
+        }
+    };
+
+    // Sign the user out everywhere; the password change itself already succeeded
+    if let Err(e) = refresh_token::revoke_all_for_user(pool.get_ref(), user_id).await {
+        eprintln!("Failed to revoke refresh tokens for user {}: {:?}", user_id, e);
+    }
+    if let Err(e) = revocations
+        .revoke_user(user_id, Utc::now().timestamp_millis(), ACCESS_TOKEN_TTL_SECS)
+        .await
+    {
+        eprintln!("Failed to revoke sessions for user {}: {:?}", user_id, e);
+    }
+
+    HttpResponse::Ok().json(json!({
+        "message": "Password has been reset"
+    }))
+}
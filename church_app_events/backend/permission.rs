@@ -0,0 +1,564 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool};
+
+use crate::user::{Claims, UserRole};
+
+// Named permissions, also seeded in the `permissions` table
+pub const EVENTS_WRITE: &str = "events.write";
+pub const EVENTS_CHECKIN: &str = "events.checkin";
+pub const HOMEGROUP_WRITE: &str = "homegroup.write";
+pub const HOMEGROUP_APPROVE: &str = "homegroup.approve";
+pub const SERVINGS_WRITE: &str = "servings.write";
+pub const MEDIA_WRITE: &str = "media.write";
+
+// Permissions that can be granted for a single resource; the handlers
+// check the resource, the middleware only lets such grant holders through
+const RESOURCE_SCOPED: &[&str] = &[HOMEGROUP_APPROVE];
+
+/// A resource a grant can be limited to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resource {
+    HomeGroup(i32),
+}
+
+impl Resource {
+    pub fn from_parts(resource_type: &str, resource_id: i32) -> Option<Self> {
+        match resource_type {
+            "home_group" => Some(Resource::HomeGroup(resource_id)),
+            _ => None,
+        }
+    }
+
+    pub fn resource_type(&self) -> &'static str {
+        match self {
+            Resource::HomeGroup(_) => "home_group",
+        }
+    }
+
+    pub fn resource_id(&self) -> i32 {
+        match self {
+            Resource::HomeGroup(id) => *id,
+        }
+    }
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct PermissionInfo {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct Role {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct PermissionGrant {
+    pub id: i32,
+    pub user_id: i32,
+    pub permission: String,
+    pub resource_type: Option<String>,
+    pub resource_id: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RoleRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateRoleRequest {
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssignRoleRequest {
+    pub role: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GrantRequest {
+    pub permission: String,
+    pub resource_type: Option<String>,
+    pub resource_id: Option<i32>,
+}
+
+/// Whether the user holds a permission, either for every resource or,
+/// when `resource` is given, for that resource. Admins hold every permission.
+pub async fn has_permission(
+    pool: &PgPool,
+    claims: &Claims,
+    permission: &str,
+    resource: Option<Resource>,
+) -> Result<bool, sqlx::Error> {
+    match claims.role {
+        UserRole::Admin => return Ok(true),
+        UserRole::Guest => return Ok(false),
+        UserRole::User => {}
+    }
+
+    sqlx::query_scalar(
+        "SELECT EXISTS (
+             SELECT 1 FROM user_roles ur
+             JOIN role_permissions rp ON rp.role_name = ur.role_name
+             WHERE ur.user_id = $1 AND rp.permission = $2
+         ) OR EXISTS (
+             SELECT 1 FROM permission_grants
+             WHERE user_id = $1 AND permission = $2
+             AND (resource_type IS NULL OR (resource_type = $3 AND resource_id = $4))
+         )"
+    )
+    .bind(claims.sub)
+    .bind(permission)
+    .bind(resource.map(|r| r.resource_type()))
+    .bind(resource.map(|r| r.resource_id()))
+    .fetch_one(pool)
+    .await
+}
+
+/// Guard for handlers: Ok when the permission is held, otherwise the
+/// response to return (403, or 500 when the lookup fails)
+pub async fn require_permission(
+    pool: &PgPool,
+    claims: &Claims,
+    permission: &str,
+    resource: Option<Resource>,
+) -> Result<(), HttpResponse> {
+    match has_permission(pool, claims, permission, resource).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(HttpResponse::Forbidden().json(json!({
+            "message": "Insufficient permissions"
+        }))),
+        Err(e) => {
+            eprintln!("Failed to load permissions for user {}: {:?}", claims.sub, e);
+            Err(HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to check permissions"
+            })))
+        }
+    }
+}
+
+/// Permissions the user holds for every resource, through roles or grants.
+/// Admins hold every permission without them being listed.
+pub async fn global_permissions(pool: &PgPool, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT rp.permission FROM user_roles ur
+         JOIN role_permissions rp ON rp.role_name = ur.role_name
+         WHERE ur.user_id = $1
+         UNION
+         SELECT permission FROM permission_grants
+         WHERE user_id = $1 AND resource_type IS NULL
+         ORDER BY 1"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+// Used by AuthMiddleware for non-admin users: the permission held globally,
+// or for resource-scoped permissions, held for at least one resource
+pub(crate) async fn may_enter(pool: &PgPool, user_id: i32, permission: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (
+             SELECT 1 FROM user_roles ur
+             JOIN role_permissions rp ON rp.role_name = ur.role_name
+             WHERE ur.user_id = $1 AND rp.permission = $2
+         ) OR EXISTS (
+             SELECT 1 FROM permission_grants
+             WHERE user_id = $1 AND permission = $2
+             AND (resource_type IS NULL OR $3)
+         )"
+    )
+    .bind(user_id)
+    .bind(permission)
+    .bind(RESOURCE_SCOPED.contains(&permission))
+    .fetch_one(pool)
+    .await
+}
+
+// Constraint violations shared by the role and grant endpoints
+fn constraint_error(e: &sqlx::Error) -> Option<HttpResponse> {
+    if let sqlx::Error::Database(db_error) = e {
+        match db_error.constraint() {
+            Some("roles_pkey") => {
+                return Some(HttpResponse::BadRequest().json(json!({
+                    "message": "Role already exists"
+                })));
+            }
+            Some("role_permissions_permission_fkey") | Some("permission_grants_permission_fkey") => {
+                return Some(HttpResponse::BadRequest().json(json!({
+                    "message": "Unknown permission"
+                })));
+            }
+            Some("user_roles_user_id_fkey") | Some("permission_grants_user_id_fkey") => {
+                return Some(HttpResponse::NotFound().json(json!({
+                    "message": "User not found"
+                })));
+            }
+            Some("user_roles_role_name_fkey") => {
+                return Some(HttpResponse::NotFound().json(json!({
+                    "message": "Role not found"
+                })));
+            }
+            Some("user_roles_pkey") | Some("permission_grants_unique") => {
+                return Some(HttpResponse::Conflict().json(json!({
+                    "message": "Already assigned"
+                })));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Replace the permissions of a role inside a transaction
+async fn set_role_permissions(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    role: &str,
+    permissions: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM role_permissions WHERE role_name = $1")
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+
+    for permission in permissions {
+        sqlx::query("INSERT INTO role_permissions (role_name, permission) VALUES ($1, $2)")
+            .bind(role)
+            .bind(permission)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// List every known permission
+pub async fn get_permissions(pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as::<_, PermissionInfo>(
+        "SELECT name, description FROM permissions ORDER BY name"
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(permissions) => HttpResponse::Ok().json(permissions),
+        Err(e) => {
+            eprintln!("Error fetching permissions: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch permissions"
+            }))
+        }
+    }
+}
+
+// List roles with their permissions
+pub async fn get_roles(pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as::<_, Role>(
+        "SELECT r.name, r.description,
+                COALESCE(array_agg(rp.permission ORDER BY rp.permission)
+                    FILTER (WHERE rp.permission IS NOT NULL), '{}')::TEXT[] AS permissions
+         FROM roles r
+         LEFT JOIN role_permissions rp ON rp.role_name = r.name
+         GROUP BY r.name, r.description
+         ORDER BY r.name"
+    )
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(roles) => HttpResponse::Ok().json(roles),
+        Err(e) => {
+            eprintln!("Error fetching roles: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch roles"
+            }))
+        }
+    }
+}
+
+// Create a role and its permissions
+pub async fn create_role(
+    pool: web::Data<PgPool>,
+    request: web::Json<RoleRequest>,
+) -> impl Responder {
+    let name = request.name.trim().to_lowercase();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "message": "Role name is required"
+        }));
+    }
+
+    let result: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("INSERT INTO roles (name, description) VALUES ($1, $2)")
+            .bind(&name)
+            .bind(&request.description)
+            .execute(&mut tx)
+            .await?;
+
+        set_role_permissions(&mut tx, &name, &request.permissions).await?;
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({
+            "name": name,
+            "description": request.description,
+            "permissions": request.permissions,
+        })),
+        Err(e) => {
+            eprintln!("Error creating role: {:?}", e);
+            constraint_error(&e).unwrap_or_else(|| {
+                HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to create role"
+                }))
+            })
+        }
+    }
+}
+
+// Replace the description and permissions of a role
+pub async fn update_role(
+    pool: web::Data<PgPool>,
+    name: web::Path<String>,
+    request: web::Json<UpdateRoleRequest>,
+) -> impl Responder {
+    let name = name.into_inner();
+
+    let result: Result<bool, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        let updated = sqlx::query("UPDATE roles SET description = $1 WHERE name = $2")
+            .bind(&request.description)
+            .bind(&name)
+            .execute(&mut tx)
+            .await?;
+
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        set_role_permissions(&mut tx, &name, &request.permissions).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "name": name,
+            "description": request.description,
+            "permissions": request.permissions,
+        })),
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "message": "Role not found"
+        })),
+        Err(e) => {
+            eprintln!("Error updating role: {:?}", e);
+            constraint_error(&e).unwrap_or_else(|| {
+                HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to update role"
+                }))
+            })
+        }
+    }
+}
+
+// Delete a role; users holding it lose its permissions
+pub async fn delete_role(pool: web::Data<PgPool>, name: web::Path<String>) -> impl Responder {
+    let result = sqlx::query("DELETE FROM roles WHERE name = $1")
+        .bind(name.into_inner())
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(done) if done.rows_affected() > 0 => HttpResponse::Ok().json(json!({
+            "message": "Role deleted successfully"
+        })),
+        Ok(_) => HttpResponse::NotFound().json(json!({
+            "message": "Role not found"
+        })),
+        Err(e) => {
+            eprintln!("Error deleting role: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to delete role"
+            }))
+        }
+    }
+}
+
+// Roles and grants of a user
+pub async fn get_user_permissions(pool: web::Data<PgPool>, user_id: web::Path<i32>) -> impl Responder {
+    let user_id = user_id.into_inner();
+
+    let roles = sqlx::query_scalar::<_, String>(
+        "SELECT role_name FROM user_roles WHERE user_id = $1 ORDER BY role_name"
+    )
+    .bind(user_id)
+    .fetch_all(pool.get_ref())
+    .await;
+
+    let grants = sqlx::query_as::<_, PermissionGrant>(
+        "SELECT id, user_id, permission, resource_type, resource_id, created_at
+         FROM permission_grants WHERE user_id = $1 ORDER BY permission, resource_id"
+    )
+    .bind(user_id)
+    .fetch_all(pool.get_ref())
+    .await;
+
+    match (roles, grants) {
+        (Ok(roles), Ok(grants)) => HttpResponse::Ok().json(json!({
+            "user_id": user_id,
+            "roles": roles,
+            "grants": grants,
+        })),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Error fetching user permissions: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch user permissions"
+            }))
+        }
+    }
+}
+
+// Give a role to a user
+pub async fn assign_role(
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i32>,
+    request: web::Json<AssignRoleRequest>,
+) -> impl Responder {
+    let result = sqlx::query("INSERT INTO user_roles (user_id, role_name) VALUES ($1, $2)")
+        .bind(user_id.into_inner())
+        .bind(&request.role)
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({
+            "message": "Role assigned"
+        })),
+        Err(e) => {
+            eprintln!("Error assigning role: {:?}", e);
+            constraint_error(&e).unwrap_or_else(|| {
+                HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to assign role"
+                }))
+            })
+        }
+    }
+}
+
+// Take a role away from a user
+pub async fn remove_role(
+    pool: web::Data<PgPool>,
+    path: web::Path<(i32, String)>,
+) -> impl Responder {
+    let (user_id, role) = path.into_inner();
+
+    let result = sqlx::query("DELETE FROM user_roles WHERE user_id = $1 AND role_name = $2")
+        .bind(user_id)
+        .bind(role)
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(done) if done.rows_affected() > 0 => HttpResponse::Ok().json(json!({
+            "message": "Role removed"
+        })),
+        Ok(_) => HttpResponse::NotFound().json(json!({
+            "message": "Role not assigned to this user"
+        })),
+        Err(e) => {
+            eprintln!("Error removing role: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to remove role"
+            }))
+        }
+    }
+}
+
+// Grant a permission to a user, optionally for a single resource
+pub async fn add_grant(
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i32>,
+    request: web::Json<GrantRequest>,
+) -> impl Responder {
+    let resource = match (&request.resource_type, request.resource_id) {
+        (None, None) => None,
+        (Some(resource_type), Some(resource_id)) => {
+            match Resource::from_parts(resource_type, resource_id) {
+                Some(resource) => Some(resource),
+                None => {
+                    return HttpResponse::BadRequest().json(json!({
+                        "message": format!("Unknown resource type: {}", resource_type)
+                    }));
+                }
+            }
+        }
+        _ => {
+            return HttpResponse::BadRequest().json(json!({
+                "message": "resource_type and resource_id must be given together"
+            }));
+        }
+    };
+
+    let result = sqlx::query_as::<_, PermissionGrant>(
+        "INSERT INTO permission_grants (user_id, permission, resource_type, resource_id)
+         VALUES ($1, $2, $3, $4)
+         RETURNING id, user_id, permission, resource_type, resource_id, created_at"
+    )
+    .bind(user_id.into_inner())
+    .bind(&request.permission)
+    .bind(resource.map(|r| r.resource_type()))
+    .bind(resource.map(|r| r.resource_id()))
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(grant) => HttpResponse::Created().json(grant),
+        Err(e) => {
+            eprintln!("Error adding grant: {:?}", e);
+            constraint_error(&e).unwrap_or_else(|| {
+                HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to add grant"
+                }))
+            })
+        }
+    }
+}
+
+// Revoke a single grant
+pub async fn delete_grant(pool: web::Data<PgPool>, id: web::Path<i32>) -> impl Responder {
+    let result = sqlx::query("DELETE FROM permission_grants WHERE id = $1")
+        .bind(id.into_inner())
+        .execute(pool.get_ref())
+        .await;
+
+    match result {
+        Ok(done) if done.rows_affected() > 0 => HttpResponse::Ok().json(json!({
+            "message": "Grant deleted successfully"
+        })),
+        Ok(_) => HttpResponse::NotFound().json(json!({
+            "message": "Grant not found"
+        })),
+        Err(e) => {
+            eprintln!("Error deleting grant: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to delete grant"
+            }))
+        }
+    }
+}
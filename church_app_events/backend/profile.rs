@@ -0,0 +1,624 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, QueryBuilder};
+
+use crate::eventrsvp::ServingStatusType as RsvpStatus;
+use crate::homegroup_waitlist::{self, Offer};
+use crate::homegrouprsvp::RegistrationStatus;
+use crate::oidc::OidcProvider;
+use crate::refresh_token;
+use crate::registration::send_verification;
+use crate::revocation::RevocationStore;
+use crate::servingrsvp::ServingStatusType as SignupStatus;
+use crate::user::{validate_email, validate_password, validate_username, Claims, UserRole, ACCESS_TOKEN_TTL_SECS};
+use crate::waitlist::{self, Promotion};
+
+// Self-service account endpoints under /me. Everything is scoped to the
+// caller's own id from the access token, so there is no path id to tamper with.
+// Changes to the email or password and deleting the account ask for the
+// current password, or for users who sign in with Google and have none, for
+// a reauth_token from re-authenticating at /me/reauth/oidc.
+
+/// The caller's own account, without the password hash
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Profile {
+    pub id: i32,
+    pub email: String,
+    pub pending_email: Option<String>, // New address awaiting verification
+    pub username: String,
+    pub role: UserRole,
+    pub profile_picture: Option<String>,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateProfileRequest {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub profile_picture: Option<String>,
+    pub current_password: Option<String>, // Or reauth_token, required to change the email
+    pub reauth_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: Option<String>,
+    pub reauth_token: Option<String>,
+    pub new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: Option<String>,
+    pub reauth_token: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MyEventRsvp {
+    id: i32,
+    event_id: i32,
+    rsvp_status: RsvpStatus,
+    rsvp_date: NaiveDate,
+    event_title: String,
+    event_date: NaiveDate,
+    event_time: NaiveTime,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MyHomeGroupRegistration {
+    id: i32,
+    home_group_id: i32,
+    registration_status: RegistrationStatus,
+    registration_date: NaiveDateTime,
+    group_name: String,
+    group_location: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MyServingRsvp {
+    id: i32,
+    serving_id: i32,
+    rsvp_status: SignupStatus,
+    rsvp_date: NaiveDateTime,
+    serving_title: Option<String>,
+    serving_location: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct MyWatchHistory {
+    media_id: i32,
+    media_title: String,
+    watched_duration: i32,
+    completed: bool,
+    last_watched_at: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct ActivityResponse {
+    event_rsvps: Vec<MyEventRsvp>,
+    home_group_registrations: Vec<MyHomeGroupRegistration>,
+    serving_rsvps: Vec<MyServingRsvp>,
+    watch_history: Vec<MyWatchHistory>,
+}
+
+const PROFILE_COLUMNS: &str = "id, email, pending_email, username, role, profile_picture, \
+     email_verified, totp_enabled, created_at, updated_at";
+
+async fn fetch_profile(pool: &PgPool, user_id: i32) -> Result<Option<Profile>, sqlx::Error> {
+    sqlx::query_as::<_, Profile>(&format!("SELECT {} FROM users WHERE id = $1", PROFILE_COLUMNS))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+async fn password_matches(pool: &PgPool, user_id: i32, password: &str) -> Result<bool, sqlx::Error> {
+    let hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(bcrypt::verify(password, &hash).unwrap_or(false))
+}
+
+// Whether the caller confirmed who they are, with their password or a re-authentication
+async fn reauthenticated(
+    pool: &PgPool,
+    oidc: &OidcProvider,
+    user_id: i32,
+    password: Option<&str>,
+    reauth_token: Option<&str>,
+) -> anyhow::Result<bool> {
+    match reauth_token {
+        Some(token) => oidc.take_reauth(token, user_id).await,
+        None => Ok(password_matches(pool, user_id, password.unwrap_or_default()).await?),
+    }
+}
+
+// End every session of the user: refresh tokens and outstanding access tokens
+async fn sign_out_everywhere(pool: &PgPool, revocations: &RevocationStore, user_id: i32) {
+    if let Err(e) = refresh_token::revoke_all_for_user(pool, user_id).await {
+        eprintln!("Failed to revoke refresh tokens for user {}: {:?}", user_id, e);
+    }
+    if let Err(e) = revocations
+        .revoke_user(user_id, Utc::now().timestamp_millis(), ACCESS_TOKEN_TTL_SECS)
+        .await
+    {
+        eprintln!("Failed to revoke sessions for user {}: {:?}", user_id, e);
+    }
+}
+
+// Get the caller's profile
+pub async fn get_profile(pool: web::Data<PgPool>, claims: Claims) -> impl Responder {
+    match fetch_profile(pool.get_ref(), claims.sub).await {
+        Ok(Some(profile)) => HttpResponse::Ok().json(profile),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "message": "User not found"
+        })),
+        Err(e) => {
+            eprintln!("Error getting profile: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to get profile"
+            }))
+        }
+    }
+}
+
+// Update the caller's username, profile picture or email. A new email only
+// replaces the current one once the link sent to it has been followed.
+pub async fn update_profile(
+    pool: web::Data<PgPool>,
+    oidc: web::Data<OidcProvider>,
+    claims: Claims,
+    update_data: web::Json<UpdateProfileRequest>,
+) -> impl Responder {
+    if let Some(username) = &update_data.username {
+        if let Err(e) = validate_username(username) {
+            return HttpResponse::BadRequest().json(json!({
+                "message": e
+            }));
+        }
+    }
+
+    let current = match fetch_profile(pool.get_ref(), claims.sub).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "message": "User not found"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Error getting profile: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to update profile"
+            }));
+        }
+    };
+
+    let new_email = update_data
+        .email
+        .as_ref()
+        .filter(|email| !email.eq_ignore_ascii_case(&current.email));
+
+    if let Some(email) = new_email {
+        if let Err(e) = validate_email(email) {
+            return HttpResponse::BadRequest().json(json!({
+                "message": e
+            }));
+        }
+
+        let confirmed = reauthenticated(
+            pool.get_ref(),
+            oidc.get_ref(),
+            claims.sub,
+            update_data.current_password.as_deref(),
+            update_data.reauth_token.as_deref(),
+        )
+        .await;
+        match confirmed {
+            Ok(true) => {}
+            Ok(false) => {
+                return HttpResponse::Unauthorized().json(json!({
+                    "message": "Current password or re-authentication is required to change the email"
+                }));
+            }
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to update profile"
+                }));
+            }
+        }
+
+        let taken = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE LOWER(email) = LOWER($1) AND id <> $2)"
+        )
+        .bind(email)
+        .bind(claims.sub)
+        .fetch_one(pool.get_ref())
+        .await;
+
+        match taken {
+            Ok(false) => {}
+            Ok(true) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "message": "Email already exists"
+                }));
+            }
+            Err(e) => {
+                eprintln!("Database error: {:?}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to update profile"
+                }));
+            }
+        }
+    }
+
+    let mut query_builder = QueryBuilder::new("UPDATE users SET updated_at = CURRENT_TIMESTAMP");
+
+    if let Some(username) = &update_data.username {
+        query_builder.push(", username = ");
+        query_builder.push_bind(username);
+    }
+
+    if let Some(profile_picture) = &update_data.profile_picture {
+        query_builder.push(", profile_picture = ");
+        query_builder.push_bind(profile_picture);
+    }
+
+    if let Some(email) = new_email {
+        query_builder.push(", pending_email = ");
+        query_builder.push_bind(email);
+    }
+
+    query_builder.push(" WHERE id = ");
+    query_builder.push_bind(claims.sub);
+    query_builder.push(format!(" RETURNING {}", PROFILE_COLUMNS));
+
+    let profile = match query_builder.build_query_as::<Profile>().fetch_one(pool.get_ref()).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("Database error while updating profile: {:?}", e);
+            if let sqlx::Error::Database(db_error) = &e {
+                if let Some("users_username_key") = db_error.constraint() {
+                    return HttpResponse::BadRequest().json(json!({
+                        "message": "Username already exists"
+                    }));
+                }
+            }
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to update profile"
+            }));
+        }
+    };
+
+    if let Some(email) = new_email {
+        if let Err(e) = send_verification(pool.get_ref(), profile.id, email, &profile.username).await {
+            eprintln!("Failed to send verification email to {}: {}", email, e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Profile updated, but the verification email could not be sent",
+                "profile": profile
+            }));
+        }
+
+        return HttpResponse::Ok().json(json!({
+            "message": "Profile updated, check your new email to confirm the change",
+            "profile": profile
+        }));
+    }
+
+    HttpResponse::Ok().json(json!({
+        "message": "Profile updated",
+        "profile": profile
+    }))
+}
+
+// Change the caller's password; every session is signed out afterwards
+pub async fn change_password(
+    pool: web::Data<PgPool>,
+    oidc: web::Data<OidcProvider>,
+    revocations: web::Data<RevocationStore>,
+    claims: Claims,
+    request: web::Json<ChangePasswordRequest>,
+) -> impl Responder {
+    if let Err(e) = validate_password(&request.new_password) {
+        return HttpResponse::BadRequest().json(json!({
+            "message": e
+        }));
+    }
+
+    let confirmed = reauthenticated(
+        pool.get_ref(),
+        oidc.get_ref(),
+        claims.sub,
+        request.current_password.as_deref(),
+        request.reauth_token.as_deref(),
+    )
+    .await;
+    match confirmed {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid password"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to change password"
+            }));
+        }
+    }
+
+    let password_hash = match bcrypt::hash(&request.new_password, bcrypt::DEFAULT_COST) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to hash password: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to change password"
+            }));
+        }
+    };
+
+    let result = sqlx::query(
+        "UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2"
+    )
+    .bind(password_hash)
+    .bind(claims.sub)
+    .execute(pool.get_ref())
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Database error while changing password: {:?}", e);
+        return HttpResponse::InternalServerError().json(json!({
+            "message": "Failed to change password"
+        }));
+    }
+
+    sign_out_everywhere(pool.get_ref(), revocations.get_ref(), claims.sub).await;
+
+    HttpResponse::Ok().json(json!({
+        "message": "Password changed, please log in again"
+    }))
+}
+
+// Everything the caller has signed up for, in one response. Sign-ups made
+// before the account existed are matched by the (verified) email.
+pub async fn get_activity(pool: web::Data<PgPool>, claims: Claims) -> impl Responder {
+    let result: Result<ActivityResponse, sqlx::Error> = async {
+        let (email, email_verified) = sqlx::query_as::<_, (String, bool)>(
+            "SELECT email, email_verified FROM users WHERE id = $1"
+        )
+        .bind(claims.sub)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+        let event_rsvps = sqlx::query_as::<_, MyEventRsvp>(
+            "SELECT r.id, r.event_id, r.rsvp_status, r.rsvp_date, e.event_title,
+                    COALESCE(r.occurrence_date, e.event_date) as event_date, e.event_time
+             FROM eventrsvp r
+             JOIN events e ON r.event_id = e.id
+             WHERE r.user_id = $1 OR ($3 AND LOWER(r.email) = LOWER($2))
+             ORDER BY COALESCE(r.occurrence_date, e.event_date) DESC, e.event_time DESC"
+        )
+        .bind(claims.sub)
+        .bind(&email)
+        .bind(email_verified)
+        .fetch_all(pool.get_ref())
+        .await?;
+
+        let home_group_registrations = sqlx::query_as::<_, MyHomeGroupRegistration>(
+            "SELECT hr.id, hr.home_group_id, hr.registration_status, hr.registration_date,
+                    hg.name as group_name, hg.location as group_location
+             FROM homegroupregistrations hr
+             JOIN homegroups hg ON hr.home_group_id = hg.id
+             WHERE hr.user_id = $1 OR ($3 AND LOWER(hr.email) = LOWER($2))
+             ORDER BY hr.registration_date DESC"
+        )
+        .bind(claims.sub)
+        .bind(&email)
+        .bind(email_verified)
+        .fetch_all(pool.get_ref())
+        .await?;
+
+        let serving_rsvps = sqlx::query_as::<_, MyServingRsvp>(
+            "SELECT sr.id, sr.serving_id, sr.rsvp_status, sr.rsvp_date,
+                    s.title as serving_title, s.location as serving_location
+             FROM servingrsvps sr
+             LEFT JOIN serving s ON sr.serving_id = s.id
+             WHERE sr.user_id = $1 OR ($3 AND LOWER(sr.email) = LOWER($2))
+             ORDER BY sr.rsvp_date DESC"
+        )
+        .bind(claims.sub)
+        .bind(&email)
+        .bind(email_verified)
+        .fetch_all(pool.get_ref())
+        .await?;
+
+        let watch_history = sqlx::query_as::<_, MyWatchHistory>(
+            "SELECT wh.media_id, m.title as media_title, wh.watched_duration,
+                    wh.completed, wh.last_watched_at
+             FROM watch_history wh
+             JOIN media m ON wh.media_id = m.id
+             WHERE wh.user_id = $1
+             ORDER BY wh.last_watched_at DESC"
+        )
+        .bind(claims.sub)
+        .fetch_all(pool.get_ref())
+        .await?;
+
+        Ok(ActivityResponse {
+            event_rsvps,
+            home_group_registrations,
+            serving_rsvps,
+            watch_history,
+        })
+    }
+    .await;
+
+    match result {
+        Ok(activity) => HttpResponse::Ok().json(activity),
+        Err(e) => {
+            eprintln!("Error getting activity for user {}: {:?}", claims.sub, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to get activity"
+            }))
+        }
+    }
+}
+
+// Seats and home group places freed by a deleted account, to be emailed
+// to those next in line
+type Freed = (Vec<Promotion>, Vec<Offer>);
+
+// Delete the caller's account together with their sign-ups
+pub async fn delete_account(
+    pool: web::Data<PgPool>,
+    oidc: web::Data<OidcProvider>,
+    revocations: web::Data<RevocationStore>,
+    claims: Claims,
+    request: web::Json<DeleteAccountRequest>,
+) -> impl Responder {
+    let confirmed = reauthenticated(
+        pool.get_ref(),
+        oidc.get_ref(),
+        claims.sub,
+        request.password.as_deref(),
+        request.reauth_token.as_deref(),
+    )
+    .await;
+    match confirmed {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid password"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to delete account"
+            }));
+        }
+    }
+
+    let result: Result<Option<Freed>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        // Keep at least one admin so the app stays manageable. The admins
+        // are locked, so two of them cannot delete themselves at once.
+        let admins = sqlx::query_scalar::<_, i32>(
+            "SELECT id FROM users WHERE role = 'admin' ORDER BY id FOR UPDATE"
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        if admins == [claims.sub] {
+            return Ok(None);
+        }
+
+        // Seats held at events go to those on the waitlist; the events are
+        // locked first, as for any other change to seats
+        let event_ids = sqlx::query_scalar::<_, i32>(
+            "SELECT DISTINCT event_id FROM eventrsvp WHERE user_id = $1 ORDER BY event_id"
+        )
+        .bind(claims.sub)
+        .fetch_all(&mut tx)
+        .await?;
+
+        let mut capacities = Vec::new();
+        for event_id in event_ids {
+            capacities.push((event_id, waitlist::lock_event(&mut tx, event_id).await?.flatten()));
+        }
+
+        let deleted = sqlx::query_as::<_, (i32, Option<NaiveDate>, bool)>(
+            "DELETE FROM eventrsvp WHERE user_id = $1
+             RETURNING event_id, occurrence_date, rsvp_status <> 'declined' AND waitlisted_at IS NULL"
+        )
+        .bind(claims.sub)
+        .fetch_all(&mut tx)
+        .await?;
+
+        let mut promotions = Vec::new();
+        for (event_id, capacity) in capacities {
+            let mut dates: Vec<Option<NaiveDate>> = deleted
+                .iter()
+                .filter(|(id, _, held_seat)| *id == event_id && *held_seat)
+                .map(|(_, date, _)| *date)
+                .collect();
+            dates.sort();
+            dates.dedup();
+            for occurrence_date in dates {
+                promotions.extend(waitlist::promote(&mut tx, event_id, occurrence_date, capacity).await?);
+            }
+        }
+
+        // Places held in home groups are offered to those waiting, the
+        // home groups locked the same way
+        let home_group_ids = sqlx::query_scalar::<_, i32>(
+            "SELECT DISTINCT home_group_id FROM homegroupregistrations WHERE user_id = $1 ORDER BY home_group_id"
+        )
+        .bind(claims.sub)
+        .fetch_all(&mut tx)
+        .await?;
+
+        let mut group_capacities = Vec::new();
+        for home_group_id in home_group_ids {
+            let capacity = homegroup_waitlist::lock_group(&mut tx, home_group_id).await?.flatten();
+            group_capacities.push((home_group_id, capacity));
+        }
+
+        let freed = sqlx::query_as::<_, (i32, bool)>(
+            "DELETE FROM homegroupregistrations WHERE user_id = $1
+             RETURNING home_group_id, registration_status = 'approved' OR COALESCE(offer_expires_at > NOW(), FALSE)"
+        )
+        .bind(claims.sub)
+        .fetch_all(&mut tx)
+        .await?;
+
+        let mut offers = Vec::new();
+        for (home_group_id, capacity) in group_capacities {
+            if freed.iter().any(|(id, held_place)| *id == home_group_id && *held_place) {
+                offers.extend(homegroup_waitlist::offer_places(&mut tx, home_group_id, capacity).await?);
+            }
+        }
+
+        sqlx::query("DELETE FROM servingrsvps WHERE user_id = $1")
+            .bind(claims.sub)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(claims.sub)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(Some((promotions, offers)))
+    }
+    .await;
+
+    match result {
+        Ok(Some((promotions, offers))) => {
+            waitlist::notify(pool.get_ref(), promotions);
+            homegroup_waitlist::notify(pool.get_ref(), offers);
+            sign_out_everywhere(pool.get_ref(), revocations.get_ref(), claims.sub).await;
+            HttpResponse::Ok().json(json!({
+                "message": "Account deleted"
+            }))
+        }
+        Ok(None) => HttpResponse::BadRequest().json(json!({
+            "message": "The last admin account cannot be deleted"
+        })),
+        Err(e) => {
+            eprintln!("Database error while deleting account {}: {:?}", claims.sub, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to delete account"
+            }))
+        }
+    }
+}
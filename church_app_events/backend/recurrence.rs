@@ -0,0 +1,453 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use std::fmt;
+use std::str::FromStr;
+
+// The subset of RFC 5545 recurrence rules used for church events:
+// FREQ=DAILY|WEEKLY|MONTHLY with INTERVAL, BYDAY (ordinals such as 1SU or
+// -1FR for monthly rules), BYMONTHDAY, and either COUNT or UNTIL.
+// The event's own date is the DTSTART of the series.
+
+// Stop after this many periods even if the rule never ends, so a rule
+// without COUNT or UNTIL cannot expand forever
+const MAX_PERIODS: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// A BYDAY entry, e.g. `SU` (every Sunday) or `-1FR` (last Friday of the month)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeekdayNum {
+    pub ordinal: Option<i32>,
+    pub weekday: Weekday,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<WeekdayNum>,
+    pub by_month_day: Vec<i32>,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDate>,
+}
+
+fn parse_weekday(code: &str) -> Result<Weekday, String> {
+    match code {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("Invalid weekday in BYDAY: {}", code)),
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn parse_weekday_num(value: &str) -> Result<WeekdayNum, String> {
+    if value.len() < 2 || !value.is_char_boundary(value.len() - 2) {
+        return Err(format!("Invalid BYDAY value: {}", value));
+    }
+    let (ordinal, code) = value.split_at(value.len() - 2);
+
+    let ordinal = match ordinal {
+        "" => None,
+        ordinal => match ordinal.parse::<i32>() {
+            Ok(n) if n != 0 && (-5..=5).contains(&n) => Some(n),
+            _ => return Err(format!("Invalid BYDAY value: {}", value)),
+        },
+    };
+
+    Ok(WeekdayNum {
+        ordinal,
+        weekday: parse_weekday(code)?,
+    })
+}
+
+// UNTIL may be a date (20250131) or a date-time (20250131T235959Z);
+// events are scheduled by date, so only the date part matters
+fn parse_until(value: &str) -> Result<NaiveDate, String> {
+    let date = value.split('T').next().unwrap_or_default();
+    NaiveDate::parse_from_str(date, "%Y%m%d").map_err(|_| format!("Invalid UNTIL value: {}", value))
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    (28..=31).rev().find(|&day| NaiveDate::from_ymd_opt(year, month, day).is_some())
+}
+
+impl FromStr for RecurrenceRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = Vec::new();
+        let mut by_month_day = Vec::new();
+        let mut count = None;
+        let mut until = None;
+
+        for part in s.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid rule part: {}", part))?;
+
+            match key.to_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(format!("Unsupported FREQ: {}", value)),
+                    });
+                }
+                "INTERVAL" => {
+                    interval = match value.parse::<u32>() {
+                        Ok(n) if n >= 1 => n,
+                        _ => return Err(format!("Invalid INTERVAL: {}", value)),
+                    };
+                }
+                "BYDAY" => {
+                    by_day = value
+                        .to_uppercase()
+                        .split(',')
+                        .map(parse_weekday_num)
+                        .collect::<Result<Vec<_>, _>>()?;
+                }
+                "BYMONTHDAY" => {
+                    by_month_day = value
+                        .split(',')
+                        .map(|day| match day.parse::<i32>() {
+                            Ok(n) if n != 0 && (-31..=31).contains(&n) => Ok(n),
+                            _ => Err(format!("Invalid BYMONTHDAY value: {}", day)),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                }
+                "COUNT" => {
+                    count = match value.parse::<u32>() {
+                        Ok(n) if n >= 1 => Some(n),
+                        _ => return Err(format!("Invalid COUNT: {}", value)),
+                    };
+                }
+                "UNTIL" => until = Some(parse_until(value)?),
+                // Weeks start on Monday, which is also the RFC default
+                "WKST" if value.eq_ignore_ascii_case("MO") => {}
+                _ => return Err(format!("Unsupported rule part: {}", part)),
+            }
+        }
+
+        let frequency = frequency.ok_or_else(|| "FREQ is required".to_string())?;
+
+        if count.is_some() && until.is_some() {
+            return Err("COUNT and UNTIL cannot be combined".to_string());
+        }
+        if frequency != Frequency::Monthly && by_day.iter().any(|day| day.ordinal.is_some()) {
+            return Err("BYDAY ordinals are only allowed with FREQ=MONTHLY".to_string());
+        }
+        if frequency == Frequency::Weekly && !by_month_day.is_empty() {
+            return Err("BYMONTHDAY is not allowed with FREQ=WEEKLY".to_string());
+        }
+
+        Ok(RecurrenceRule {
+            frequency,
+            interval,
+            by_day,
+            by_month_day,
+            count,
+            until,
+        })
+    }
+}
+
+impl fmt::Display for RecurrenceRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|day| match day.ordinal {
+                    Some(n) => format!("{}{}", n, weekday_code(day.weekday)),
+                    None => weekday_code(day.weekday).to_string(),
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(|day| day.to_string()).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RecurrenceRule {
+    /// Candidate dates of the `period`-th period after `start`, sorted.
+    /// None once the dates run past what chrono can represent.
+    fn period_dates(&self, start: NaiveDate, period: i64) -> Option<Vec<NaiveDate>> {
+        let step = period * self.interval as i64;
+
+        let mut dates = match self.frequency {
+            Frequency::Daily => vec![start.checked_add_signed(Duration::try_days(step)?)?],
+            Frequency::Weekly => {
+                let week_start = start
+                    .checked_sub_signed(Duration::days(start.weekday().num_days_from_monday() as i64))?
+                    .checked_add_signed(Duration::try_weeks(step)?)?;
+
+                let weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+                    vec![start.weekday()]
+                } else {
+                    self.by_day.iter().map(|day| day.weekday).collect()
+                };
+                weekdays
+                    .into_iter()
+                    .map(|weekday| week_start.checked_add_signed(Duration::days(weekday.num_days_from_monday() as i64)))
+                    .collect::<Option<Vec<_>>>()?
+            }
+            Frequency::Monthly => {
+                let month_index = start.year() as i64 * 12 + start.month0() as i64 + step;
+                let year = i32::try_from(month_index.div_euclid(12)).ok()?;
+                let month = month_index.rem_euclid(12) as u32 + 1;
+                let last_day = days_in_month(year, month)?;
+
+                // BYMONTHDAY, with negative values counting from the end
+                let month_days: Vec<u32> = self
+                    .by_month_day
+                    .iter()
+                    .filter_map(|&day| {
+                        let day = if day > 0 { day } else { last_day as i32 + day + 1 };
+                        (1..=last_day as i32).contains(&day).then_some(day as u32)
+                    })
+                    .collect();
+
+                let days: Vec<u32> = if !self.by_day.is_empty() {
+                    let mut days = Vec::new();
+                    for by_day in &self.by_day {
+                        let matching: Vec<u32> = (1..=last_day)
+                            .filter(|&day| {
+                                NaiveDate::from_ymd_opt(year, month, day)
+                                    .map_or(false, |date| date.weekday() == by_day.weekday)
+                            })
+                            .collect();
+
+                        match by_day.ordinal {
+                            None => days.extend(matching),
+                            Some(n) if n > 0 => days.extend(matching.get(n as usize - 1)),
+                            Some(n) => days.extend(matching.len().checked_sub(n.unsigned_abs() as usize).map(|i| matching[i])),
+                        }
+                    }
+                    // BYMONTHDAY narrows BYDAY when both are given
+                    if self.by_month_day.is_empty() {
+                        days
+                    } else {
+                        days.into_iter().filter(|day| month_days.contains(day)).collect()
+                    }
+                } else if !self.by_month_day.is_empty() {
+                    month_days
+                } else if start.day() <= last_day {
+                    // Months without that day (e.g. the 31st) are skipped
+                    vec![start.day()]
+                } else {
+                    Vec::new()
+                };
+
+                days.into_iter()
+                    .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                    .collect()
+            }
+        };
+
+        dates.sort();
+        dates.dedup();
+        Some(dates)
+    }
+
+    /// Dates of the series starting at `start`, up to and including `to`.
+    /// COUNT is applied before exception dates are removed, as in RFC 5545.
+    pub fn dates_until(&self, start: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+
+        for period in 0..MAX_PERIODS {
+            let candidates = match self.period_dates(start, period) {
+                Some(candidates) => candidates,
+                None => break,
+            };
+
+            for date in candidates.into_iter().filter(|date| *date >= start) {
+                if date > to || self.until.map_or(false, |until| date > until) {
+                    return dates;
+                }
+                if self.count.map_or(false, |count| dates.len() >= count as usize) {
+                    return dates;
+                }
+                dates.push(date);
+            }
+        }
+
+        dates
+    }
+
+    /// Dates of the series starting at `start` within `from..=to`
+    pub fn dates_between(&self, start: NaiveDate, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        self.dates_until(start, to)
+            .into_iter()
+            .filter(|date| *date >= from)
+            .collect()
+    }
+
+    /// Whether `date` is an occurrence of the series starting at `start`
+    pub fn includes(&self, start: NaiveDate, date: NaiveDate) -> bool {
+        self.dates_until(start, date).last() == Some(&date)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_zone;
+    use chrono::{NaiveDateTime, NaiveTime};
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn rule(s: &str) -> RecurrenceRule {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn rules_round_trip() {
+        for s in ["FREQ=WEEKLY;BYDAY=SU", "FREQ=MONTHLY;INTERVAL=2;BYDAY=-1FR;COUNT=6", "FREQ=DAILY;UNTIL=20261231"] {
+            assert_eq!(rule(s).to_string(), s);
+        }
+        assert_eq!(rule("RRULE:freq=weekly;byday=mo").to_string(), "FREQ=WEEKLY;BYDAY=MO");
+    }
+
+    #[test]
+    fn invalid_rules_are_refused() {
+        for s in [
+            "",
+            "BYDAY=SU",
+            "FREQ=YEARLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;COUNT=2;UNTIL=20261231",
+            "FREQ=WEEKLY;BYDAY=1SU",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=MONTHLY;BYDAY=XX",
+        ] {
+            assert!(s.parse::<RecurrenceRule>().is_err(), "{} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn count_limits_the_series() {
+        let dates = rule("FREQ=DAILY;INTERVAL=2;COUNT=3").dates_until(date(2026, 1, 1), date(2027, 1, 1));
+        assert_eq!(dates, vec![date(2026, 1, 1), date(2026, 1, 3), date(2026, 1, 5)]);
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let dates = rule("FREQ=WEEKLY;UNTIL=20260115T235959Z").dates_until(date(2026, 1, 1), date(2027, 1, 1));
+        assert_eq!(dates, vec![date(2026, 1, 1), date(2026, 1, 8), date(2026, 1, 15)]);
+    }
+
+    #[test]
+    fn weekly_by_day_starts_at_dtstart() {
+        // Thursday 1 January; the Tuesday of that week is before the start
+        let dates = rule("FREQ=WEEKLY;BYDAY=TU,TH;COUNT=4").dates_until(date(2026, 1, 1), date(2027, 1, 1));
+        assert_eq!(dates, vec![date(2026, 1, 1), date(2026, 1, 6), date(2026, 1, 8), date(2026, 1, 13)]);
+    }
+
+    #[test]
+    fn monthly_by_day_ordinals() {
+        let first_sundays = rule("FREQ=MONTHLY;BYDAY=1SU;COUNT=3").dates_until(date(2026, 1, 4), date(2027, 1, 1));
+        assert_eq!(first_sundays, vec![date(2026, 1, 4), date(2026, 2, 1), date(2026, 3, 1)]);
+
+        let last_fridays = rule("FREQ=MONTHLY;BYDAY=-1FR;COUNT=3").dates_until(date(2026, 1, 30), date(2027, 1, 1));
+        assert_eq!(last_fridays, vec![date(2026, 1, 30), date(2026, 2, 27), date(2026, 3, 27)]);
+    }
+
+    #[test]
+    fn month_ends() {
+        // Months without a 31st are skipped
+        let dates = rule("FREQ=MONTHLY;COUNT=3").dates_until(date(2026, 1, 31), date(2027, 1, 1));
+        assert_eq!(dates, vec![date(2026, 1, 31), date(2026, 3, 31), date(2026, 5, 31)]);
+
+        let last_days = rule("FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=3").dates_until(date(2028, 1, 31), date(2029, 1, 1));
+        assert_eq!(last_days, vec![date(2028, 1, 31), date(2028, 2, 29), date(2028, 3, 31)]);
+    }
+
+    #[test]
+    fn dates_between_and_includes() {
+        let sundays = rule("FREQ=WEEKLY;BYDAY=SU");
+        let start = date(2026, 1, 4);
+        assert_eq!(sundays.dates_between(start, date(2026, 2, 1), date(2026, 2, 14)), vec![date(2026, 2, 1), date(2026, 2, 8)]);
+        assert!(sundays.includes(start, date(2026, 3, 1)));
+        assert!(!sundays.includes(start, date(2026, 3, 2)));
+        assert!(!sundays.includes(start, date(2025, 12, 28)));
+    }
+
+    #[test]
+    fn occurrences_keep_their_local_time_across_dst() {
+        let zone = time_zone::parse("Europe/London").unwrap();
+        let ten = NaiveTime::from_hms_opt(10, 0, 0).unwrap();
+        // Clocks go forward on 29 March 2026
+        let dates = rule("FREQ=WEEKLY;COUNT=2").dates_until(date(2026, 3, 22), date(2026, 4, 30));
+        assert_eq!(dates, vec![date(2026, 3, 22), date(2026, 3, 29)]);
+
+        let instants: Vec<_> = dates
+            .iter()
+            .map(|date| time_zone::localize(zone, NaiveDateTime::new(*date, ten)))
+            .collect();
+        assert_eq!(instants[0].time(), NaiveTime::from_hms_opt(10, 0, 0).unwrap());
+        assert_eq!(instants[1].time(), NaiveTime::from_hms_opt(9, 0, 0).unwrap());
+        for instant in instants {
+            assert_eq!(time_zone::in_zone(instant, zone).time(), ten);
+        }
+    }
+
+    #[test]
+    fn endless_rules_stop_at_the_last_date() {
+        let start = NaiveDate::MAX - Duration::days(20);
+        for s in ["FREQ=DAILY", "FREQ=WEEKLY", "FREQ=WEEKLY;BYDAY=MO,SU", "FREQ=MONTHLY", "FREQ=DAILY;INTERVAL=4294967295"] {
+            let dates = rule(s).dates_until(start, NaiveDate::MAX);
+            assert!(!dates.is_empty() && dates.len() <= 21, "{}", s);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres};
+use uuid::Uuid;
+
+// Refresh tokens slide: every rotation issues a token valid for this long again
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+#[derive(sqlx::FromRow)]
+struct RefreshTokenRow {
+    id: i32,
+    user_id: i32,
+    family_id: String,
+    expires_at: NaiveDateTime,
+    rotated_at: Option<NaiveDateTime>,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+/// A freshly issued refresh token; only its hash is persisted
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub enum RotationError {
+    NotFound,
+    Expired,
+    Reused,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RotationError {
+    fn from(error: sqlx::Error) -> Self {
+        RotationError::Database(error)
+    }
+}
+
+/// Generates an opaque, URL-safe random token (256 bits, hex encoded)
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes a token for storage and lookup
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+async fn insert_token<'e, E>(
+    executor: E,
+    user_id: i32,
+    family_id: &str,
+) -> Result<IssuedRefreshToken, sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    let token = generate_token();
+    let expires_at = Utc::now().naive_utc() + ChronoDuration::days(REFRESH_TOKEN_TTL_DAYS);
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+         VALUES ($1, $2, $3, $4)"
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .execute(executor)
+    .await?;
+
+    Ok(IssuedRefreshToken { token, expires_at })
+}
+
+async fn revoke_family<'e, E>(executor: E, family_id: &str) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+         WHERE family_id = $1 AND revoked_at IS NULL"
+    )
+    .bind(family_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Starts a new token family for a login
+pub async fn issue(pool: &PgPool, user_id: i32) -> Result<IssuedRefreshToken, sqlx::Error> {
+    insert_token(pool, user_id, &Uuid::new_v4().to_string()).await
+}
+
+/// Exchanges a refresh token for a new one in the same family.
+/// Presenting a token that was already rotated or revoked is treated as
+/// theft and revokes the whole family.
+pub async fn rotate(pool: &PgPool, token: &str) -> Result<(i32, IssuedRefreshToken), RotationError> {
+    let mut tx = pool.begin().await?;
+
+    // Lock the row so two concurrent refreshes cannot both rotate it
+    let row = sqlx::query_as::<_, RefreshTokenRow>(
+        "SELECT id, user_id, family_id, expires_at, rotated_at, revoked_at
+         FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE"
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Err(RotationError::NotFound),
+    };
+
+    if row.rotated_at.is_some() || row.revoked_at.is_some() {
+        revoke_family(&mut tx, &row.family_id).await?;
+        tx.commit().await?;
+        return Err(RotationError::Reused);
+    }
+
+    if row.expires_at <= Utc::now().naive_utc() {
+        return Err(RotationError::Expired);
+    }
+
+    sqlx::query("UPDATE refresh_tokens SET rotated_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(row.id)
+        .execute(&mut tx)
+        .await?;
+
+    let issued = insert_token(&mut tx, row.user_id, &row.family_id).await?;
+    tx.commit().await?;
+
+    Ok((row.user_id, issued))
+}
+
+/// Revokes the family a refresh token belongs to (logout).
+/// Returns false when the token is unknown.
+pub async fn revoke(pool: &PgPool, token: &str) -> Result<bool, sqlx::Error> {
+    let family_id: Option<String> = sqlx::query_scalar(
+        "SELECT family_id FROM refresh_tokens WHERE token_hash = $1"
+    )
+    .bind(hash_token(token))
+    .fetch_optional(pool)
+    .await?;
+
+    match family_id {
+        Some(family_id) => {
+            revoke_family(pool, &family_id).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Revokes every refresh token of a user
+pub async fn revoke_all_for_user(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = CURRENT_TIMESTAMP
+         WHERE user_id = $1 AND revoked_at IS NULL"
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use std::env;
+
+use crate::email;
+use crate::refresh_token::{generate_token, hash_token};
+use crate::user::{validate_email, validate_password, validate_username, UserRole};
+
+// How long a verification link stays valid
+const VERIFICATION_TOKEN_TTL_HOURS: i64 = 24;
+
+#[derive(Deserialize, Debug)]
+pub struct RegisterRequest {
+    pub email: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+// Create a verification token for the user and mail the link.
+// Earlier links for the same user stop working.
+pub async fn send_verification(
+    pool: &PgPool,
+    user_id: i32,
+    email: &str,
+    username: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    sqlx::query(
+        "UPDATE email_verification_tokens SET used_at = CURRENT_TIMESTAMP
+         WHERE user_id = $1 AND used_at IS NULL"
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    let token = generate_token();
+    let expires_at = Utc::now().naive_utc() + ChronoDuration::hours(VERIFICATION_TOKEN_TTL_HOURS);
+
+    sqlx::query(
+        "INSERT INTO email_verification_tokens (user_id, token_hash, expires_at)
+         VALUES ($1, $2, $3)"
+    )
+    .bind(user_id)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    let verification_url = env::var("EMAIL_VERIFICATION_URL")
+        .expect("EMAIL_VERIFICATION_URL must be set");
+    let verification_link = format!("{}?token={}", verification_url, token);
+
+    email::send_verification_email(pool, email, username, &verification_link).await
+}
+
+enum Verification {
+    Verified,
+    Invalid,    // The token is unknown, used or expired
+    EmailTaken, // Another account took the pending email in the meantime
+}
+
+// Consume a valid token and mark the email as verified, applying a pending
+// email change if there is one
+async fn apply_verification(pool: &PgPool, token: &str) -> Result<Verification, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let verification = sqlx::query_as::<_, (i32, i32)>(
+        "SELECT id, user_id FROM email_verification_tokens
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+         FOR UPDATE"
+    )
+    .bind(hash_token(token))
+    .bind(Utc::now().naive_utc())
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (verification_id, user_id) = match verification {
+        Some(verification) => verification,
+        None => return Ok(Verification::Invalid),
+    };
+
+    let updated = sqlx::query(
+        "UPDATE users SET email = COALESCE(pending_email, email), pending_email = NULL,
+         email_verified = TRUE, updated_at = CURRENT_TIMESTAMP WHERE id = $1"
+    )
+    .bind(user_id)
+    .execute(&mut tx)
+    .await;
+
+    if let Err(sqlx::Error::Database(db_error)) = &updated {
+        if db_error.constraint() == Some("users_email_key") {
+            return Ok(Verification::EmailTaken);
+        }
+    }
+    updated?;
+
+    sqlx::query("UPDATE email_verification_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(verification_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Verification::Verified)
+}
+
+// Public self-registration, creates an unverified regular user
+pub async fn register(
+    pool: web::Data<PgPool>,
+    request: web::Json<RegisterRequest>,
+) -> impl Responder {
+    // Validate username
+    if let Err(e) = validate_username(&request.username) {
+        return HttpResponse::BadRequest().json(json!({
+            "message": e
+        }));
+    }
+
+    // Validate email
+    if let Err(e) = validate_email(&request.email) {
+        return HttpResponse::BadRequest().json(json!({
+            "message": e
+        }));
+    }
+
+    // Validate password
+    if let Err(e) = validate_password(&request.password) {
+        return HttpResponse::BadRequest().json(json!({
+            "message": e
+        }));
+    }
+
+    let password_hash = match bcrypt::hash(&request.password, bcrypt::DEFAULT_COST) {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("Failed to hash password: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to register user"
+            }));
+        }
+    };
+
+    let result = sqlx::query_as::<_, (i32, String, String)>(
+        "INSERT INTO users (email, password_hash, username, role, email_verified)
+         VALUES ($1, $2, $3, $4, FALSE)
+         RETURNING id, email, username"
+    )
+    .bind(&request.email)
+    .bind(password_hash)
+    .bind(&request.username)
+    .bind(UserRole::User)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match result {
+        Ok((id, email, username)) => {
+            // Mail the link in the background; the user can ask for a new one
+            let pool = pool.get_ref().clone();
+            let (to, name) = (email.clone(), username.clone());
+            actix_web::rt::spawn(async move {
+                if let Err(e) = send_verification(&pool, id, &to, &name).await {
+                    eprintln!("Failed to send verification email to {}: {}", to, e);
+                }
+            });
+
+            HttpResponse::Created().json(json!({
+                "message": "Registration successful, please check your email to verify your account",
+                "user": {
+                    "id": id,
+                    "email": email,
+                    "username": username,
+                    "role": UserRole::User,
+                }
+            }))
+        }
+        Err(e) => {
+            eprintln!("Error registering user: {:?}", e);
+            // Check for duplicate email or username
+            if let sqlx::Error::Database(db_error) = e {
+                match db_error.constraint() {
+                    Some("users_email_key") => {
+                        return HttpResponse::BadRequest().json(json!({
+                            "message": "Email already exists"
+                        }));
+                    }
+                    Some("users_username_key") => {
+                        return HttpResponse::BadRequest().json(json!({
+                            "message": "Username already exists"
+                        }));
+                    }
+                    _ => {}
+                }
+            }
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to register user"
+            }))
+        }
+    }
+}
+
+// Follow the verification link
+pub async fn verify_email(
+    pool: web::Data<PgPool>,
+    query: web::Query<VerifyEmailQuery>,
+) -> impl Responder {
+    match apply_verification(pool.get_ref(), &query.token).await {
+        Ok(Verification::Verified) => HttpResponse::Ok().json(json!({
+            "message": "Email verified, you can now log in"
+        })),
+        Ok(Verification::Invalid) => HttpResponse::BadRequest().json(json!({
+            "message": "Invalid or expired verification link"
+        })),
+        Ok(Verification::EmailTaken) => HttpResponse::Conflict().json(json!({
+            "message": "Email already exists"
+        })),
+        Err(e) => {
+            eprintln!("Database error while verifying email: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to verify email"
+            }))
+        }
+    }
+}
+
+// Send a new verification link to an unverified account or a pending email change
+pub async fn resend_verification(
+    pool: web::Data<PgPool>,
+    request: web::Json<ResendVerificationRequest>,
+) -> impl Responder {
+    let pool = pool.get_ref().clone();
+    let email = request.into_inner().email;
+
+    // Same response whether or not the account exists or is already verified
+    actix_web::rt::spawn(async move {
+        let user = sqlx::query_as::<_, (i32, String)>(
+            "SELECT id, username FROM users
+             WHERE (LOWER(email) = LOWER($1) AND email_verified = FALSE)
+                OR LOWER(pending_email) = LOWER($1)"
+        )
+        .bind(&email)
+        .fetch_optional(&pool)
+        .await;
+
+        match user {
+            Ok(Some((id, username))) => {
+                if let Err(e) = send_verification(&pool, id, &email, &username).await {
+                    eprintln!("Failed to resend verification email to {}: {}", email, e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Database error while resending verification: {:?}", e),
+        }
+    });
+
+    HttpResponse::Ok().json(json!({
+        "message": "If an unverified account exists for this email, a new link has been sent"
+    }))
+}
@@ -0,0 +1,438 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+// Registration forms of events. An event may ask its own questions when
+// people RSVP; the form is stored on the event and the answers on the RSVP,
+// keyed by question id, once they have been checked against the form.
+// Answers given before a form changed are kept as they were.
+
+const MAX_QUESTIONS: usize = 30;
+const MAX_OPTIONS: usize = 50;
+const MAX_ID_LENGTH: usize = 50;
+const MAX_LABEL_LENGTH: usize = 500;
+const MAX_ANSWER_LENGTH: usize = 1000;
+
+// Offered by dietary questions without options of their own
+const DIETARY_OPTIONS: &[&str] = &[
+    "Vegetarian",
+    "Vegan",
+    "Gluten-free",
+    "Dairy-free",
+    "Nut allergy",
+    "Halal",
+    "Kosher",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Question {
+    pub id: String, // Key of the answer, e.g. "tshirt_size"
+    pub label: String,
+    #[serde(flatten)]
+    pub kind: QuestionKind,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Answered with a string (text, single choice), a list of strings
+/// (multiple choice, dietary) or a boolean (consent)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QuestionKind {
+    Text {
+        #[serde(default)]
+        multiline: bool,
+    },
+    SingleChoice {
+        options: Vec<String>,
+    },
+    MultipleChoice {
+        options: Vec<String>,
+    },
+    // Options to tick; anything else is kept as free text, e.g. allergies
+    Dietary {
+        #[serde(default)]
+        options: Vec<String>,
+    },
+    // Must be ticked when required, e.g. photo consent
+    Consent,
+}
+
+fn validate_options(question_id: &str, options: &[String]) -> Result<Vec<String>, String> {
+    let mut validated: Vec<String> = Vec::new();
+    for option in options.iter().map(|option| option.trim()) {
+        if option.is_empty() || option.chars().count() > MAX_LABEL_LENGTH {
+            return Err(format!("Question {} has an empty or overlong option", question_id));
+        }
+        if validated.iter().any(|existing| existing.eq_ignore_ascii_case(option)) {
+            return Err(format!("Question {} lists {} twice", question_id, option));
+        }
+        validated.push(option.to_string());
+    }
+    if validated.len() > MAX_OPTIONS {
+        return Err(format!("Question {} has more than {} options", question_id, MAX_OPTIONS));
+    }
+    Ok(validated)
+}
+
+/// Check a form definition, trimming labels and options
+pub fn validate_form(questions: Vec<Question>) -> Result<Vec<Question>, String> {
+    if questions.len() > MAX_QUESTIONS {
+        return Err(format!("A form has at most {} questions", MAX_QUESTIONS));
+    }
+
+    let mut validated: Vec<Question> = Vec::new();
+    for question in questions {
+        let id = question.id.trim().to_string();
+        let valid_id = !id.is_empty()
+            && id.len() <= MAX_ID_LENGTH
+            && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_id {
+            return Err(format!(
+                "Question id {:?} must be 1 to {} lowercase letters, digits or underscores",
+                question.id, MAX_ID_LENGTH
+            ));
+        }
+        if validated.iter().any(|existing| existing.id == id) {
+            return Err(format!("Question id {} is used twice", id));
+        }
+
+        let label = question.label.trim().to_string();
+        if label.is_empty() || label.chars().count() > MAX_LABEL_LENGTH {
+            return Err(format!("Question {} needs a label of at most {} characters", id, MAX_LABEL_LENGTH));
+        }
+
+        let kind = match question.kind {
+            QuestionKind::SingleChoice { options } | QuestionKind::MultipleChoice { options } if options.is_empty() => {
+                return Err(format!("Question {} needs options", id));
+            }
+            QuestionKind::SingleChoice { options } => QuestionKind::SingleChoice {
+                options: validate_options(&id, &options)?,
+            },
+            QuestionKind::MultipleChoice { options } => QuestionKind::MultipleChoice {
+                options: validate_options(&id, &options)?,
+            },
+            QuestionKind::Dietary { options } if options.is_empty() => QuestionKind::Dietary {
+                options: DIETARY_OPTIONS.iter().map(|option| option.to_string()).collect(),
+            },
+            QuestionKind::Dietary { options } => QuestionKind::Dietary {
+                options: validate_options(&id, &options)?,
+            },
+            kind => kind,
+        };
+
+        validated.push(Question {
+            id,
+            label,
+            kind,
+            required: question.required,
+        });
+    }
+
+    Ok(validated)
+}
+
+fn choice(options: &[String], value: &str) -> Option<String> {
+    options
+        .iter()
+        .find(|option| option.eq_ignore_ascii_case(value.trim()))
+        .cloned()
+}
+
+fn strings(value: &Value) -> Option<Vec<&str>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str())
+        .collect()
+}
+
+// A normalized answer, None when left blank
+fn check_answer(question: &Question, value: &Value) -> Result<Option<Value>, String> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    let label = &question.label;
+
+    match &question.kind {
+        QuestionKind::Text { .. } => {
+            let text = value.as_str().ok_or_else(|| format!("{}: expected text", label))?.trim();
+            if text.chars().count() > MAX_ANSWER_LENGTH {
+                return Err(format!("{}: longer than {} characters", label, MAX_ANSWER_LENGTH));
+            }
+            Ok((!text.is_empty()).then(|| json!(text)))
+        }
+        QuestionKind::SingleChoice { options } => {
+            let text = value.as_str().ok_or_else(|| format!("{}: expected one of the options", label))?;
+            if text.trim().is_empty() {
+                return Ok(None);
+            }
+            choice(options, text)
+                .map(|option| Some(json!(option)))
+                .ok_or_else(|| format!("{}: {} is not an option", label, text))
+        }
+        QuestionKind::MultipleChoice { options } => {
+            let values = strings(value).ok_or_else(|| format!("{}: expected a list of options", label))?;
+            let mut chosen: Vec<String> = Vec::new();
+            for text in values {
+                let option = choice(options, text).ok_or_else(|| format!("{}: {} is not an option", label, text))?;
+                if !chosen.contains(&option) {
+                    chosen.push(option);
+                }
+            }
+            Ok((!chosen.is_empty()).then(|| json!(chosen)))
+        }
+        QuestionKind::Dietary { options } => {
+            let values = strings(value).ok_or_else(|| format!("{}: expected a list", label))?;
+            let mut needs: Vec<String> = Vec::new();
+            for text in values.into_iter().map(str::trim).filter(|text| !text.is_empty()) {
+                if text.chars().count() > MAX_ANSWER_LENGTH {
+                    return Err(format!("{}: longer than {} characters", label, MAX_ANSWER_LENGTH));
+                }
+                let need = choice(options, text).unwrap_or_else(|| text.to_string());
+                if !needs.contains(&need) {
+                    needs.push(need);
+                }
+            }
+            Ok((!needs.is_empty()).then(|| json!(needs)))
+        }
+        QuestionKind::Consent => {
+            let accepted = value.as_bool().ok_or_else(|| format!("{}: expected true or false", label))?;
+            Ok(accepted.then_some(json!(true)))
+        }
+    }
+}
+
+/// Check answers against a form. Returns the answers to store, or every
+/// problem found.
+pub fn check_answers(form: &[Question], answers: &Map<String, Value>) -> Result<Value, Vec<String>> {
+    let mut errors: Vec<String> = answers
+        .keys()
+        .filter(|id| !form.iter().any(|question| &question.id == *id))
+        .map(|id| format!("Unknown question: {}", id))
+        .collect();
+
+    let mut checked = Map::new();
+    for question in form {
+        let answer = match answers.get(&question.id).map(|value| check_answer(question, value)) {
+            Some(Ok(answer)) => answer,
+            Some(Err(e)) => {
+                errors.push(e);
+                continue;
+            }
+            None => None,
+        };
+
+        match answer {
+            Some(answer) => {
+                checked.insert(question.id.clone(), answer);
+            }
+            None if question.required => match question.kind {
+                QuestionKind::Consent => errors.push(format!("{}: must be accepted", question.label)),
+                _ => errors.push(format!("{}: an answer is required", question.label)),
+            },
+            None => {}
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Value::Object(checked))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Questions asked by an event, None if the event does not exist
+pub async fn fetch_form(pool: &PgPool, event_id: i32) -> Result<Option<Vec<Question>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Json<Vec<Question>>>("SELECT registration_questions FROM events WHERE id = $1")
+        .bind(event_id)
+        .fetch_optional(pool)
+        .await
+        .map(|form| form.map(|form| form.0))
+}
+
+// Questions of an event, for the RSVP form
+pub async fn get_questions(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+
+    match fetch_form(pool.get_ref(), event_id).await {
+        Ok(Some(questions)) => HttpResponse::Ok().json(questions),
+        Ok(None) => HttpResponse::NotFound().json(json!({ "message": "Event not found" })),
+        Err(e) => {
+            eprintln!("Failed to fetch questions of event {}: {}", event_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch questions"
+            }))
+        }
+    }
+}
+
+// Replace the questions of an event
+pub async fn set_questions(
+    pool: web::Data<PgPool>,
+    event_id: web::Path<i32>,
+    questions: web::Json<Vec<Question>>,
+) -> impl Responder {
+    let event_id = event_id.into_inner();
+
+    let questions = match validate_form(questions.into_inner()) {
+        Ok(questions) => questions,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "message": e })),
+    };
+
+    let result = sqlx::query(
+        "UPDATE events SET registration_questions = $1, updated_at = NOW() WHERE id = $2"
+    )
+    .bind(Json(&questions))
+    .bind(event_id)
+    .execute(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 0 => {
+            HttpResponse::NotFound().json(json!({ "message": "Event not found" }))
+        }
+        Ok(_) => HttpResponse::Ok().json(questions),
+        Err(e) => {
+            eprintln!("Failed to update questions of event {}: {}", event_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to update questions"
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(id: &str, kind: QuestionKind, required: bool) -> Question {
+        Question { id: id.to_string(), label: id.to_string(), kind, required }
+    }
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(|option| option.to_string()).collect()
+    }
+
+    fn answers(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn form() -> Vec<Question> {
+        validate_form(vec![
+            question("name_badge", QuestionKind::Text { multiline: false }, true),
+            question("size", QuestionKind::SingleChoice { options: options(&["S", "M", "L"]) }, false),
+            question("sessions", QuestionKind::MultipleChoice { options: options(&["Morning", "Evening"]) }, false),
+            question("diet", QuestionKind::Dietary { options: Vec::new() }, false),
+            question("photos", QuestionKind::Consent, true),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn form_is_trimmed_and_gets_dietary_defaults() {
+        let form = validate_form(vec![Question {
+            id: " diet ".to_string(),
+            label: "  Dietary needs ".to_string(),
+            kind: QuestionKind::Dietary { options: Vec::new() },
+            required: false,
+        }])
+        .unwrap();
+
+        assert_eq!(form[0].id, "diet");
+        assert_eq!(form[0].label, "Dietary needs");
+        match &form[0].kind {
+            QuestionKind::Dietary { options } => assert_eq!(options.len(), DIETARY_OPTIONS.len()),
+            kind => panic!("unexpected kind {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn invalid_forms_are_refused() {
+        let text = || QuestionKind::Text { multiline: false };
+        assert!(validate_form(vec![question("Bad Id", text(), false)]).is_err());
+        assert!(validate_form(vec![question("", text(), false)]).is_err());
+        assert!(validate_form(vec![question("a", text(), false), question("a", text(), false)]).is_err());
+        assert!(validate_form(vec![question("a", QuestionKind::SingleChoice { options: Vec::new() }, false)]).is_err());
+        assert!(validate_form(vec![question(
+            "a",
+            QuestionKind::SingleChoice { options: options(&["Yes", "yes"]) },
+            false
+        )])
+        .is_err());
+
+        let too_many = (0..=MAX_QUESTIONS).map(|i| question(&format!("q{}", i), text(), false)).collect();
+        assert!(validate_form(too_many).is_err());
+    }
+
+    #[test]
+    fn answers_are_normalized() {
+        let checked = check_answers(
+            &form(),
+            &answers(json!({
+                "name_badge": "  Sam ",
+                "size": "m",
+                "sessions": ["evening", "Evening", "morning"],
+                "diet": ["vegan", "  no mushrooms ", ""],
+                "photos": true,
+            })),
+        )
+        .unwrap();
+
+        assert_eq!(
+            checked,
+            json!({
+                "name_badge": "Sam",
+                "size": "M",
+                "sessions": ["Evening", "Morning"],
+                "diet": ["Vegan", "no mushrooms"],
+                "photos": true,
+            })
+        );
+    }
+
+    #[test]
+    fn blank_optional_answers_are_dropped() {
+        let checked = check_answers(
+            &form(),
+            &answers(json!({ "name_badge": "Sam", "size": " ", "sessions": [], "diet": null, "photos": true })),
+        )
+        .unwrap();
+        assert_eq!(checked, json!({ "name_badge": "Sam", "photos": true }));
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let errors = check_answers(
+            &form(),
+            &answers(json!({ "size": "XXL", "sessions": "Morning", "photos": false, "extra": 1 })),
+        )
+        .unwrap_err();
+
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        assert!(errors.contains(&"Unknown question: extra".to_string()));
+        assert!(errors.contains(&"name_badge: an answer is required".to_string()));
+        assert!(errors.contains(&"size: XXL is not an option".to_string()));
+        assert!(errors.contains(&"sessions: expected a list of options".to_string()));
+        assert!(errors.contains(&"photos: must be accepted".to_string()));
+    }
+
+    #[test]
+    fn overlong_answers_are_refused() {
+        let long = "x".repeat(MAX_ANSWER_LENGTH + 1);
+        let errors = check_answers(&form(), &answers(json!({ "name_badge": long, "photos": true }))).unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        let errors = check_answers(
+            &form(),
+            &answers(json!({ "name_badge": "Sam", "diet": [long], "photos": true })),
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,73 @@
+use redis::{AsyncCommands, Client, RedisResult};
+
+/// RevocationStore keeps revoked JWTs in Redis so every backend instance
+/// sees the same logout state and entries expire with the tokens themselves.
+/// Also compiled into event_management, see session.rs
+#[derive(Clone)]
+pub struct RevocationStore {
+    client: Client,
+}
+
+impl RevocationStore {
+    /// Creates a new RevocationStore instance
+    ///
+    /// # Arguments
+    /// * `client` - Redis client instance
+    pub fn new_with_client(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Gets an async connection from the client
+    async fn get_connection(&self) -> RedisResult<redis::aio::Connection> {
+        self.client.get_async_connection().await
+    }
+
+    fn token_key(jti: &str) -> String {
+        format!("revoked:jti:{}", jti)
+    }
+
+    // Cut-offs are in milliseconds, under their own key so none is read as seconds
+    fn user_key(user_id: i32) -> String {
+        format!("revoked:user_ms:{}", user_id)
+    }
+
+    /// Revokes a single token by its `jti`
+    ///
+    /// # Arguments
+    /// * `jti` - Unique token identifier
+    /// * `ttl_secs` - Remaining lifetime of the token; nothing is stored if it already expired
+    pub async fn revoke_token(&self, jti: &str, ttl_secs: i64) -> RedisResult<()> {
+        if ttl_secs <= 0 {
+            return Ok(());
+        }
+        let mut conn = self.get_connection().await?;
+        let _: () = conn.set_ex(Self::token_key(jti), "1", ttl_secs as usize).await?;
+        Ok(())
+    }
+
+    /// Checks whether a token has been revoked
+    pub async fn is_token_revoked(&self, jti: &str) -> RedisResult<bool> {
+        let mut conn = self.get_connection().await?;
+        conn.exists(Self::token_key(jti)).await
+    }
+
+    /// Revokes every token issued to a user up to now
+    ///
+    /// # Arguments
+    /// * `user_id` - User whose sessions are revoked
+    /// * `revoked_at_ms` - Unix time in milliseconds; tokens issued at or before it are rejected
+    /// * `ttl_secs` - How long to remember the cut-off (the longest token lifetime)
+    pub async fn revoke_user(&self, user_id: i32, revoked_at_ms: i64, ttl_secs: i64) -> RedisResult<()> {
+        let mut conn = self.get_connection().await?;
+        let _: () = conn.set_ex(Self::user_key(user_id), revoked_at_ms, ttl_secs as usize).await?;
+        Ok(())
+    }
+
+    /// Checks whether a token issued at `issued_at_ms` (Unix time in
+    /// milliseconds) predates a user-wide revocation
+    pub async fn is_user_revoked(&self, user_id: i32, issued_at_ms: i64) -> RedisResult<bool> {
+        let mut conn = self.get_connection().await?;
+        let revoked_at_ms: Option<i64> = conn.get(Self::user_key(user_id)).await?;
+        Ok(matches!(revoked_at_ms, Some(revoked_at_ms) if issued_at_ms <= revoked_at_ms))
+    }
+}
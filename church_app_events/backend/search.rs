@@ -0,0 +1,288 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder, Row};
+use std::collections::HashMap;
+
+use crate::pagination::{Page, PageParams, SortField, Sorting};
+
+// Full-text search of events, media, home groups and servings. Each table
+// keeps a weighted `search_vector` up to date with triggers, stemmed in
+// English or, for home groups, in the group's own language. Titles are
+// also matched by trigrams, so a misspelt word still finds its target.
+// Results are ranked and come with a snippet of their text, the matched
+// words wrapped in <mark>.
+
+const MAX_QUERY_LENGTH: usize = 200;
+
+// Around matched words in ts_headline output, until the snippet is escaped
+const START_MARK: char = '\u{2}';
+const STOP_MARK: char = '\u{3}';
+
+/// A searchable table
+pub struct Source {
+    kind: &'static str, // Kind of result in the mixed search
+    table: &'static str,
+    title: &'static str, // Also matched by trigrams
+    body: &'static str,  // Snippets are taken from it, or from the title
+    config: &'static str, // Text search configuration of a row
+}
+
+pub static EVENTS: Source = Source {
+    kind: "event",
+    table: "events",
+    title: "event_title",
+    body: "description",
+    config: "'english'::regconfig",
+};
+
+pub static MEDIA: Source = Source {
+    kind: "media",
+    table: "media",
+    title: "title",
+    body: "description",
+    config: "'english'::regconfig",
+};
+
+pub static HOME_GROUPS: Source = Source {
+    kind: "home_group",
+    table: "homegroups",
+    title: "name",
+    body: "description",
+    config: "search_config(language)",
+};
+
+pub static SERVINGS: Source = Source {
+    kind: "serving",
+    table: "serving",
+    title: "title",
+    body: "description",
+    config: "'english'::regconfig",
+};
+
+// In the order their ids are interleaved in the mixed search
+const SOURCES: &[&Source] = &[&EVENTS, &MEDIA, &HOME_GROUPS, &SERVINGS];
+
+impl Source {
+    /// Keep the rows whose words match `text`, or whose title is close to
+    /// it. A blank text keeps every row.
+    pub fn push_match(&self, query: &mut QueryBuilder<'_, Postgres>, text: &str) {
+        if text.trim().is_empty() {
+            return;
+        }
+        query.push(format!(" AND (search_vector @@ websearch_to_tsquery({}, ", self.config));
+        query.push_bind(text.to_string());
+        query.push(") OR ");
+        query.push_bind(text.to_string());
+        query.push(format!(" <% {})", self.title));
+    }
+
+    /// Select the `relevance` and `snippet` of each row for `text`
+    pub fn push_ranking(&self, query: &mut QueryBuilder<'_, Postgres>, text: &str) {
+        if text.trim().is_empty() {
+            query.push(", 0::REAL AS relevance, NULL::TEXT AS snippet");
+            return;
+        }
+
+        // Normalized rank of the words, plus the closeness of the title
+        query.push(format!(", ts_rank_cd(search_vector, websearch_to_tsquery({}, ", self.config));
+        query.push_bind(text.to_string());
+        query.push("), 32) + word_similarity(");
+        query.push_bind(text.to_string());
+        query.push(format!(", {}) AS relevance", self.title));
+
+        query.push(format!(
+            ", ts_headline({}, COALESCE(NULLIF({}, ''), {}), websearch_to_tsquery({}, ",
+            self.config, self.body, self.title, self.config
+        ));
+        query.push_bind(text.to_string());
+        query.push("), ");
+        query.push_bind(format!(
+            "StartSel={}, StopSel={}, MaxWords=35, MinWords=15, MaxFragments=2",
+            START_MARK, STOP_MARK
+        ));
+        query.push(") AS snippet");
+    }
+
+    /// Relevance and snippets of the rows `ids` for `text`
+    pub async fn rankings(&self, pool: &PgPool, text: &str, ids: &[i32]) -> Result<HashMap<i32, Ranking>, sqlx::Error> {
+        let mut query = QueryBuilder::new("SELECT id");
+        self.push_ranking(&mut query, text);
+        query.push(format!(" FROM {} WHERE id = ANY(", self.table));
+        query.push_bind(ids.to_vec());
+        query.push(")");
+
+        let rows = query.build().fetch_all(pool).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get("id")?, Ranking::from_row(row)?)))
+            .collect()
+    }
+}
+
+/// How well a row matched, read from the columns of `push_ranking`
+#[derive(Debug, Clone, FromRow)]
+pub struct Ranking {
+    pub relevance: f32,
+    pub snippet: Option<String>,
+}
+
+/// A search result: the item itself, its relevance and a snippet
+#[derive(Debug, Serialize)]
+pub struct Hit<T> {
+    #[serde(flatten)]
+    pub item: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relevance: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>, // HTML
+}
+
+impl<T> Hit<T> {
+    pub fn new(item: T, ranking: Option<&Ranking>) -> Hit<T> {
+        Hit {
+            item,
+            relevance: ranking.map(|ranking| ranking.relevance),
+            snippet: ranking.and_then(|ranking| ranking.snippet.as_deref()).map(highlight),
+        }
+    }
+}
+
+/// Escape a ts_headline snippet for HTML, marking the matched words
+pub fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            START_MARK => html.push_str("<mark>"),
+            STOP_MARK => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: String,
+    types: Option<String>, // Comma-separated kinds, e.g. event,media; all by default
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SearchResult {
+    kind: String,
+    id: i32,
+    title: String,
+    relevance: f32,
+    snippet: Option<String>,
+}
+
+// Mixed results are sorted on their `hit_key`, unique across the tables
+static SEARCH_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("relevance", "relevance", "REAL"),
+        SortField::new("title", "LOWER(title)", "TEXT"),
+    ],
+    descending: true,
+    id: "hit_key",
+};
+
+// Matching rows of the `sources`, one table after the other
+fn push_hits(query: &mut QueryBuilder<'_, Postgres>, sources: &[(usize, &Source)], text: &str, ranked: bool) {
+    for (n, (position, source)) in sources.iter().enumerate() {
+        if n > 0 {
+            query.push(" UNION ALL ");
+        }
+        query.push(format!("SELECT '{}' AS kind, id, {} AS title", source.kind, source.title));
+        if ranked {
+            source.push_ranking(query, text);
+            query.push(format!(", id::BIGINT * {} + {} AS hit_key", SOURCES.len(), position));
+        }
+        query.push(format!(" FROM {} WHERE 1=1", source.table));
+        source.push_match(query, text);
+    }
+}
+
+// Search events, media, home groups and servings at once, best matches first
+pub async fn search(
+    pool: web::Data<PgPool>,
+    params: web::Query<SearchParams>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let text = params.q.trim();
+    if text.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "message": "Enter something to search for" }));
+    }
+    if text.chars().count() > MAX_QUERY_LENGTH {
+        return HttpResponse::BadRequest().json(json!({
+            "message": format!("Searches are at most {} characters", MAX_QUERY_LENGTH)
+        }));
+    }
+
+    let mut sources: Vec<(usize, &Source)> = Vec::new();
+    match params.types.as_deref().map(str::trim).filter(|types| !types.is_empty()) {
+        None => sources.extend(SOURCES.iter().copied().enumerate()),
+        Some(types) => {
+            for kind in types.split(',').map(str::trim).filter(|kind| !kind.is_empty()) {
+                match SOURCES.iter().position(|source| source.kind == kind) {
+                    Some(position) if sources.iter().any(|(existing, _)| *existing == position) => {}
+                    Some(position) => sources.push((position, SOURCES[position])),
+                    None => {
+                        return HttpResponse::BadRequest().json(json!({
+                            "message": format!("Cannot search for {}", kind),
+                            "types": SOURCES.iter().map(|source| source.kind).collect::<Vec<_>>(),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+
+    let page = match Page::new(&page, &SEARCH_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let mut count_query = QueryBuilder::new("SELECT kind, COUNT(*) FROM (");
+    push_hits(&mut count_query, &sources, text, false);
+    count_query.push(") AS hits GROUP BY kind");
+
+    let counts: HashMap<String, i64> = match count_query.build_query_as::<(String, i64)>().fetch_all(pool.get_ref()).await {
+        Ok(counts) => counts.into_iter().collect(),
+        Err(e) => {
+            eprintln!("Error counting search results: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({ "message": "Failed to search" }));
+        }
+    };
+    let counts: HashMap<&str, i64> = sources
+        .iter()
+        .map(|(_, source)| (source.kind, counts.get(source.kind).copied().unwrap_or(0)))
+        .collect();
+
+    let mut query = QueryBuilder::new(format!("SELECT *, {} FROM (", page.key_columns()));
+    push_hits(&mut query, &sources, text, true);
+    query.push(") AS hits WHERE 1=1");
+    page.push_to(&mut query);
+
+    let results = match query.build().fetch_all(pool.get_ref()).await {
+        Ok(rows) => page.finish(rows, counts.values().sum(), |row| SearchResult::from_row(row)),
+        Err(e) => Err(e),
+    };
+
+    match results {
+        Ok(results) => {
+            let results = results.map(|result| SearchResult {
+                snippet: result.snippet.as_deref().map(highlight),
+                ..result
+            });
+            HttpResponse::Ok().json(results.with("counts", json!(counts)))
+        }
+        Err(e) => {
+            eprintln!("Error searching: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({ "message": "Failed to search" }))
+        }
+    }
+}
@@ -2,7 +2,9 @@
 use actix_web::{web, HttpResponse, Responder};
 use chrono::{NaiveDateTime};
 use serde::{Serialize, Deserialize};
-use sqlx::{PgPool, FromRow};
+use sqlx::{PgPool, FromRow, Postgres, QueryBuilder};
+
+use crate::pagination::{Listing, Page, PageParams, SortField, Sorting};
 
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -42,7 +44,7 @@ pub async fn add_serving(
         Serving,
         r#"INSERT INTO serving (title, description, location, created_by)
         VALUES ($1, $2, $3, $4)
-        RETURNING *"#,
+        RETURNING id, title, description, location, created_by, created_at, updated_at"#,
         serving.title,
         serving.description,
         serving.location,
@@ -62,14 +64,15 @@ pub async fn add_serving(
 
 // Get all servings
 pub async fn get_all_servings (
-    pool: web::Data<PgPool>
+    pool: web::Data<PgPool>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query_as!(
-        Serving,
-        r#"SELECT * FROM serving"#
-    )
-    .fetch_all(pool.get_ref())
-    .await;
+    let page = match Page::new(&page, &SERVING_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let result = fetch_servings(pool.get_ref(), &SearchQuery::default(), &page).await;
 
     match result {
         Ok(serving) => HttpResponse::Ok().json(serving),
@@ -87,7 +90,7 @@ pub async fn get_serving(
 ) -> impl Responder {
     let result = sqlx::query_as!(
         Serving,
-        r#"SELECT * FROM serving WHERE id = $1"#,
+        r#"SELECT id, title, description, location, created_by, created_at, updated_at FROM serving WHERE id = $1"#,
         id.into_inner()
     )
     .fetch_optional(pool.get_ref())
@@ -117,7 +120,8 @@ pub async fn update_serving(
             description = COALESCE($2, description),
             location = COALESCE($3, location),
             updated_at = CURRENT_TIMESTAMP
-        WHERE id = $4 RETURNING *"#,
+        WHERE id = $4
+        RETURNING id, title, description, location, created_by, created_at, updated_at"#,
         request.title,
         request.description,
         request.location,
@@ -159,44 +163,64 @@ pub async fn delete_serving (
 }
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 pub struct SearchQuery{
     pub title: Option<String>,
     pub location: Option<String>
 }
 
-// Search for servings
-pub async fn search_servings(
-    pool: web::Data<PgPool>,
-    query: web::Query<SearchQuery>
-) -> impl Responder {
-    let mut sql = String::from(
-        r#"
-        SELECT * FROM serving WHERE 1 = 1"#
-    );
-
-    let mut bindings = vec![];
-
-    if let Some(title) = &query.title{
-        sql.push_str(" AND LOWER(title) LIKE LOWER($1)");
-        bindings.push(format!("%{}%", title))
+// Sorts of serving listings, newest first by default
+static SERVING_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("created_at", "COALESCE(created_at, 'epoch')", "TIMESTAMP"),
+        SortField::new("title", "LOWER(title)", "TEXT"),
+        SortField::new("location", "LOWER(COALESCE(location, ''))", "TEXT"),
+        SortField::new("id", "id", "INTEGER"),
+    ],
+    descending: true,
+    id: "id",
+};
+
+fn push_filters(query: &mut QueryBuilder<'_, Postgres>, params: &SearchQuery) {
+    if let Some(title) = &params.title {
+        query.push(" AND LOWER(title) LIKE LOWER(");
+        query.push_bind(format!("%{}%", title));
+        query.push(")");
     }
 
-    if let Some(location) = &query.location{
-        let param_num = bindings.len() + 1;
-        sql.push_str(&format!(" AND LOWER(location) LIKE LOWER(${})", param_num));
-        bindings.push(format!("%{}%", location))
+    if let Some(location) = &params.location {
+        query.push(" AND LOWER(location) LIKE LOWER(");
+        query.push_bind(format!("%{}%", location));
+        query.push(")");
     }
+}
 
-    sql.push_str(" ORDER BY created_by DESC");
+// A page of the servings matching `params`
+async fn fetch_servings(pool: &PgPool, params: &SearchQuery, page: &Page) -> Result<Listing<Serving>, sqlx::Error> {
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM serving WHERE 1 = 1");
+    push_filters(&mut count_query, params);
+    let (total,): (i64,) = count_query.build_query_as().fetch_one(pool).await?;
 
-    let mut query = sqlx::query_as::<_, Serving>(&sql);
+    let mut query = QueryBuilder::new(format!("SELECT *, {} FROM serving WHERE 1 = 1", page.key_columns()));
+    push_filters(&mut query, params);
+    page.push_to(&mut query);
 
-    for binding in bindings{
-        query = query.bind(binding)
-    }
+    let rows = query.build().fetch_all(pool).await?;
+    page.finish(rows, total, |row| Serving::from_row(row))
+}
 
-    let result = query.fetch_all(pool.get_ref()).await;
+// Search for servings
+pub async fn search_servings(
+    pool: web::Data<PgPool>,
+    query: web::Query<SearchQuery>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &SERVING_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+
+    let result = fetch_servings(pool.get_ref(), &query, &page).await;
 
     match result {
         Ok(servings) => HttpResponse::Ok().json(servings),
@@ -205,4 +229,4 @@ pub async fn search_servings(
             HttpResponse::InternalServerError().json("Failed to search servings")
         }
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,10 @@
 use actix_web::{web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+
+use crate::export::{self, Column, ExportParams};
+use crate::pagination::{Listing, Page, PageParams, SortField, Sorting};
+use crate::user::Claims;
 use chrono::NaiveDateTime;
 
 // Define RSVP status enum
@@ -109,41 +113,56 @@ pub async fn create_serving_rsvp(
     }
 }
 
+const SERVING_RSVP_COLUMNS: &str = "sr.id, sr.user_id, sr.serving_id, sr.email, sr.name, sr.phone, \
+     sr.rsvp_status, sr.rsvp_date, COALESCE(s.title, '') AS serving_title, \
+     COALESCE(s.location, '') AS serving_location";
+
+// Sorts of serving RSVP listings, newest first by default
+static SERVING_RSVP_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("rsvp_date", "COALESCE(sr.rsvp_date, 'epoch')", "TIMESTAMP"),
+        SortField::new("name", "LOWER(sr.name)", "TEXT"),
+        SortField::new("email", "LOWER(sr.email)", "TEXT"),
+        SortField::new("status", "sr.rsvp_status::TEXT", "TEXT"),
+    ],
+    descending: true,
+    id: "sr.id",
+};
+
+// A page of the serving RSVPs matching `params`
+async fn fetch_serving_rsvps(
+    pool: &PgPool,
+    params: &SearchParams,
+    page: &Page,
+) -> Result<Listing<ServingRSVPResponse>, sqlx::Error> {
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM servingrsvps sr WHERE 1=1");
+    push_filters(&mut count_query, params);
+    let (total,): (i64,) = count_query.build_query_as().fetch_one(pool).await?;
+
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {}, {} FROM servingrsvps sr LEFT JOIN serving s ON s.id = sr.serving_id WHERE 1=1",
+        SERVING_RSVP_COLUMNS,
+        page.key_columns()
+    ));
+    push_filters(&mut query, params);
+    page.push_to(&mut query);
+
+    let rows = query.build().fetch_all(pool).await?;
+    page.finish(rows, total, |row| ServingRSVPResponse::from_row(row))
+}
+
 // Get all serving RSVPs
-pub async fn get_all_serving_rsvps(pool: web::Data<PgPool>) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        SELECT
-            sr.id, sr.user_id, sr.serving_id, sr.email, sr.name, sr.phone,
-            sr.rsvp_status as "rsvp_status!: ServingStatusType", sr.rsvp_date,
-            (SELECT title FROM serving WHERE id = sr.serving_id) as serving_title,
-            (SELECT location FROM serving WHERE id = sr.serving_id) as serving_location
-        FROM servingrsvps sr
-        ORDER BY sr.rsvp_date DESC
-        "#
-    )
-    .fetch_all(pool.get_ref())
-    .await;
+pub async fn get_all_serving_rsvps(
+    pool: web::Data<PgPool>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &SERVING_RSVP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    match result {
-        Ok(rsvps) => {
-            let response: Vec<ServingRSVPResponse> = rsvps
-                .iter()
-                .map(|r| ServingRSVPResponse {
-                    id: r.id,
-                    user_id: r.user_id,
-                    serving_id: r.serving_id,
-                    email: r.email.clone(),
-                    name: r.name.clone(),
-                    phone: r.phone.clone(),
-                    rsvp_status: r.rsvp_status.clone(),
-                    rsvp_date: r.rsvp_date.expect("RSVP date should be present"),
-                    serving_title: Some(r.serving_title.clone().unwrap_or_default()),
-                    serving_location: Some(r.serving_location.clone().unwrap_or_default()),
-                })
-                .collect();
-            HttpResponse::Ok().json(response)
-        }
+    match fetch_serving_rsvps(pool.get_ref(), &SearchParams::default(), &page).await {
+        Ok(rsvps) => HttpResponse::Ok().json(rsvps),
         Err(e) => {
             eprintln!("Failed to fetch serving RSVPs: {}", e);
             HttpResponse::InternalServerError().json("Failed to fetch serving RSVPs")
@@ -152,11 +171,11 @@ pub async fn get_all_serving_rsvps(pool: web::Data<PgPool>) -> impl Responder {
 }
 
 // Update a serving RSVP
-pub async fn update_serving_rsvp (
+pub async fn update_serving_rsvp(
     pool: web::Data<PgPool>,
     rsvp_id: web::Path<i32>,
     status: web::Json<ServingStatusType>,
-) -> impl Responder {
+) -> HttpResponse {
     let result = sqlx::query!(
         r#"
         UPDATE servingrsvps
@@ -197,7 +216,7 @@ pub async fn update_serving_rsvp (
 pub async fn delete_serving_rsvp(
     pool: web::Data<PgPool>,
     rsvp_id: web::Path<i32>,
-) -> impl Responder {
+) -> HttpResponse {
     let result = sqlx::query!(
         "DELETE FROM servingrsvps WHERE id = $1 RETURNING id",
         rsvp_id.into_inner()
@@ -215,6 +234,49 @@ pub async fn delete_serving_rsvp(
     }
 }
 
+// Make sure the user may change a serving RSVP; the error is the response to give
+async fn check_owner(pool: &PgPool, claims: &Claims, rsvp_id: i32) -> Result<(), HttpResponse> {
+    let owner = sqlx::query_as::<_, (String, Option<i32>)>("SELECT email, user_id FROM servingrsvps WHERE id = $1")
+        .bind(rsvp_id)
+        .fetch_optional(pool)
+        .await;
+
+    match owner {
+        Ok(Some((email, user_id))) if claims.owns(&email, user_id) => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().json("You can only change your own serving RSVPs")),
+        Ok(None) => Err(HttpResponse::NotFound().json("Serving RSVP not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Update a serving RSVP of the logged-in user
+pub async fn update_own_serving_rsvp(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rsvp_id: web::Path<i32>,
+    status: web::Json<ServingStatusType>,
+) -> HttpResponse {
+    if let Err(response) = check_owner(pool.get_ref(), &claims, *rsvp_id).await {
+        return response;
+    }
+    update_serving_rsvp(pool, rsvp_id, status).await
+}
+
+// Delete a serving RSVP of the logged-in user
+pub async fn delete_own_serving_rsvp(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    rsvp_id: web::Path<i32>,
+) -> HttpResponse {
+    if let Err(response) = check_owner(pool.get_ref(), &claims, *rsvp_id).await {
+        return response;
+    }
+    delete_serving_rsvp(pool, rsvp_id).await
+}
+
 // Confirm a serving RSVP
 pub async fn confirm_serving_rsvp(
     pool: web::Data<PgPool>,
@@ -310,63 +372,26 @@ pub struct ServingWithResponse {
     serving_location: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct SearchParams {
     pub email: Option<String>,
     pub name: Option<String>,
     pub rsvp_status: Option<ServingStatusType>,
     pub user_id: Option<i32>,
+    pub serving_id: Option<i32>,
 }
 
 pub async fn search_serving_rsvp(
     pool: web::Data<PgPool>,
     query: web::Query<SearchParams>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let result = sqlx::query!(
-        r#"
-        SELECT
-            id,
-            user_id,
-            serving_id,
-            email,
-            name,
-            phone,
-            rsvp_status as "rsvp_status!: ServingStatusType",
-            rsvp_date,
-            (SELECT title FROM serving WHERE id = serving_id) as serving_title,
-            (SELECT location FROM serving WHERE id = serving_id) as serving_location
-        FROM servingrsvps
-        WHERE ($1::text IS NULL OR LOWER(email) LIKE CONCAT('%', LOWER($1), '%'))
-        AND ($2::text IS NULL OR LOWER(name) LIKE CONCAT('%', LOWER($2), '%'))
-        AND ($3::text IS NULL OR rsvp_status::text = $3)
-        AND ($4::integer IS NULL OR user_id = $4)
-        ORDER BY rsvp_date DESC
-        "#,
-        query.email.as_deref(),
-        query.name.as_deref(),
-        query.rsvp_status.as_ref().map(|s| s.to_string()),
-        query.user_id
-    )
-    .fetch_all(pool.get_ref())
-    .await
-    .map(|rows| {
-        rows.into_iter()
-            .map(|row| ServingRSVPResponse {
-                id: row.id,
-                user_id: row.user_id,
-                serving_id: row.serving_id,
-                email: row.email,
-                name: row.name,
-                phone: row.phone,
-                rsvp_status: row.rsvp_status,
-                rsvp_date: row.rsvp_date.expect("RSVP date should be present"),
-                serving_title: Some(row.serving_title.unwrap_or_default()),
-                serving_location: Some(row.serving_location.unwrap_or_default()),
-            })
-            .collect::<Vec<_>>()
-    });
+    let page = match Page::new(&page, &SERVING_RSVP_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    match result {
+    match fetch_serving_rsvps(pool.get_ref(), &query, &page).await {
         Ok(rsvps) => HttpResponse::Ok().json(rsvps),
         Err(e) => {
             eprintln!("Search error: {}", e);
@@ -375,3 +400,65 @@ pub async fn search_serving_rsvp(
     }
 }
 
+// Filters of a search, on servingrsvps sr
+fn push_filters(query: &mut QueryBuilder<'_, Postgres>, params: &SearchParams) {
+    if let Some(email) = &params.email {
+        query.push(" AND LOWER(sr.email) LIKE ");
+        query.push_bind(format!("%{}%", email.to_lowercase()));
+    }
+    if let Some(name) = &params.name {
+        query.push(" AND LOWER(sr.name) LIKE ");
+        query.push_bind(format!("%{}%", name.to_lowercase()));
+    }
+    if let Some(rsvp_status) = &params.rsvp_status {
+        query.push(" AND sr.rsvp_status = ");
+        query.push_bind(rsvp_status.clone());
+    }
+    if let Some(user_id) = params.user_id {
+        query.push(" AND sr.user_id = ");
+        query.push_bind(user_id);
+    }
+    if let Some(serving_id) = params.serving_id {
+        query.push(" AND sr.serving_id = ");
+        query.push_bind(serving_id);
+    }
+}
+
+// Columns of a serving RSVP export
+fn export_columns() -> Vec<Column> {
+    vec![
+        Column::number("id", "sr.id"),
+        Column::text("email", "sr.email"),
+        Column::text("name", "sr.name"),
+        Column::text("phone", "sr.phone"),
+        Column::number("user_id", "sr.user_id"),
+        Column::number("serving_id", "sr.serving_id"),
+        Column::text("serving_title", "s.title"),
+        Column::text("serving_location", "s.location"),
+        Column::text("status", "sr.rsvp_status"),
+        Column::text("rsvp_date", "sr.rsvp_date"),
+    ]
+}
+
+// Export serving RSVPs as CSV or XLSX with the search filters
+pub async fn export_serving_rsvps(
+    pool: web::Data<PgPool>,
+    params: web::Query<SearchParams>,
+    export_params: web::Query<ExportParams>,
+) -> impl Responder {
+    let (format, columns) = match export::parse_params(&export_params, export_columns()) {
+        Ok(selection) => selection,
+        Err(response) => return response,
+    };
+
+    let mut query = export::select(&columns);
+    query.push(" FROM servingrsvps sr LEFT JOIN serving s ON s.id = sr.serving_id WHERE 1=1");
+    push_filters(&mut query, &params);
+    query.push(" ORDER BY sr.rsvp_date DESC, sr.id DESC");
+
+    let filename = match params.serving_id {
+        Some(serving_id) => format!("serving-{}-rsvps", serving_id),
+        None => "serving-rsvps".to_string(),
+    };
+    export::stream(pool.get_ref(), query, columns, format, &filename)
+}
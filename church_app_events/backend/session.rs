@@ -0,0 +1,170 @@
+use actix_web::{
+    dev::{Payload, ServiceRequest},
+    http::{header::HeaderMap, StatusCode},
+    web, Error, FromRequest, HttpMessage, HttpRequest,
+};
+use dotenv::dotenv;
+use futures::future::{err, ok, Ready};
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::revocation::RevocationStore;
+
+// Sessions: the claims of the JWTs issued at login and how a request is
+// authenticated with them. event_management includes this file and
+// revocation.rs with #[path], so both services accept the same tokens and
+// honour the same logouts; they must not use other modules of this crate.
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Admin,
+    User,
+    Guest
+}
+
+impl std::fmt::Display for UserRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserRole::Admin => write!(f, "admin"),
+            UserRole::User => write!(f, "user"),
+            UserRole::Guest => write!(f, "guest"),
+        }
+    }
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "admin" => Ok(UserRole::Admin),
+            "user" => Ok(UserRole::User),
+            "guest" => Ok(UserRole::Guest),
+            _ => Err(format!("Invalid role: {}", s))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    // Standard JWT claims
+    pub sub: i32, // subject: User ID
+    pub exp: usize, // expiration: Expiration time
+    pub iat: usize, // issued at: Issued at time
+    pub jti: String, // JWT ID: Unique token identifier used for revocation
+    // Custom claims
+    #[serde(default)]
+    pub iat_ms: i64, // Issued at in milliseconds, to tell it from a revocation in the same second
+    #[serde(default)]
+    pub two_factor: bool, // Account has 2FA enabled, so the session passed a second factor
+    #[serde(default)]
+    pub two_factor_optional: bool, // The security policy let this admin in without a second factor
+    #[serde(default)]
+    pub permissions: Vec<String>, // Held for every resource, for event_management which cannot look them up
+    pub role: UserRole,
+    pub email: String,
+    pub username: String,
+}
+
+pub fn jwt_secret() -> String {
+    std::env::var("JWT_SECRET")
+    .unwrap_or_else(|_| {
+        dotenv().ok();
+        std::env::var("JWT_SECRET").expect("JWT_SECRET must be set")
+    })
+}
+
+pub fn decode_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let secret = jwt_secret();
+
+    let validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation
+    )
+    .map(|token_data| token_data.claims)
+}
+
+impl Claims {
+    /// When the token was issued, in milliseconds. Older tokens only carry
+    /// the second, taken as its start so a revocation in it still applies.
+    pub fn issued_at_ms(&self) -> i64 {
+        if self.iat_ms > 0 {
+            self.iat_ms
+        } else {
+            self.iat as i64 * 1000
+        }
+    }
+}
+
+// Decode the token and make sure it was not revoked by logout or by an admin
+pub async fn verify_jwt(token: &str, revocations: &RevocationStore) -> Result<Claims, String> {
+    let claims = decode_jwt(token).map_err(|e| format!("Invalid token: {}", e))?;
+
+    let revoked = async {
+        Ok::<_, redis::RedisError>(
+            revocations.is_token_revoked(&claims.jti).await?
+                || revocations.is_user_revoked(claims.sub, claims.issued_at_ms()).await?,
+        )
+    }
+    .await;
+
+    match revoked {
+        Ok(false) => Ok(claims),
+        Ok(true) => Err("Token has been revoked".to_string()),
+        Err(e) => Err(format!("Failed to check revocation: {}", e)),
+    }
+}
+
+// Extract the token from an "Authorization: Bearer <token>" header
+pub fn extract_bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Claims of the request's bearer token, or the status and message to
+/// reject it with. Fails closed: a token is rejected when revocation cannot
+/// be checked.
+pub async fn authenticate(req: &ServiceRequest) -> Result<Claims, (StatusCode, &'static str)> {
+    let token = match extract_bearer_token(req.headers()) {
+        Some(token) => token,
+        None => return Err((StatusCode::UNAUTHORIZED, "Missing or invalid Authorization header")),
+    };
+
+    let revocations = match req.app_data::<web::Data<RevocationStore>>() {
+        Some(revocations) => revocations,
+        None => {
+            eprintln!("RevocationStore is not registered as app data");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Authentication is unavailable"));
+        }
+    };
+
+    verify_jwt(token, revocations).await.map_err(|e| {
+        eprintln!("Rejected token for {}: {}", req.path(), e);
+        (StatusCode::UNAUTHORIZED, "Invalid or expired token")
+    })
+}
+
+// Handlers behind an auth middleware can take `Claims` as an extractor
+impl FromRequest for Claims {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Claims>() {
+            Some(claims) => ok(claims.clone()),
+            None => err(actix_web::error::ErrorUnauthorized(json!({
+                "message": "Authentication required"
+            }))),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::env;
+
+// Events are stored as a local date and time plus an IANA zone, either
+// their own or the church's (CHURCH_TIME_ZONE), so a weekly service stays
+// at 10:00 across daylight saving changes. Instants are only worked out
+// when needed: to tell whether an event is under way and to render start
+// and end in the zone a caller asks for.
+
+pub fn parse(name: &str) -> Result<Tz, String> {
+    name.trim()
+        .parse::<Tz>()
+        .map_err(|_| format!("Unknown time zone: {}", name))
+}
+
+/// Zone of the church, used for events without their own
+pub fn church_zone() -> Tz {
+    env::var("CHURCH_TIME_ZONE")
+        .ok()
+        .and_then(|name| parse(&name).ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// Zone of an event, falling back to the church's
+pub fn event_zone(time_zone: Option<&str>) -> Tz {
+    time_zone
+        .and_then(|name| parse(name).ok())
+        .unwrap_or_else(church_zone)
+}
+
+/// Zone requested by a caller (`?tz=Europe/London`), by default the church's
+pub fn requested(time_zone: Option<&str>) -> Result<Tz, String> {
+    match time_zone.map(str::trim) {
+        None | Some("") => Ok(church_zone()),
+        Some(name) => parse(name),
+    }
+}
+
+/// Today's date in `zone`
+pub fn today(zone: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&zone).date_naive()
+}
+
+/// The instant of a local date and time in `zone`. Times repeated when the
+/// clocks go back take the first; times skipped when they go forward are
+/// moved past the gap.
+pub fn localize(zone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(instant) | LocalResult::Ambiguous(instant, _) => instant.with_timezone(&Utc),
+        LocalResult::None => zone
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .map(|instant| instant.with_timezone(&Utc))
+            .unwrap_or_else(|| Utc.from_utc_datetime(&local)),
+    }
+}
+
+/// An instant as local time in `zone`, with its UTC offset
+pub fn in_zone(instant: DateTime<Utc>, zone: Tz) -> DateTime<FixedOffset> {
+    instant.with_timezone(&zone).fixed_offset()
+}
@@ -0,0 +1,733 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::json;
+use sha1::Sha1;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::env;
+
+use crate::refresh_token::{generate_token, hash_token};
+use crate::user::{token_response, Claims, User, UserRole};
+
+// RFC 6238 parameters understood by every common authenticator app
+const TOTP_PERIOD_SECS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// Accept the previous and next time step to tolerate clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+
+// How long the second login step may take
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+// Wrong codes allowed per challenge, and per user across challenges
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+const MAX_FAILED_CODES_PER_WINDOW: i64 = 10;
+const FAILED_CODES_WINDOW_MINUTES: i64 = 15;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+type HmacSha1 = Hmac<Sha1>;
+
+// A second factor: either a current TOTP code or an unused recovery code
+#[derive(Deserialize, Debug)]
+pub struct SecondFactor {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyChallengeRequest {
+    pub challenge_token: String,
+    #[serde(flatten)]
+    pub factor: SecondFactor,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ConfirmSetupRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactorRequest {
+    pub password: String,
+    #[serde(flatten)]
+    pub factor: SecondFactor,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TwoFactorPolicy {
+    pub require_admin_two_factor: bool,
+}
+
+// HOTP value (RFC 4226) of a counter
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+// Check a TOTP code against a base32 secret.
+// Returns the matched time step, which must be newer than `last_step`
+// so an intercepted code cannot be used twice.
+fn verify_totp(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let current = Utc::now().timestamp() / TOTP_PERIOD_SECS;
+    (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS)
+        .filter(|step| last_step.map_or(true, |last| *step > last))
+        .find(|step| hotp(&key, *step as u64) == code)
+}
+
+// 160-bit secret, the size recommended by RFC 4226
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+// Provisioning URI for authenticator apps (usually rendered as a QR code)
+fn otpauth_uri(secret: &str, account: &str) -> String {
+    let issuer = env::var("TOTP_ISSUER").unwrap_or_else(|_| "Church App".to_string());
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(&issuer),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(&issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD_SECS,
+    )
+}
+
+// Recovery codes are shown as "xxxxx-xxxxx" but matched without the dash
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+// Replace all recovery codes of a user; the plain codes are returned once
+async fn replace_recovery_codes(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query("DELETE FROM two_factor_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| generate_recovery_code()).collect();
+
+    for code in &codes {
+        sqlx::query("INSERT INTO two_factor_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(hash_token(&normalize_recovery_code(code)))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(codes)
+}
+
+// Verify a second factor of a user with 2FA enabled.
+// A matching TOTP code advances totp_last_step, a matching recovery code is consumed.
+async fn check_second_factor(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    factor: &SecondFactor,
+) -> Result<bool, sqlx::Error> {
+    if let Some(code) = &factor.code {
+        let row = sqlx::query_as::<_, (Option<String>, Option<i64>)>(
+            "SELECT totp_secret, totp_last_step FROM users
+             WHERE id = $1 AND totp_enabled = TRUE FOR UPDATE"
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let (secret, last_step) = match row {
+            Some((Some(secret), last_step)) => (secret, last_step),
+            _ => return Ok(false),
+        };
+
+        return match verify_totp(&secret, code, last_step) {
+            Some(step) => {
+                sqlx::query("UPDATE users SET totp_last_step = $1 WHERE id = $2")
+                    .bind(step)
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        };
+    }
+
+    if let Some(recovery_code) = &factor.recovery_code {
+        let used = sqlx::query(
+            "UPDATE two_factor_recovery_codes SET used_at = CURRENT_TIMESTAMP
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL"
+        )
+        .bind(user_id)
+        .bind(hash_token(&normalize_recovery_code(recovery_code)))
+        .execute(&mut *tx)
+        .await?;
+
+        return Ok(used.rows_affected() == 1);
+    }
+
+    Ok(false)
+}
+
+// Wrong codes of the user in the window, at login and elsewhere
+async fn recent_failures(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    now: NaiveDateTime,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT (SELECT COALESCE(SUM(attempts), 0) FROM two_factor_challenges
+                 WHERE user_id = $1 AND created_at > $2)
+              + (SELECT COUNT(*) FROM two_factor_failures
+                 WHERE user_id = $1 AND created_at > $2)"
+    )
+    .bind(user_id)
+    .bind(now - ChronoDuration::minutes(FAILED_CODES_WINDOW_MINUTES))
+    .fetch_one(&mut *tx)
+    .await
+}
+
+enum FactorCheck {
+    Passed,
+    Failed, // Recorded in the transaction, which the caller must commit
+    Locked,
+}
+
+// Verify a second factor outside the login, limited like login attempts
+async fn check_limited_factor(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    factor: &SecondFactor,
+) -> Result<FactorCheck, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+
+    // Concurrent attempts of the same user are counted one after another
+    sqlx::query("SELECT 1 FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    if recent_failures(tx, user_id, now).await? >= MAX_FAILED_CODES_PER_WINDOW {
+        return Ok(FactorCheck::Locked);
+    }
+
+    if check_second_factor(tx, user_id, factor).await? {
+        return Ok(FactorCheck::Passed);
+    }
+
+    sqlx::query("INSERT INTO two_factor_failures (user_id, created_at) VALUES ($1, $2)")
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(FactorCheck::Failed)
+}
+
+/// Whether the security policy requires every admin to use 2FA
+pub async fn admin_two_factor_required(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let required: Option<bool> = sqlx::query_scalar(
+        "SELECT require_admin_two_factor FROM security_policy WHERE id = 1"
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(required.unwrap_or(false))
+}
+
+/// Starts the second login step for a user whose password was accepted
+pub async fn challenge_response(pool: &PgPool, user_id: i32) -> HttpResponse {
+    let token = generate_token();
+    let expires_at = Utc::now().naive_utc() + ChronoDuration::minutes(CHALLENGE_TTL_MINUTES);
+
+    let result = sqlx::query(
+        "INSERT INTO two_factor_challenges (user_id, token_hash, expires_at)
+         VALUES ($1, $2, $3)"
+    )
+    .bind(user_id)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .execute(pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "two_factor_required": true,
+            "challenge_token": token,
+            "expires_in": CHALLENGE_TTL_MINUTES * 60,
+        })),
+        Err(e) => {
+            eprintln!("Failed to create two-factor challenge: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to start two-factor authentication"
+            }))
+        }
+    }
+}
+
+enum ChallengeOutcome {
+    Verified(i32),
+    Rejected,
+    Locked,
+}
+
+// Check the factor against a login challenge, counting wrong codes
+async fn apply_challenge(
+    pool: &PgPool,
+    request: &VerifyChallengeRequest,
+) -> Result<ChallengeOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now().naive_utc();
+
+    let challenge = sqlx::query_as::<_, (i32, i32, i32)>(
+        "SELECT id, user_id, attempts FROM two_factor_challenges
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > $2
+         FOR UPDATE"
+    )
+    .bind(hash_token(&request.challenge_token))
+    .bind(now)
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (challenge_id, user_id, attempts) = match challenge {
+        Some(challenge) if challenge.2 < MAX_CHALLENGE_ATTEMPTS => challenge,
+        _ => return Ok(ChallengeOutcome::Rejected),
+    };
+
+    // New challenges come with every correct password, so wrong codes are
+    // also limited per user across challenges
+    if recent_failures(&mut tx, user_id, now).await? >= MAX_FAILED_CODES_PER_WINDOW {
+        return Ok(ChallengeOutcome::Locked);
+    }
+
+    if check_second_factor(&mut tx, user_id, &request.factor).await? {
+        sqlx::query("UPDATE two_factor_challenges SET used_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(challenge_id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(ChallengeOutcome::Verified(user_id));
+    }
+
+    sqlx::query("UPDATE two_factor_challenges SET attempts = $1 WHERE id = $2")
+        .bind(attempts + 1)
+        .bind(challenge_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(ChallengeOutcome::Rejected)
+}
+
+fn too_many_codes() -> HttpResponse {
+    HttpResponse::TooManyRequests().json(json!({
+        "message": "Too many invalid codes, please try again later"
+    }))
+}
+
+// Second login step: exchange the challenge and a code for tokens
+pub async fn verify_challenge(
+    pool: web::Data<PgPool>,
+    request: web::Json<VerifyChallengeRequest>,
+) -> impl Responder {
+    let user_id = match apply_challenge(pool.get_ref(), &request).await {
+        Ok(ChallengeOutcome::Verified(user_id)) => user_id,
+        Ok(ChallengeOutcome::Rejected) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid code or expired challenge"
+            }));
+        }
+        Ok(ChallengeOutcome::Locked) => return too_many_codes(),
+        Err(e) => {
+            eprintln!("Database error while verifying two-factor code: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to verify code"
+            }));
+        }
+    };
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, email, password_hash, username, role as "role!: UserRole",
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
+        FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match user {
+        Ok(user) => token_response(pool.get_ref(), &user, None).await,
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch user"
+            }))
+        }
+    }
+}
+
+// Current 2FA state of the logged-in user
+pub async fn get_two_factor_status(pool: web::Data<PgPool>, claims: Claims) -> impl Responder {
+    let enabled = sqlx::query_scalar::<_, bool>("SELECT totp_enabled FROM users WHERE id = $1")
+        .bind(claims.sub)
+        .fetch_one(pool.get_ref())
+        .await;
+
+    let remaining = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM two_factor_recovery_codes WHERE user_id = $1 AND used_at IS NULL"
+    )
+    .bind(claims.sub)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    let required = if claims.role == UserRole::Admin {
+        admin_two_factor_required(pool.get_ref()).await
+    } else {
+        Ok(false)
+    };
+
+    match (enabled, remaining, required) {
+        (Ok(enabled), Ok(remaining), Ok(required)) => HttpResponse::Ok().json(json!({
+            "enabled": enabled,
+            "required": required,
+            "recovery_codes_remaining": remaining,
+        })),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch two-factor status"
+            }))
+        }
+    }
+}
+
+// Start enrollment: generate a secret that becomes active once confirmed
+pub async fn setup_two_factor(pool: web::Data<PgPool>, claims: Claims) -> impl Responder {
+    let secret = generate_secret();
+
+    let result = sqlx::query(
+        "UPDATE users SET totp_secret = $1, totp_last_step = NULL
+         WHERE id = $2 AND totp_enabled = FALSE"
+    )
+    .bind(&secret)
+    .bind(claims.sub)
+    .execute(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(done) if done.rows_affected() == 1 => HttpResponse::Ok().json(json!({
+            "secret": secret,
+            "otpauth_uri": otpauth_uri(&secret, &claims.email),
+        })),
+        Ok(_) => HttpResponse::Conflict().json(json!({
+            "message": "Two-factor authentication is already enabled"
+        })),
+        Err(e) => {
+            eprintln!("Failed to store TOTP secret: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to start two-factor setup"
+            }))
+        }
+    }
+}
+
+enum ConfirmOutcome {
+    Enabled(Vec<String>),
+    NotStarted,
+    AlreadyEnabled,
+    InvalidCode,
+}
+
+async fn apply_confirm(pool: &PgPool, user_id: i32, code: &str) -> Result<ConfirmOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let (secret, enabled) = sqlx::query_as::<_, (Option<String>, bool)>(
+        "SELECT totp_secret, totp_enabled FROM users WHERE id = $1 FOR UPDATE"
+    )
+    .bind(user_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    if enabled {
+        return Ok(ConfirmOutcome::AlreadyEnabled);
+    }
+
+    let secret = match secret {
+        Some(secret) => secret,
+        None => return Ok(ConfirmOutcome::NotStarted),
+    };
+
+    let step = match verify_totp(&secret, code, None) {
+        Some(step) => step,
+        None => return Ok(ConfirmOutcome::InvalidCode),
+    };
+
+    sqlx::query(
+        "UPDATE users SET totp_enabled = TRUE, totp_last_step = $1, updated_at = CURRENT_TIMESTAMP
+         WHERE id = $2"
+    )
+    .bind(step)
+    .bind(user_id)
+    .execute(&mut tx)
+    .await?;
+
+    let codes = replace_recovery_codes(&mut tx, user_id).await?;
+    tx.commit().await?;
+
+    Ok(ConfirmOutcome::Enabled(codes))
+}
+
+// Finish enrollment with a code from the authenticator app
+pub async fn confirm_two_factor(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    request: web::Json<ConfirmSetupRequest>,
+) -> impl Responder {
+    match apply_confirm(pool.get_ref(), claims.sub, &request.code).await {
+        Ok(ConfirmOutcome::Enabled(recovery_codes)) => HttpResponse::Ok().json(json!({
+            "message": "Two-factor authentication enabled, store the recovery codes in a safe place",
+            "recovery_codes": recovery_codes,
+        })),
+        Ok(ConfirmOutcome::NotStarted) => HttpResponse::BadRequest().json(json!({
+            "message": "Two-factor setup has not been started"
+        })),
+        Ok(ConfirmOutcome::AlreadyEnabled) => HttpResponse::Conflict().json(json!({
+            "message": "Two-factor authentication is already enabled"
+        })),
+        Ok(ConfirmOutcome::InvalidCode) => HttpResponse::BadRequest().json(json!({
+            "message": "Invalid code"
+        })),
+        Err(e) => {
+            eprintln!("Database error while enabling two-factor: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to enable two-factor authentication"
+            }))
+        }
+    }
+}
+
+// Turn 2FA off; needs the password and a second factor
+pub async fn disable_two_factor(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    request: web::Json<DisableTwoFactorRequest>,
+) -> impl Responder {
+    if claims.role == UserRole::Admin {
+        match admin_two_factor_required(pool.get_ref()).await {
+            Ok(false) => {}
+            Ok(true) => {
+                return HttpResponse::Forbidden().json(json!({
+                    "message": "Two-factor authentication is required for admin accounts"
+                }));
+            }
+            Err(e) => {
+                eprintln!("Failed to load security policy: {:?}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to disable two-factor authentication"
+                }));
+            }
+        }
+    }
+
+    let password_hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE id = $1")
+        .bind(claims.sub)
+        .fetch_one(pool.get_ref())
+        .await;
+
+    match password_hash {
+        Ok(hash) if bcrypt::verify(&request.password, &hash).unwrap_or(false) => {}
+        Ok(_) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid password"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to disable two-factor authentication"
+            }));
+        }
+    }
+
+    let result: Result<FactorCheck, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        match check_limited_factor(&mut tx, claims.sub, &request.factor).await? {
+            FactorCheck::Passed => {}
+            FactorCheck::Failed => {
+                tx.commit().await?;
+                return Ok(FactorCheck::Failed);
+            }
+            FactorCheck::Locked => return Ok(FactorCheck::Locked),
+        }
+
+        sqlx::query(
+            "UPDATE users SET totp_enabled = FALSE, totp_secret = NULL, totp_last_step = NULL,
+             updated_at = CURRENT_TIMESTAMP WHERE id = $1"
+        )
+        .bind(claims.sub)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query("DELETE FROM two_factor_recovery_codes WHERE user_id = $1")
+            .bind(claims.sub)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(FactorCheck::Passed)
+    }
+    .await;
+
+    match result {
+        Ok(FactorCheck::Passed) => HttpResponse::Ok().json(json!({
+            "message": "Two-factor authentication disabled"
+        })),
+        Ok(FactorCheck::Failed) => HttpResponse::Unauthorized().json(json!({
+            "message": "Invalid code"
+        })),
+        Ok(FactorCheck::Locked) => too_many_codes(),
+        Err(e) => {
+            eprintln!("Database error while disabling two-factor: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to disable two-factor authentication"
+            }))
+        }
+    }
+}
+
+// Issue a fresh set of recovery codes, invalidating the old ones
+pub async fn regenerate_recovery_codes(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    request: web::Json<ConfirmSetupRequest>,
+) -> impl Responder {
+    let factor = SecondFactor {
+        code: Some(request.into_inner().code),
+        recovery_code: None,
+    };
+
+    let result: Result<Result<Vec<String>, FactorCheck>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+
+        match check_limited_factor(&mut tx, claims.sub, &factor).await? {
+            FactorCheck::Passed => {}
+            FactorCheck::Failed => {
+                tx.commit().await?;
+                return Ok(Err(FactorCheck::Failed));
+            }
+            FactorCheck::Locked => return Ok(Err(FactorCheck::Locked)),
+        }
+
+        let codes = replace_recovery_codes(&mut tx, claims.sub).await?;
+        tx.commit().await?;
+        Ok(Ok(codes))
+    }
+    .await;
+
+    match result {
+        Ok(Ok(recovery_codes)) => HttpResponse::Ok().json(json!({
+            "recovery_codes": recovery_codes,
+        })),
+        Ok(Err(FactorCheck::Locked)) => too_many_codes(),
+        Ok(Err(_)) => HttpResponse::Unauthorized().json(json!({
+            "message": "Invalid code or two-factor authentication is not enabled"
+        })),
+        Err(e) => {
+            eprintln!("Database error while regenerating recovery codes: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to regenerate recovery codes"
+            }))
+        }
+    }
+}
+
+// Read the 2FA policy (admin only)
+pub async fn get_two_factor_policy(pool: web::Data<PgPool>) -> impl Responder {
+    match admin_two_factor_required(pool.get_ref()).await {
+        Ok(required) => HttpResponse::Ok().json(json!({
+            "require_admin_two_factor": required
+        })),
+        Err(e) => {
+            eprintln!("Failed to load security policy: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch security policy"
+            }))
+        }
+    }
+}
+
+// Require (or stop requiring) 2FA for every admin (admin only)
+pub async fn update_two_factor_policy(
+    pool: web::Data<PgPool>,
+    claims: Claims,
+    policy: web::Json<TwoFactorPolicy>,
+) -> impl Responder {
+    // Turning the policy on from a session without 2FA would lock the caller out
+    if policy.require_admin_two_factor && !claims.two_factor {
+        return HttpResponse::BadRequest().json(json!({
+            "message": "Enable two-factor authentication on your own account first"
+        }));
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO security_policy (id, require_admin_two_factor, updated_at)
+         VALUES (1, $1, CURRENT_TIMESTAMP)
+         ON CONFLICT (id) DO UPDATE
+         SET require_admin_two_factor = EXCLUDED.require_admin_two_factor,
+             updated_at = EXCLUDED.updated_at"
+    )
+    .bind(policy.require_admin_two_factor)
+    .execute(pool.get_ref())
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "require_admin_two_factor": policy.require_admin_two_factor
+        })),
+        Err(e) => {
+            eprintln!("Failed to update security policy: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to update security policy"
+            }))
+        }
+    }
+}
@@ -3,65 +3,45 @@ use actix_web::{
     HttpResponse,
     Responder,
     HttpRequest,
-    body::MessageBody
+    HttpMessage,
+    body::{EitherBody, MessageBody},
+    http::{Method, StatusCode},
 
 };
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, QueryBuilder};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
 use serde_json::json;
 use regex::Regex;
 use lazy_static::lazy_static;
 use bcrypt;
-use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Validation};
+use jsonwebtoken::{encode, Header, EncodingKey};
 use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
 use actix_web::Error;
-use futures::future::{ok, Ready};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::rc::Rc;
 use std::task::{Context, Poll};
-use dotenv::dotenv;
 use chrono::{Utc, Duration as ChronoDuration};
-use std::sync::Mutex;
-use std::collections::HashSet;
+use uuid::Uuid;
+use crate::revocation::RevocationStore;
+use crate::session::{self, decode_jwt, extract_bearer_token, jwt_secret};
+pub use crate::session::{Claims, UserRole};
+use crate::refresh_token::{self, RotationError};
+use crate::login_guard::{client_ip, normalize_identifier, too_many_attempts, LoginGuard};
+use crate::two_factor;
+use crate::permission;
+use crate::pagination::{Listing, Page, PageParams, SortField, Sorting};
 
 lazy_static! {
     static ref EMAIL_REGEX: Regex =
     Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap();
     static ref USERNAME_REGEX: Regex =
     Regex::new(r"^[a-zA-Z0-9_-]{3,20}$").unwrap();
-    static ref TOKEN_BLACKLIST: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, sqlx::Type, PartialEq)]
-#[sqlx(type_name = "user_role", rename_all = "lowercase")]
-#[serde(rename_all = "lowercase")]
-pub enum UserRole {
-    Admin,
-    User,
-    Guest
-}
-
-impl std::fmt::Display for UserRole {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            UserRole::Admin => write!(f, "admin"),
-            UserRole::User => write!(f, "user"),
-            UserRole::Guest => write!(f, "guest"),
-        }
-    }
-}
-
-impl std::str::FromStr for UserRole {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "admin" => Ok(UserRole::Admin),
-            "user" => Ok(UserRole::User),
-            "guest" => Ok(UserRole::Guest),
-            _ => Err(format!("Invalid role: {}", s))
-        }
-    }
-}
+// Lifetime of an access token, also used as the TTL of revocation entries.
+// Kept short because clients renew it through /auth/refresh.
+pub const ACCESS_TOKEN_TTL_SECS: i64 = 900; // 15 minutes
 
 #[derive(Deserialize, Serialize, sqlx::FromRow, Debug)]
 pub struct User {
@@ -71,6 +51,8 @@ pub struct User {
     pub username: String,
     pub role: UserRole,
     pub profile_picture: Option<String>,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
 }
@@ -92,13 +74,24 @@ pub struct UpdateUserData {
     pub profile_picture: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct SearchUserParams {
     pub email: Option<String>,
     pub username: Option<String>,
     pub role: Option<UserRole>,
 }
 
+static USER_SORTING: Sorting = Sorting {
+    fields: &[
+        SortField::new("id", "id", "INTEGER"),
+        SortField::new("username", "LOWER(username)", "TEXT"),
+        SortField::new("email", "LOWER(email)", "TEXT"),
+        SortField::new("created_at", "COALESCE(created_at, 'epoch')", "TIMESTAMP"),
+    ],
+    descending: false,
+    id: "id",
+};
+
 #[derive(Deserialize)]
 pub struct VerifyPasswordRequest {
     pub identifier: String,  // Can be email or username
@@ -124,29 +117,187 @@ pub struct LoginRequest {
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Claims {
-    // Standard JWT claims
-    pub sub: i32, // subject: User ID
-    pub exp: usize, // expiration: Expiration time
-    pub iat: usize, // issued at: Issued at time
-    // Custom claims
-    pub role: UserRole,
-    pub email: String,
-    pub username: String,
+#[derive(Deserialize, Debug)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+// Generate a signed access token for the user
+fn create_access_token(
+    user: &User,
+    two_factor_optional: bool,
+    permissions: Vec<String>,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    // Current time
+    let now = Utc::now();
+
+    let expiration = now.checked_add_signed(ChronoDuration::seconds(ACCESS_TOKEN_TTL_SECS))
+        .expect("Invalid timestamp")
+        .timestamp() as usize;
+
+    let issued_at = now.timestamp() as usize; // Issued at time
+
+    // Create claims for JWT
+    let claims = Claims {
+        sub: user.id,
+        exp: expiration,
+        iat: issued_at,
+        iat_ms: now.timestamp_millis(),
+        jti: Uuid::new_v4().to_string(),
+        two_factor: user.totp_enabled,
+        two_factor_optional,
+        permissions,
+        role: user.role,
+        email: user.email.clone(),
+        username: user.username.clone(),
+    };
+
+    // Create JWT header and payload
+    let header = Header::new(jsonwebtoken::Algorithm::HS256);
+
+    encode(
+        &header,
+        &claims,
+        &EncodingKey::from_secret(jwt_secret().as_bytes())
+    )
+}
+
+// Issue an access token plus a refresh token and build the auth response.
+// `refresh` is the rotated token when renewing, or None to start a new family.
+pub async fn token_response(
+    pool: &PgPool,
+    user: &User,
+    refresh: Option<refresh_token::IssuedRefreshToken>,
+) -> HttpResponse {
+    let permissions = match user.role {
+        UserRole::User => match permission::global_permissions(pool, user.id).await {
+            Ok(permissions) => permissions,
+            Err(e) => {
+                eprintln!("Failed to load permissions for user {}: {:?}", user.id, e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to load permissions"
+                }));
+            }
+        },
+        UserRole::Admin | UserRole::Guest => Vec::new(),
+    };
+
+    // event_management has no security policy of its own and reads it from the token
+    let two_factor_optional = match user.role {
+        UserRole::Admin if !user.totp_enabled => match two_factor::admin_two_factor_required(pool).await {
+            Ok(required) => !required,
+            Err(e) => {
+                eprintln!("Failed to load security policy: {:?}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to load security policy"
+                }));
+            }
+        },
+        _ => false,
+    };
+
+    let token = match create_access_token(user, two_factor_optional, permissions) {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Failed to generate JWT: {}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": format!("Failed to generate JWT: {}", e)
+            }));
+        }
+    };
+
+    let refresh = match refresh {
+        Some(refresh) => refresh,
+        None => match refresh_token::issue(pool, user.id).await {
+            Ok(refresh) => refresh,
+            Err(e) => {
+                eprintln!("Failed to issue refresh token: {:?}", e);
+                return HttpResponse::InternalServerError().json(json!({
+                    "message": "Failed to issue refresh token"
+                }));
+            }
+        },
+    };
+
+    let refresh_expires_in = (refresh.expires_at - Utc::now().naive_utc()).num_seconds();
+
+    HttpResponse::Ok().json(json!({
+        "token": token,
+        "token_type": "Bearer",
+        "expires_in": ACCESS_TOKEN_TTL_SECS,
+        "refresh_token": refresh.token,
+        "refresh_expires_in": refresh_expires_in,
+        "user": {
+            "id": user.id,
+            "email": user.email,
+            "username": user.username,
+            "role": user.role,
+            "profile_picture": user.profile_picture,
+        }
+    }))
+}
+
+// Reject the attempt early if the identifier or client IP is locked out.
+// Throttling fails open when Redis is unavailable so logins keep working.
+async fn check_login_throttle(
+    login_guard: &LoginGuard,
+    identifier: &str,
+    ip: &str,
+) -> Option<HttpResponse> {
+    match login_guard.retry_after(identifier, ip).await {
+        Ok(Some(retry_after)) => Some(too_many_attempts(retry_after)),
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Login throttling unavailable: {:?}", e);
+            None
+        }
+    }
+}
+
+async fn record_login_failure(
+    login_guard: &LoginGuard,
+    pool: &PgPool,
+    identifier: &str,
+    ip: &str,
+    user_id: Option<i32>,
+    reason: &str,
+) {
+    if let Err(e) = login_guard.record_failure(pool, identifier, ip, user_id, reason).await {
+        eprintln!("Failed to record failed login for {}: {:?}", identifier, e);
+    }
+}
+
+async fn record_login_success(login_guard: &LoginGuard, identifier: &str) {
+    if let Err(e) = login_guard.record_success(identifier).await {
+        eprintln!("Failed to reset login failures for {}: {:?}", identifier, e);
+    }
 }
 
 // Login handler
 pub async fn login(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
+    login_guard: web::Data<LoginGuard>,
     request: web::Json<LoginRequest>
 ) -> impl Responder {
+    let identifier = normalize_identifier(&request.identifier);
+    let ip = client_ip(&req);
+
+    if let Some(response) = check_login_throttle(&login_guard, &identifier, &ip).await {
+        return response;
+    }
+
     // Find user by email or username
     let user = sqlx::query_as!(
         User,
         r#"
         SELECT id, email, password_hash, username, role as "role!: UserRole",
-        profile_picture, created_at, updated_at
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
         FROM users
         WHERE email = $1 OR username = $1"#,
         request.identifier
@@ -160,72 +311,87 @@ pub async fn login(
             let is_valid = bcrypt::verify(&request.password, &user.password_hash)
                 .unwrap_or(false);
             if !is_valid {
+                record_login_failure(
+                    &login_guard, pool.get_ref(), &identifier, &ip, Some(user.id), "invalid_password",
+                ).await;
                 return HttpResponse::Unauthorized().json(json!({
                     "message": "Invalid password"
                 }));
             }
 
-            // Current time
-            let now = Utc::now();
-
-            // Generate JWT
-            let expiration = now.checked_add_signed(ChronoDuration::hours(24))
-                .expect("Invalid timestamp")
-                .timestamp() as usize; // 1 day expiration
+            record_login_success(&login_guard, &identifier).await;
 
+            // Self-registered accounts must confirm their email first
+            if !user.email_verified {
+                return HttpResponse::Forbidden().json(json!({
+                    "message": "Please verify your email before logging in"
+                }));
+            }
 
-            let issued_at = now.timestamp() as usize; // Issued at time
+            // Tokens are only issued once the second factor is verified
+            if user.totp_enabled {
+                return two_factor::challenge_response(pool.get_ref(), user.id).await;
+            }
 
-            // Create claims for JWT
-            let claims = Claims {
-                sub: user.id,
-                exp: expiration,
-                iat: issued_at,
-                role: user.role,
-                email: user.email.clone(),
-                username: user.username.clone(),
-            };
+            token_response(pool.get_ref(), &user, None).await
+        }
+        Ok(None) => {
+            record_login_failure(
+                &login_guard, pool.get_ref(), &identifier, &ip, None, "unknown_identifier",
+            ).await;
+            HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid credentials"
+            }))
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch user"
+            }))
+        }
+    }
+}
 
-            // Create JWT header and payload
-            let header = Header::new(jsonwebtoken::Algorithm::HS256);
-
-            // Generate JWT Token
-            let secret = std::env::var("JWT_SECRET")
-            .unwrap_or_else(|_| {
-                dotenv().ok();
-                std::env::var("JWT_SECRET").expect("JWT_SECRET must be set")
-            });
-
-            match encode(
-                &header,
-                &claims,
-                &EncodingKey::from_secret(secret.as_bytes())
-            ) {
-                Ok(token) =>
-                {
-                    // Create response
-                    let response = json!({
-                        "token": token,
-                        "token_type": "Bearer",
-                        "expires_in": 86400, // 1 day
-                        "user": {
-                            "id": user.id,
-                            "email": user.email,
-                            "username": user.username,
-                            "role": user.role,
-                            "profile_picture": user.profile_picture,
-                        }
-                    });
-                    HttpResponse::Ok().json(response)
-                }
-                Err(e) => {
-                    eprintln!("Failed to generate JWT: {}", e);
-                    HttpResponse::InternalServerError().json(json!({
-                        "message": format!("Failed to generate JWT: {}", e)
-                    }))
-                }
-            }
+// Exchange a refresh token for a new access token and a rotated refresh token
+pub async fn refresh(
+    pool: web::Data<PgPool>,
+    request: web::Json<RefreshRequest>,
+) -> impl Responder {
+    let (user_id, rotated) = match refresh_token::rotate(pool.get_ref(), &request.refresh_token).await {
+        Ok(result) => result,
+        Err(RotationError::NotFound) | Err(RotationError::Expired) => {
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid or expired refresh token"
+            }));
+        }
+        Err(RotationError::Reused) => {
+            eprintln!("Refresh token reuse detected, token family revoked");
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Refresh token has already been used, please log in again"
+            }));
+        }
+        Err(RotationError::Database(e)) => {
+            eprintln!("Database error while rotating refresh token: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to refresh session"
+            }));
         }
+    };
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, email, password_hash, username, role as "role!: UserRole",
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
+        FROM users
+        WHERE id = $1"#,
+        user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await;
+
+    match user {
+        Ok(Some(user)) => token_response(pool.get_ref(), &user, Some(rotated)).await,
         Ok(None) => HttpResponse::Unauthorized().json(json!({
             "message": "Invalid credentials"
         })),
@@ -238,31 +404,89 @@ pub async fn login(
     }
 }
 
-#[allow(dead_code)]
-pub fn verify_jwt (token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    // Check if token is blacklisted
-    if let Ok(blacklist) = TOKEN_BLACKLIST.lock() {
-        if blacklist.contains(token) {
-            return Err(jsonwebtoken::errors::Error::from(
-                jsonwebtoken::errors::ErrorKind::InvalidToken
-            ));
+// Access level required to reach a route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessPolicy {
+    Public,
+    Authenticated,
+    AdminOnly,
+    // Admins, or users holding the named permission
+    Permission(&'static str),
+}
+
+impl AccessPolicy {
+    // Decision based on the role alone; `Permission` also needs a lookup for users
+    pub fn allows(&self, role: UserRole) -> bool {
+        match self {
+            AccessPolicy::Public => true,
+            AccessPolicy::Authenticated => matches!(role, UserRole::Admin | UserRole::User),
+            AccessPolicy::AdminOnly | AccessPolicy::Permission(_) => role == UserRole::Admin,
         }
     }
+}
 
-    let secret = std::env::var("JWT_SECRET")
-    .unwrap_or_else(|_| {
-        dotenv().ok();
-        std::env::var("JWT_SECRET").expect("JWT_SECRET must be set")
-    });
+// Public routes inside the user scopes, checked first
+const PUBLIC_SCOPES: &[&str] = &[
+    "/events/rsvp/add",
+    "/events/rsvp/reconfirm",
+    "/servings/rsvp/add",
+];
+
+// Scopes any logged-in user can reach, checked before the admin scopes.
+// Handlers of the RSVP scopes only let users change their own RSVPs
+const USER_SCOPES: &[&str] = &[
+    "/admin/media/watch_history",
+    "/events/rsvp",
+    "/me",
+    "/search",
+    "/servings/rsvp",
+];
+
+// Admin scopes also open to users holding the matching permission,
+// checked after the user scopes and before the admin scopes
+const PERMISSION_SCOPES: &[(&str, &str)] = &[
+    ("/admin/home_group/rsvp", permission::HOMEGROUP_APPROVE),
+    ("/admin/home_group", permission::HOMEGROUP_WRITE),
+    ("/admin/events/checkin", permission::EVENTS_CHECKIN),
+    ("/admin/events", permission::EVENTS_WRITE),
+    ("/admin/servings", permission::SERVINGS_WRITE),
+    ("/admin/media", permission::MEDIA_WRITE),
+];
+
+// Scopes restricted to admins
+const ADMIN_SCOPES: &[&str] = &[
+    "/admin",
+];
+
+pub(crate) fn path_in_scope(path: &str, scope: &str) -> bool {
+    path == scope || path.starts_with(&format!("{}/", scope))
+}
 
-    let validation = Validation::new(jsonwebtoken::Algorithm::HS256);
+// Everything outside the user and admin scopes (auth, member reads, new RSVPs) is public
+pub fn access_policy(path: &str) -> AccessPolicy {
+    if PUBLIC_SCOPES.iter().any(|scope| path_in_scope(path, scope)) {
+        AccessPolicy::Public
+    } else if USER_SCOPES.iter().any(|scope| path_in_scope(path, scope)) {
+        AccessPolicy::Authenticated
+    } else if let Some((_, permission)) = PERMISSION_SCOPES
+        .iter()
+        .find(|(scope, _)| path_in_scope(path, scope))
+    {
+        AccessPolicy::Permission(permission)
+    } else if ADMIN_SCOPES.iter().any(|scope| path_in_scope(path, scope)) {
+        AccessPolicy::AdminOnly
+    } else {
+        AccessPolicy::Public
+    }
+}
 
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &validation
-    )
-    .map(|token_data| token_data.claims)
+impl Claims {
+    // Whether the user may change a record made by `email` or `user_id`.
+    // Logins need a verified email, so a matching email proves ownership;
+    // admins may change any record
+    pub fn owns(&self, email: &str, user_id: Option<i32>) -> bool {
+        self.role == UserRole::Admin || user_id == Some(self.sub) || email.eq_ignore_ascii_case(&self.email)
+    }
 }
 
 // Middleware to protect routes
@@ -273,57 +497,136 @@ pub struct AuthMiddleware;
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
     B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Transform = AuthMiddlewareService<S>;
     type InitError = ();
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(AuthMiddlewareService { service })
+        ok(AuthMiddlewareService { service: Rc::new(service) })
     }
 }
 
 // Define the service that will be used by the middleware
 pub struct AuthMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
+}
+
+// Short-circuit the request with a JSON error body
+fn reject<B>(req: ServiceRequest, status: StatusCode, message: &str) -> ServiceResponse<EitherBody<B>> {
+    let response = HttpResponse::build(status).json(json!({
+        "message": message
+    }));
+    req.into_response(response).map_into_right_body()
 }
 
 // Implement the necessary traits for AuthMiddlewareService
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
     B: MessageBody + 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
-    type Future = S::Future;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.service.poll_ready(cx)
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        // Add your authentication logic here
-        self.service.call(req)
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let policy = access_policy(req.path());
+
+            // CORS preflight requests never carry the Authorization header
+            if policy == AccessPolicy::Public || req.method() == Method::OPTIONS {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            }
+
+            let claims = match session::authenticate(&req).await {
+                Ok(claims) => claims,
+                Err((status, message)) => return Ok(reject(req, status, message)),
+            };
+
+            let pool = match req.app_data::<web::Data<PgPool>>() {
+                Some(pool) => pool.clone(),
+                None => {
+                    eprintln!("PgPool is not registered as app data");
+                    return Ok(reject(req, StatusCode::INTERNAL_SERVER_ERROR, "Authentication is unavailable"));
+                }
+            };
+
+            let allowed = match policy {
+                AccessPolicy::Permission(name) if claims.role == UserRole::User => {
+                    match permission::may_enter(pool.get_ref(), claims.sub, name).await {
+                        Ok(allowed) => allowed,
+                        Err(e) => {
+                            eprintln!("Failed to load permissions for user {}: {:?}", claims.sub, e);
+                            return Ok(reject(req, StatusCode::INTERNAL_SERVER_ERROR, "Authentication is unavailable"));
+                        }
+                    }
+                }
+                _ => policy.allows(claims.role),
+            };
+
+            if !allowed {
+                return Ok(reject(req, StatusCode::FORBIDDEN, "Insufficient permissions"));
+            }
+
+            // Admins without a second factor can still reach /me to enroll,
+            // but not the admin routes while the policy requires 2FA
+            if claims.role == UserRole::Admin && policy != AccessPolicy::Authenticated && !claims.two_factor {
+                match two_factor::admin_two_factor_required(pool.get_ref()).await {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        return Ok(reject(
+                            req,
+                            StatusCode::FORBIDDEN,
+                            "Two-factor authentication is required for admin accounts, enroll at /me/two-factor/setup",
+                        ));
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to load security policy: {:?}", e);
+                        return Ok(reject(req, StatusCode::INTERNAL_SERVER_ERROR, "Authentication is unavailable"));
+                    }
+                }
+            }
+
+            // Make the decoded claims available to handlers
+            req.extensions_mut().insert(claims);
+
+            service.call(req).await.map(ServiceResponse::map_into_left_body)
+        })
     }
 }
 
-fn validate_username(username: &str) -> Result<(), String> {
+pub fn validate_username(username: &str) -> Result<(), String> {
     if !USERNAME_REGEX.is_match(username) {
         return Err("Username must be between 3 and 20 characters, and can only contain letters, numbers, and underscores".to_string());
     }
     Ok(())
 }
 
-fn validate_email(email: &str) -> Result<(), String> {
+pub fn validate_email(email: &str) -> Result<(), String> {
     if !EMAIL_REGEX.is_match(email) {
         return Err("Invalid email format".to_string());
     }
     Ok(())
 }
+
+pub fn validate_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < 8 {
+        return Err("Password must be at least 8 characters long".to_string());
+    }
+    Ok(())
+}
 // Create a new user
 pub async fn add_user(
     pool: web::Data<PgPool>,
@@ -347,10 +650,10 @@ pub async fn add_user(
     let result = sqlx::query_as!(
         User,
         r#"
-        INSERT INTO users (email, password_hash, username, role, profile_picture)
-        VALUES ($1, crypt($2, gen_salt('bf')), $3, $4, $5)
+        INSERT INTO users (email, password_hash, username, role, profile_picture, email_verified)
+        VALUES ($1, crypt($2, gen_salt('bf')), $3, $4, $5, TRUE)
         RETURNING id, email, password_hash, username, role as "role!: UserRole",
-        profile_picture, created_at, updated_at
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
         "#,
         new_user.email,
         new_user.password,
@@ -400,7 +703,7 @@ pub async fn get_user(pool: web::Data<PgPool>, id: web::Path<i32>) -> impl Respo
         User,
         r#"
         SELECT id, email, password_hash, username, role as "role: UserRole",
-        profile_picture, created_at, updated_at
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
         FROM users WHERE id = $1
         "#,
         id.into_inner()
@@ -459,7 +762,7 @@ pub async fn update_user(
     query_builder.push(
         " RETURNING id, email, password_hash, username, \
          (SELECT role::text)::user_role as role, \
-         profile_picture, created_at, updated_at"
+         profile_picture, email_verified, totp_enabled, created_at, updated_at"
     );
 
     let result = query_builder
@@ -509,13 +812,24 @@ pub async fn delete_user(
 // Search for users
 pub async fn search_users(
     pool: web::Data<PgPool>,
-    params: web::Query<SearchUserParams>
+    params: web::Query<SearchUserParams>,
+    page: web::Query<PageParams>,
 ) -> impl Responder {
-    let mut query_builder = QueryBuilder::new(
-        "SELECT id, email, password_hash, username, role as \"role: UserRole\", \
-         profile_picture, created_at, updated_at FROM users WHERE 1=1"
-    );
+    let page = match Page::new(&page, &USER_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
+    match fetch_users(pool.get_ref(), &params, &page).await {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(e) => {
+            eprintln!("Error searching users: {:?}", e);
+            HttpResponse::InternalServerError().body("Failed to search users")
+        }
+    }
+}
+
+fn push_user_filters(query_builder: &mut QueryBuilder<'_, Postgres>, params: &SearchUserParams) {
     if let Some(email) = &params.email {
         query_builder.push(" AND email ILIKE ");
         query_builder.push_bind(format!("%{}%", email));
@@ -526,40 +840,41 @@ pub async fn search_users(
         query_builder.push_bind(format!("%{}%", username));
     }
 
-    if let Some(role) = &params.role {
+    if let Some(role) = params.role {
         query_builder.push(" AND role = ");
         query_builder.push_bind(role);
     }
+}
 
-    let result = query_builder
-        .build_query_as::<User>()
-        .fetch_all(pool.get_ref())
-        .await;
-
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(e) => {
-            eprintln!("Error searching users: {:?}", e);
-            HttpResponse::InternalServerError().body("Failed to search users")
-        }
-    }
+// A page of the users matching `params`
+async fn fetch_users(pool: &PgPool, params: &SearchUserParams, page: &Page) -> Result<Listing<User>, sqlx::Error> {
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM users WHERE 1=1");
+    push_user_filters(&mut count_query, params);
+    let (total,): (i64,) = count_query.build_query_as().fetch_one(pool).await?;
+
+    let mut query_builder = QueryBuilder::new(format!(
+        "SELECT id, email, password_hash, username, role, \
+         profile_picture, email_verified, totp_enabled, created_at, updated_at, {} FROM users WHERE 1=1",
+        page.key_columns()
+    ));
+    push_user_filters(&mut query_builder, params);
+    page.push_to(&mut query_builder);
+
+    let rows = query_builder.build().fetch_all(pool).await?;
+    page.finish(rows, total, |row| User::from_row(row))
 }
 
 // Get all users
-pub async fn get_all_users(pool: web::Data<PgPool>) -> impl Responder {
-    let result = sqlx::query_as!(
-        User,
-        r#"
-        SELECT id, email, password_hash, username, role as "role: UserRole",
-        profile_picture, created_at, updated_at
-        FROM users
-        ORDER BY id
-        "#,
-    )
-    .fetch_all(pool.get_ref())
-    .await;
+pub async fn get_all_users(
+    pool: web::Data<PgPool>,
+    page: web::Query<PageParams>,
+) -> impl Responder {
+    let page = match Page::new(&page, &USER_SORTING) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
 
-    match result {
+    match fetch_users(pool.get_ref(), &SearchUserParams::default(), &page).await {
         Ok(users) => HttpResponse::Ok().json(users),
         Err(e) => {
             eprintln!("Error fetching users: {:?}", e);
@@ -576,7 +891,7 @@ pub async fn get_user_by_email(
         User,
         r#"
         SELECT id, email, password_hash, username, role as "role!: UserRole",
-        profile_picture, created_at, updated_at
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
         FROM users
         WHERE email = $1
         "#,
@@ -602,7 +917,7 @@ pub async fn get_user_by_username(
 ) -> impl Responder {
     let result = sqlx::query_as!(User, r#"
         SELECT id, email, password_hash, username, role as "role!: UserRole",
-        profile_picture, created_at, updated_at
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
         FROM users
         WHERE username = $1
         "#,
@@ -623,15 +938,24 @@ pub async fn get_user_by_username(
 }
 
 pub async fn verify_password(
+    req: HttpRequest,
     pool: web::Data<PgPool>,
+    login_guard: web::Data<LoginGuard>,
     request: web::Json<VerifyPasswordRequest>,
 ) -> impl Responder {
+    let identifier = normalize_identifier(&request.identifier);
+    let ip = client_ip(&req);
+
+    if let Some(response) = check_login_throttle(&login_guard, &identifier, &ip).await {
+        return response;
+    }
+
     // First, find the user by email or username
     let user_query = sqlx::query_as!(
         User,
         r#"
         SELECT id, email, password_hash, username, role as "role!: UserRole",
-        profile_picture, created_at, updated_at
+        profile_picture, email_verified, totp_enabled, created_at, updated_at
         FROM users
         WHERE email = $1 OR username = $1
         "#,
@@ -647,6 +971,8 @@ pub async fn verify_password(
                 .unwrap_or(false);
 
             if is_valid {
+                record_login_success(&login_guard, &identifier).await;
+
                 // If password is valid, return user data (excluding password_hash)
                 let user_response = User {
                     password_hash: "".to_string(), // Don't send password hash
@@ -658,16 +984,24 @@ pub async fn verify_password(
                     user: Some(user_response),
                 })
             } else {
+                record_login_failure(
+                    &login_guard, pool.get_ref(), &identifier, &ip, Some(user.id), "invalid_password",
+                ).await;
                 HttpResponse::Ok().json(VerifyPasswordResponse {
                     valid: false,
                     user: None,
                 })
             }
         }
-        Ok(None) => HttpResponse::Ok().json(VerifyPasswordResponse {
-            valid: false,
-            user: None,
-        }),
+        Ok(None) => {
+            record_login_failure(
+                &login_guard, pool.get_ref(), &identifier, &ip, None, "unknown_identifier",
+            ).await;
+            HttpResponse::Ok().json(VerifyPasswordResponse {
+                valid: false,
+                user: None,
+            })
+        }
         Err(e) => {
             eprintln!("Database error during password verification: {:?}", e);
             HttpResponse::InternalServerError().json(json!({
@@ -677,25 +1011,104 @@ pub async fn verify_password(
     }
 }
 
-pub async fn logout(req: HttpRequest) -> impl Responder {
+pub async fn logout(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    revocations: web::Data<RevocationStore>,
+    body: Option<web::Json<LogoutRequest>>,
+) -> impl Responder {
+    // End the refresh token family so the session cannot be renewed
+    if let Some(refresh) = body.as_ref().and_then(|b| b.refresh_token.as_deref()) {
+        if let Err(e) = refresh_token::revoke(pool.get_ref(), refresh).await {
+            eprintln!("Failed to revoke refresh token: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to log out"
+            }));
+        }
+    }
+
     // Extract the token from the Authorization header
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = auth_str[7..].to_string();
-
-                // Add the token to the blacklist
-                if let Ok(mut blacklist) = TOKEN_BLACKLIST.lock() {
-                    blacklist.insert(token);
-                    return HttpResponse::Ok().json(json!({
-                        "message": "Successfully logged out"
-                    }));
-                }
-            }
+    let token = match extract_bearer_token(req.headers()) {
+        Some(token) => token,
+        None => {
+            return HttpResponse::BadRequest().json(json!({
+                "message": "Invalid token format"
+            }));
+        }
+    };
+
+    let claims = match decode_jwt(token) {
+        Ok(claims) => claims,
+        Err(e) => {
+            eprintln!("Logout with invalid token: {}", e);
+            return HttpResponse::Unauthorized().json(json!({
+                "message": "Invalid or expired token"
+            }));
+        }
+    };
+
+    // Keep the revocation only as long as the token would have been valid
+    let remaining = claims.exp as i64 - Utc::now().timestamp();
+
+    match revocations.revoke_token(&claims.jti, remaining).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "Successfully logged out"
+        })),
+        Err(e) => {
+            eprintln!("Failed to revoke token: {:?}", e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to log out"
+            }))
         }
     }
+}
 
-    HttpResponse::BadRequest().json(json!({
-        "message": "Invalid token format"
-    }))
-}
\ No newline at end of file
+// Revoke every session of a user (admin only)
+pub async fn revoke_user_sessions(
+    pool: web::Data<PgPool>,
+    revocations: web::Data<RevocationStore>,
+    id: web::Path<i32>,
+) -> impl Responder {
+    let user_id = id.into_inner();
+
+    let exists = sqlx::query!("SELECT id FROM users WHERE id = $1", user_id)
+        .fetch_optional(pool.get_ref())
+        .await;
+
+    match exists {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "message": "User not found"
+            }));
+        }
+        Err(e) => {
+            eprintln!("Database error: {:?}", e);
+            return HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to fetch user"
+            }));
+        }
+    }
+
+    if let Err(e) = refresh_token::revoke_all_for_user(pool.get_ref(), user_id).await {
+        eprintln!("Failed to revoke refresh tokens for user {}: {:?}", user_id, e);
+        return HttpResponse::InternalServerError().json(json!({
+            "message": "Failed to revoke sessions"
+        }));
+    }
+
+    match revocations
+        .revoke_user(user_id, Utc::now().timestamp_millis(), ACCESS_TOKEN_TTL_SECS)
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "message": "All sessions revoked"
+        })),
+        Err(e) => {
+            eprintln!("Failed to revoke sessions for user {}: {:?}", user_id, e);
+            HttpResponse::InternalServerError().json(json!({
+                "message": "Failed to revoke sessions"
+            }))
+        }
+    }
+}
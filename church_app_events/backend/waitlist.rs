@@ -0,0 +1,290 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+
+use crate::email;
+use crate::event_status;
+use crate::events::{self, Event, EventOccurrence};
+
+// Capacity limits and waitlists for event RSVPs. An event may have a
+// capacity, per occurrence for recurring events. Pending and confirmed RSVPs
+// hold a seat for every adult and child in the party; a party that does not
+// fit in the seats left joins a waitlist ordered by when it joined. Seats
+// freed by a decline, a deleted RSVP, a smaller party or a larger capacity
+// go to those first in line, who are emailed; a party never jumps ahead of
+// a larger one waiting before it. Every change to seats locks the event row
+// first, so concurrent RSVPs cannot overbook. Lowering the capacity never
+// takes seats away from existing RSVPs. The waitlist of a cancelled event
+// stays as it is.
+
+/// Capacity and seat counts of an event occurrence, in people, or of a
+/// home group, in registrations
+#[derive(Debug, Serialize, Clone, Copy, Default)]
+pub struct Availability {
+    pub capacity: Option<i32>,
+    pub seats_taken: i64,
+    pub seats_left: Option<i64>,
+    pub waitlist_count: i64, // Parties or registrations waiting, not people
+}
+
+impl Availability {
+    pub fn new(capacity: Option<i32>, seats_taken: i64, waitlist_count: i64) -> Self {
+        Availability {
+            capacity,
+            seats_taken,
+            seats_left: capacity.map(|capacity| (capacity as i64 - seats_taken).max(0)),
+            waitlist_count,
+        }
+    }
+}
+
+/// An RSVP moved from the waitlist to a seat
+#[derive(Debug, sqlx::FromRow)]
+pub struct Promotion {
+    pub rsvp_id: i32,
+    pub email: String,
+    pub event_id: i32,
+}
+
+/// Lock an event for seat changes and return its capacity.
+/// None if the event does not exist.
+pub async fn lock_event(
+    tx: &mut Transaction<'_, Postgres>,
+    event_id: i32,
+) -> Result<Option<Option<i32>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<i32>>("SELECT capacity FROM events WHERE id = $1 FOR UPDATE")
+        .bind(event_id)
+        .fetch_optional(&mut *tx)
+        .await
+}
+
+/// Whether an event is cancelled. Read with the event locked, the answer
+/// holds until the transaction ends.
+pub async fn is_cancelled(tx: &mut Transaction<'_, Postgres>, event_id: i32) -> Result<bool, sqlx::Error> {
+    let status = sqlx::query_scalar::<_, String>("SELECT status FROM events WHERE id = $1")
+        .bind(event_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    Ok(status == event_status::CANCELLED)
+}
+
+async fn seats_taken(
+    tx: &mut Transaction<'_, Postgres>,
+    event_id: i32,
+    occurrence_date: Option<NaiveDate>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COALESCE(SUM(adults + children), 0)::BIGINT FROM eventrsvp
+         WHERE event_id = $1 AND occurrence_date IS NOT DISTINCT FROM $2
+           AND rsvp_status <> 'declined' AND waitlisted_at IS NULL"
+    )
+    .bind(event_id)
+    .bind(occurrence_date)
+    .fetch_one(&mut *tx)
+    .await
+}
+
+/// Whether an occurrence has seats for `party` more people; the event
+/// must be locked
+pub async fn has_seats_for(
+    tx: &mut Transaction<'_, Postgres>,
+    event_id: i32,
+    occurrence_date: Option<NaiveDate>,
+    capacity: Option<i32>,
+    party: i32,
+) -> Result<bool, sqlx::Error> {
+    match capacity {
+        None => Ok(true),
+        Some(capacity) => Ok(seats_taken(tx, event_id, occurrence_date).await? + party as i64 <= capacity as i64),
+    }
+}
+
+/// Give the free seats of an occurrence to the first in line; the event
+/// must be locked
+pub async fn promote(
+    tx: &mut Transaction<'_, Postgres>,
+    event_id: i32,
+    occurrence_date: Option<NaiveDate>,
+    capacity: Option<i32>,
+) -> Result<Vec<Promotion>, sqlx::Error> {
+    // Nobody is offered a seat at a cancelled event
+    if is_cancelled(tx, event_id).await? {
+        return Ok(Vec::new());
+    }
+
+    // No limit once the event has no capacity any more
+    let mut free_seats = match capacity {
+        Some(capacity) => {
+            let free = capacity as i64 - seats_taken(tx, event_id, occurrence_date).await?;
+            if free <= 0 {
+                return Ok(Vec::new());
+            }
+            Some(free)
+        }
+        None => None,
+    };
+
+    let waiting = sqlx::query_as::<_, (i32, i32)>(
+        "SELECT id, adults + children FROM eventrsvp
+         WHERE event_id = $1 AND occurrence_date IS NOT DISTINCT FROM $2
+           AND waitlisted_at IS NOT NULL
+         ORDER BY waitlisted_at, id"
+    )
+    .bind(event_id)
+    .bind(occurrence_date)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    // In order, until a party does not fit
+    let mut promoted = Vec::new();
+    for (rsvp_id, party) in waiting {
+        if let Some(free) = free_seats.as_mut() {
+            if (party as i64) > *free {
+                break;
+            }
+            *free -= party as i64;
+        }
+        promoted.push(rsvp_id);
+    }
+
+    if promoted.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_as::<_, Promotion>(
+        "UPDATE eventrsvp SET waitlisted_at = NULL
+         WHERE id = ANY($1)
+         RETURNING id AS rsvp_id, email, event_id"
+    )
+    .bind(&promoted)
+    .fetch_all(&mut *tx)
+    .await
+}
+
+/// Fill free seats of every occurrence of an event, e.g. after its
+/// capacity was raised, and email those promoted
+pub async fn fill_free_seats(pool: &PgPool, event_id: i32) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let capacity = match lock_event(&mut tx, event_id).await? {
+        Some(capacity) => capacity,
+        None => return Ok(()),
+    };
+
+    let occurrence_dates = sqlx::query_scalar::<_, Option<NaiveDate>>(
+        "SELECT DISTINCT occurrence_date FROM eventrsvp
+         WHERE event_id = $1 AND waitlisted_at IS NOT NULL"
+    )
+    .bind(event_id)
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut promotions = Vec::new();
+    for occurrence_date in occurrence_dates {
+        promotions.extend(promote(&mut tx, event_id, occurrence_date, capacity).await?);
+    }
+
+    tx.commit().await?;
+    notify(pool, promotions);
+    Ok(())
+}
+
+/// Email those promoted from the waitlist, in the background
+pub fn notify(pool: &PgPool, promotions: Vec<Promotion>) {
+    for promotion in promotions {
+        let pool = pool.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = email::send_waitlist_promotion_email(
+                &pool,
+                promotion.rsvp_id,
+                &promotion.email,
+                promotion.event_id,
+            )
+            .await
+            {
+                eprintln!("Failed to send waitlist promotion email to {}: {}", promotion.email, e);
+            }
+        });
+    }
+}
+
+/// 1-based place of an RSVP on its waitlist, None if it is not waitlisted
+pub async fn position(pool: &PgPool, rsvp_id: i32) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM eventrsvp w
+         JOIN eventrsvp r ON w.event_id = r.event_id
+          AND w.occurrence_date IS NOT DISTINCT FROM r.occurrence_date
+         WHERE r.id = $1 AND r.waitlisted_at IS NOT NULL AND w.waitlisted_at IS NOT NULL
+           AND (w.waitlisted_at, w.id) <= (r.waitlisted_at, r.id)"
+    )
+    .bind(rsvp_id)
+    .fetch_one(pool)
+    .await
+    .map(|position| (position > 0).then_some(position))
+}
+
+// Seats taken and parties waiting per event and occurrence
+async fn seat_counts(
+    pool: &PgPool,
+    event_ids: &[i32],
+) -> Result<HashMap<(i32, Option<NaiveDate>), (i64, i64)>, sqlx::Error> {
+    let counts = sqlx::query_as::<_, (i32, Option<NaiveDate>, i64, i64)>(
+        "SELECT event_id, occurrence_date,
+                COALESCE(SUM(adults + children) FILTER (WHERE rsvp_status <> 'declined' AND waitlisted_at IS NULL), 0)::BIGINT,
+                COUNT(*) FILTER (WHERE waitlisted_at IS NOT NULL)
+         FROM eventrsvp WHERE event_id = ANY($1)
+         GROUP BY event_id, occurrence_date"
+    )
+    .bind(event_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(counts
+        .into_iter()
+        .map(|(event_id, occurrence_date, taken, waiting)| ((event_id, occurrence_date), (taken, waiting)))
+        .collect())
+}
+
+/// Add capacity and seat counts to listed occurrences
+pub async fn fill_availability(pool: &PgPool, occurrences: &mut [EventOccurrence]) -> Result<(), sqlx::Error> {
+    let mut event_ids: Vec<i32> = occurrences.iter().map(|occurrence| occurrence.id).collect();
+    event_ids.sort_unstable();
+    event_ids.dedup();
+
+    if event_ids.is_empty() {
+        return Ok(());
+    }
+
+    let counts = seat_counts(pool, &event_ids).await?;
+
+    for occurrence in occurrences.iter_mut() {
+        let (taken, waiting) = counts
+            .get(&(occurrence.id, occurrence.occurrence_date))
+            .copied()
+            .unwrap_or_default();
+        occurrence.availability = Availability::new(occurrence.availability.capacity, taken, waiting);
+    }
+
+    Ok(())
+}
+
+/// Capacity and seat counts of listed events: of the event itself, or of
+/// the next occurrence of a recurring event
+pub async fn event_availability(pool: &PgPool, events: &[Event]) -> Result<HashMap<i32, Availability>, sqlx::Error> {
+    let event_ids: Vec<i32> = events.iter().map(|event| event.id).collect();
+    if event_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let counts = seat_counts(pool, &event_ids).await?;
+
+    Ok(events
+        .iter()
+        .map(|event| {
+            let occurrence_date = events::next_occurrence(event);
+            let (taken, waiting) = counts.get(&(event.id, occurrence_date)).copied().unwrap_or_default();
+            (event.id, Availability::new(event.capacity, taken, waiting))
+        })
+        .collect())
+}
@@ -0,0 +1,14 @@
+-- Failed password attempts on /auth/login and /admin/users/verify-password,
+-- kept for admins to review
+CREATE TABLE failed_login_attempts (
+    id SERIAL PRIMARY KEY,
+    identifier VARCHAR(255) NOT NULL,
+    user_id INTEGER,
+    ip_address VARCHAR(64) NOT NULL,
+    reason VARCHAR(50) NOT NULL,
+    attempted_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_failed_login_attempts_identifier ON failed_login_attempts (identifier);
+CREATE INDEX idx_failed_login_attempts_attempted_at ON failed_login_attempts (attempted_at DESC);
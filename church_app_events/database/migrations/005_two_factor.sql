@@ -0,0 +1,43 @@
+-- Optional TOTP (RFC 6238) second factor.
+-- The secret is written at enrollment and only takes effect once the user
+-- confirms a code, which sets totp_enabled. totp_last_step is the last
+-- accepted time step, so a code cannot be replayed.
+ALTER TABLE users ADD COLUMN totp_secret VARCHAR(64);
+ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- Single-use recovery codes, stored as SHA-256 hashes
+CREATE TABLE two_factor_recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_two_factor_recovery_codes_user ON two_factor_recovery_codes (user_id);
+
+-- Issued by /auth/login once the password is correct; exchanged together
+-- with a code at /auth/2fa/verify for the actual tokens
+CREATE TABLE two_factor_challenges (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_two_factor_challenges_user ON two_factor_challenges (user_id);
+
+-- Site-wide security policy, a single row edited by admins
+CREATE TABLE security_policy (
+    id INTEGER PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    require_admin_two_factor BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO security_policy (id) VALUES (1);
@@ -0,0 +1,60 @@
+-- Fine-grained permissions on top of users.role.
+-- Admins implicitly hold every permission. Other users get permissions
+-- through named roles, or through grants that may be limited to a single
+-- resource (e.g. homegroup.approve for home group 7).
+CREATE TABLE permissions (
+    name VARCHAR(100) PRIMARY KEY,
+    description TEXT
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('events.write', 'Manage events and their RSVPs'),
+    ('homegroup.write', 'Manage home groups'),
+    ('homegroup.approve', 'Approve or decline home group registrations'),
+    ('servings.write', 'Manage servings and their RSVPs'),
+    ('media.write', 'Manage media and YouTube sync');
+
+CREATE TABLE roles (
+    name VARCHAR(50) PRIMARY KEY,
+    description TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE role_permissions (
+    role_name VARCHAR(50) NOT NULL,
+    permission VARCHAR(100) NOT NULL,
+    PRIMARY KEY (role_name, permission),
+    FOREIGN KEY (role_name) REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (permission) REFERENCES permissions(name) ON DELETE CASCADE
+);
+
+CREATE TABLE user_roles (
+    user_id INTEGER NOT NULL,
+    role_name VARCHAR(50) NOT NULL,
+    PRIMARY KEY (user_id, role_name),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (role_name) REFERENCES roles(name) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- resource_type/resource_id are both NULL for a grant on every resource
+CREATE TABLE permission_grants (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    permission VARCHAR(100) NOT NULL,
+    resource_type VARCHAR(50),
+    resource_id INTEGER,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (permission) REFERENCES permissions(name) ON DELETE CASCADE,
+    CONSTRAINT permission_grants_resource CHECK ((resource_type IS NULL) = (resource_id IS NULL))
+);
+
+CREATE UNIQUE INDEX permission_grants_unique
+    ON permission_grants (user_id, permission, COALESCE(resource_type, ''), COALESCE(resource_id, 0));
+CREATE INDEX idx_permission_grants_user ON permission_grants (user_id);
+
+INSERT INTO roles (name, description) VALUES
+    ('media_team', 'Manages media and YouTube sync');
+
+INSERT INTO role_permissions (role_name, permission) VALUES
+    ('media_team', 'media.write');
@@ -0,0 +1,22 @@
+-- One row per successful mutating request on an admin route.
+-- before/after are row snapshots (secrets removed), changes holds only the
+-- fields that differ. actor_id is kept when the user is deleted.
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    actor_id INTEGER,
+    actor_username VARCHAR(255),
+    action VARCHAR(50) NOT NULL,
+    entity_type VARCHAR(50) NOT NULL,
+    entity_id INTEGER,
+    method VARCHAR(10) NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    before JSONB,
+    after JSONB,
+    changes JSONB,
+    ip_address VARCHAR(64) NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_audit_log_created_at ON audit_log (created_at DESC);
+CREATE INDEX idx_audit_log_entity ON audit_log (entity_type, entity_id);
+CREATE INDEX idx_audit_log_actor ON audit_log (actor_id);
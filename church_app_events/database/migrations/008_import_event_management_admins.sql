@@ -0,0 +1,36 @@
+-- event_management now authenticates against this database through
+-- /auth/login, so its `admins` accounts move into `users`.
+--
+-- Copy the table over from the event_management database first, e.g.
+--   pg_dump --table=admins --no-owner $EVENT_MANAGEMENT_DB | psql $DATABASE_URL
+--
+-- Its /auth/signup was open to anyone and made every account an admin, so
+-- the accounts come over as regular users who may only manage events
+-- (events.write); promote the real admins afterwards.
+--
+-- `admins` has no email, so each account gets a placeholder address. It is
+-- not verified, so the account cannot log in until an admin sets its real
+-- address and the user verifies it through /auth/verify-email/resend.
+-- The bcrypt hashes are kept as they are.
+-- Usernames already taken in `users` are skipped; they stay in
+-- admins_imported for manual review:
+--   SELECT * FROM admins_imported a WHERE NOT EXISTS
+--     (SELECT 1 FROM users u WHERE u.password_hash = a.password);
+DO $$
+BEGIN
+    IF to_regclass('public.admins') IS NOT NULL THEN
+        WITH imported AS (
+            INSERT INTO users (email, password_hash, username, role, email_verified)
+            SELECT LOWER(a.username) || '@event-management.invalid', a.password, a.username, 'user', FALSE
+            FROM admins a
+            WHERE NOT EXISTS (
+                SELECT 1 FROM users u WHERE LOWER(u.username) = LOWER(a.username)
+            )
+            RETURNING id
+        )
+        INSERT INTO permission_grants (user_id, permission)
+        SELECT id, 'events.write' FROM imported;
+
+        ALTER TABLE admins RENAME TO admins_imported;
+    END IF;
+END $$;
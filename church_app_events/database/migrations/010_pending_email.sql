@@ -0,0 +1,3 @@
+-- Email change requested through /me/profile, applied once the new address
+-- is verified
+ALTER TABLE users ADD COLUMN pending_email VARCHAR(255);
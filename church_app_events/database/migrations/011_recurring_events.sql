@@ -0,0 +1,24 @@
+-- Recurring events: an RFC 5545 RRULE on the event (its event_date is the
+-- first occurrence), cancelled occurrences, and changes to single occurrences.
+ALTER TABLE events ADD COLUMN recurrence_rule TEXT;
+ALTER TABLE events ADD COLUMN recurrence_exceptions DATE[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE event_occurrence_overrides (
+    id SERIAL PRIMARY KEY,
+    event_id INTEGER NOT NULL,
+    occurrence_date DATE NOT NULL,
+    event_title VARCHAR(255),
+    event_date DATE,
+    event_time TIME,
+    address TEXT,
+    description TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (event_id) REFERENCES events(id) ON DELETE CASCADE,
+    UNIQUE (event_id, occurrence_date)
+);
+
+-- RSVPs to a recurring event are for one occurrence, NULL for one-off events
+ALTER TABLE eventrsvp ADD COLUMN occurrence_date DATE;
+
+CREATE INDEX idx_eventrsvp_event_occurrence ON eventrsvp (event_id, occurrence_date);
@@ -0,0 +1,6 @@
+-- Category and owning home group of an event, used to filter the calendar feed
+ALTER TABLE events ADD COLUMN IF NOT EXISTS category VARCHAR(100);
+ALTER TABLE events ADD COLUMN home_group_id INTEGER REFERENCES homegroups(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_events_category ON events (category);
+CREATE INDEX idx_events_home_group_id ON events (home_group_id);
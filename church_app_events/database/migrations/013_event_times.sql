@@ -0,0 +1,9 @@
+-- Event ends and time zones. event_date and event_time stay the local start;
+-- duration_minutes is the wall-clock length (NULL when no end was given) and
+-- time_zone an IANA zone name (NULL for the church's CHURCH_TIME_ZONE).
+ALTER TABLE events ADD COLUMN duration_minutes INTEGER CHECK (duration_minutes > 0);
+ALTER TABLE events ADD COLUMN all_day BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE events ADD COLUMN time_zone VARCHAR(64);
+
+-- A changed occurrence may also change its length
+ALTER TABLE event_occurrence_overrides ADD COLUMN duration_minutes INTEGER CHECK (duration_minutes > 0);
@@ -0,0 +1,8 @@
+-- Optional number of seats per event (per occurrence for recurring events)
+ALTER TABLE events ADD COLUMN capacity INTEGER CHECK (capacity > 0);
+
+-- Set while an RSVP waits for a seat; the waitlist is ordered by it
+ALTER TABLE eventrsvp ADD COLUMN waitlisted_at TIMESTAMP;
+
+CREATE INDEX idx_eventrsvp_waitlist ON eventrsvp (event_id, occurrence_date, waitlisted_at)
+    WHERE waitlisted_at IS NOT NULL;
@@ -0,0 +1,33 @@
+-- Categories managed by admins; events refer to them by name
+CREATE TABLE event_categories (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL UNIQUE,
+    description TEXT,
+    color VARCHAR(7), -- Hex color such as #3366ff
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX idx_event_categories_name_lower ON event_categories (LOWER(name));
+
+-- Keep the categories events already use
+INSERT INTO event_categories (name)
+SELECT DISTINCT ON (LOWER(TRIM(category))) TRIM(category)
+FROM events
+WHERE TRIM(category) <> ''
+ORDER BY LOWER(TRIM(category)), TRIM(category);
+
+UPDATE events e SET category = c.name
+FROM event_categories c
+WHERE LOWER(TRIM(e.category)) = LOWER(c.name);
+
+UPDATE events SET category = NULL WHERE TRIM(category) = '';
+
+ALTER TABLE events ADD CONSTRAINT fk_events_category
+    FOREIGN KEY (category) REFERENCES event_categories (name)
+    ON UPDATE CASCADE ON DELETE SET NULL;
+
+-- Free-form tags, lowercase
+ALTER TABLE events ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX idx_events_tags ON events USING GIN (tags);
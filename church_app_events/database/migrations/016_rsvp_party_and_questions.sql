@@ -0,0 +1,10 @@
+-- Questions an event asks when people RSVP, see registration_form.rs
+ALTER TABLE events ADD COLUMN registration_questions JSONB NOT NULL DEFAULT '[]';
+
+-- Party size of an RSVP; every adult and child takes a seat
+ALTER TABLE eventrsvp ADD COLUMN adults INTEGER NOT NULL DEFAULT 1 CHECK (adults >= 0);
+ALTER TABLE eventrsvp ADD COLUMN children INTEGER NOT NULL DEFAULT 0 CHECK (children >= 0);
+ALTER TABLE eventrsvp ADD CONSTRAINT eventrsvp_party_size CHECK (adults + children > 0);
+
+-- Answers to the event's questions, keyed by question id
+ALTER TABLE eventrsvp ADD COLUMN answers JSONB NOT NULL DEFAULT '{}';
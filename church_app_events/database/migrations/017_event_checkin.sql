@@ -0,0 +1,10 @@
+-- Attendance: when and by whom an RSVP was checked in at the door
+ALTER TABLE eventrsvp ADD COLUMN checked_in_at TIMESTAMP;
+ALTER TABLE eventrsvp ADD COLUMN checked_in_by INTEGER REFERENCES users(id) ON DELETE SET NULL;
+-- Registered at the door without an RSVP
+ALTER TABLE eventrsvp ADD COLUMN walk_in BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_eventrsvp_checked_in ON eventrsvp (event_id, occurrence_date) WHERE checked_in_at IS NOT NULL;
+
+INSERT INTO permissions (name, description) VALUES
+    ('events.checkin', 'Check in attendees at events');
@@ -0,0 +1,106 @@
+-- Full-text search of events, media, home groups and servings, with
+-- trigram matching of titles to forgive typos
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Text search configuration of a language name or code, such as the
+-- language of a home group. Languages without stemming rules use 'simple'.
+CREATE FUNCTION search_config(language TEXT) RETURNS regconfig AS $$
+    SELECT (CASE
+        WHEN l IN ('', 'en', 'english') THEN 'english'
+        WHEN l IN ('es', 'spanish', 'español', 'espanol') THEN 'spanish'
+        WHEN l IN ('pt', 'portuguese', 'português', 'portugues') THEN 'portuguese'
+        WHEN l IN ('fr', 'french', 'français', 'francais') THEN 'french'
+        WHEN l IN ('de', 'german', 'deutsch') THEN 'german'
+        WHEN l IN ('it', 'italian', 'italiano') THEN 'italian'
+        WHEN l IN ('nl', 'dutch', 'nederlands') THEN 'dutch'
+        WHEN l IN ('ro', 'romanian', 'română', 'romana') THEN 'romanian'
+        WHEN l IN ('ru', 'russian', 'русский') THEN 'russian'
+        WHEN l IN ('sv', 'swedish', 'svenska') THEN 'swedish'
+        WHEN l IN ('no', 'nb', 'norwegian', 'norsk') THEN 'norwegian'
+        WHEN l IN ('da', 'danish', 'dansk') THEN 'danish'
+        WHEN l IN ('fi', 'finnish', 'suomi') THEN 'finnish'
+        WHEN l IN ('hu', 'hungarian', 'magyar') THEN 'hungarian'
+        WHEN l IN ('tr', 'turkish', 'türkçe', 'turkce') THEN 'turkish'
+        ELSE 'simple'
+    END)::regconfig
+    FROM (SELECT LOWER(TRIM(COALESCE(language, ''))) AS l) AS normalized
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- Titles weigh most, then categories, tags and places, then descriptions
+ALTER TABLE events ADD COLUMN search_vector tsvector;
+
+CREATE FUNCTION events_search_vector() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector('english', COALESCE(NEW.event_title, '')), 'A') ||
+        setweight(to_tsvector('english', COALESCE(NEW.category, '') || ' ' || array_to_string(NEW.tags, ' ')), 'B') ||
+        setweight(to_tsvector('english', COALESCE(NEW.address, '')), 'C') ||
+        setweight(to_tsvector('english', COALESCE(NEW.description, '')), 'D');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER events_search_vector BEFORE INSERT OR UPDATE ON events
+    FOR EACH ROW EXECUTE FUNCTION events_search_vector();
+
+ALTER TABLE media ADD COLUMN search_vector tsvector;
+
+CREATE FUNCTION media_search_vector() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector('english', COALESCE(NEW.title, '')), 'A') ||
+        setweight(to_tsvector('english', COALESCE(NEW.description, '')), 'D');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER media_search_vector BEFORE INSERT OR UPDATE ON media
+    FOR EACH ROW EXECUTE FUNCTION media_search_vector();
+
+-- Home groups are stemmed in their own language
+ALTER TABLE homegroups ADD COLUMN search_vector tsvector;
+
+CREATE FUNCTION homegroups_search_vector() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector(search_config(NEW.language), COALESCE(NEW.name, '')), 'A') ||
+        setweight(to_tsvector('simple', COALESCE(NEW.language, '')), 'B') ||
+        setweight(to_tsvector(search_config(NEW.language), COALESCE(NEW.location, '')), 'C') ||
+        setweight(to_tsvector(search_config(NEW.language), COALESCE(NEW.description, '')), 'D');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER homegroups_search_vector BEFORE INSERT OR UPDATE ON homegroups
+    FOR EACH ROW EXECUTE FUNCTION homegroups_search_vector();
+
+ALTER TABLE serving ADD COLUMN search_vector tsvector;
+
+CREATE FUNCTION serving_search_vector() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector('english', COALESCE(NEW.title, '')), 'A') ||
+        setweight(to_tsvector('english', COALESCE(NEW.location, '')), 'C') ||
+        setweight(to_tsvector('english', COALESCE(NEW.description, '')), 'D');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER serving_search_vector BEFORE INSERT OR UPDATE ON serving
+    FOR EACH ROW EXECUTE FUNCTION serving_search_vector();
+
+-- Fill in existing rows through the triggers
+UPDATE events SET search_vector = NULL;
+UPDATE media SET search_vector = NULL;
+UPDATE homegroups SET search_vector = NULL;
+UPDATE serving SET search_vector = NULL;
+
+CREATE INDEX idx_events_search ON events USING GIN (search_vector);
+CREATE INDEX idx_media_search ON media USING GIN (search_vector);
+CREATE INDEX idx_homegroups_search ON homegroups USING GIN (search_vector);
+CREATE INDEX idx_serving_search ON serving USING GIN (search_vector);
+
+CREATE INDEX idx_events_title_trgm ON events USING GIN (event_title gin_trgm_ops);
+CREATE INDEX idx_media_title_trgm ON media USING GIN (title gin_trgm_ops);
+CREATE INDEX idx_homegroups_name_trgm ON homegroups USING GIN (name gin_trgm_ops);
+CREATE INDEX idx_serving_title_trgm ON serving USING GIN (title gin_trgm_ops);
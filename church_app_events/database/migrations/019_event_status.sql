@@ -0,0 +1,11 @@
+-- Events are cancelled or rescheduled instead of deleted, see event_status.rs
+ALTER TABLE events ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'scheduled'
+    CHECK (status IN ('scheduled', 'cancelled', 'rescheduled'));
+ALTER TABLE events ADD COLUMN status_reason TEXT;
+ALTER TABLE events ADD COLUMN status_changed_at TIMESTAMP;
+
+-- After a reschedule, attendees are asked whether they can still come
+ALTER TABLE eventrsvp ADD COLUMN reconfirm_requested_at TIMESTAMP;
+ALTER TABLE eventrsvp ADD COLUMN reconfirmed_at TIMESTAMP;
+
+CREATE INDEX idx_eventrsvp_reconfirm ON eventrsvp (event_id) WHERE reconfirm_requested_at IS NOT NULL;
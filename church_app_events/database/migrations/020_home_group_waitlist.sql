@@ -0,0 +1,6 @@
+-- Set while a registration waits for a place in a full home group; the
+-- waitlist is ordered by it
+ALTER TABLE homegroupregistrations ADD COLUMN waitlisted_at TIMESTAMP;
+
+CREATE INDEX idx_homegroupregistrations_waitlist ON homegroupregistrations (home_group_id, waitlisted_at)
+    WHERE waitlisted_at IS NOT NULL;
@@ -0,0 +1,6 @@
+-- Set while the first in line is offered a freed place in a home group. The
+-- place is held for them until they accept it or the offer expires.
+ALTER TABLE homegroupregistrations ADD COLUMN offer_expires_at TIMESTAMP;
+
+CREATE INDEX idx_homegroupregistrations_offers ON homegroupregistrations (offer_expires_at)
+    WHERE offer_expires_at IS NOT NULL;
@@ -0,0 +1,59 @@
+-- Revision of an event for calendar apps (SEQUENCE in RFC 5545), raised
+-- by every change to the event or to one of its occurrences
+ALTER TABLE events ADD COLUMN sequence INTEGER NOT NULL DEFAULT 0;
+
+CREATE FUNCTION events_next_sequence() RETURNS trigger AS $$
+BEGIN
+    NEW.sequence := OLD.sequence + 1;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER events_next_sequence BEFORE UPDATE ON events
+    FOR EACH ROW EXECUTE FUNCTION events_next_sequence();
+
+CREATE FUNCTION event_occurrence_overrides_touch_event() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        UPDATE events SET updated_at = NOW() WHERE id = OLD.event_id;
+    ELSE
+        UPDATE events SET updated_at = NOW() WHERE id = NEW.event_id;
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER event_occurrence_overrides_touch_event AFTER INSERT OR UPDATE OR DELETE ON event_occurrence_overrides
+    FOR EACH ROW EXECUTE FUNCTION event_occurrence_overrides_touch_event();
+
+-- Deleted events stay in calendar feeds for a while as cancelled, so
+-- calendar apps remove the entries they already have
+CREATE TABLE deleted_events (
+    id INTEGER PRIMARY KEY, -- The id the event had
+    event_title VARCHAR(255) NOT NULL,
+    event_date DATE NOT NULL,
+    event_time TIME NOT NULL,
+    recurrence_rule TEXT,
+    category VARCHAR(100),
+    home_group_id INTEGER,
+    all_day BOOLEAN NOT NULL,
+    time_zone VARCHAR(64),
+    sequence INTEGER NOT NULL,
+    deleted_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE FUNCTION events_keep_deleted() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO deleted_events (id, event_title, event_date, event_time, recurrence_rule, category,
+        home_group_id, all_day, time_zone, sequence)
+    VALUES (OLD.id, OLD.event_title, OLD.event_date, OLD.event_time, OLD.recurrence_rule, OLD.category,
+        OLD.home_group_id, OLD.all_day, OLD.time_zone, OLD.sequence + 1)
+    ON CONFLICT (id) DO NOTHING;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER events_keep_deleted AFTER DELETE ON events
+    FOR EACH ROW EXECUTE FUNCTION events_keep_deleted();
+
+CREATE INDEX idx_deleted_events_deleted_at ON deleted_events (deleted_at);
@@ -0,0 +1,11 @@
+-- Wrong codes given outside the login, when turning 2FA off or replacing
+-- the recovery codes. They count toward the same per-user limit as wrong
+-- codes at /auth/2fa/verify.
+CREATE TABLE two_factor_failures (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_two_factor_failures_user ON two_factor_failures (user_id, created_at);
@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from '../pagination';
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -23,6 +24,15 @@ const api = axios.create({
     },
 });
 
+// Send the stored token, the backend requires it outside the public routes
+api.interceptors.request.use(async request => {
+    const token = await AsyncStorage.getItem('token');
+    if (token) {
+        request.headers.Authorization = `Bearer ${token}`;
+    }
+    return request;
+});
+
 // Add request interceptor for debugging
 api.interceptors.request.use(request => {
     console.log('Starting Request:', {
@@ -80,7 +90,7 @@ export const addRsvp = async (rsvpData) => {
 
 export const updateRsvp = async (id, rsvpData) => {
     try {
-        const response = await api.put(`/events/rsvp/edit/${id}`, rsvpData);
+        const response = await api.put(`/admin/events/rsvp/edit/${id}`, rsvpData);
         console.log('RSVP updated:', response.data);
         return response.data;
     } catch (error) {
@@ -91,7 +101,7 @@ export const updateRsvp = async (id, rsvpData) => {
 
 export const deleteRsvp = async (id) => {
     try {
-        const response = await api.delete(`/events/rsvp/${id}`);
+        const response = await api.delete(`/admin/events/rsvp/${id}`);
         console.log('RSVP deleted:', response.data);
         return response.data;
     } catch (error) {
@@ -104,9 +114,9 @@ export const deleteRsvp = async (id) => {
 // Get all RSVPs
 export const getAllRsvps = async () => {
     try {
-        const response = await api.get('/events/rsvp/list');
-        console.log('RSVPs fetched:', response.data);
-        return response.data;
+        const response = await fetchAllPages(api, '/admin/events/rsvp/list');
+        console.log('RSVPs fetched:', response.items);
+        return { ...response, rsvps: response.items };
     } catch (error) {
         console.error('Error fetching RSVPs:', error);
         throw error;
@@ -115,9 +125,9 @@ export const getAllRsvps = async () => {
 
 export const getRsvpsByEmail = async (email) => {
     try {
-        const response = await api.get(`/events/rsvp/email/${encodeURIComponent(email)}`);
-        console.log('RSVPs for email fetched:', response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/events/rsvp/email/${encodeURIComponent(email)}`);
+        console.log('RSVPs for email fetched:', response.items);
+        return response.items;
     } catch (error) {
         console.error('Error fetching RSVPs for email:', error);
         throw error;
@@ -127,9 +137,9 @@ export const getRsvpsByEmail = async (email) => {
 
 export const getRsvpsByEvent = async (eventId) => {
     try {
-        const response = await api.get(`/events/rsvp/event/${eventId}`);
-        console.log('RSVPs for event fetched:', response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/admin/events/rsvp/event/${eventId}`);
+        console.log('RSVPs for event fetched:', response.items);
+        return { ...response, rsvps: response.items };
     } catch (error) {
         console.error('Error fetching RSVPs for event:', error);
         throw error;
@@ -264,9 +274,9 @@ export const searchRsvps = async (searchParams = {}) => {
         const url = `/admin/events/rsvp/search?${queryString}`;
         console.log('Final URL:', url);
 
-        const response = await api.get(url);
-        console.log('RSVPs searched:', response.data);
-        return response.data;
+        const response = await fetchAllPages(api, url);
+        console.log('RSVPs searched:', response.items);
+        return { ...response, rsvps: response.items };
     } catch (error) {
         console.error('Error searching RSVPs:', error);
         throw error;
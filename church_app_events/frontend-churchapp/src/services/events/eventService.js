@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from '../pagination';
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -23,6 +24,15 @@ const api = axios.create({
     },
 });
 
+// Send the stored token, the backend requires it outside the public routes
+api.interceptors.request.use(async request => {
+    const token = await AsyncStorage.getItem('token');
+    if (token) {
+        request.headers.Authorization = `Bearer ${token}`;
+    }
+    return request;
+});
+
 // Add request interceptor for debugging
 api.interceptors.request.use(request => {
     console.log('Starting Request:', {
@@ -54,9 +64,9 @@ api.interceptors.response.use(
 
 export const getEvents = async () => {
     try {
-        const response = await api.get('/admin/events/list');
-        console.log('Events fetched:', response.data);  // Log response
-        return response.data;
+        const response = await fetchAllPages(api, '/events/list');
+        console.log('Events fetched:', response.items);  // Log response
+        return response.items;
     } catch (error) {
         console.error('Error fetching events:', error);
         throw error;
@@ -115,9 +125,9 @@ export const searchEvents = async (params) => {
 
         const query = searchParams.toString();
 
-        const response = await api.get(`/admin/events/search?${query}`);
-        console.log('Events searched:', response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/events/search?${query}`);
+        console.log('Events searched:', response.items);
+        return response.items;
     } catch (error) {
         console.error('Error searching events:', error);
         throw error;
@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from "../pagination";
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -94,9 +95,9 @@ export const deleteRegistration = async (id) => {
 // Get all registrations by home group
 export const getAllRegistrations = async () => {
     try {
-        const response = await api.get("/admin/home_group/rsvp/list");
-        console.log("Registrations fetched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, "/admin/home_group/rsvp/list");
+        console.log("Registrations fetched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error fetching registrations:", error);
         throw new Error(error.response?.data || 'Failed to fetch registrations');
@@ -105,9 +106,9 @@ export const getAllRegistrations = async () => {
 
 export const getRegistrationsByGroup = async (groupId) => {
     try {
-        const response = await api.get(`/admin/home_group/rsvp/group/${groupId}`);
-        console.log("Registrations by group fetched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/admin/home_group/rsvp/group/${groupId}`);
+        console.log("Registrations by group fetched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error fetching registrations by group:", error);
         throw new Error(error.response?.data || 'Failed to fetch registrations by group');
@@ -116,9 +117,9 @@ export const getRegistrationsByGroup = async (groupId) => {
 
 export const getRegistrationsByEmail = async (email) => {
     try {
-        const response = await api.get(`/admin/home_group/rsvp/email/${email}`);
-        console.log("Registrations by email fetched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/admin/home_group/rsvp/email/${email}`);
+        console.log("Registrations by email fetched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error fetching registrations by email:", error);
         throw new Error(error.response?.data || 'Failed to fetch registrations by email');
@@ -149,9 +150,9 @@ export const declineRegistration = async (id) => {
 
 export const searchRegistrations = async (searchParams) => {
     try {
-        const response = await api.get("/admin/home_group/rsvp/search", { params: searchParams });
-        console.log("Registrations searched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, "/admin/home_group/rsvp/search", { params: searchParams });
+        console.log("Registrations searched:", response.items);
+        return { ...response, registrations: response.items };
     } catch (error) {
         console.error("Error searching registrations:", error);
         throw new Error(error.response?.data || 'Failed to search registrations');
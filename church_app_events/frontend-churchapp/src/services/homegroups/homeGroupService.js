@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from "../pagination";
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -53,9 +54,9 @@ api.interceptors.response.use(
 
 export const getHomeGroups = async () => {
     try {
-        const response = await api.get("/admin/home_group/list");
-        console.log("Home groups fetched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, "/admin/home_group/list");
+        console.log("Home groups fetched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error fetching home groups:", error);
         throw error;
@@ -128,9 +129,9 @@ export const searchHomeGroups = async (params) => {
         if (params.language) searchParams.append("language", params.language);
         if (params.location) searchParams.append("location", params.location);
 
-        const response = await api.get(`/admin/home_group/search?${params}`);
-        console.log("Home groups searched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/admin/home_group/search?${params}`);
+        console.log("Home groups searched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error searching home groups:", error);
         throw new Error(error.response?.data?.message || 'Failed to search home groups');
@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from '../pagination';
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -23,6 +24,15 @@ const api = axios.create({
     },
 });
 
+// Send the stored token, the backend requires it outside the public routes
+api.interceptors.request.use(async request => {
+    const token = await AsyncStorage.getItem('token');
+    if (token) {
+        request.headers.Authorization = `Bearer ${token}`;
+    }
+    return request;
+});
+
 // Add request interceptor for debugging
 api.interceptors.request.use(request => {
     console.log('Starting Request:', {
@@ -87,11 +97,11 @@ export const getAllContent = async () => {
     try {
         console.log('Fetching content...');
         const [mediaResponse, youtubeResponse] = await Promise.allSettled([
-            api.get('/admin/media/list').catch(err => {
+            fetchAllPages(api, '/media/list').then(page => ({ data: page.items })).catch(err => {
                 console.error('Failed to fetch saved media:', err.response || err);
                 return { data: [] };
             }),
-            api.get('/admin/media/youtube/videos').catch(err => {
+            api.get('/media/youtube/videos').catch(err => {
                 console.error('Failed to fetch YouTube videos:', err.response || err);
                 return { data: [] };
             })
@@ -162,9 +172,9 @@ export const getYoutubeVideos = async () => {
 // Get saved media only
 export const getSavedMedia = async () => {
     try {
-        const response = await api.get('/admin/media/list');
-        console.log("Saved media fetched successfully:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, '/media/list');
+        console.log("Saved media fetched successfully:", response.items);
+        return response.items;
     } catch (error) {
         console.error('Error fetching saved media:', error);
         return [];
@@ -186,9 +196,9 @@ export const deleteMedia = async (mediaId) => {
 // Search media
 export const searchMedia = async (searchParams) => {
     try {
-        const response = await api.get('/admin/media/search', { params: searchParams });
-        console.log("Media fetched successfully:", response.data); ubuntu
-        return response.data;
+        const response = await fetchAllPages(api, '/media/search', { params: searchParams });
+        console.log("Media fetched successfully:", response.items); ubuntu
+        return response.items;
     } catch (error) {
         console.error('Error fetching media:', error);
         throw new Error(error.response?.data?.message || 'Failed to fetch media');
@@ -198,7 +208,7 @@ export const searchMedia = async (searchParams) => {
 // Get a media
 export const getMedia = async (mediaId) => {
     try {
-        const response = await api.get(`/admin/media/${mediaId}`);
+        const response = await api.get(`/media/${mediaId}`);
         console.log("Media fetched successfully:", response.data);
         return response.data;
     } catch (error) {
@@ -0,0 +1,20 @@
+// List and search endpoints answer a page at a time, as
+// { items, total, next_cursor, ...other fields }. The screens show whole
+// lists, so follow the cursors and gather every page; other fields, like
+// status counts, come from the first page.
+const PAGE_LIMIT = 200;
+
+export const fetchAllPages = async (api, url, config = {}) => {
+    const params = { ...config.params, limit: PAGE_LIMIT };
+    const first = (await api.get(url, { ...config, params })).data;
+    const items = [...first.items];
+
+    let cursor = first.next_cursor;
+    while (cursor) {
+        const page = (await api.get(url, { ...config, params: { ...params, cursor } })).data;
+        items.push(...page.items);
+        cursor = page.next_cursor;
+    }
+
+    return { ...first, items, next_cursor: null };
+};
@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from "../pagination";
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -23,6 +24,15 @@ const api = axios.create({
     },
 });
 
+// Send the stored token, the backend requires it outside the public routes
+api.interceptors.request.use(async request => {
+    const token = await AsyncStorage.getItem('token');
+    if (token) {
+        request.headers.Authorization = `Bearer ${token}`;
+    }
+    return request;
+});
+
 // Add request interceptor for debugging
 api.interceptors.request.use(request => {
     console.log('Starting Request:', {
@@ -67,7 +77,7 @@ export const createServingRsvp = async (rsvpData) => {
 // Update a serving RSVP
 export const updateServingRsvp = async (id, status) => {
     try {
-        const response = await api.put(`/servings/rsvp/edit/${id}`, status);
+        const response = await api.put(`/admin/servings/rsvp/edit/${id}`, status);
         console.log("Serving RSVP updated:", response.data);
         return response.data;
     } catch (error) {
@@ -79,7 +89,7 @@ export const updateServingRsvp = async (id, status) => {
 // Delete a serving RSVP
 export const deleteServingRsvp = async (id) => {
     try {
-        const response = await api.delete(`/servings/rsvp/${id}`);
+        const response = await api.delete(`/admin/servings/rsvp/${id}`);
         console.log("Serving RSVP deleted:", response.data);
         return response.data;
     } catch (error) {
@@ -91,9 +101,9 @@ export const deleteServingRsvp = async (id) => {
 // Get all serving RSVPs
 export const getAllServingRsvps = async () => {
     try {
-        const response = await api.get("/servings/rsvp/list");
-        console.log("Serving RSVPs fetched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, "/admin/servings/rsvp/list");
+        console.log("Serving RSVPs fetched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error fetching serving RSVPs:", error);
         throw new Error(error.response?.data || 'Failed to fetch serving RSVPs');
@@ -107,15 +117,15 @@ export const searchServingRsvps = async (searchParams) => {
         console.log("Searching serving RSVPs with params:", searchParams);
 
         // Add timeout and retry logic
-        const response = await api.get("/servings/rsvp/search", {
+        const response = await fetchAllPages(api, "/admin/servings/rsvp/search", {
             params: searchParams,
             timeout: 5000, // 5 second timeout
             retry: 3,      // Retry 3 times
             retryDelay: 1000 // Wait 1 second between retries
         });
 
-        console.log("Serving RSVPs search response:", response.data);
-        return response.data;
+        console.log("Serving RSVPs search response:", response.items);
+        return response.items;
     } catch (error) {
         // Enhanced error logging
         console.error("Error searching serving RSVPs:", {
@@ -2,6 +2,7 @@ import AsyncStorage from '@react-native-async-storage/async-storage';
 import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
+import { fetchAllPages } from "../pagination";
 
 const getApiUrl = () => {
     switch (Platform.OS) {
@@ -79,9 +80,9 @@ export const updateServing = async (id, servingData) => {
 // Get all serving signups
 export const getAllServing = async () => {
     try {
-        const response = await api.get("/admin/servings/list");
-        console.log("All serving fetched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, "/admin/servings/list");
+        console.log("All serving fetched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error fetching serving:", error);
         throw new Error(error.response?.data?.message || 'Failed to fetch serving');
@@ -104,9 +105,9 @@ export const deleteServing = async (id) => {
 export const searchServings = async (params) => {
     try {
         const searchParams = new URLSearchParams(params);
-        const response = await api.get(`/admin/servings/search?${searchParams}`);
-        console.log("Serving searched:", response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/admin/servings/search?${searchParams}`);
+        console.log("Serving searched:", response.items);
+        return response.items;
     } catch (error) {
         console.error("Error searching serving:", error);
         throw new Error(error.response?.data?.message || 'Failed to search serving');
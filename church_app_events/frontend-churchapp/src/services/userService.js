@@ -3,6 +3,7 @@ import { Platform } from "react-native";
 import axios from "axios";
 import { API_URL_ANDROID_DEVICE, API_URL_IOS, API_URL_WEB } from "@env"
 import NetInfo from "@react-native-community/netinfo";
+import { fetchAllPages } from './pagination';
 
 const getApiUrl = async () => {
     // Check network connection type
@@ -107,8 +108,8 @@ let api;
 export const getUsers = async () => {
     try {
         if (!api) api = await createApi();
-        const response = await api.get('/admin/users/list');
-        return response.data;
+        const response = await fetchAllPages(api, '/admin/users/list');
+        return response.items;
     } catch (error) {
         console.error('Error fetching users:', error);
         throw error;
@@ -265,9 +266,9 @@ export const searchUsers = async (params) => {
 
         const query = searchParams.toString();
 
-        const response = await api.get(`/admin/users/search?${query}`);
-        console.log('Users searched:', response.data);
-        return response.data;
+        const response = await fetchAllPages(api, `/admin/users/search?${query}`);
+        console.log('Users searched:', response.items);
+        return response.items;
     } catch (error) {
         console.error('Error searching users:', error);
         throw error;
@@ -0,0 +1,140 @@
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{Method, StatusCode},
+    Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde_json::json;
+use std::rc::Rc;
+
+use crate::session::{authenticate, Claims, UserRole};
+
+// Tokens are issued by church_app_events (/auth/login) and signed with the
+// shared JWT_SECRET, so one identity works across both services. Claims,
+// token checks and revocations come from its session.rs and revocation.rs.
+// Permissions come from the token, as this service has no permission tables.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessPolicy {
+    Public,
+    Authenticated,
+    EventsWrite, // Admins, and users granted events.write
+    AdminOnly,
+}
+
+// The permission that lets users other than admins edit events
+const EVENTS_WRITE: &str = "events.write";
+
+// Login and reading events are public, syncing to one's own calendar needs a
+// login, editing events needs events.write, user management is for admins
+pub fn access_policy(method: &Method, path: &str) -> AccessPolicy {
+    if path.starts_with("/auth/") {
+        AccessPolicy::Public
+    } else if path.starts_with("/events/sync_google_calendar/") {
+        AccessPolicy::Authenticated
+    } else if path.starts_with("/events/") && *method == Method::GET {
+        AccessPolicy::Public
+    } else if path.starts_with("/events/") {
+        AccessPolicy::EventsWrite
+    } else {
+        AccessPolicy::AdminOnly
+    }
+}
+
+// Whether the token carries `permission`; admins hold every permission
+fn carries(claims: &Claims, permission: &str) -> bool {
+    match claims.role {
+        UserRole::Admin => true,
+        UserRole::User => claims.permissions.iter().any(|held| held == permission),
+        UserRole::Guest => false,
+    }
+}
+
+pub struct AuthMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = AuthMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthMiddlewareService { service: Rc::new(service) })
+    }
+}
+
+pub struct AuthMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+fn reject<B>(req: ServiceRequest, status: StatusCode, message: &str) -> ServiceResponse<EitherBody<B>> {
+    let response = HttpResponse::build(status).json(json!({ "message": message }));
+    req.into_response(response).map_into_right_body()
+}
+
+impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let policy = access_policy(req.method(), req.path());
+
+            if policy == AccessPolicy::Public || req.method() == Method::OPTIONS {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            }
+
+            let claims = match authenticate(&req).await {
+                Ok(claims) => claims,
+                Err((status, message)) => return Ok(reject(req, status, message)),
+            };
+
+            let allowed = match policy {
+                AccessPolicy::Public => true,
+                AccessPolicy::Authenticated => matches!(claims.role, UserRole::Admin | UserRole::User),
+                AccessPolicy::EventsWrite => carries(&claims, EVENTS_WRITE),
+                AccessPolicy::AdminOnly => claims.role == UserRole::Admin,
+            };
+
+            if !allowed {
+                return Ok(reject(req, StatusCode::FORBIDDEN, "Insufficient permissions"));
+            }
+
+            // As in church_app_events, admins without a second factor only get
+            // past the Authenticated routes if the security policy allowed it
+            // when their token was issued
+            if claims.role == UserRole::Admin
+                && policy != AccessPolicy::Authenticated
+                && !claims.two_factor
+                && !claims.two_factor_optional
+            {
+                return Ok(reject(
+                    req,
+                    StatusCode::FORBIDDEN,
+                    "Two-factor authentication is required for admin accounts, enroll in the church app first",
+                ));
+            }
+
+            req.extensions_mut().insert(claims);
+
+            service.call(req).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
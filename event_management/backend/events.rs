@@ -3,6 +3,7 @@ use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
 
+use crate::session::{Claims, UserRole};
 use crate::google_calendar::GoogleCalendar;
 use crate::users::{get_user_from_db, User}; // Adjust if `get_user_from_db` is in a different module
 
@@ -233,9 +234,15 @@ pub async fn update_event(
 // Send event to google calendar handler
 pub async fn send_event_to_google_calendar_handler(
     pool: web::Data<PgPool>,
+    claims: Claims,
     event_id: web::Path<i32>,
     user_email: web::Query<String>,
 ) -> impl Responder {
+    // Users may only sync to their own calendar
+    if claims.role != UserRole::Admin && *user_email != claims.email {
+        return HttpResponse::Forbidden().json("You can only sync events to your own calendar");
+    }
+
     let event = sqlx::query_as!(
          Event,
         "SELECT id, event_title, event_day, event_time, address, content, created_at, updated_at FROM events WHERE id = $1",
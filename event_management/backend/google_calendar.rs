@@ -1,6 +1,7 @@
 use crate::events::Event;
 use crate::users::User;
 use actix_web::web::Data;
+use chrono::{Duration, NaiveDateTime};
 use oauth2::basic::BasicClient;
 use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
 use reqwest::Client;
@@ -8,12 +9,42 @@ use serde::Serialize;
 use sqlx::PgPool;
 use std::env;
 
+// Events are stored without an end; they are added to calendars with
+// this length, overridable with EVENT_DURATION_MINUTES
+const DEFAULT_DURATION_MINUTES: i64 = 60;
+
 #[derive(Serialize)]
 pub struct GoogleCalendarEvent {
     pub summary: String,
     pub description: String,
-    pub start_time: String,
-    pub end_time: String,
+    pub location: String,
+    pub start: EventDateTime,
+    pub end: EventDateTime,
+}
+
+// Local time plus IANA zone, as the Calendar API expects
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventDateTime {
+    pub date_time: String,
+    pub time_zone: String,
+}
+
+fn event_duration() -> Duration {
+    let minutes = env::var("EVENT_DURATION_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse::<i64>().ok())
+        .filter(|minutes| *minutes > 0)
+        .unwrap_or(DEFAULT_DURATION_MINUTES);
+    Duration::minutes(minutes)
+}
+
+// Event times are local to the church, CHURCH_TIME_ZONE
+fn event_date_time(local: NaiveDateTime) -> EventDateTime {
+    EventDateTime {
+        date_time: local.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        time_zone: env::var("CHURCH_TIME_ZONE").unwrap_or_else(|_| "UTC".to_string()),
+    }
 }
 
 pub struct GoogleCalendar {
@@ -64,11 +95,13 @@ impl GoogleCalendar {
             .map_err(|_| "Failed to fetch user token")?;
 
         if let Some(access_token) = user.google_access_token {
+            let start = NaiveDateTime::new(event.event_day, event.event_time);
             let google_event = GoogleCalendarEvent {
                 summary: event.event_title.clone(),
                 description: event.content.clone(),
-                start_time: format!("{}T{}:00", event.event_day, event.event_time),
-                end_time: format!("{}T{}:00", event.event_day, event.event_time),
+                location: event.address.clone(),
+                start: event_date_time(start),
+                end: event_date_time(start + event_duration()),
             };
 
             let client = Client::new();
@@ -1,134 +1,111 @@
-use actix_web::{web, HttpResponse, Responder};
-use bcrypt::{hash, verify, DEFAULT_COST};
-use serde::{Deserialize, Serialize};
-use sqlx::{Error, PgPool};
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, Responder};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
 
-use crate::users::User;
-
-// Register
-
-#[derive(sqlx::FromRow, Serialize)]
-pub struct RegisterResponse {
-    pub id: i32,
-    pub username: String,
-    is_admin: bool,
-}
+// Accounts live in the church_app_events `users` table. Login, signup and
+// token refresh are forwarded to its /auth routes, so both services use the
+// same accounts, roles, JWTs, login throttling and two-factor checks.
 
 #[derive(Deserialize)]
 pub struct RegisterInfo {
     pub username: String,
+    pub email: String,
     pub password: String,
 }
 
 #[derive(Deserialize)]
 pub struct LoginInfo {
-    pub username: String,
+    pub username: String, // Email or username
     pub password: String,
 }
 
-async fn username_exists(pool: &PgPool, username: &str) -> Result<bool, Error> {
-    let result = sqlx::query!("SELECT id FROM admins WHERE username = $1", username)
-        .fetch_optional(pool)
-        .await?;
-
-    Ok(result.is_some())
+#[derive(Deserialize)]
+pub struct RefreshInfo {
+    pub refresh_token: String,
 }
 
-pub async fn signup(
-    pool: &PgPool,
-    username: &str,
-    password: &str,
-) -> Result<RegisterResponse, String> {
-    match username_exists(pool, username).await {
-        Ok(true) => return Err("Username already exists".to_string()),
-        Ok(false) => {}
-        Err(err) => return Err(format!("Database error: {}", err)),
+// POST a JSON body to church_app_events and return its status and JSON body
+async fn forward(req: &HttpRequest, path: &str, body: Value) -> Result<(StatusCode, Value), String> {
+    let auth_url = env::var("AUTH_SERVICE_URL").map_err(|_| "AUTH_SERVICE_URL must be set".to_string())?;
+
+    let mut request = Client::new()
+        .post(format!("{}{}", auth_url.trim_end_matches('/'), path))
+        .json(&body);
+
+    // Pass on the address the caller connected from, never its own
+    // X-Forwarded-For, so login throttling applies per client. church_app_events
+    // only believes it when this service is listed in its TRUSTED_PROXIES.
+    if let Some(peer) = req.peer_addr() {
+        request = request.header("X-Forwarded-For", peer.ip().to_string());
     }
 
-    // Hash the password before storing it in the database
-    let hashed_password = match hash(password, DEFAULT_COST) {
-        Ok(hash) => hash,
-        Err(_) => return Err("Failed to hash password".to_string()),
-    };
+    if let Some(authorization) = req.headers().get("Authorization").and_then(|v| v.to_str().ok()) {
+        request = request.header("Authorization", authorization);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Auth service unreachable: {}", e))?;
 
-    // Insert the new admin into the database
-    let result = sqlx::query!(
-        "INSERT INTO admins (username, password, is_admin) VALUES ($1, $2, $3) RETURNING id, username, is_admin",
-        username,
-        hashed_password,
-        true
-    )
-    .fetch_one(pool)
-    .await;
+    let status = StatusCode::from_u16(response.status().as_u16())
+        .unwrap_or(StatusCode::BAD_GATEWAY);
+    let body = response.json::<Value>().await.unwrap_or(Value::Null);
 
-    println!("User registered login successfully.");
+    Ok((status, body))
+}
 
+fn respond(result: Result<(StatusCode, Value), String>) -> HttpResponse {
     match result {
-        Ok(row) => Ok(RegisterResponse {
-            id: row.id,
-            username: username.to_string(),
-            is_admin: row.is_admin,
-        }),
-        Err(err) => Err(format!("Database error: {}", err)),
+        Ok((status, body)) => HttpResponse::build(status).json(body),
+        Err(err) => {
+            eprintln!("{}", err);
+            HttpResponse::BadGateway().json("Authentication service unavailable")
+        }
     }
 }
 
-pub async fn signup_handler(
-    pool: web::Data<PgPool>,
-    user: web::Json<RegisterInfo>,
-) -> impl Responder {
-    match signup(pool.get_ref(), &user.username, &user.password).await {
-        Ok(response) => HttpResponse::Created().json(response),
-        Err(err) => HttpResponse::BadRequest().json(err),
-    }
+pub async fn signup_handler(req: HttpRequest, user: web::Json<RegisterInfo>) -> impl Responder {
+    let body = json!({
+        "username": user.username,
+        "email": user.email,
+        "password": user.password,
+    });
+
+    respond(forward(&req, "/auth/register", body).await)
 }
 
-async fn validate_login(
-    pool: &PgPool,
-    username: &str,
-    password: &str,
-) -> Result<Option<bool>, Error> {
-    // Query the admins table to find the user by username
-    let row = sqlx::query!(
-        "SELECT id, password, is_admin FROM admins WHERE username = $1",
-        username
-    )
-    .fetch_optional(pool)
-    .await?;
-
-    match row {
-        Some(user) => {
-            // verify if the hashed password from DB matches the provided password
-            if verify(password, &user.password).unwrap_or(false) {
-                // Update the is_admin field to true if not already set
-                if !user.is_admin {
-                    sqlx::query!("UPDATE admins SET is_admin = true WHERE id = $1", user.id)
-                        .execute(pool)
-                        .await?;
-                }
-
-                // Return is_admin status after potential update
-                Ok(Some(true))
-            } else {
-                Ok(None)
-            }
-        }
-        None => Ok(None),
+// The frontend still reads `isAdmin` from a response that signs the user in
+fn with_is_admin((status, mut body): (StatusCode, Value)) -> (StatusCode, Value) {
+    let is_admin = body.pointer("/user/role").and_then(Value::as_str) == Some("admin");
+    if let Some(fields) = body.as_object_mut().filter(|fields| fields.contains_key("token")) {
+        fields.insert("isAdmin".to_string(), json!(is_admin));
     }
+    (status, body)
 }
 
 // Login handle function
-pub async fn login_handler(
-    pool: web::Data<PgPool>,
-    credentials: web::Json<LoginInfo>,
-) -> impl Responder {
-    let username = &credentials.username;
-    let password = &credentials.password;
-
-    match validate_login(pool.get_ref(), username, password).await {
-        Ok(Some(is_admin)) => HttpResponse::Ok()
-            .json(serde_json::json!({ "message": "Login successful." , "isAdmin": is_admin})),
-        Ok(None) => HttpResponse::Unauthorized().json("Invalid username or password"),
-        Err(err) => HttpResponse::InternalServerError().json(format!("Database error:{}", err)),
-    }
+pub async fn login_handler(req: HttpRequest, credentials: web::Json<LoginInfo>) -> impl Responder {
+    let body = json!({
+        "identifier": credentials.username,
+        "password": credentials.password,
+    });
+
+    respond(forward(&req, "/auth/login", body).await.map(with_is_admin))
+}
+
+// Second login step for accounts with two-factor authentication
+pub async fn verify_two_factor_handler(req: HttpRequest, body: web::Json<Value>) -> impl Responder {
+    respond(forward(&req, "/auth/2fa/verify", body.into_inner()).await.map(with_is_admin))
+}
+
+pub async fn refresh_handler(req: HttpRequest, info: web::Json<RefreshInfo>) -> impl Responder {
+    respond(forward(&req, "/auth/refresh", json!({ "refresh_token": info.refresh_token })).await)
+}
+
+pub async fn logout_handler(req: HttpRequest, body: Option<web::Json<Value>>) -> impl Responder {
+    let body = body.map(|b| b.into_inner()).unwrap_or_else(|| json!({}));
+    respond(forward(&req, "/auth/logout", body).await)
 }
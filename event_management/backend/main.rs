@@ -7,6 +7,13 @@ use dotenv::dotenv;
 use sqlx::PgPool;
 use std::env;
 
+mod auth;
+// Shared with church_app_events, which issues the tokens and records revocations
+#[path = "../../church_app_events/backend/session.rs"]
+mod session;
+#[allow(dead_code)] // Only church_app_events revokes tokens
+#[path = "../../church_app_events/backend/revocation.rs"]
+mod revocation;
 mod events;
 mod google_calendar;
 mod login;
@@ -27,11 +34,18 @@ async fn main() -> std::io::Result<()> {
     let pool = PgPool::connect(&database_url).await.unwrap();
     let google_calendar_data = google_calendar::google_calendar_data(pool.clone());
 
+    // Shared with church_app_events, which records revoked tokens there
+    let redis_url = env::var("REDIS_URL").expect("REDIS_URL must be set");
+    let redis_client = redis::Client::open(redis_url).expect("Invalid REDIS_URL");
+    let revocations = web::Data::new(revocation::RevocationStore::new_with_client(redis_client));
+
     // Create the HTTP server
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone())) // Clone the pool for each instance
             .app_data(google_calendar_data.clone()) // Pass the GoogleCalendar struct
+            .app_data(revocations.clone())
+            .wrap(auth::AuthMiddleware) // Inside CORS so rejections get CORS headers too
             .wrap(
                 Cors::default()
                     .allowed_origin(&format!("http://localhost:{}", frontend_port))
@@ -43,6 +57,9 @@ async fn main() -> std::io::Result<()> {
             .service(
                 web::scope("/auth")
                     .route("/login", web::post().to(login::login_handler))
+                    .route("/2fa/verify", web::post().to(login::verify_two_factor_handler))
+                    .route("/refresh", web::post().to(login::refresh_handler))
+                    .route("/logout", web::post().to(login::logout_handler))
                     .route("/signup", web::post().to(login::signup_handler)),
             )
             // Users
@@ -0,0 +1,7 @@
+// Bearer token saved at login, sent with every request to protected routes
+const authHeaders = () => {
+  const token = localStorage.getItem ('token');
+  return token ? {Authorization: `Bearer ${token}`} : {};
+};
+
+export default authHeaders;
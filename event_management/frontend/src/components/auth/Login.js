@@ -7,8 +7,29 @@ const Login = () => {
   const [password, setPassword] = useState ('');
   const [notification, setNotification] = useState ('');
   const [isSuccess, setIsSuccess] = useState (false);
+  const [challengeToken, setChallengeToken] = useState ('');
+  const [code, setCode] = useState ('');
   const navigate = useNavigate ();
 
+  // Save the session from a response that signed the user in
+  const completeLogin = data => {
+    setNotification (`Login sucessful:, ${data.user.username}`);
+    setIsSuccess (true);
+    setUsername ('');
+    setPassword ('');
+    setChallengeToken ('');
+    setCode ('');
+
+    // Save login status to localStorage
+    localStorage.setItem ('isLoggedIn', true);
+    localStorage.setItem ('username', data.user.username);
+    localStorage.setItem ('isAdmin', data.isAdmin); // Save isAdmin flag here
+    localStorage.setItem ('token', data.token);
+    localStorage.setItem ('refreshToken', data.refresh_token);
+
+    navigate ('/auth/dashboard');
+  };
+
   const handleSubmit = async e => {
     e.preventDefault ();
 
@@ -27,17 +48,16 @@ const Login = () => {
     // Check for successful login
     if (response.ok) {
       const data = await response.json ();
-      setNotification (`Login sucessful:, ${data.username}`);
-      setIsSuccess (true);
-      setUsername ('');
-      setPassword ('');
 
-      // Save login status to localStorage
-      localStorage.setItem ('isLoggedIn', true);
-      localStorage.setItem ('username', username);
-      localStorage.setItem ('isAdmin', data.isAdmin); // Save isAdmin flag here
+      // Accounts with two-factor authentication get a challenge, not a token
+      if (data.two_factor_required) {
+        setChallengeToken (data.challenge_token);
+        setNotification ('Enter the code from your authenticator app');
+        setIsSuccess (true);
+        return;
+      }
 
-      navigate ('/auth/dashboard');
+      completeLogin (data);
     } else {
       const error = await response.text ();
       setNotification (`Login failed: ${error}`);
@@ -45,6 +65,31 @@ const Login = () => {
     }
   };
 
+  // Second login step: answer the challenge with a code
+  const handleVerifyCode = async e => {
+    e.preventDefault ();
+
+    const response = await fetch (
+      `${process.env.REACT_APP_BACKEND_URL}/auth/2fa/verify`,
+      {
+        method: 'POST',
+        headers: {
+          'Content-Type': 'application/json',
+        },
+        body: JSON.stringify ({challenge_token: challengeToken, code}),
+      }
+    );
+
+    if (response.ok) {
+      completeLogin (await response.json ());
+    } else {
+      const error = await response.text ();
+      setNotification (`Verification failed: ${error}`);
+      setIsSuccess (false);
+      setCode ('');
+    }
+  };
+
   //TODO
   const handleGoogleLogin = () => {
     // Implement Google login logic here
@@ -66,23 +111,36 @@ const Login = () => {
             {notification}
           </p>}
 
-        <form onSubmit={handleSubmit} className="login-form">
-          <input
-            type="text"
-            placeholder="Username"
-            value={username}
-            onChange={e => setUsername (e.target.value)}
-            required
-          />
-          <input
-            type="password"
-            placeholder="Password"
-            value={password}
-            onChange={e => setPassword (e.target.value)}
-            required
-          />
-          <button type="submit">Login</button>
-        </form>
+        {challengeToken
+          ? <form onSubmit={handleVerifyCode} className="login-form">
+              <input
+                type="text"
+                inputMode="numeric"
+                autoComplete="one-time-code"
+                placeholder="Authentication code"
+                value={code}
+                onChange={e => setCode (e.target.value)}
+                required
+              />
+              <button type="submit">Verify</button>
+            </form>
+          : <form onSubmit={handleSubmit} className="login-form">
+              <input
+                type="text"
+                placeholder="Username"
+                value={username}
+                onChange={e => setUsername (e.target.value)}
+                required
+              />
+              <input
+                type="password"
+                placeholder="Password"
+                value={password}
+                onChange={e => setPassword (e.target.value)}
+                required
+              />
+              <button type="submit">Login</button>
+            </form>}
         <div className="login-options">
           <button className="google-login" onClick={handleGoogleLogin}>
             Login with Google
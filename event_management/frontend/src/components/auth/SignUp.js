@@ -5,6 +5,7 @@ import './SignUp.css';
 
 const SignUp = () => {
   const [username, setUsername] = useState ('');
+  const [email, setEmail] = useState ('');
   const [password, setPassword] = useState ('');
   const [notification, setNotification] = useState ('');
   const [isSuccess, setIsSuccess] = useState (false); // Changed initial state to false
@@ -21,21 +22,18 @@ const SignUp = () => {
         headers: {
           'Content-Type': 'application/json',
         },
-        body: JSON.stringify ({username, password}),
+        body: JSON.stringify ({username, email, password}),
       }
     );
 
     if (response.ok) {
       const data = await response.json (); // Corrected: Await the response
-      setNotification (`Registration successful: ${data.username}`);
+      setNotification (`Registration successful: ${data.user.username}`);
       setIsSuccess (true);
       setUsername ('');
+      setEmail ('');
       setPassword ('');
 
-      localStorage.setItem ('isLoggedIn', true);
-      localStorage.setItem ('username', username);
-      localStorage.setItem ('isAdmin', data.isAdmin);
-
       // Redirect to dashboard
       navigate ('/auth/login');
     } else {
@@ -68,6 +66,13 @@ const SignUp = () => {
             onChange={e => setUsername (e.target.value)}
             required
           />
+          <input
+            type="email"
+            placeholder="Email"
+            value={email}
+            onChange={e => setEmail (e.target.value)}
+            required
+          />
           <input
             type="password"
             placeholder="Password"
@@ -1,6 +1,7 @@
 import React, {useState, useEffect, useCallback} from 'react';
 import {Link, useNavigate} from 'react-router-dom';
 import './EventManagement.css';
+import authHeaders from '../../authHeaders';
 
 const EventManagement = () => {
   const [eventTitle, setEventTitle] = useState ('');
@@ -29,6 +30,7 @@ const EventManagement = () => {
         method,
         headers: {
           'Content-Type': 'application/json',
+          ...authHeaders (),
         },
         body: JSON.stringify ({
           event_title: eventTitle,
@@ -95,6 +97,7 @@ const EventManagement = () => {
         `${process.env.REACT_APP_BACKEND_URL}/events/${id}`,
         {
           method: 'DELETE',
+          headers: authHeaders (),
         }
       );
       if (!response.ok) {
@@ -1,6 +1,7 @@
 import React, {useState} from 'react';
 import {Link, useNavigate} from 'react-router-dom';
 import './EventRegister.css'; // Import the CSS file
+import authHeaders from '../../authHeaders';
 
 const EventRegister = () => {
   const [eventTitle, setEventTitle] = useState ('');
@@ -23,6 +24,7 @@ const EventRegister = () => {
         method,
         headers: {
           'Content-Type': 'application/json',
+          ...authHeaders (),
         },
         body: JSON.stringify ({
           event_title: eventTitle,
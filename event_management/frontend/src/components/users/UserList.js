@@ -1,6 +1,7 @@
 import React, {useState, useEffect, useCallback} from 'react';
 import {Link, useNavigate} from 'react-router-dom';
 import './UserList.css'; // Import the CSS file
+import authHeaders from '../../authHeaders';
 
 const UserList = () => {
   const [username, setUsername] = useState ('');
@@ -29,6 +30,7 @@ const UserList = () => {
         method,
         headers: {
           'Content-Type': 'application/json',
+          ...authHeaders (),
         },
         body: JSON.stringify ({username, email}),
       });
@@ -59,7 +61,8 @@ const UserList = () => {
     async () => {
       try {
         const response = await fetch (
-          `${process.env.REACT_APP_BACKEND_URL}/users/admin/list?sort_by=${sortBy}`
+          `${process.env.REACT_APP_BACKEND_URL}/users/admin/list?sort_by=${sortBy}`,
+          {headers: authHeaders ()}
         );
         if (!response.ok) {
           setMessage ('Failed to fetch users.');
@@ -87,7 +90,7 @@ const UserList = () => {
     try {
       const response = await fetch (
         `${process.env.REACT_APP_BACKEND_URL}/users/${id}`,
-        {method: 'DELETE'}
+        {method: 'DELETE', headers: authHeaders ()}
       );
       if (!response.ok) {
         setMessage ('Failed to delete user.');
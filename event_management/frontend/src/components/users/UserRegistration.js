@@ -1,6 +1,7 @@
 import React, {useState} from 'react';
 import {Link} from 'react-router-dom';
 import './UserRegistration.css';
+import authHeaders from '../../authHeaders';
 
 const UserRegistration = () => {
   const [username, setUsername] = useState ('');
@@ -17,6 +18,7 @@ const UserRegistration = () => {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
+          ...authHeaders (),
         },
         body: JSON.stringify ({username, email}),
       }